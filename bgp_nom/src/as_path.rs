@@ -0,0 +1,272 @@
+// Working with AS paths.
+//
+// As text a path is written the usual way, with sequences as ASes
+// separated by spaces and sets in braces, and the confederation segments
+// (RFC 5065) in parentheses for a sequence or brackets for a set:
+//
+//   (65010 65011) 65001 65002 {65003,65004}
+//
+// The path length used in best path selection counts a set as one AS,
+// and doesn't count the confederation segments at all. The origin AS is
+// the last one of a path ending in a sequence, with none for a path
+// ending in a set (RFC 6811), and the neighbor AS the first one after any
+// confederation segments.
+//
+// Private ASes (RFC 6996) are 64512 to 65534, and 4200000000 to
+// 4294967294 of the four octet ones (RFC 6793). Removing or replacing them
+// leaves the confederation segments alone, as the members of a
+// confederation usually have private ASes and those segments are taken
+// out anyway before sending a route outside it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::*;
+
+// The most ASes a segment can hold.
+const MAX_SEGMENT_LENGTH: usize = 255;
+
+pub fn is_private(asn: u32) -> bool {
+    (64512..=65534).contains(&asn) || (4200000000..=4294967294).contains(&asn)
+}
+
+impl AsPathSegment {
+    pub fn asns(&self) -> &[u32] {
+        match *self {
+            AsPathSegment::AsSet(ref v) | AsPathSegment::AsSequence(ref v) => v,
+            AsPathSegment::AsConfedSequence(ref v) | AsPathSegment::AsConfedSet(ref v) => v,
+        }
+    }
+
+    fn asns_mut(&mut self) -> &mut Vec<u32> {
+        match *self {
+            AsPathSegment::AsSet(ref mut v) | AsPathSegment::AsSequence(ref mut v) => v,
+            AsPathSegment::AsConfedSequence(ref mut v) | AsPathSegment::AsConfedSet(ref mut v) => v,
+        }
+    }
+
+    pub fn is_confederation(&self) -> bool {
+        matches!(*self, AsPathSegment::AsConfedSequence(_) | AsPathSegment::AsConfedSet(_))
+    }
+}
+
+impl AsPathAttribute {
+    pub fn new(as_path: Vec<AsPathSegment>) -> AsPathAttribute {
+        AsPathAttribute { as_path }
+    }
+
+    pub fn segments(&self) -> &[AsPathSegment] {
+        &self.as_path
+    }
+
+    // The length compared in best path selection.
+    pub fn path_length(&self) -> usize {
+        self.as_path.iter().map(|segment| match *segment {
+            AsPathSegment::AsSequence(ref v) => v.len(),
+            AsPathSegment::AsSet(_) => 1,
+            AsPathSegment::AsConfedSequence(_) | AsPathSegment::AsConfedSet(_) => 0,
+        }).sum()
+    }
+
+    // None for an empty path too, in which case the route is our own.
+    pub fn origin_as(&self) -> Option<u32> {
+        match self.as_path.last() {
+            Some(AsPathSegment::AsSequence(ref v)) => v.last().cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn neighbor_as(&self) -> Option<u32> {
+        match self.as_path.iter().find(|s| !s.is_confederation()) {
+            Some(AsPathSegment::AsSequence(ref v)) => v.first().cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, asn: u32) -> bool {
+        self.as_path.iter().any(|s| s.asns().contains(&asn))
+    }
+
+    // Whether the path loops back through the local AS, which is allowed
+    // to be in it up to `allowas_in` times.
+    pub fn has_loop(&self, local_as: u32, allowas_in: usize) -> bool {
+        self.as_path.iter().map(|s| s.asns().iter().filter(|asn| **asn == local_as).count()).sum::<usize>() > allowas_in
+    }
+
+    // Put the AS on the front of the path, `count` times. This goes ahead
+    // of any confederation segments, so outside a confederation they
+    // should be taken out first.
+    pub fn prepend(&mut self, asn: u32, count: usize) {
+        for _ in 0..count {
+            match self.as_path.first_mut() {
+                Some(AsPathSegment::AsSequence(ref mut v)) if v.len() < MAX_SEGMENT_LENGTH => v.insert(0, asn),
+                _ => self.as_path.insert(0, AsPathSegment::AsSequence(vec![asn])),
+            }
+        }
+    }
+
+    // The path as sent to a peer outside the confederation, which
+    // doesn't see its members.
+    pub fn without_confederation(&self) -> AsPathAttribute {
+        AsPathAttribute::new(self.as_path.iter().filter(|s| !s.is_confederation()).cloned().collect())
+    }
+
+    pub fn remove_private(&mut self) {
+        for segment in self.as_path.iter_mut().filter(|s| !s.is_confederation()) {
+            segment.asns_mut().retain(|asn| !is_private(*asn));
+        }
+        self.as_path.retain(|s| !s.asns().is_empty());
+    }
+
+    pub fn replace_private(&mut self, replacement: u32) {
+        for segment in self.as_path.iter_mut().filter(|s| !s.is_confederation()) {
+            for asn in segment.asns_mut().iter_mut().filter(|asn| is_private(**asn)) {
+                *asn = replacement;
+            }
+        }
+    }
+}
+
+impl fmt::Display for AsPathAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |v: &[u32], separator: &str| v.iter().map(|asn| asn.to_string()).collect::<Vec<_>>().join(separator);
+        let segments: Vec<String> = self.as_path.iter().map(|segment| match *segment {
+            AsPathSegment::AsSequence(ref v) => join(v, " "),
+            AsPathSegment::AsSet(ref v) => format!("{{{}}}", join(v, ",")),
+            AsPathSegment::AsConfedSequence(ref v) => format!("({})", join(v, " ")),
+            AsPathSegment::AsConfedSet(ref v) => format!("[{}]", join(v, ",")),
+        }).collect();
+
+        f.write_str(&segments.join(" "))
+    }
+}
+
+impl FromStr for AsPathAttribute {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<AsPathAttribute, ()> {
+        let asns = |s: &str| s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+            .map(|a| a.parse::<u32>().map_err(|_| ()))
+            .collect::<Result<Vec<u32>, ()>>();
+
+        let mut as_path = Vec::new();
+        let mut rest = s.trim_start();
+        while let Some(c) = rest.chars().next() {
+            let (segment, remaining) = match c {
+                '{' | '(' | '[' => {
+                    let close = match c { '{' => '}', '(' => ')', _ => ']' };
+                    let end = rest.find(close).ok_or(())?;
+                    let v = asns(&rest[1..end])?;
+                    if v.is_empty() {
+                        return Err(());
+                    }
+                    let segment = match c {
+                        '{' => AsPathSegment::AsSet(v),
+                        '(' => AsPathSegment::AsConfedSequence(v),
+                        _ => AsPathSegment::AsConfedSet(v),
+                    };
+                    (segment, &rest[end + 1..])
+                },
+                _ => {
+                    // A run of ASes up to the next segment of another kind.
+                    let end = rest.find(['{', '(', '[']).unwrap_or(rest.len());
+                    if rest[..end].contains(',') {
+                        return Err(());
+                    }
+                    (AsPathSegment::AsSequence(asns(&rest[..end])?), &rest[end..])
+                },
+            };
+            as_path.push(segment);
+            rest = remaining.trim_start();
+        }
+
+        Ok(AsPathAttribute::new(as_path))
+    }
+}
+
+impl BgpUpdateMessage {
+    pub fn as_path(&self) -> Option<&AsPathAttribute> {
+        self.path_attributes.iter().filter_map(|a| match a.attribute {
+            PathAttribute::AsPath(ref as_path) => Some(&**as_path),
+            _ => None,
+        }).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> AsPathAttribute {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn as_path_string_test() {
+        let as_path = AsPathAttribute::new(vec![
+            AsPathSegment::AsConfedSequence(vec![65010, 65011]),
+            AsPathSegment::AsConfedSet(vec![65012, 65013]),
+            AsPathSegment::AsSequence(vec![65001, 65002]),
+            AsPathSegment::AsSet(vec![65003, 65004]),
+        ]);
+        assert_eq!(as_path.to_string(), "(65010 65011) [65012,65013] 65001 65002 {65003,65004}");
+        assert_eq!(path("(65010 65011) [65012,65013] 65001 65002 {65003,65004}"), as_path);
+        assert_eq!(path("  (65010  65011)[65012, 65013]65001 65002{ 65003 , 65004 } "), as_path);
+
+        assert_eq!(path(""), AsPathAttribute::new(vec![]));
+        assert_eq!(path("100 {200} 300").segments().len(), 3);
+
+        for s in &["4294967296", "100 {200", "100 {}", "100,200", "a", "100 )"] {
+            assert!(s.parse::<AsPathAttribute>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn as_path_query_test() {
+        let as_path = path("(65010) 65001 65002 65002 {65003,65004}");
+        assert_eq!(as_path.path_length(), 4);
+        assert_eq!(as_path.origin_as(), None);
+        assert_eq!(as_path.neighbor_as(), Some(65001));
+        assert!(as_path.contains(65004));
+        assert!(as_path.contains(65010));
+        assert!(!as_path.contains(65005));
+
+        let as_path = path("65001 65002 65003");
+        assert_eq!(as_path.origin_as(), Some(65003));
+        assert_eq!(path("{65001} 65002").neighbor_as(), None);
+        assert_eq!(path("").origin_as(), None);
+
+        // Loops, allowing the local AS in the path some number of times.
+        let as_path = path("65001 65000 65002 65000");
+        assert!(as_path.has_loop(65000, 0));
+        assert!(as_path.has_loop(65000, 1));
+        assert!(!as_path.has_loop(65000, 2));
+        assert!(!as_path.has_loop(65003, 0));
+    }
+
+    #[test]
+    fn as_path_modify_test() {
+        let mut as_path = path("{65003,65004}");
+        as_path.prepend(65001, 2);
+        assert_eq!(as_path.to_string(), "65001 65001 {65003,65004}");
+        as_path.prepend(65000, 1);
+        assert_eq!(as_path.to_string(), "65000 65001 65001 {65003,65004}");
+
+        // A full segment starts another.
+        let mut as_path = AsPathAttribute::new(vec![AsPathSegment::AsSequence(vec![1; 255])]);
+        as_path.prepend(2, 1);
+        assert_eq!(as_path.segments().len(), 2);
+        assert_eq!(as_path.path_length(), 256);
+
+        assert_eq!(path("(65010) 65001").without_confederation(), path("65001"));
+
+        let mut as_path = path("(64512) 100 64513 200 {64514,65535} {64515}");
+        as_path.remove_private();
+        assert_eq!(as_path.to_string(), "(64512) 100 200 {65535}");
+
+        let mut as_path = path("(64512) 100 64513 {64514,300}");
+        as_path.replace_private(65000);
+        assert_eq!(as_path.to_string(), "(64512) 100 65000 {65000,300}");
+    }
+}
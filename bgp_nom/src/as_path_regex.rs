@@ -0,0 +1,603 @@
+// Regular expressions over AS paths, for filters like `_65000$` or
+// `^65001_[0-9]+_`.
+//
+// These are written like the Cisco ones, but are matched against the
+// ASes of a path rather than its text, each AS being one symbol. So:
+//
+//   65001        the AS 65001
+//   6500[0-9]    a number can have digit classes, which can be repeated,
+//   [0-9]+       so this is any AS
+//   64512-65534  an AS in the range
+//   .            any AS
+//   _            where one AS ends and another starts, or the start or
+//                end of the path. Since numbers always match whole ASes
+//                it's only a separator, written for readability
+//   ^ $          the start and end of the path
+//   ( | )        grouping and alternatives
+//   * + ? {n,m}  repetition of the AS, class or group before
+//
+// A digit class repeated matches within an AS, so `[0-9]+` is one AS
+// and `.+` one or more of them. Unlike matching the text a number never
+// matches part of an AS: `500$` doesn't match a path ending in 65500.
+//
+// An AS_SET is one symbol that matches if any of its ASes do. The
+// confederation segments are left out, so a path is matched as it would
+// be seen outside the confederation.
+//
+// Patterns are compiled once into an NFA, which is then run over a path
+// following all the states it could be in at once, so matching takes
+// time in proportion to the path length times the pattern's size. As
+// repetitions are copies, nesting them multiplies the size, so there's a
+// limit on that and on how deep groups nest.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::*;
+
+// How many times a {n,m} repetition can be, since each is a copy.
+const MAX_REPEAT: u32 = 255;
+
+// The most instructions a pattern can compile to.
+const MAX_PROGRAM: usize = 10_000;
+
+// How deep groups can nest.
+const MAX_DEPTH: usize = 100;
+
+// A decimal digit pattern matching one AS, like `6[45][0-9]{3}`: each
+// piece is the digits it allows and how many times.
+#[derive(Debug,PartialEq,Clone)]
+struct DigitPiece {
+    digits: [bool; 10],
+    min: u32,
+    max: Option<u32>,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+enum Atom {
+    Any,
+    As(u32),
+    Range(u32, u32),
+    Digits(Vec<DigitPiece>),
+}
+
+impl Atom {
+    fn matches(&self, asn: u32) -> bool {
+        match *self {
+            Atom::Any => true,
+            Atom::As(a) => asn == a,
+            Atom::Range(low, high) => asn >= low && asn <= high,
+            Atom::Digits(ref pieces) => match_digits(pieces, asn.to_string().as_bytes()),
+        }
+    }
+}
+
+// Whether the pieces match all of the digits. Numbers are short, so this
+// just tries each count for each piece.
+fn match_digits(pieces: &[DigitPiece], digits: &[u8]) -> bool {
+    let (piece, rest) = match pieces.split_first() {
+        Some(split) => split,
+        None => return digits.is_empty(),
+    };
+    let max = piece.max.map_or(digits.len(), |m| (m as usize).min(digits.len()));
+    for count in piece.min as usize..=max {
+        if count > digits.len() || !digits[..count].iter().all(|d| piece.digits[(d - b'0') as usize]) {
+            return false;
+        }
+        if match_digits(rest, &digits[count..]) {
+            return true;
+        }
+    }
+    false
+}
+
+#[derive(Debug,PartialEq,Clone)]
+enum Node {
+    Atom(Atom),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>),
+}
+
+#[derive(Debug,PartialEq,Clone)]
+enum Inst {
+    // Consume an AS matching the atom and go on to the next instruction.
+    Atom(Atom),
+    // Go on only at the start or end of the path.
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug,Clone)]
+pub struct AsPathRegex {
+    pattern: String,
+    program: Vec<Inst>,
+}
+
+impl AsPathRegex {
+    pub fn new(pattern: &str) -> Result<AsPathRegex, String> {
+        let mut parser = Parser { chars: pattern.chars().collect(), position: 0, depth: 0 };
+
+        let node = parser.alternate()?;
+        if let Some(c) = parser.peek() {
+            return Err(format!("unexpected '{}' at {} in {}", c, parser.position, pattern));
+        }
+        if program_size(&node) >= MAX_PROGRAM {
+            return Err(format!("{} is too large", pattern));
+        }
+
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+
+        Ok(AsPathRegex { pattern: pattern.to_string(), program })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, as_path: &[AsPathSegment]) -> bool {
+        let symbols: Vec<Vec<u32>> = as_path.iter().flat_map(|segment| match *segment {
+            AsPathSegment::AsSequence(ref v) => v.iter().map(|asn| vec![*asn]).collect(),
+            AsPathSegment::AsSet(ref v) => vec![v.clone()],
+            AsPathSegment::AsConfedSequence(_) | AsPathSegment::AsConfedSet(_) => vec![],
+        }).collect();
+
+        let mut states = vec![false; self.program.len()];
+        for position in 0..=symbols.len() {
+            // A match can start anywhere, unless anchored.
+            self.add_state(&mut states, 0, position, symbols.len());
+            if states[self.program.len() - 1] {
+                return true;
+            }
+            let symbol = match symbols.get(position) {
+                Some(symbol) => symbol,
+                None => break,
+            };
+            let mut next = vec![false; self.program.len()];
+            for (pc, inst) in self.program.iter().enumerate() {
+                if let (true, Inst::Atom(ref atom)) = (states[pc], inst) {
+                    if symbol.iter().any(|asn| atom.matches(*asn)) {
+                        self.add_state(&mut next, pc + 1, position + 1, symbols.len());
+                    }
+                }
+            }
+            states = next;
+        }
+        false
+    }
+
+    // Add the state and those reachable from it without consuming an AS,
+    // at the position in a path of the length.
+    fn add_state(&self, states: &mut [bool], pc: usize, position: usize, length: usize) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if states[pc] {
+                continue;
+            }
+            states[pc] = true;
+            match self.program[pc] {
+                Inst::Start if position == 0 => stack.push(pc + 1),
+                Inst::End if position == length => stack.push(pc + 1),
+                Inst::Split(a, b) => {
+                    stack.push(b);
+                    stack.push(a);
+                },
+                Inst::Jump(a) => stack.push(a),
+                Inst::Atom(_) | Inst::Start | Inst::End | Inst::Match => {},
+            }
+        }
+    }
+}
+
+impl fmt::Display for AsPathRegex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl FromStr for AsPathRegex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AsPathRegex, String> {
+        AsPathRegex::new(s)
+    }
+}
+
+// How many instructions the node compiles to, as compile() does it.
+fn program_size(node: &Node) -> usize {
+    match *node {
+        Node::Atom(_) | Node::Start | Node::End => 1,
+        Node::Concat(ref nodes) => nodes.iter().fold(0, |size, node| size.saturating_add(program_size(node))),
+        Node::Alternate(ref nodes) => nodes.iter().fold(2 * (nodes.len() - 1), |size, node| size.saturating_add(program_size(node))),
+        Node::Repeat(ref node, min, max) => {
+            let size = program_size(node);
+            let optional = match max {
+                None => size.saturating_add(2),
+                Some(max) => ((max - min) as usize).saturating_mul(size.saturating_add(1)),
+            };
+            (min as usize).saturating_mul(size).saturating_add(optional)
+        },
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match *node {
+        Node::Atom(ref atom) => program.push(Inst::Atom(atom.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(ref nodes) => {
+            for node in nodes {
+                compile(node, program);
+            }
+        },
+        Node::Alternate(ref nodes) => {
+            // Each but the last is split off from the next, and jumps to
+            // the end when done.
+            let mut jumps = Vec::new();
+            for (i, node) in nodes.iter().enumerate() {
+                if i + 1 == nodes.len() {
+                    compile(node, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(node, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        },
+        Node::Repeat(ref node, min, max) => {
+            for _ in 0..min {
+                compile(node, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                },
+                Some(max) => {
+                    for _ in min..max {
+                        let split = program.len();
+                        program.push(Inst::Split(split + 1, 0));
+                        compile(node, program);
+                        program[split] = Inst::Split(split + 1, program.len());
+                    }
+                },
+            }
+        },
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    // How many groups we're in.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_separators();
+        match self.peek() == Some(c) {
+            true => {
+                self.position += 1;
+                true
+            },
+            false => false,
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while let Some('_') | Some(' ') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(c) => format!("expected {} at {}, found '{}'", expected, self.position, c),
+            None => format!("expected {} at the end", expected),
+        }
+    }
+
+    fn alternate(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![self.concat()?];
+        while self.eat('|') {
+            nodes.push(self.concat()?);
+        }
+        Ok(match nodes.len() {
+            1 => nodes.remove(0),
+            _ => Node::Alternate(nodes),
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_separators();
+            match self.peek() {
+                None | Some(')') | Some('|') => break,
+                _ => nodes.push(self.repeat()?),
+            }
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn repeat(&mut self) -> Result<Node, String> {
+        let mut node = self.primary()?;
+        if let Node::Start | Node::End = node {
+            return Ok(node);
+        }
+        while let Some((min, max)) = self.quantifier()? {
+            node = Node::Repeat(Box::new(node), min, max);
+        }
+        Ok(node)
+    }
+
+    fn quantifier(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let quantifier = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.position += 1;
+                let min = self.number()?;
+                let max = match self.peek() {
+                    Some(',') => {
+                        self.position += 1;
+                        match self.peek() {
+                            Some('}') => None,
+                            _ => Some(self.number()?),
+                        }
+                    },
+                    _ => Some(min),
+                };
+                if self.peek() != Some('}') {
+                    return Err(self.error("'}'"));
+                }
+                if max.is_some_and(|max| max < min) || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+                    return Err(format!("invalid repetition {{{},{}}}", min, max.map(|m| m.to_string()).unwrap_or_default()));
+                }
+                (min, max)
+            },
+            _ => return Ok(None),
+        };
+        self.position += 1;
+        Ok(Some(quantifier))
+    }
+
+    fn number(&mut self) -> Result<u32, String> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect::<String>().parse().map_err(|_| self.error("a number"))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some('(') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(format!("groups nested too deep at {}", self.position));
+                }
+                self.position += 1;
+                self.depth += 1;
+                let node = self.alternate()?;
+                self.skip_separators();
+                match self.peek() {
+                    Some(')') => {
+                        self.position += 1;
+                        self.depth -= 1;
+                        Ok(node)
+                    },
+                    _ => Err(self.error("')'")),
+                }
+            },
+            Some('.') => {
+                self.position += 1;
+                Ok(Node::Atom(Atom::Any))
+            },
+            Some('^') => {
+                self.position += 1;
+                Ok(Node::Start)
+            },
+            Some('$') => {
+                self.position += 1;
+                Ok(Node::End)
+            },
+            Some(c) if c.is_ascii_digit() || c == '[' => self.digits(),
+            _ => Err(self.error("an AS")),
+        }
+    }
+
+    // A number, a range of them, or a pattern of digits.
+    fn digits(&mut self) -> Result<Node, String> {
+        let mut pieces = Vec::new();
+        loop {
+            match self.peek() {
+                Some(c) if c.is_ascii_digit() => {
+                    self.position += 1;
+                    let mut digits = [false; 10];
+                    digits[c as usize - '0' as usize] = true;
+                    pieces.push(DigitPiece { digits, min: 1, max: Some(1) });
+                },
+                Some('[') => {
+                    self.position += 1;
+                    let digits = self.class()?;
+                    // Repetition of a class is of digits.
+                    let (min, max) = self.quantifier()?.unwrap_or((1, Some(1)));
+                    pieces.push(DigitPiece { digits, min, max });
+                },
+                _ => break,
+            }
+        }
+
+        let literal = |pieces: &[DigitPiece]| -> Option<String> {
+            pieces.iter().map(|p| match (p.min, p.max, p.digits.iter().filter(|d| **d).count()) {
+                (1, Some(1), 1) => p.digits.iter().position(|d| *d).map(|d| (b'0' + d as u8) as char),
+                _ => None,
+            }).collect()
+        };
+        let asn = |s: String| s.parse::<u32>().map_err(|_| format!("invalid AS {}", s));
+
+        match literal(&pieces) {
+            Some(low) if self.peek() == Some('-') => {
+                self.position += 1;
+                let high = self.number()?.to_string();
+                let (low, high) = (asn(low)?, asn(high)?);
+                if high < low {
+                    return Err(format!("invalid range {}-{}", low, high));
+                }
+                Ok(Node::Atom(Atom::Range(low, high)))
+            },
+            Some(literal) => Ok(Node::Atom(Atom::As(asn(literal)?))),
+            None => Ok(Node::Atom(Atom::Digits(pieces))),
+        }
+    }
+
+    // The digits in a class like [0-9] or [^5], after the '['.
+    fn class(&mut self) -> Result<[bool; 10], String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut digits = [false; 10];
+        loop {
+            match (self.peek(), self.chars.get(self.position + 1).cloned(), self.chars.get(self.position + 2).cloned()) {
+                (Some(']'), _, _) => {
+                    self.position += 1;
+                    break;
+                },
+                (Some(low), Some('-'), Some(high)) if low.is_ascii_digit() && high.is_ascii_digit() && low <= high => {
+                    self.position += 3;
+                    for d in low as usize..=high as usize {
+                        digits[d - '0' as usize] = true;
+                    }
+                },
+                (Some(c), _, _) if c.is_ascii_digit() => {
+                    self.position += 1;
+                    digits[c as usize - '0' as usize] = true;
+                },
+                _ => return Err(self.error("a digit or ']'")),
+            }
+        }
+        if negated {
+            for d in digits.iter_mut() {
+                *d = !*d;
+            }
+        }
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        let as_path: AsPathAttribute = path.parse().unwrap();
+        AsPathRegex::new(pattern).unwrap().is_match(as_path.segments())
+    }
+
+    #[test]
+    fn as_path_regex_policy_test() {
+        let cases: &[(&str, &str, bool)] = &[
+            // Locally originated.
+            ("^$", "", true),
+            ("^$", "65001", false),
+            // Anything.
+            (".*", "", true),
+            (".*", "65001 65002", true),
+            // Learned from the neighbor 65001.
+            ("^65001_", "65001 65002", true),
+            ("^65001_", "65002 65001", false),
+            // Originated by 65000.
+            ("_65000$", "65001 65000", true),
+            ("_65000$", "65000 65001", false),
+            ("_500$", "65001 65500", false),
+            // Through 65000 anywhere.
+            ("_65000_", "65001 65000 65002", true),
+            ("_65000_", "65000", true),
+            ("_65000_", "65001 65002", false),
+            // Directly connected.
+            ("^[0-9]+$", "65001", true),
+            ("^[0-9]+$", "65001 65002", false),
+            // The customers of 65001, one AS behind it.
+            ("^65001_[0-9]+$", "65001 65002", true),
+            ("^65001_[0-9]+$", "65001 65002 65003", false),
+            ("^65001_[0-9]+_", "65001 65002 65003", true),
+            // 65001 and its prepends.
+            ("^65001(_65001)*$", "65001 65001 65001", true),
+            ("^65001(_65001)*$", "65001 65001 65002", false),
+            ("^(65001_)+65002$", "65001 65001 65002", true),
+            // Private ASes, by range and by digits.
+            ("_64512-65534_", "65001 100", true),
+            ("_64512-65534_", "100 200 65535", false),
+            ("_6[45][0-9][0-9][0-9]_", "100 64600", true),
+            ("_6[45][0-9]{3}_", "100 64600", true),
+            ("_6[45][0-9]{3}_", "100 6460", false),
+            // Paths of up to three ASes.
+            ("^.{0,3}$", "1 2 3", true),
+            ("^.{0,3}$", "1 2 3 4", false),
+            ("^.?$", "", true),
+            // Alternatives.
+            ("^(65001|65002)_", "65002 100", true),
+            ("^(65001|65002)_", "65003 100", false),
+            ("_(100|200)$|^300_", "300 400", true),
+            // Not 5 in the last digit.
+            ("_[0-9]+[^5]$", "100 65004", true),
+            ("_[0-9]+[^5]$", "100 65005", false),
+            // A set matches if any of its ASes do.
+            ("_65003$", "65001 {65002,65003}", true),
+            ("^65001_65004$", "65001 {65002,65003}", false),
+            ("^65001_[0-9]+$", "65001 {65002,65003}", true),
+            // Confederation segments are left out.
+            ("^65001_", "(64512 64513) 65001 65002", true),
+            ("_64512_", "(64512 64513) 65001", false),
+        ];
+        for &(pattern, path, expected) in cases {
+            assert_eq!(matches(pattern, path), expected, "{} against {:?}", pattern, path);
+        }
+    }
+
+    #[test]
+    fn as_path_regex_invalid_test() {
+        for pattern in &["(65001", "65001)", "4294967296", "[0-9", "[a]", "65002-65001", "65001{2,1}", "65001{300}", "*", "^*", "$+"] {
+            assert!(AsPathRegex::new(pattern).is_err(), "{}", pattern);
+        }
+        assert_eq!(AsPathRegex::new("(65001").unwrap_err(), "expected ')' at the end");
+
+        // Too large, even though each repetition is allowed.
+        assert_eq!(AsPathRegex::new("((.{255}){255}){255}").unwrap_err(), "((.{255}){255}){255} is too large");
+        assert!(AsPathRegex::new(".{200}|.{200}").is_ok());
+        let nested = format!("{}65001{}", "(".repeat(1000), ")".repeat(1000));
+        assert!(AsPathRegex::new(&nested).is_err());
+        assert_eq!("_65000$".parse::<AsPathRegex>().unwrap().to_string(), "_65000$");
+    }
+
+    #[test]
+    fn as_path_regex_empty_loop_test() {
+        // Repeating something that can match nothing mustn't go on for ever.
+        assert!(matches("^(.*)*$", "1 2 3"));
+        assert!(matches("^(_)*65001$", "65001"));
+    }
+}
@@ -0,0 +1,280 @@
+// AS path verification with Autonomous System Provider Authorization
+// (ASPA), following draft-ietf-sidrops-aspa-verification.
+//
+// An ASPA record lists the providers of a customer AS. With them we can
+// tell whether each hop of an AS_PATH went up from a customer to one of
+// its providers, and so whether the path has the shape a route should
+// have: up from the origin through providers, across at most one peering,
+// and down through customers to us. A route leak shows up as a hop that
+// can't be part of that shape, such as a customer passing a route from
+// one provider to another.
+//
+// How much of the shape there is to check depends on where the route came
+// from. From a customer or a peer the whole path has to be going up
+// (upstream verification). From a provider it can go up and then down
+// (downstream verification).
+//
+// The AS_PATH is taken with prepends collapsed. A path with an AS_SET in
+// it is Invalid, as is an empty one, since a route from an external
+// neighbor at least has their AS in it. Checking that the first AS is
+// actually the neighbor's is left to the caller. Confederation segments
+// are left out, as the members of a confederation are one AS to the
+// outside.
+//
+// Records come from an RTR cache speaking version 2 (see rtr.rs), or with
+// the "serde" feature from the JSON written by rpki-client and Routinator:
+//
+//   {"aspas":[{"customer_asid":64496,"providers":[64497,64498]}]}
+//
+// AS numbers can also be strings like "AS64497", and "customer" can be
+// used for "customer_asid".
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::*;
+
+// What the neighbor a route came from is to us.
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Relation {
+    Customer,
+    Peer,
+    Provider,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum AspaState {
+    Valid,
+    Invalid,
+    Unknown,
+}
+
+impl fmt::Display for AspaState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            AspaState::Valid => "valid",
+            AspaState::Invalid => "invalid",
+            AspaState::Unknown => "unknown",
+        })
+    }
+}
+
+// Whether one AS is a provider of another, going by the records.
+#[derive(Debug,PartialEq,Clone,Copy)]
+enum Authorization {
+    ProviderPlus,
+    NotProviderPlus,
+    NoAttestation,
+}
+
+#[derive(Debug,Default,Clone)]
+pub struct AspaTable {
+    // The providers of each customer, sorted.
+    records: HashMap<u32, Vec<u32>>,
+}
+
+impl AspaTable {
+    pub fn new() -> AspaTable {
+        AspaTable::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    // Set the providers of a customer, replacing any it had.
+    pub fn insert(&mut self, customer: u32, mut providers: Vec<u32>) {
+        providers.sort_unstable();
+        providers.dedup();
+        self.records.insert(customer, providers);
+    }
+
+    // Returns false if there wasn't a record for the customer.
+    pub fn remove(&mut self, customer: u32) -> bool {
+        self.records.remove(&customer).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    pub fn providers(&self, customer: u32) -> Option<&[u32]> {
+        self.records.get(&customer).map(|p| &p[..])
+    }
+
+    fn authorized(&self, customer: u32, provider: u32) -> Authorization {
+        match self.records.get(&customer) {
+            Some(providers) if providers.binary_search(&provider).is_ok() => Authorization::ProviderPlus,
+            Some(_) => Authorization::NotProviderPlus,
+            None => Authorization::NoAttestation,
+        }
+    }
+
+    // Verify an AS_PATH received from a neighbor with the given relation.
+    pub fn verify(&self, as_path: &[AsPathSegment], relation: Relation) -> AspaState {
+        // The path from the origin to the neighbor, without prepends.
+        let mut path: Vec<u32> = Vec::new();
+        for segment in as_path.iter().rev() {
+            match *segment {
+                AsPathSegment::AsSequence(ref asns) => {
+                    for asn in asns.iter().rev().cloned() {
+                        if path.last() != Some(&asn) {
+                            path.push(asn);
+                        }
+                    }
+                },
+                AsPathSegment::AsSet(_) => return AspaState::Invalid,
+                AsPathSegment::AsConfedSequence(_) | AsPathSegment::AsConfedSet(_) => {},
+            }
+        }
+
+        let n = path.len();
+        if n == 0 {
+            return AspaState::Invalid;
+        }
+
+        // How far the path can be going up from the origin, at most and
+        // for certain.
+        let up = |accept: &dyn Fn(Authorization) -> bool| {
+            (0..n - 1).find(|&i| !accept(self.authorized(path[i], path[i + 1]))).map_or(n, |i| i + 1)
+        };
+        let max_up = up(&|a| a != Authorization::NotProviderPlus);
+        let min_up = up(&|a| a == Authorization::ProviderPlus);
+
+        match relation {
+            Relation::Customer | Relation::Peer => {
+                if max_up < n {
+                    AspaState::Invalid
+                } else if min_up < n {
+                    AspaState::Unknown
+                } else {
+                    AspaState::Valid
+                }
+            },
+            Relation::Provider => {
+                // And how far it can be coming down to us.
+                let down = |accept: &dyn Fn(Authorization) -> bool| {
+                    (1..n).rev().find(|&j| !accept(self.authorized(path[j], path[j - 1]))).map_or(n, |j| n - j)
+                };
+                let max_down = down(&|a| a != Authorization::NotProviderPlus);
+                let min_down = down(&|a| a == Authorization::ProviderPlus);
+
+                if n <= 2 {
+                    AspaState::Valid
+                } else if max_up + max_down < n {
+                    AspaState::Invalid
+                } else if min_up + min_down < n {
+                    AspaState::Unknown
+                } else {
+                    AspaState::Valid
+                }
+            },
+        }
+    }
+
+    pub fn verify_update(&self, update: &BgpUpdateMessage, relation: Relation) -> AspaState {
+        let as_path = update.path_attributes.iter().filter_map(|a| match a.attribute {
+            PathAttribute::AsPath(ref as_path) => Some(&as_path.as_path[..]),
+            _ => None,
+        }).next();
+
+        self.verify(as_path.unwrap_or(&[]), relation)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<AspaTable, serde_json::Error> {
+        let file: json::AspaFile = serde_json::from_str(json)?;
+        let mut table = AspaTable::new();
+        for record in file.aspas {
+            table.insert(record.customer, record.providers);
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(asns: &[u32]) -> Vec<AsPathSegment> {
+        vec![AsPathSegment::AsSequence(asns.to_vec())]
+    }
+
+    #[test]
+    fn aspa_verify_upstream_test() {
+        // 1 is a customer of 2, which is a customer of 3.
+        let mut table = AspaTable::new();
+        table.insert(1, vec![2]);
+        table.insert(2, vec![3, 4]);
+
+        // Received from 3, a customer or peer. The path is written
+        // neighbor first.
+        assert_eq!(table.verify(&path(&[3, 2, 1]), Relation::Customer), AspaState::Valid);
+        assert_eq!(table.verify(&path(&[3, 3, 2, 2, 2, 1]), Relation::Peer), AspaState::Valid);
+        assert_eq!(table.verify(&path(&[1]), Relation::Customer), AspaState::Valid);
+        // 2 passing a route on to 5, which isn't one of its providers.
+        assert_eq!(table.verify(&path(&[5, 2, 1]), Relation::Customer), AspaState::Invalid);
+        // 3 hasn't any record.
+        assert_eq!(table.verify(&path(&[6, 3, 2, 1]), Relation::Customer), AspaState::Unknown);
+        assert_eq!(table.verify(&path(&[3, 2, 6]), Relation::Customer), AspaState::Unknown);
+        // But a leak is a leak whatever else is unknown.
+        assert_eq!(table.verify(&path(&[7, 6, 5, 2, 1]), Relation::Customer), AspaState::Invalid);
+
+        assert_eq!(table.verify(&[], Relation::Customer), AspaState::Invalid);
+        assert_eq!(table.verify(&[AsPathSegment::AsSequence(vec![3, 2]), AsPathSegment::AsSet(vec![1, 8])], Relation::Customer), AspaState::Invalid);
+    }
+
+    #[test]
+    fn aspa_verify_downstream_test() {
+        // Up from 1 to 2, across to 3, then down to 4 and to us. 2 and 3
+        // have other providers.
+        let mut table = AspaTable::new();
+        table.insert(1, vec![2]);
+        table.insert(2, vec![9]);
+        table.insert(3, vec![9]);
+        table.insert(4, vec![3]);
+
+        assert_eq!(table.verify(&path(&[4, 3, 2, 1]), Relation::Provider), AspaState::Valid);
+        // The same from a customer is a leak.
+        assert_eq!(table.verify(&path(&[4, 3, 2, 1]), Relation::Customer), AspaState::Invalid);
+        // Two hops are always fine from a provider.
+        assert_eq!(table.verify(&path(&[5, 6]), Relation::Provider), AspaState::Valid);
+
+        // A valley: down from 2 to 3 and up again to 5.
+        table.insert(5, vec![9]);
+        table.insert(6, vec![5]);
+        assert_eq!(table.verify(&path(&[6, 5, 3, 2, 1]), Relation::Provider), AspaState::Invalid);
+
+        // Without a record for 4 we can't say 3 is its provider.
+        table.remove(4);
+        assert_eq!(table.verify(&path(&[4, 3, 2, 1]), Relation::Provider), AspaState::Unknown);
+    }
+
+    #[test]
+    fn aspa_verify_update_test() {
+        let update = match parse_bgp_message(&include_bytes!("../assets/test_bgp_update1.bin")[..]) {
+            Done(_, BgpMessage::Update(update)) => update,
+            other => panic!("failed to parse: {:?}", other),
+        };
+
+        // The AS_PATH has an AS_SET in it.
+        let table = AspaTable::new();
+        assert_eq!(table.verify_update(&update, Relation::Provider), AspaState::Invalid);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn aspa_from_json_test() {
+        let table = AspaTable::from_json(r#"{"metadata":{},"aspas":[
+            {"customer_asid":64496,"providers":[64498,64497]},
+            {"customer":"AS64499","providers":["AS64496"]}
+        ]}"#).unwrap();
+        assert_eq!(table.providers(64496), Some(&[64497, 64498][..]));
+        assert_eq!(table.providers(64499), Some(&[64496][..]));
+        assert!(AspaTable::from_json(r#"{"aspas":[{"customer":"64496x","providers":[]}]}"#).is_err());
+    }
+}
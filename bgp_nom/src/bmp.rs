@@ -0,0 +1,847 @@
+// BGP Monitoring Protocol (RFC 7854).
+//
+// BMP wraps the BGP messages a router sends and receives, along with a
+// per-peer header saying which peer they belong to. We decode the BMP
+// framing here and hand the BGP PDUs to parse_bgp_message().
+//
+// The UPDATE in a Route Monitoring message is parsed as the peer would
+// have sent it, with two differences from a plain BGP session:
+//
+// - Unless the A flag is set in the per-peer header, the AS_PATH and
+//   AGGREGATOR hold four octet AS numbers (RFC 6793), whatever was
+//   negotiated.
+//
+// - RFC 7854 has no ADD-PATH flag. Whether the NLRI carry path
+//   identifiers (RFC 7911) is worked out from the ADD-PATH capability in
+//   the OPENs of the Peer Up message for that peer, so BmpParser keeps
+//   track of those across a session. parse_bmp_message() on its own
+//   doesn't know them, and assumes no path identifiers.
+//
+// Route Mirroring messages are not decoded.
+//
+// There is also a small listener that accepts BMP sessions from routers
+// and turns them into a stream of events.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+use nom::{be_u8, be_u16, be_u32, be_u64};
+use nom::IResult;
+use nom::IResult::*;
+
+use super::{BgpMessage, MAX_EXTENDED_MESSAGE_LENGTH, UpdateEncoding, extended_bgp_message_frame, parse_bgp_message, parse_bgp_message_with_encoding, parse_extended_bgp_message};
+
+const BMP_VERSION: u8 = 3;
+const BMP_COMMON_HEADER_LENGTH: u32 = 6;
+const BMP_PER_PEER_HEADER_LENGTH: u32 = 42;
+
+// The longest BMP message we'll take. The 32 bit length allows far more,
+// but the largest message carries an extended BGP message (RFC 8654)
+// after the per-peer header, with room left for a TLV of the most a 16
+// bit length allows. Anything longer means the stream has gone wrong,
+// and isn't worth buffering up to 4GB waiting for.
+const MAX_BMP_MESSAGE_LENGTH: u32 = BMP_COMMON_HEADER_LENGTH + BMP_PER_PEER_HEADER_LENGTH + MAX_EXTENDED_MESSAGE_LENGTH as u32 + 4 + 0xffff;
+
+#[derive(Debug,PartialEq)]
+pub enum BmpMessage {
+    RouteMonitoring(Box<RouteMonitoring>),
+    StatisticsReport(Box<StatisticsReport>),
+    PeerDown(Box<PeerDownNotification>),
+    PeerUp(Box<PeerUpNotification>),
+    Initiation(Vec<InformationTlv>),
+    Termination(Vec<TerminationTlv>),
+}
+
+#[derive(Debug,PartialEq)]
+pub enum PeerType {
+    Global,
+    RdInstance,
+    Local,
+    Unknown(u8),
+}
+
+impl From<u8> for PeerType {
+    fn from(peer_type: u8) -> PeerType {
+        match peer_type {
+            0 => PeerType::Global,
+            1 => PeerType::RdInstance,
+            2 => PeerType::Local,
+            other => PeerType::Unknown(other),
+        }
+    }
+}
+
+// The flags are V (IPv6 peer address), L (post-policy), A (legacy two
+// octet AS_PATH) and, from RFC 8671, O (Adj-RIB-Out).
+
+#[derive(Debug,PartialEq)]
+pub struct PerPeerHeader {
+    pub peer_type: PeerType,
+    pub post_policy: bool,
+    pub legacy_as_path: bool,
+    pub adj_rib_out: bool,
+    pub peer_distinguisher: u64,
+    pub peer_address: IpAddr,
+    pub peer_as: u32,
+    pub peer_bgp_id: Ipv4Addr,
+    pub timestamp: Duration,
+}
+
+#[derive(Debug,PartialEq)]
+pub struct RouteMonitoring {
+    pub peer: PerPeerHeader,
+    pub message: BgpMessage,
+}
+
+#[derive(Debug,PartialEq)]
+pub struct StatisticsReport {
+    pub peer: PerPeerHeader,
+    pub statistics: Vec<Statistic>,
+}
+
+// Most statistics are 32 bit counters. Types 7 and 8 are 64 bit gauges,
+// and 9 and 10 are 64 bit gauges per AFI/SAFI.
+
+#[derive(Debug,PartialEq)]
+pub enum Statistic {
+    Counter(u16, u32),
+    Gauge(u16, u64),
+    AfiSafiGauge(u16, u16, u8, u64),
+    Unknown(u16, Vec<u8>),
+}
+
+#[derive(Debug,PartialEq)]
+pub struct PeerDownNotification {
+    pub peer: PerPeerHeader,
+    pub reason: PeerDownReason,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum PeerDownReason {
+    LocalNotification(BgpMessage),
+    LocalNoNotification(u16), // the FSM event code
+    RemoteNotification(BgpMessage),
+    RemoteNoData,
+    PeerDeconfigured,
+    Unknown(u8, Vec<u8>),
+}
+
+#[derive(Debug,PartialEq)]
+pub struct PeerUpNotification {
+    pub peer: PerPeerHeader,
+    pub local_address: IpAddr,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub sent_open: BgpMessage,
+    pub received_open: BgpMessage,
+    pub information: Vec<InformationTlv>,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum InformationTlv {
+    String(String),
+    SysDescr(String),
+    SysName(String),
+    Unknown(u16, Vec<u8>),
+}
+
+#[derive(Debug,PartialEq)]
+pub enum TerminationTlv {
+    String(String),
+    Reason(TerminationReason),
+    Unknown(u16, Vec<u8>),
+}
+
+#[derive(Debug,PartialEq)]
+pub enum TerminationReason {
+    AdministrativelyClosed,
+    Unspecified,
+    OutOfResources,
+    RedundantConnection,
+    PermanentlyAdministrativelyClosed,
+    Unknown(u16),
+}
+
+impl From<u16> for TerminationReason {
+    fn from(reason: u16) -> TerminationReason {
+        match reason {
+            0 => TerminationReason::AdministrativelyClosed,
+            1 => TerminationReason::Unspecified,
+            2 => TerminationReason::OutOfResources,
+            3 => TerminationReason::RedundantConnection,
+            4 => TerminationReason::PermanentlyAdministrativelyClosed,
+            other => TerminationReason::Unknown(other),
+        }
+    }
+}
+
+// Split one BMP message off the front of a stream, like
+// bgp_message_frame() does for BGP.
+
+named!(pub bmp_message_frame,
+    do_parse!(
+        length: peek!(preceded!(tag!([BMP_VERSION]), verify!(be_u32, |v: u32| (BMP_COMMON_HEADER_LENGTH..=MAX_BMP_MESSAGE_LENGTH).contains(&v)))) >>
+        frame: take!(length) >>
+        (frame)
+    )
+);
+
+named!(pub parse_bmp_message<BmpMessage>, call!(parse_bmp_message_with_peers, &HashMap::new()));
+
+// Route Monitoring messages are parsed with the path identifiers the Peer
+// Up for their peer said were negotiated, for the UPDATEs received from
+// the peer and for those sent to it.
+
+#[derive(Debug,Default,Clone)]
+struct PeerEncoding {
+    received: UpdateEncoding,
+    sent: UpdateEncoding,
+}
+
+type PeerKey = (u64, IpAddr);
+
+#[derive(Debug,Default)]
+pub struct BmpParser {
+    peers: HashMap<PeerKey, PeerEncoding>,
+}
+
+impl BmpParser {
+    pub fn new() -> BmpParser {
+        BmpParser::default()
+    }
+
+    pub fn parse<'a>(&mut self, input: &'a [u8]) -> IResult<&'a [u8], BmpMessage> {
+        let result = parse_bmp_message_with_peers(input, &self.peers);
+        match result {
+            Done(_, BmpMessage::PeerUp(ref up)) => {
+                if let (BgpMessage::Open(ref sent), BgpMessage::Open(ref received)) = (&up.sent_open, &up.received_open) {
+                    self.peers.insert(peer_key(&up.peer), PeerEncoding {
+                        received: UpdateEncoding::negotiated(received, sent),
+                        sent: UpdateEncoding::negotiated(sent, received),
+                    });
+                }
+            },
+            Done(_, BmpMessage::PeerDown(ref down)) => {
+                self.peers.remove(&peer_key(&down.peer));
+            },
+            _ => {},
+        }
+        result
+    }
+}
+
+fn peer_key(peer: &PerPeerHeader) -> PeerKey {
+    (peer.peer_distinguisher, peer.peer_address)
+}
+
+fn route_monitoring_encoding(peers: &HashMap<PeerKey, PeerEncoding>, peer: &PerPeerHeader) -> UpdateEncoding {
+    let add_path = peers.get(&peer_key(peer)).map(|p| match peer.adj_rib_out {
+        true => p.sent.add_path.clone(),
+        false => p.received.add_path.clone(),
+    });
+    UpdateEncoding { four_octet_as: !peer.legacy_as_path, add_path: add_path.unwrap_or_default() }
+}
+
+named_args!(parse_bmp_message_with_peers<'a>(peers: &'a HashMap<PeerKey, PeerEncoding>) <BmpMessage>,
+    do_parse!(
+        tag!([BMP_VERSION]) >>
+        length: verify!(be_u32, |v: u32| v >= BMP_COMMON_HEADER_LENGTH) >>
+        message_type: be_u8 >>
+        message: flat_map!(take!(length - BMP_COMMON_HEADER_LENGTH), switch!(value!(message_type),
+            0u8 => call!(route_monitoring, peers) |
+            1u8 => call!(statistics_report) |
+            2u8 => call!(peer_down_notification) |
+            3u8 => call!(peer_up_notification) |
+            4u8 => map!(complete!(many0!(information_tlv)), BmpMessage::Initiation) |
+            5u8 => map!(complete!(many0!(termination_tlv)), BmpMessage::Termination)
+        )) >>
+        (message)
+    )
+);
+
+// A BGP message carried inside a BMP message.
+named!(embedded_bgp_message<BgpMessage>, flat_map!(extended_bgp_message_frame, parse_extended_bgp_message));
+
+named_args!(embedded_update_message<'a>(encoding: &'a UpdateEncoding) <BgpMessage>,
+    flat_map!(call!(extended_bgp_message_frame), call!(parse_bgp_message_with_encoding, MAX_EXTENDED_MESSAGE_LENGTH, encoding))
+);
+
+named!(embedded_open_message<BgpMessage>,
+    map_opt!(embedded_bgp_message, |m| match m {
+        m @ BgpMessage::Open(_) => Some(m),
+        _ => None,
+    })
+);
+
+// Addresses in BMP are always 16 bytes, with IPv4 addresses in the last
+// four.
+named_args!(bmp_address(ipv6: bool) <IpAddr>,
+    map!(take!(16), |v: &[u8]| match ipv6 {
+        true => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(v);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        },
+        false => IpAddr::V4(Ipv4Addr::new(v[12], v[13], v[14], v[15])),
+    })
+);
+
+named!(per_peer_header<PerPeerHeader>,
+    do_parse!(
+        peer_type: be_u8 >>
+        flags: be_u8 >>
+        peer_distinguisher: be_u64 >>
+        peer_address: call!(bmp_address, flags & 0x80 != 0) >>
+        peer_as: be_u32 >>
+        peer_bgp_id: be_u32 >>
+        seconds: be_u32 >>
+        microseconds: verify!(be_u32, |v: u32| v < 1_000_000) >>
+        (PerPeerHeader {
+            peer_type: PeerType::from(peer_type),
+            post_policy: flags & 0x40 != 0,
+            legacy_as_path: flags & 0x20 != 0,
+            adj_rib_out: flags & 0x10 != 0,
+            peer_distinguisher,
+            peer_address,
+            peer_as,
+            peer_bgp_id: Ipv4Addr::from(peer_bgp_id),
+            timestamp: Duration::new(seconds as u64, microseconds * 1_000),
+        })
+    )
+);
+
+named_args!(route_monitoring<'a>(peers: &'a HashMap<PeerKey, PeerEncoding>) <BmpMessage>,
+    do_parse!(
+        peer: per_peer_header >>
+        encoding: value!(route_monitoring_encoding(peers, &peer)) >>
+        message: call!(embedded_update_message, &encoding) >>
+        eof!() >>
+        (BmpMessage::RouteMonitoring(Box::new(RouteMonitoring { peer, message })))
+    )
+);
+
+named!(statistic<Statistic>,
+    map!(tlv, |(stat_type, value): (u16, &[u8])| {
+        let number = |v: &[u8]| v.iter().fold(0u64, |n, b| n << 8 | *b as u64);
+        match (stat_type, value.len()) {
+            (7, 8) | (8, 8) => Statistic::Gauge(stat_type, number(value)),
+            (9, 11) | (10, 11) => Statistic::AfiSafiGauge(stat_type, number(&value[..2]) as u16, value[2], number(&value[3..])),
+            (_, 4) => Statistic::Counter(stat_type, number(value) as u32),
+            _ => Statistic::Unknown(stat_type, value.to_vec()),
+        }
+    })
+);
+
+named!(statistics_report<BmpMessage>,
+    do_parse!(
+        peer: per_peer_header >>
+        statistics: length_count!(be_u32, statistic) >>
+        eof!() >>
+        (BmpMessage::StatisticsReport(Box::new(StatisticsReport { peer, statistics })))
+    )
+);
+
+named!(peer_down_reason<PeerDownReason>,
+    switch!(be_u8,
+        1u8 => map!(embedded_bgp_message, PeerDownReason::LocalNotification) |
+        2u8 => map!(be_u16, PeerDownReason::LocalNoNotification) |
+        3u8 => map!(embedded_bgp_message, PeerDownReason::RemoteNotification) |
+        4u8 => value!(PeerDownReason::RemoteNoData) |
+        5u8 => value!(PeerDownReason::PeerDeconfigured)
+    )
+);
+
+named!(peer_down_notification<BmpMessage>,
+    do_parse!(
+        peer: per_peer_header >>
+        reason: alt!(peer_down_reason | do_parse!(reason: be_u8 >> data: call!(rest) >> (PeerDownReason::Unknown(reason, data.to_vec())))) >>
+        eof!() >>
+        (BmpMessage::PeerDown(Box::new(PeerDownNotification { peer, reason })))
+    )
+);
+
+named!(peer_up_notification<BmpMessage>,
+    do_parse!(
+        peer: per_peer_header >>
+        local_address: call!(bmp_address, peer.peer_address.is_ipv6()) >>
+        local_port: be_u16 >>
+        remote_port: be_u16 >>
+        sent_open: embedded_open_message >>
+        received_open: embedded_open_message >>
+        information: complete!(many0!(information_tlv)) >>
+        eof!() >>
+        (BmpMessage::PeerUp(Box::new(PeerUpNotification {
+            peer,
+            local_address,
+            local_port,
+            remote_port,
+            sent_open,
+            received_open,
+            information,
+        })))
+    )
+);
+
+fn tlv(input: &[u8]) -> IResult<&[u8], (u16, &[u8])> {
+    do_parse!(input,
+        tlv_type: be_u16 >>
+        value: length_bytes!(be_u16) >>
+        (tlv_type, value)
+    )
+}
+
+named!(information_tlv<InformationTlv>,
+    map!(tlv, |(tlv_type, value): (u16, &[u8])| match tlv_type {
+        0 => InformationTlv::String(String::from_utf8_lossy(value).into_owned()),
+        1 => InformationTlv::SysDescr(String::from_utf8_lossy(value).into_owned()),
+        2 => InformationTlv::SysName(String::from_utf8_lossy(value).into_owned()),
+        other => InformationTlv::Unknown(other, value.to_vec()),
+    })
+);
+
+named!(termination_tlv<TerminationTlv>,
+    map!(tlv, |(tlv_type, value): (u16, &[u8])| match (tlv_type, value.len()) {
+        (0, _) => TerminationTlv::String(String::from_utf8_lossy(value).into_owned()),
+        (1, 2) => TerminationTlv::Reason(TerminationReason::from((value[0] as u16) << 8 | value[1] as u16)),
+        (other, _) => TerminationTlv::Unknown(other, value.to_vec()),
+    })
+);
+
+fn rest(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    Done(&input[input.len()..], input)
+}
+
+// Listening for BMP sessions.
+//
+// Routers connect to the monitoring station, so we listen and accept any
+// number of them. Each connection gets a thread that frames and parses
+// messages and sends them down a channel, tagged with the address of the
+// router that sent them.
+
+#[derive(Debug,PartialEq)]
+pub enum BmpEvent {
+    Connected(SocketAddr),
+    Message(SocketAddr, BmpMessage),
+    // A message we couldn't parse. It has been skipped.
+    Error(SocketAddr, Vec<u8>),
+    Disconnected(SocketAddr),
+}
+
+pub struct BmpListener {
+    listener: TcpListener,
+}
+
+impl BmpListener {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<BmpListener> {
+        Ok(BmpListener { listener: TcpListener::bind(addr)? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Start accepting connections, returning the stream of events from
+    // all of them.
+    pub fn events(self) -> Receiver<BmpEvent> {
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let sender = sender.clone();
+                thread::spawn(move || read_bmp_session(stream, sender));
+            }
+        });
+
+        receiver
+    }
+}
+
+fn read_bmp_session(stream: TcpStream, sender: Sender<BmpEvent>) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => return,
+    };
+
+    if sender.send(BmpEvent::Connected(peer)).is_err() {
+        return;
+    }
+
+    let _ = read_bmp_stream(stream, peer, &sender);
+    let _ = sender.send(BmpEvent::Disconnected(peer));
+}
+
+// Read messages until the stream ends or something goes wrong. A message
+// that frames correctly but doesn't parse is reported and skipped, but if
+// the framing itself is broken there is no way to find the next message
+// so we give up on the stream.
+fn read_bmp_stream<R: Read>(mut reader: R, peer: SocketAddr, sender: &Sender<BmpEvent>) -> io::Result<()> {
+    let mut parser = BmpParser::new();
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+
+        let mut pos = 0;
+        loop {
+            let frame = match bmp_message_frame(&buffer[pos..]) {
+                Done(_, frame) => frame,
+                Incomplete(_) => break,
+                Error(_) => {
+                    let _ = sender.send(BmpEvent::Error(peer, buffer[pos..].to_vec()));
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "bad BMP message header"));
+                },
+            };
+
+            let event = match parser.parse(frame) {
+                Done(_, message) => BmpEvent::Message(peer, message),
+                _ => BmpEvent::Error(peer, frame.to_vec()),
+            };
+            pos += frame.len();
+
+            if sender.send(event).is_err() {
+                return Ok(());
+            }
+        }
+        buffer.drain(..pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{AddPathCapability, AddPathFamily, AggregatorAttribute, BgpNotificationMessage, BgpOpenMessage, BgpUpdateMessage, CapabilityParameter, Ipv4Prefix, OptionalParameter, PathAttribute};
+    use std::net::Shutdown;
+
+    fn bmp(message_type: u8, body: &[u8]) -> Vec<u8> {
+        let length = body.len() as u32 + 6;
+        let mut out = vec![3, (length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8, message_type];
+        out.extend_from_slice(body);
+        out
+    }
+
+    // A per-peer header for 192.168.0.15 in AS 65033, post-policy with a
+    // legacy AS_PATH.
+    fn peer_header() -> Vec<u8> {
+        let mut out = vec![0, 0x60, 0, 0, 0, 0, 0, 0, 0, 0];
+        out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 0, 15]);
+        out.extend_from_slice(&[0, 0, 0xfe, 0x09, 192, 168, 0, 15, 0x38, 0xe3, 0x5a, 0x98, 0, 0x07, 0xa1, 0x20]);
+        out
+    }
+
+    fn expected_peer_header() -> PerPeerHeader {
+        PerPeerHeader {
+            peer_type: PeerType::Global,
+            post_policy: true,
+            legacy_as_path: true,
+            adj_rib_out: false,
+            peer_distinguisher: 0,
+            peer_address: "192.168.0.15".parse().unwrap(),
+            peer_as: 65033,
+            peer_bgp_id: Ipv4Addr::new(192, 168, 0, 15),
+            timestamp: Duration::new(954423960, 500_000_000),
+        }
+    }
+
+    fn parse_bgp(input: &[u8]) -> BgpMessage {
+        match parse_bgp_message(input) {
+            Done(_, message) => message,
+            other => panic!("failed to parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_initiation_termination_test() {
+        let message = bmp(4, &[0, 1, 0, 5, b'J', b'u', b'n', b'o', b's', 0, 2, 0, 2, b'r', b'1', 0, 99, 0, 1, 0xff]);
+        assert_eq!(parse_bmp_message(&message), Done(&b""[..], BmpMessage::Initiation(vec![
+            InformationTlv::SysDescr("Junos".to_string()),
+            InformationTlv::SysName("r1".to_string()),
+            InformationTlv::Unknown(99, vec![0xff]),
+        ])));
+
+        let message = bmp(5, &[0, 0, 0, 3, b'b', b'y', b'e', 0, 1, 0, 2, 0, 3]);
+        assert_eq!(parse_bmp_message(&message), Done(&b""[..], BmpMessage::Termination(vec![
+            TerminationTlv::String("bye".to_string()),
+            TerminationTlv::Reason(TerminationReason::RedundantConnection),
+        ])));
+    }
+
+    #[test]
+    fn parse_route_monitoring_test() {
+        let update = include_bytes!("../assets/test_bgp_update1.bin");
+        let mut body = peer_header();
+        body.extend_from_slice(&update[..]);
+
+        assert_eq!(parse_bmp_message(&bmp(0, &body)), Done(&b""[..], BmpMessage::RouteMonitoring(Box::new(RouteMonitoring {
+            peer: expected_peer_header(),
+            message: parse_bgp(&update[..]),
+        }))));
+
+        // Pre-policy, legacy AS_PATH, IPv6 peer.
+        body[1] = 0xa0;
+        body[10..26].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        match parse_bmp_message(&bmp(0, &body)) {
+            Done(_, BmpMessage::RouteMonitoring(rm)) => {
+                assert!(!rm.peer.post_policy);
+                assert!(rm.peer.legacy_as_path);
+                assert_eq!(rm.peer.peer_address, "2001:db8::1".parse::<IpAddr>().unwrap());
+            },
+            other => panic!("expected route monitoring: {:?}", other),
+        }
+
+        // Trailing junk after the BGP message.
+        body.push(0);
+        match parse_bmp_message(&bmp(0, &body)) { Error(_) => {}, other => panic!("expected an error: {:?}", other), }
+    }
+
+    // An UPDATE with the given attributes and NLRI.
+    fn update(attributes: &[u8], nlri: &[u8]) -> Vec<u8> {
+        let length = 23 + attributes.len() + nlri.len();
+        let mut out = vec![0xff; 16];
+        out.extend_from_slice(&[(length >> 8) as u8, length as u8, 2, 0, 0, 0, attributes.len() as u8]);
+        out.extend_from_slice(attributes);
+        out.extend_from_slice(nlri);
+        out
+    }
+
+    fn update_of(message: IResult<&[u8], BmpMessage>) -> BgpUpdateMessage {
+        match message {
+            Done(_, BmpMessage::RouteMonitoring(rm)) => match rm.message {
+                BgpMessage::Update(update) => *update,
+                other => panic!("expected an UPDATE: {:?}", other),
+            },
+            other => panic!("expected route monitoring: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_route_monitoring_four_octet_as_test() {
+        // An AS_PATH of 65001 4200000001, and an AGGREGATOR of 4200000001.
+        let attributes = [
+            0x40, 1, 1, 0,
+            0x40, 2, 10, 2, 2, 0, 0, 0xfd, 0xe9, 0xfa, 0x56, 0xea, 0x01,
+            0x40, 3, 4, 192, 0, 2, 1,
+            0xc0, 7, 8, 0xfa, 0x56, 0xea, 0x01, 192, 0, 2, 1,
+        ];
+        let mut body = peer_header();
+        body[1] = 0x40;
+        body.extend_from_slice(&update(&attributes, &[24, 198, 51, 100]));
+
+        let update = update_of(parse_bmp_message(&bmp(0, &body)));
+        assert_eq!(update.as_path().unwrap().to_string(), "65001 4200000001");
+        assert_eq!(update.path_attributes[3].attribute, PathAttribute::Aggregator(Box::new(AggregatorAttribute {
+            aggregator_as: 4200000001,
+            aggregator_id: Ipv4Addr::new(192, 0, 2, 1),
+        })));
+        assert_eq!(update.nlri, vec!["198.51.100.0/24".parse().unwrap()]);
+    }
+
+    #[test]
+    fn bmp_parser_add_path_test() {
+        let open = |send: bool, receive: bool| BgpMessage::Open(Box::new(BgpOpenMessage {
+            version: 4,
+            my_autonomous_system: 65033,
+            hold_time: 180,
+            bgp_identifier: Ipv4Addr::new(192, 168, 0, 15),
+            optional_parameters: vec![OptionalParameter::Capability(vec![
+                CapabilityParameter::AddPath(Box::new(AddPathCapability { families: vec![AddPathFamily { afi: 1, safi: 1, receive, send }] })),
+            ])],
+        })).encode().unwrap();
+
+        // The router can receive more than one path and the peer can send
+        // them, so those received from the peer have path identifiers.
+        let mut peer_up = peer_header();
+        peer_up.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 0, 33, 0, 179, 0x08, 0x4c]);
+        peer_up.extend_from_slice(&open(false, true));
+        peer_up.extend_from_slice(&open(true, false));
+
+        let attributes = [0x40, 1, 1, 0, 0x40, 2, 0, 0x40, 3, 4, 192, 0, 2, 1];
+        let received = [peer_header(), update(&attributes, &[0, 0, 0, 7, 24, 198, 51, 100])].concat();
+        let mut sent = [peer_header(), update(&attributes, &[24, 198, 51, 100])].concat();
+        sent[1] |= 0x10;
+
+        let mut parser = BmpParser::new();
+        assert!(matches!(parser.parse(&bmp(3, &peer_up)), Done(_, BmpMessage::PeerUp(_))));
+        assert_eq!(update_of(parser.parse(&bmp(0, &received))).nlri, vec![Ipv4Prefix { prefix: vec![198, 51, 100], length: 24, path_id: Some(7) }]);
+        assert_eq!(update_of(parser.parse(&bmp(0, &sent))).nlri, vec!["198.51.100.0/24".parse().unwrap()]);
+
+        // Once the peer is down it's forgotten.
+        let peer_down = [peer_header(), vec![4]].concat();
+        assert!(matches!(parser.parse(&bmp(2, &peer_down)), Done(_, BmpMessage::PeerDown(_))));
+        let received = [peer_header(), update(&attributes, &[24, 198, 51, 100])].concat();
+        assert_eq!(update_of(parser.parse(&bmp(0, &received))).nlri, vec!["198.51.100.0/24".parse().unwrap()]);
+    }
+
+    #[test]
+    fn parse_peer_up_test() {
+        let sent = include_bytes!("../assets/test_bgp_open1.bin");
+        let received = include_bytes!("../assets/test_bgp_open2.bin");
+
+        let mut body = peer_header();
+        body.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 0, 33, 0, 179, 0x08, 0x4c]);
+        body.extend_from_slice(&sent[..]);
+        body.extend_from_slice(&received[..]);
+        body.extend_from_slice(&[0, 0, 0, 2, b'h', b'i']);
+
+        assert_eq!(parse_bmp_message(&bmp(3, &body)), Done(&b""[..], BmpMessage::PeerUp(Box::new(PeerUpNotification {
+            peer: expected_peer_header(),
+            local_address: "192.168.0.33".parse().unwrap(),
+            local_port: 179,
+            remote_port: 2124,
+            sent_open: parse_bgp(&sent[..]),
+            received_open: parse_bgp(&received[..]),
+            information: vec![InformationTlv::String("hi".to_string())],
+        }))));
+
+        // The embedded messages must be OPENs.
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+        let mut body = peer_header();
+        body.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 192, 168, 0, 33, 0, 179, 0x08, 0x4c]);
+        body.extend_from_slice(&keepalive[..]);
+        body.extend_from_slice(&received[..]);
+        match parse_bmp_message(&bmp(3, &body)) { Error(_) => {}, other => panic!("expected an error: {:?}", other), }
+    }
+
+    #[test]
+    fn parse_peer_down_test() {
+        let notification = include_bytes!("../assets/test_bgp_notification1.bin");
+
+        let mut body = peer_header();
+        body.push(3);
+        body.extend_from_slice(&notification[..]);
+        assert_eq!(parse_bmp_message(&bmp(2, &body)), Done(&b""[..], BmpMessage::PeerDown(Box::new(PeerDownNotification {
+            peer: expected_peer_header(),
+            reason: PeerDownReason::RemoteNotification(BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 2, error_subcode: 2, data: vec![0xfe, 0xb0] }))),
+        }))));
+
+        let mut body = peer_header();
+        body.extend_from_slice(&[2, 0, 24]);
+        match parse_bmp_message(&bmp(2, &body)) {
+            Done(_, BmpMessage::PeerDown(pd)) => assert_eq!(pd.reason, PeerDownReason::LocalNoNotification(24)),
+            other => panic!("expected peer down: {:?}", other),
+        }
+
+        let mut body = peer_header();
+        body.extend_from_slice(&[9, 1, 2]);
+        match parse_bmp_message(&bmp(2, &body)) {
+            Done(_, BmpMessage::PeerDown(pd)) => assert_eq!(pd.reason, PeerDownReason::Unknown(9, vec![1, 2])),
+            other => panic!("expected peer down: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_statistics_report_test() {
+        let mut body = peer_header();
+        body.extend_from_slice(&[0, 0, 0, 4]);
+        body.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 5]);
+        body.extend_from_slice(&[0, 7, 0, 8, 0, 0, 0, 1, 0, 0, 0, 0]);
+        body.extend_from_slice(&[0, 9, 0, 11, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 42]);
+        body.extend_from_slice(&[0, 99, 0, 1, 7]);
+
+        assert_eq!(parse_bmp_message(&bmp(1, &body)), Done(&b""[..], BmpMessage::StatisticsReport(Box::new(StatisticsReport {
+            peer: expected_peer_header(),
+            statistics: vec![
+                Statistic::Counter(0, 5),
+                Statistic::Gauge(7, 1 << 32),
+                Statistic::AfiSafiGauge(9, 1, 1, 42),
+                Statistic::Unknown(99, vec![7]),
+            ],
+        }))));
+    }
+
+    #[test]
+    fn parse_bmp_errors_test() {
+        // Wrong version.
+        let mut message = bmp(4, &[]);
+        message[0] = 2;
+        match parse_bmp_message(&message) { Error(_) => {}, other => panic!("expected an error: {:?}", other), }
+
+        // Route mirroring isn't supported.
+        match parse_bmp_message(&bmp(6, &peer_header())) { Error(_) => {}, other => panic!("expected an error: {:?}", other), }
+
+        assert_eq!(parse_bmp_message(&bmp(4, &[0, 1, 0, 5])[..8]), Incomplete(::nom::Needed::Size(10)));
+    }
+
+    #[test]
+    fn bmp_listener_test() {
+        let listener = BmpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let events = listener.events();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let local = stream.local_addr().unwrap();
+
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+        let mut route_monitoring = peer_header();
+        route_monitoring.extend_from_slice(&keepalive[..]);
+
+        let mut data = bmp(4, &[0, 2, 0, 2, b'r', b'1']);
+        data.extend(bmp(0, &route_monitoring));
+        data.extend(bmp(6, &[]));
+        data.extend(bmp(5, &[0, 1, 0, 2, 0, 0]));
+
+        // Dribble the data out to exercise the framing.
+        for chunk in data.chunks(7) {
+            stream.write_all(chunk).unwrap();
+        }
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(events.recv_timeout(timeout).unwrap(), BmpEvent::Connected(local));
+        assert_eq!(events.recv_timeout(timeout).unwrap(), BmpEvent::Message(local, BmpMessage::Initiation(vec![InformationTlv::SysName("r1".to_string())])));
+        assert_eq!(events.recv_timeout(timeout).unwrap(), BmpEvent::Message(local, BmpMessage::RouteMonitoring(Box::new(RouteMonitoring {
+            peer: expected_peer_header(),
+            message: BgpMessage::Keepalive,
+        }))));
+        assert_eq!(events.recv_timeout(timeout).unwrap(), BmpEvent::Error(local, bmp(6, &[])));
+        assert_eq!(events.recv_timeout(timeout).unwrap(), BmpEvent::Message(local, BmpMessage::Termination(vec![
+            TerminationTlv::Reason(TerminationReason::AdministrativelyClosed),
+        ])));
+        assert_eq!(events.recv_timeout(timeout).unwrap(), BmpEvent::Disconnected(local));
+    }
+
+    #[test]
+    fn read_bmp_stream_bad_framing_test() {
+        let (sender, receiver) = channel();
+        let peer: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+        let mut data = bmp(4, &[]);
+        data.extend_from_slice(&[2, 0, 0, 0, 6, 4]);
+
+        assert!(read_bmp_stream(&data[..], peer, &sender).is_err());
+        assert_eq!(receiver.recv().unwrap(), BmpEvent::Message(peer, BmpMessage::Initiation(vec![])));
+        assert_eq!(receiver.recv().unwrap(), BmpEvent::Error(peer, vec![2, 0, 0, 0, 6, 4]));
+    }
+
+    #[test]
+    fn read_bmp_stream_too_long_test() {
+        let (sender, receiver) = channel();
+        let peer: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+        // A header saying there's 4GB to come is an error straight away,
+        // rather than waiting for it.
+        let data = [3, 0xff, 0xff, 0xff, 0xff, 0];
+        assert_eq!(read_bmp_stream(&data[..], peer, &sender).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(receiver.recv().unwrap(), BmpEvent::Error(peer, data.to_vec()));
+
+        let length = MAX_BMP_MESSAGE_LENGTH + 1;
+        let header = [3, (length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8, 0];
+        assert!(bmp_message_frame(&header).is_err());
+        let length = MAX_BMP_MESSAGE_LENGTH;
+        let header = [3, (length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8, 0];
+        assert!(bmp_message_frame(&header).is_incomplete());
+    }
+}
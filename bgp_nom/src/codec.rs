@@ -0,0 +1,237 @@
+// A tokio codec for reading and writing BGP messages on a TCP stream.
+//
+// Decoding frames a message with bgp_message_frame() and parses it with
+// parse_bgp_message_with_encoding(). A message that fails to parse is an error that
+// ends the stream. The error carries the BGP error code and subcode to
+// send back in a NOTIFICATION, so the session can tell the peer what it
+// did wrong before closing the connection.
+//
+// Messages can be up to 4096 bytes long until the session raises the
+// limit, after negotiating Extended Messages (RFC 8654). An UPDATE too
+// long to send is split into ones that fit, while anything else too long
+// is an error, as is a message that can't be encoded. AS numbers in
+// UPDATEs are likewise two octets until the session says they're four.
+
+use std::fmt;
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::*;
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+    // A message we couldn't parse, with the error as CODE<<8|SUBCODE.
+    Message(u32),
+}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> CodecError {
+        CodecError::Io(e)
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::Io(ref e) => write!(f, "{}", e),
+            CodecError::Message(code) => write!(f, "bad message ({})", NotificationError::new((code >> 8) as u8, code as u8)),
+        }
+    }
+}
+
+pub(crate) struct BgpCodec {
+    max_length: u16,
+    four_octet_as: bool,
+}
+
+impl Default for BgpCodec {
+    fn default() -> BgpCodec {
+        BgpCodec { max_length: MAX_MESSAGE_LENGTH, four_octet_as: false }
+    }
+}
+
+impl BgpCodec {
+    pub fn new() -> BgpCodec {
+        BgpCodec::default()
+    }
+
+    pub fn max_length(&self) -> u16 {
+        self.max_length
+    }
+
+    pub fn set_max_length(&mut self, max_length: u16) {
+        self.max_length = max_length;
+    }
+
+    pub fn set_four_octet_as(&mut self, four_octet_as: bool) {
+        self.four_octet_as = four_octet_as;
+    }
+}
+
+impl Decoder for BgpCodec {
+    type Item = BgpMessage;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BgpMessage>, CodecError> {
+        let length = match bgp_message_frame_with_max(src, self.max_length) {
+            Done(_, frame) => frame.len(),
+            Incomplete(_) => return Ok(None),
+            Error(ref e) => return Err(CodecError::Message(error_details(e).1.unwrap_or(MESSAGE_HEADER_ERROR << 8))),
+        };
+
+        // A session only changes whether AS numbers are four octets, which
+        // is all an archive says too.
+        let encoding = UpdateEncoding::archived(self.four_octet_as);
+        let result = match parse_bgp_message_with_encoding(&src[..length], self.max_length, encoding) {
+            Done(&[], message) => Ok(Some(message)),
+            Error(ref e) => Err(CodecError::Message(error_details(e).1.unwrap_or_else(|| unspecific_error(src[18])))),
+            _ => Err(CodecError::Message(unspecific_error(src[18]))),
+        };
+
+        src.advance(length);
+        result
+    }
+}
+
+impl Encoder<BgpMessage> for BgpCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: BgpMessage, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let max_length = self.max_length as usize;
+        let encoded = message.encode_with_as4(self.four_octet_as).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if encoded.len() <= max_length {
+            dst.extend_from_slice(&encoded);
+            return Ok(());
+        }
+
+        let updates = match message {
+            BgpMessage::Update(ref update) => update.split(max_length, self.four_octet_as),
+            _ => vec![],
+        };
+        let encoded = updates.into_iter().map(|u| BgpMessage::Update(Box::new(u)).encode_with_as4(self.four_octet_as)).collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if encoded.is_empty() || encoded.iter().any(|e| e.len() > max_length) {
+            return Err(CodecError::Io(io::Error::new(io::ErrorKind::InvalidInput, "message too long")));
+        }
+        for e in encoded {
+            dst.extend_from_slice(&e);
+        }
+        Ok(())
+    }
+}
+
+// Where the parsers didn't say what was wrong, use the error code for the
+// message type with the unspecific subcode of zero.
+fn unspecific_error(message_type: u8) -> u32 {
+    match message_type {
+        1 => OPEN_MESSAGE_ERROR << 8,
+        2 => UPDATE_MESSAGE_ERROR << 8,
+        _ => MESSAGE_HEADER_ERROR << 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_test() {
+        let open = include_bytes!("../assets/test_bgp_open1.bin");
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&open[..10]);
+        assert_eq!(BgpCodec::new().decode(&mut src).unwrap(), None);
+
+        src.extend_from_slice(&open[10..]);
+        src.extend_from_slice(&keepalive[..]);
+        match BgpCodec::new().decode(&mut src).unwrap() {
+            Some(BgpMessage::Open(_)) => {},
+            other => panic!("expected an open: {:?}", other),
+        }
+        assert_eq!(BgpCodec::new().decode(&mut src).unwrap(), Some(BgpMessage::Keepalive));
+        assert_eq!(BgpCodec::new().decode(&mut src).unwrap(), None);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn decode_error_test() {
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&[0xff; 15]);
+        src.extend_from_slice(&[0, 0, 19, 4]);
+        match BgpCodec::new().decode(&mut src) {
+            Err(CodecError::Message(code)) => assert_eq!(code, CONNECTION_NOT_SYNCHRONIZED),
+            other => panic!("expected an error: {:?}", other),
+        }
+
+        let mut open = include_bytes!("../assets/test_bgp_open1.bin").to_vec();
+        open[19] = 3; // version
+        let mut src = BytesMut::from(&open[..]);
+        match BgpCodec::new().decode(&mut src) {
+            Err(CodecError::Message(code)) => assert_eq!(code, UNSUPPORTED_VERSION_NUMBER),
+            other => panic!("expected an error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encode_test() {
+        let mut dst = BytesMut::new();
+        BgpCodec::new().encode(BgpMessage::Keepalive, &mut dst).unwrap();
+        assert_eq!(&dst[..], &include_bytes!("../assets/test_bgp_keepalive1.bin")[..]);
+    }
+
+    #[test]
+    fn extended_message_test() {
+        let nlri: Vec<Ipv4Prefix> = (0..1200).map(|i| format!("10.{}.{}.0/24", i / 256, i % 256).parse().unwrap()).collect();
+        let update = BgpMessage::Update(Box::new(BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: nlri.clone() }));
+
+        // Too long for the usual limit, so it's split.
+        let mut dst = BytesMut::new();
+        BgpCodec::new().encode(update.clone(), &mut dst).unwrap();
+        assert_eq!(dst.len(), 2 * 23 + 4 * 1200);
+        let mut decoded = vec![];
+        while let Some(BgpMessage::Update(update)) = BgpCodec::new().decode(&mut dst).unwrap() {
+            decoded.extend(update.nlri);
+        }
+        assert_eq!(decoded, nlri);
+
+        // With Extended Messages it fits.
+        let mut codec = BgpCodec::new();
+        codec.set_max_length(MAX_EXTENDED_MESSAGE_LENGTH);
+        let mut dst = BytesMut::new();
+        codec.encode(update.clone(), &mut dst).unwrap();
+        assert_eq!(dst.len(), 23 + 4 * 1200);
+
+        match BgpCodec::new().decode(&mut dst.clone()) {
+            Err(CodecError::Message(code)) => assert_eq!(code, BAD_MESSAGE_LENGTH),
+            other => panic!("expected an error: {:?}", other),
+        }
+        assert_eq!(codec.decode(&mut dst).unwrap(), Some(update));
+
+        // Other messages can't be split.
+        let notification = BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 6, error_subcode: 0, data: vec![0; 5000] }));
+        assert!(BgpCodec::new().encode(notification, &mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn four_octet_as_test() {
+        let update = BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: vec![AsPathSegment::AsSequence(vec![65001, 4200000001])] })),
+            }],
+            nlri: vec!["10.0.0.0/8".parse().unwrap()],
+        }));
+
+        let mut codec = BgpCodec::new();
+        codec.set_four_octet_as(true);
+        let mut dst = BytesMut::new();
+        codec.encode(update.clone(), &mut dst).unwrap();
+        assert_eq!(dst.len(), 23 + 13 + 2);
+        assert_eq!(codec.decode(&mut dst).unwrap(), Some(update));
+    }
+}
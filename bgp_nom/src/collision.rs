@@ -0,0 +1,211 @@
+// Connection collision detection (RFC 4271 section 6.8).
+//
+// If two speakers connect to each other at the same time they end up
+// with two connections between them, and one of them has to go. Both
+// ends have to pick the same one, which they do by comparing BGP
+// identifiers once they have seen each other's OPEN: the connection
+// started by the speaker with the higher identifier is kept. RFC 6286
+// lets speakers in different ASes have the same identifier, and then
+// it's the one started by the speaker with the larger AS.
+//
+// The resolver keeps track of the connections to each peer, in both
+// directions. Like the FSM it doesn't do any IO. The speaker tells it
+// about connections coming and going, and when a connection receives an
+// OPEN it says which connection, if any, should be closed. That
+// connection should be sent a Cease with the Connection Collision
+// Resolution subcode.
+//
+// A connection that collides with one which is already established is
+// always the one closed.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub struct ConnectionId(u64);
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Direction {
+    // They connected to us.
+    Inbound,
+    // We connected to them.
+    Outbound,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+enum ConnectionState {
+    OpenSent,
+    OpenConfirm,
+    Established,
+}
+
+#[derive(Debug)]
+struct Connection {
+    id: ConnectionId,
+    direction: Direction,
+    state: ConnectionState,
+}
+
+pub struct CollisionResolver {
+    local_identifier: Ipv4Addr,
+    local_as: u16,
+    next_id: u64,
+    peers: HashMap<IpAddr, Vec<Connection>>,
+}
+
+impl CollisionResolver {
+    pub fn new(local_identifier: Ipv4Addr, local_as: u16) -> CollisionResolver {
+        CollisionResolver { local_identifier, local_as, next_id: 0, peers: HashMap::new() }
+    }
+
+    // A new connection to or from the peer, which is about to send its
+    // OPEN.
+    pub fn connected(&mut self, peer: IpAddr, direction: Direction) -> ConnectionId {
+        let id = ConnectionId(self.next_id);
+        self.next_id += 1;
+        self.peers.entry(peer).or_default().push(Connection { id, direction, state: ConnectionState::OpenSent });
+        id
+    }
+
+    // The connection received an OPEN from the peer. Returns the
+    // connection to close, if there's a collision.
+    pub fn open_received(&mut self, peer: IpAddr, id: ConnectionId, remote_identifier: Ipv4Addr, remote_as: u16) -> Option<ConnectionId> {
+        let local = (u32::from(self.local_identifier), self.local_as);
+        let remote = (u32::from(remote_identifier), remote_as);
+        let connections = self.peers.get_mut(&peer)?;
+
+        let direction = {
+            let connection = connections.iter_mut().find(|c| c.id == id)?;
+            connection.state = ConnectionState::OpenConfirm;
+            connection.direction
+        };
+
+        let other = connections.iter().find(|c| c.id != id)?;
+
+        if other.state == ConnectionState::Established {
+            return Some(id);
+        }
+
+        // Keep the connection opened by whoever has the higher identifier,
+        // or AS if those are the same.
+        let keep = match local > remote {
+            true => Direction::Outbound,
+            false => Direction::Inbound,
+        };
+
+        match direction == keep {
+            true => Some(other.id),
+            false => Some(id),
+        }
+    }
+
+    pub fn established(&mut self, peer: IpAddr, id: ConnectionId) {
+        if let Some(connection) = self.peers.get_mut(&peer).and_then(|c| c.iter_mut().find(|c| c.id == id)) {
+            connection.state = ConnectionState::Established;
+        }
+    }
+
+    pub fn closed(&mut self, peer: IpAddr, id: ConnectionId) {
+        if let Some(connections) = self.peers.get_mut(&peer) {
+            connections.retain(|c| c.id != id);
+            if connections.is_empty() {
+                self.peers.remove(&peer);
+            }
+        }
+    }
+
+    pub fn connections(&self, peer: IpAddr) -> usize {
+        self.peers.get(&peer).map_or(0, |c| c.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> IpAddr {
+        "192.0.2.1".parse().unwrap()
+    }
+
+    fn identifier(n: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, n)
+    }
+
+    #[test]
+    fn no_collision_test() {
+        let mut resolver = CollisionResolver::new(identifier(2), 65000);
+        let id = resolver.connected(peer(), Direction::Outbound);
+        assert_eq!(resolver.open_received(peer(), id, identifier(1), 65001), None);
+        resolver.established(peer(), id);
+
+        resolver.closed(peer(), id);
+        assert_eq!(resolver.connections(peer()), 0);
+        let id = resolver.connected(peer(), Direction::Inbound);
+        assert_eq!(resolver.open_received(peer(), id, identifier(1), 65001), None);
+    }
+
+    #[test]
+    fn collision_higher_local_identifier_test() {
+        // Ours is higher, so our outbound connection is kept.
+        let mut resolver = CollisionResolver::new(identifier(2), 65000);
+        let outbound = resolver.connected(peer(), Direction::Outbound);
+        let inbound = resolver.connected(peer(), Direction::Inbound);
+
+        assert_eq!(resolver.open_received(peer(), outbound, identifier(1), 65001), Some(inbound));
+        resolver.closed(peer(), inbound);
+        assert_eq!(resolver.connections(peer()), 1);
+
+        // The same whichever order the OPENs arrive in.
+        let mut resolver = CollisionResolver::new(identifier(2), 65000);
+        let outbound = resolver.connected(peer(), Direction::Outbound);
+        let inbound = resolver.connected(peer(), Direction::Inbound);
+        assert_eq!(resolver.open_received(peer(), inbound, identifier(1), 65001), Some(inbound));
+        assert_eq!(resolver.open_received(peer(), outbound, identifier(1), 65001), Some(inbound));
+    }
+
+    #[test]
+    fn collision_lower_local_identifier_test() {
+        // Theirs is higher, so their connection to us is kept.
+        let mut resolver = CollisionResolver::new(identifier(1), 65000);
+        let outbound = resolver.connected(peer(), Direction::Outbound);
+        let inbound = resolver.connected(peer(), Direction::Inbound);
+
+        assert_eq!(resolver.open_received(peer(), outbound, identifier(2), 65001), Some(outbound));
+        assert_eq!(resolver.open_received(peer(), inbound, identifier(2), 65001), Some(outbound));
+    }
+
+    #[test]
+    fn collision_same_identifier_test() {
+        // The identifiers are the same, so the larger AS decides.
+        let mut resolver = CollisionResolver::new(identifier(1), 65000);
+        let outbound = resolver.connected(peer(), Direction::Outbound);
+        let inbound = resolver.connected(peer(), Direction::Inbound);
+        assert_eq!(resolver.open_received(peer(), outbound, identifier(1), 65001), Some(outbound));
+
+        let mut resolver = CollisionResolver::new(identifier(1), 65002);
+        let outbound = resolver.connected(peer(), Direction::Outbound);
+        let inbound = resolver.connected(peer(), Direction::Inbound);
+        assert_eq!(resolver.open_received(peer(), outbound, identifier(1), 65001), Some(inbound));
+    }
+
+    #[test]
+    fn collision_with_established_test() {
+        // Even though ours is higher, the established connection stays.
+        let mut resolver = CollisionResolver::new(identifier(2), 65000);
+        let inbound = resolver.connected(peer(), Direction::Inbound);
+        assert_eq!(resolver.open_received(peer(), inbound, identifier(1), 65001), None);
+        resolver.established(peer(), inbound);
+
+        let outbound = resolver.connected(peer(), Direction::Outbound);
+        assert_eq!(resolver.open_received(peer(), outbound, identifier(1), 65001), Some(outbound));
+    }
+
+    #[test]
+    fn collision_different_peers_test() {
+        let mut resolver = CollisionResolver::new(identifier(2), 65000);
+        let a = resolver.connected(peer(), Direction::Outbound);
+        let b = resolver.connected("192.0.2.2".parse().unwrap(), Direction::Inbound);
+        assert_eq!(resolver.open_received(peer(), a, identifier(1), 65001), None);
+        assert_eq!(resolver.open_received("192.0.2.2".parse().unwrap(), b, identifier(1), 65001), None);
+    }
+}
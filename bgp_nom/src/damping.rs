@@ -0,0 +1,356 @@
+// Route flap damping (RFC 2439).
+//
+// A route that keeps being withdrawn and announced again, or keeps
+// changing its attributes, costs every router that hears about it. Damping
+// gives each path, that is each prefix from each peer, a penalty that
+// goes up every time it flaps and decays exponentially with a half-life.
+// Once the penalty reaches the suppress threshold the path is suppressed,
+// and shouldn't be used, until the penalty decays below the reuse
+// threshold. It is never suppressed for longer than the maximum suppress
+// time, which is enforced both directly and by capping the penalty at
+// the level that decays to the reuse threshold in that time.
+//
+// This sits in front of an Adj-RIB-In: run every UPDATE from a peer
+// through update(), which says which of the announced prefixes are
+// suppressed, and call reuse() now and then to find the suppressed paths
+// that can be used again. Withdrawing a path counts as a flap, and so
+// does announcing it again with different attributes. Announcing it again
+// after a withdrawal doesn't, the withdrawal has already been counted.
+//
+// Penalties are worked out against a Clock (timers.rs) so the decay can
+// be tested without waiting for it. A path's penalty is only brought up
+// to date when something happens to it, or when it's looked at.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::*;
+use super::timers::{Clock, SystemClock};
+
+// The defaults are the ones most routers use.
+#[derive(Debug,PartialEq,Clone)]
+pub struct DampingConfig {
+    pub withdrawal_penalty: f64,
+    pub attribute_change_penalty: f64,
+    pub half_life: Duration,
+    pub suppress_threshold: f64,
+    pub reuse_threshold: f64,
+    pub max_suppress_time: Duration,
+}
+
+impl Default for DampingConfig {
+    fn default() -> DampingConfig {
+        DampingConfig {
+            withdrawal_penalty: 1000.0,
+            attribute_change_penalty: 500.0,
+            half_life: Duration::from_secs(15 * 60),
+            suppress_threshold: 2000.0,
+            reuse_threshold: 750.0,
+            max_suppress_time: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl DampingConfig {
+    // The highest the penalty can go, which decays to the reuse threshold
+    // in the maximum suppress time.
+    fn ceiling(&self) -> f64 {
+        self.reuse_threshold * (self.max_suppress_time.as_secs_f64() / self.half_life.as_secs_f64()).exp2()
+    }
+
+    fn decay(&self, penalty: f64, elapsed: Duration) -> f64 {
+        penalty * (-elapsed.as_secs_f64() / self.half_life.as_secs_f64()).exp2()
+    }
+}
+
+// A suppressed path, as listed by dampened().
+#[derive(Debug,PartialEq,Clone)]
+pub struct DampenedPath {
+    pub peer: IpAddr,
+    pub prefix: Ipv4Prefix,
+    pub penalty: f64,
+    pub suppressed_for: Duration,
+    // How long until it can be used again, if it doesn't flap before then.
+    pub reuse_in: Duration,
+}
+
+#[derive(Debug)]
+struct Path {
+    penalty: f64,
+    updated: Instant,
+    suppressed_since: Option<Instant>,
+    // The attributes last announced, or None if it's withdrawn.
+    attributes: Option<Vec<BgpPathAttribute>>,
+}
+
+pub struct Damping<C: Clock = SystemClock> {
+    config: DampingConfig,
+    clock: C,
+    paths: HashMap<(IpAddr, Ipv4Prefix), Path>,
+}
+
+impl Damping<SystemClock> {
+    pub fn new(config: DampingConfig) -> Damping<SystemClock> {
+        Damping::with_clock(config, SystemClock)
+    }
+}
+
+impl<C: Clock> Damping<C> {
+    pub fn with_clock(config: DampingConfig, clock: C) -> Damping<C> {
+        Damping { config, clock, paths: HashMap::new() }
+    }
+
+    pub fn config(&self) -> &DampingConfig {
+        &self.config
+    }
+
+    // Apply an UPDATE from a peer, returning the prefixes it announces
+    // which are suppressed.
+    pub fn update(&mut self, peer: IpAddr, update: &BgpUpdateMessage) -> Vec<Ipv4Prefix> {
+        let now = self.clock.now();
+        let config = &self.config;
+
+        for prefix in &update.withdrawn_routes {
+            // Withdrawing something that isn't there isn't a flap.
+            if let Some(path) = self.paths.get_mut(&(peer, prefix.clone())) {
+                if path.attributes.is_some() {
+                    path.decay(config, now);
+                    path.penalize(config, config.withdrawal_penalty, now);
+                    path.attributes = None;
+                }
+            }
+        }
+
+        let mut suppressed = vec![];
+        for prefix in &update.nlri {
+            let path = self.paths.entry((peer, prefix.clone())).or_insert(Path { penalty: 0.0, updated: now, suppressed_since: None, attributes: None });
+            path.decay(config, now);
+            if path.attributes.as_ref().is_some_and(|a| *a != update.path_attributes) {
+                path.penalize(config, config.attribute_change_penalty, now);
+            }
+            path.attributes = Some(update.path_attributes.clone());
+
+            if path.suppressed_since.is_some() {
+                suppressed.push(prefix.clone());
+            }
+        }
+
+        suppressed
+    }
+
+    // The peer's session went down, so all of its paths are gone. That
+    // isn't counted as a flap.
+    pub fn peer_down(&mut self, peer: IpAddr) {
+        for (_, path) in self.paths.iter_mut().filter(|(k, _)| k.0 == peer) {
+            path.attributes = None;
+        }
+    }
+
+    // Find the suppressed paths which can be used again, returning those
+    // that are still announced. Withdrawn paths whose penalty has decayed
+    // away are forgotten.
+    pub fn reuse(&mut self) -> Vec<(IpAddr, Ipv4Prefix)> {
+        let now = self.clock.now();
+        let config = &self.config;
+        let mut reusable = vec![];
+
+        self.paths.retain(|key, path| {
+            let suppressed = path.suppressed_since.is_some();
+            path.decay(config, now);
+            if suppressed && path.suppressed_since.is_none() && path.attributes.is_some() {
+                reusable.push(key.clone());
+            }
+            path.attributes.is_some() || path.suppressed_since.is_some() || path.penalty >= config.reuse_threshold / 2.0
+        });
+
+        reusable
+    }
+
+    // The penalty of a path as of now, if it has one.
+    pub fn penalty(&self, peer: IpAddr, prefix: &Ipv4Prefix) -> Option<f64> {
+        let path = self.paths.get(&(peer, prefix.clone()))?;
+        Some(self.config.decay(path.penalty, self.clock.now() - path.updated))
+    }
+
+    pub fn is_suppressed(&self, peer: IpAddr, prefix: &Ipv4Prefix) -> bool {
+        self.paths.get(&(peer, prefix.clone())).is_some_and(|p| self.dampened_path(peer, prefix, p).is_some())
+    }
+
+    // The paths suppressed right now, ordered by peer and prefix.
+    pub fn dampened(&self) -> Vec<DampenedPath> {
+        let mut dampened: Vec<DampenedPath> = self.paths.iter()
+            .filter_map(|(&(peer, ref prefix), path)| self.dampened_path(peer, prefix, path))
+            .collect();
+        dampened.sort_by_key(|p| (p.peer, p.prefix.addr(), p.prefix.length));
+        dampened
+    }
+
+    fn dampened_path(&self, peer: IpAddr, prefix: &Ipv4Prefix, path: &Path) -> Option<DampenedPath> {
+        let now = self.clock.now();
+        let suppressed_for = now - path.suppressed_since?;
+        let penalty = self.config.decay(path.penalty, now - path.updated);
+        if penalty < self.config.reuse_threshold || suppressed_for >= self.config.max_suppress_time {
+            return None;
+        }
+
+        let decay_time = self.config.half_life.mul_f64((penalty / self.config.reuse_threshold).log2());
+        Some(DampenedPath {
+            peer,
+            prefix: prefix.clone(),
+            penalty,
+            suppressed_for,
+            reuse_in: ::std::cmp::min(decay_time, self.config.max_suppress_time - suppressed_for),
+        })
+    }
+}
+
+impl Path {
+    // Bring the penalty up to date, ending the suppression if it has
+    // decayed enough or gone on too long.
+    fn decay(&mut self, config: &DampingConfig, now: Instant) {
+        self.penalty = config.decay(self.penalty, now - self.updated);
+        self.updated = now;
+
+        if let Some(since) = self.suppressed_since {
+            if self.penalty < config.reuse_threshold || now - since >= config.max_suppress_time {
+                self.suppressed_since = None;
+            }
+        }
+    }
+
+    fn penalize(&mut self, config: &DampingConfig, penalty: f64, now: Instant) {
+        self.penalty = (self.penalty + penalty).min(config.ceiling());
+        if self.penalty >= config.suppress_threshold && self.suppressed_since.is_none() {
+            self.suppressed_since = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::timers::MockClock;
+
+    fn peer() -> IpAddr {
+        "192.0.2.1".parse().unwrap()
+    }
+
+    fn prefix() -> Ipv4Prefix {
+        "10.0.0.0/8".parse().unwrap()
+    }
+
+    fn announce(med: u32) -> BgpUpdateMessage {
+        BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+                attribute: PathAttribute::MultiExitDisc(Box::new(MultiExitDiscAttribute { metric: med })),
+            }],
+            nlri: vec![prefix()],
+        }
+    }
+
+    fn withdraw() -> BgpUpdateMessage {
+        BgpUpdateMessage { withdrawn_routes: vec![prefix()], path_attributes: vec![], nlri: vec![] }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn damping_flap_test() {
+        let clock = MockClock::new();
+        let mut damping = Damping::with_clock(DampingConfig::default(), clock.clone());
+
+        // Flapping twice is enough to be suppressed.
+        for _ in 0..2 {
+            assert_eq!(damping.update(peer(), &announce(0)), vec![]);
+            damping.update(peer(), &withdraw());
+        }
+        assert!(close(damping.penalty(peer(), &prefix()).unwrap(), 2000.0));
+        assert!(damping.is_suppressed(peer(), &prefix()));
+        assert_eq!(damping.update(peer(), &announce(0)), vec![prefix()]);
+
+        // It takes a little over a half-life to decay below 750.
+        clock.advance(Duration::from_secs(15 * 60));
+        assert!(close(damping.penalty(peer(), &prefix()).unwrap(), 1000.0));
+        let dampened = damping.dampened();
+        assert_eq!(dampened.len(), 1);
+        assert_eq!(dampened[0].suppressed_for, Duration::from_secs(15 * 60));
+        assert_eq!(dampened[0].reuse_in.as_secs(), 373); // 15 minutes * log2(1000 / 750)
+        assert_eq!(damping.reuse(), vec![]);
+
+        clock.advance(Duration::from_secs(376));
+        assert_eq!(damping.dampened(), vec![]);
+        assert_eq!(damping.reuse(), vec![(peer(), prefix())]);
+        assert_eq!(damping.reuse(), vec![]);
+        assert_eq!(damping.update(peer(), &announce(0)), vec![]);
+    }
+
+    #[test]
+    fn damping_attribute_change_test() {
+        let clock = MockClock::new();
+        let mut damping = Damping::with_clock(DampingConfig::default(), clock.clone());
+
+        damping.update(peer(), &announce(0));
+        damping.update(peer(), &announce(0));
+        assert_eq!(damping.penalty(peer(), &prefix()), Some(0.0));
+
+        damping.update(peer(), &announce(1));
+        assert!(close(damping.penalty(peer(), &prefix()).unwrap(), 500.0));
+
+        // Announcing after a withdrawal only counts the withdrawal.
+        damping.update(peer(), &withdraw());
+        damping.update(peer(), &announce(2));
+        assert!(close(damping.penalty(peer(), &prefix()).unwrap(), 1500.0));
+        assert!(!damping.is_suppressed(peer(), &prefix()));
+        clock.advance(Duration::from_secs(30 * 60));
+        assert!(close(damping.penalty(peer(), &prefix()).unwrap(), 375.0));
+
+        // Other peers are damped separately, and a peer going down isn't
+        // a flap.
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        assert_eq!(damping.penalty(other, &prefix()), None);
+        damping.update(other, &announce(0));
+        damping.peer_down(other);
+        damping.update(other, &withdraw());
+        assert_eq!(damping.penalty(other, &prefix()), Some(0.0));
+    }
+
+    #[test]
+    fn damping_max_suppress_test() {
+        let clock = MockClock::new();
+        let mut damping = Damping::with_clock(DampingConfig::default(), clock.clone());
+
+        for _ in 0..50 {
+            damping.update(peer(), &announce(0));
+            damping.update(peer(), &withdraw());
+        }
+        assert!(close(damping.penalty(peer(), &prefix()).unwrap(), 12000.0));
+        damping.update(peer(), &announce(0));
+        assert_eq!(damping.dampened()[0].reuse_in, Duration::from_secs(60 * 60));
+
+        clock.advance(Duration::from_secs(60 * 60));
+        assert!(!damping.is_suppressed(peer(), &prefix()));
+        assert_eq!(damping.reuse(), vec![(peer(), prefix())]);
+    }
+
+    #[test]
+    fn damping_forget_test() {
+        let clock = MockClock::new();
+        let mut damping = Damping::with_clock(DampingConfig::default(), clock.clone());
+
+        damping.update(peer(), &announce(0));
+        damping.update(peer(), &withdraw());
+        clock.advance(Duration::from_secs(15 * 60));
+        damping.reuse();
+        assert!(damping.penalty(peer(), &prefix()).is_some());
+
+        // Once it's down to half the reuse threshold it's forgotten.
+        clock.advance(Duration::from_secs(15 * 60));
+        damping.reuse();
+        assert_eq!(damping.penalty(peer(), &prefix()), None);
+    }
+}
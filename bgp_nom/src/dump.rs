@@ -0,0 +1,982 @@
+// A bgpdump like command line decoder.
+//
+// Reads files of raw BGP messages (like the test fixtures), MRT archives
+// or packet captures, and prints each message either on one line or as a
+// multi-line tree. Messages can be filtered by type, by prefix and by AS.
+// Or instead the BGP-LS routes in the updates can be put together into a
+// topology, printed at the end.
+//
+// It can also listen for BMP sessions from routers and print what they
+// send as it arrives.
+//
+// Messages that fail to parse are reported with the offset of the error
+// within the file, or within the TCP stream for captures, along with a
+// hex dump of the bytes around it.
+
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use nom::{ErrorKind, HexDisplay, IResult};
+use nom::Err;
+use nom::IResult::*;
+
+use super::*;
+use super::bmp::*;
+use super::mrt::{MrtError, read_mrt};
+use super::pcap::{PcapError, is_pcap, read_pcap};
+
+const USAGE: &str = "usage: bgp_nom [options] <file>...
+       bgp_nom [options] --bmp-listen <address>
+
+Decode BGP messages from raw message files, MRT archives or packet captures.
+
+options:
+    -f, --format <format>   input format: raw, mrt or pcap (default: detect)
+    -v, --verbose           print each message as a multi-line tree
+    -j, --json              print each message as a line of JSON (needs
+                            the serde feature)
+    -t, --type <types>      only show these message types, comma separated:
+                            open, update, notification, keepalive
+    -p, --prefix <prefix>   only show updates for prefixes within <prefix>
+    -a, --as <asn>          only show opens from <asn> and updates with
+                            <asn> in the AS path
+    --topology              print the BGP-LS topology from the updates
+                            instead of the messages, as GraphViz DOT, or
+                            as JSON with --json
+    --bmp-listen <address>  accept BMP sessions on <address>, e.g.
+                            0.0.0.0:11019, and print the messages received.
+                            The filters apply to Route Monitoring messages
+    -h, --help              show this help
+";
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+enum Format {
+    Raw,
+    Mrt,
+    Pcap,
+}
+
+#[derive(Debug,PartialEq)]
+struct Options {
+    format: Option<Format>,
+    verbose: bool,
+    json: bool,
+    types: Vec<u8>,
+    prefix: Option<(Ipv4Addr, u8)>,
+    asn: Option<u32>,
+    topology: bool,
+    bmp_listen: Option<String>,
+    files: Vec<String>,
+}
+
+// A message read from one of the input formats, along with whatever we
+// know about where it came from.
+
+struct Record {
+    timestamp: Option<Duration>,
+    peers: Option<String>,
+    // Whether the message has four octet AS numbers, which only an MRT
+    // archive says.
+    as4: bool,
+    offset: usize,
+    data: Vec<u8>,
+}
+
+// Run the decoder, returning the process exit code. Output is written to
+// `out` and errors to `err` so the whole thing can be tested.
+
+pub fn run<W: Write, E: Write>(args: &[String], out: &mut W, err: &mut E) -> i32 {
+    let options = match parse_args(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            let _ = write!(out, "{}", USAGE);
+            return 0;
+        },
+        Err(e) => {
+            let _ = write!(err, "bgp_nom: {}\n\n{}", e, USAGE);
+            return 2;
+        },
+    };
+
+    if let Some(ref address) = options.bmp_listen {
+        return listen_bmp(address, &options, out, err);
+    }
+
+    let mut status = 0;
+    let mut topology = link_state::Topology::new();
+
+    for file in &options.files {
+        let records = match read_file(file, options.format) {
+            Ok(records) => records,
+            Err(e) => {
+                let _ = writeln!(err, "{}: {}", file, e);
+                status = 1;
+                continue;
+            },
+        };
+
+        for record in records {
+            match parse_bgp_message_with_encoding(&record.data, MAX_EXTENDED_MESSAGE_LENGTH, UpdateEncoding::archived(record.as4)) {
+                Done(&[], ref message) => {
+                    if !matches(&options, message) {
+                        continue;
+                    }
+                    match *message {
+                        BgpMessage::Update(ref update) if options.topology => topology.update(update),
+                        _ if options.topology => {},
+                        _ => { let _ = out.write_all(format_record(&record, message, &options).as_bytes()); },
+                    }
+                },
+                result => {
+                    let _ = err.write_all(format_error(file, &record, &result).as_bytes());
+                    status = 1;
+                },
+            }
+        }
+    }
+
+    if options.topology {
+        let _ = out.write_all(format_topology(&topology, options.json).as_bytes());
+    }
+
+    status
+}
+
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut options = Options { format: None, verbose: false, json: false, types: vec![], prefix: None, asn: None, topology: false, bmp_listen: None, files: vec![] };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} requires a value", name));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-v" | "--verbose" => options.verbose = true,
+            "-j" | "--json" if cfg!(feature = "serde") => options.json = true,
+            "-j" | "--json" => return Err("built without JSON support, rebuild with --features serde".to_string()),
+            "-f" | "--format" => options.format = Some(match value(arg)?.as_str() {
+                "raw" => Format::Raw,
+                "mrt" => Format::Mrt,
+                "pcap" => Format::Pcap,
+                other => return Err(format!("unknown format: {}", other)),
+            }),
+            "-t" | "--type" => for name in value(arg)?.split(',') {
+                options.types.push(match name {
+                    "open" => 1,
+                    "update" => 2,
+                    "notification" => 3,
+                    "keepalive" => 4,
+                    other => return Err(format!("unknown message type: {}", other)),
+                });
+            },
+            "-p" | "--prefix" => options.prefix = Some(parse_prefix(&value(arg)?)?),
+            "-a" | "--as" => {
+                let asn = value(arg)?;
+                options.asn = Some(asn.parse().map_err(|_| format!("invalid AS number: {}", asn))?);
+            },
+            "--topology" => options.topology = true,
+            "--bmp-listen" => options.bmp_listen = Some(value(arg)?),
+            other if other.starts_with('-') && other.len() > 1 => return Err(format!("unknown option: {}", other)),
+            other => options.files.push(other.to_string()),
+        }
+    }
+
+    if options.bmp_listen.is_some() {
+        if !options.files.is_empty() || options.format.is_some() {
+            return Err("--bmp-listen doesn't read files".to_string());
+        }
+        if options.verbose || options.json || options.topology {
+            return Err("--bmp-listen only supports the one line format".to_string());
+        }
+    } else if options.files.is_empty() {
+        return Err("no input files".to_string());
+    }
+
+    Ok(Some(options))
+}
+
+fn parse_prefix(s: &str) -> Result<(Ipv4Addr, u8), String> {
+    let mut parts = s.splitn(2, '/');
+    let address = parts.next().and_then(|a| a.parse::<Ipv4Addr>().ok());
+    let length = match parts.next() {
+        Some(l) => l.parse::<u8>().ok().filter(|l| *l <= 32),
+        None => Some(32),
+    };
+
+    match (address, length) {
+        (Some(address), Some(length)) => Ok((address, length)),
+        _ => Err(format!("invalid prefix: {}", s)),
+    }
+}
+
+// Reading input files.
+
+fn detect_format(data: &[u8]) -> Format {
+    if is_pcap(data) {
+        Format::Pcap
+    } else if data.len() >= 16 && data[..16] == [0xff; 16] {
+        Format::Raw
+    } else {
+        Format::Mrt
+    }
+}
+
+fn read_file(path: &str, format: Option<Format>) -> Result<Vec<Record>, String> {
+    let mut data = Vec::new();
+    File::open(path).and_then(|mut f| f.read_to_end(&mut data)).map_err(|e| e.to_string())?;
+
+    match format.unwrap_or_else(|| detect_format(&data)) {
+        Format::Raw => Ok(read_raw(&data)),
+        Format::Mrt => match read_mrt(&data) {
+            Ok(messages) => Ok(messages.into_iter().map(|m| Record {
+                timestamp: Some(m.timestamp),
+                peers: Some(format!("AS{} {} > AS{} {}", m.peer_as, m.peer_address, m.local_as, m.local_address)),
+                as4: m.as4,
+                offset: m.offset,
+                data: m.data,
+            }).collect()),
+            Err(MrtError::Truncated(offset)) => Err(format!("MRT record at offset {} is truncated", offset)),
+            Err(MrtError::Malformed(offset)) => Err(format!("MRT record at offset {} is malformed", offset)),
+        },
+        Format::Pcap => match read_pcap(&data) {
+            Ok(messages) => Ok(messages.into_iter().map(|m| Record {
+                timestamp: Some(m.timestamp),
+                peers: Some(format!("{} > {}", m.source, m.destination)),
+                as4: false,
+                offset: m.offset,
+                data: m.data,
+            }).collect()),
+            Err(PcapError::Io(e)) => Err(e.to_string()),
+            Err(PcapError::UnknownFormat) => Err("not a pcap or pcapng file".to_string()),
+            Err(PcapError::Truncated) => Err("capture file is truncated".to_string()),
+            Err(PcapError::Malformed) => Err("capture file is malformed".to_string()),
+        },
+    }
+}
+
+// A raw file is just messages back to back. If framing fails the rest of
+// the file is returned as one last record so the parse error for it gets
+// reported with the right offset.
+
+fn read_raw(data: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let length = match extended_bgp_message_frame(&data[pos..]) {
+            Done(_, frame) => frame.len(),
+            _ => data.len() - pos,
+        };
+        records.push(Record { timestamp: None, peers: None, as4: false, offset: pos, data: data[pos..pos + length].to_vec() });
+        pos += length;
+    }
+
+    records
+}
+
+// Filtering.
+
+fn message_type(message: &BgpMessage) -> u8 {
+    match *message {
+        BgpMessage::Open(_) => 1,
+        BgpMessage::Update(_) => 2,
+        BgpMessage::Notification(_) => 3,
+        BgpMessage::Keepalive => 4,
+    }
+}
+
+fn prefix_within(prefix: &Ipv4Prefix, within: &(Ipv4Addr, u8)) -> bool {
+    let mask = |length: u8| match length {
+        0 => 0,
+        l => u32::MAX << (32 - l as u32),
+    };
+
+    prefix.length >= within.1 && u32::from(prefix.addr()) & mask(within.1) == u32::from(within.0) & mask(within.1)
+}
+
+fn as_path_contains(update: &BgpUpdateMessage, asn: u32) -> bool {
+    update.as_path().is_some_and(|as_path| as_path.contains(asn))
+}
+
+fn matches(options: &Options, message: &BgpMessage) -> bool {
+    if !options.types.is_empty() && !options.types.contains(&message_type(message)) {
+        return false;
+    }
+
+    if let Some(ref prefix) = options.prefix {
+        match *message {
+            BgpMessage::Update(ref update) => {
+                if !update.nlri.iter().chain(update.withdrawn_routes.iter()).any(|p| prefix_within(p, prefix)) {
+                    return false;
+                }
+            },
+            _ => return false,
+        }
+    }
+
+    if let Some(asn) = options.asn {
+        match *message {
+            BgpMessage::Open(ref open) => return u32::from(open.my_autonomous_system) == asn,
+            BgpMessage::Update(ref update) => return as_path_contains(update, asn),
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+// Output formatting.
+
+fn format_timestamp(timestamp: Duration) -> String {
+    format!("{}.{:06}", timestamp.as_secs(), timestamp.subsec_micros())
+}
+
+fn format_origin(origin: &OriginAttribute) -> &'static str {
+    match origin.origin_code {
+        BgpOriginCode::Igp => "IGP",
+        BgpOriginCode::Egp => "EGP",
+        BgpOriginCode::Incomplete => "INCOMPLETE",
+    }
+}
+
+fn format_capability(capability: &CapabilityParameter) -> String {
+    match *capability {
+        CapabilityParameter::MultiprotocolExtensions(ref mp) => format!("multiprotocol({},{})", mp.afi, mp.safi),
+        CapabilityParameter::RouteRefresh => "route-refresh".to_string(),
+        CapabilityParameter::ExtendedMessage => "extended-message".to_string(),
+        CapabilityParameter::MultipleLabels(ref m) => {
+            format!("multiple-labels({})", m.counts.iter().map(|c| format!("{},{},{}", c.afi, c.safi, c.count)).collect::<Vec<_>>().join(";"))
+        },
+        CapabilityParameter::Role(ref r) => format!("role({})", r.role),
+        CapabilityParameter::FourOctetAs(ref a) => format!("4-octet-as({})", a.asn),
+        CapabilityParameter::AddPath(ref a) => {
+            let mode = |f: &AddPathFamily| match (f.send, f.receive) { (true, true) => "send-receive", (true, false) => "send", _ => "receive" };
+            format!("add-path({})", a.families.iter().map(|f| format!("{},{},{}", f.afi, f.safi, mode(f))).collect::<Vec<_>>().join(";"))
+        },
+        CapabilityParameter::Unknown(ref u) if u.data.is_empty() => format!("unknown({})", u.code),
+        CapabilityParameter::Unknown(ref u) => format!("unknown({},{})", u.code, format_hex(&u.data)),
+    }
+}
+
+fn format_prefixes(prefixes: &[Ipv4Prefix]) -> String {
+    prefixes.iter().map(|p| match p.path_id {
+        Some(path_id) => format!("{} path-id {}", p, path_id),
+        None => p.to_string(),
+    }).collect::<Vec<_>>().join(" ")
+}
+
+fn format_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The name and value of a path attribute, as used in both formats.
+fn format_attribute(attribute: &PathAttribute) -> (&'static str, String) {
+    match *attribute {
+        PathAttribute::Origin(ref a) => ("origin", format_origin(a).to_string()),
+        PathAttribute::AsPath(ref a) => ("as-path", a.to_string()),
+        PathAttribute::NextHop(ref a) => ("next-hop", a.next_hop.to_string()),
+        PathAttribute::MultiExitDisc(ref a) => ("med", a.metric.to_string()),
+        PathAttribute::LocalPref(ref a) => ("local-pref", a.preference.to_string()),
+        PathAttribute::AtomicAggregate => ("atomic-aggregate", String::new()),
+        PathAttribute::Aggregator(ref a) => ("aggregator", format!("AS{} {}", a.aggregator_as, a.aggregator_id)),
+        PathAttribute::Communities(ref a) => ("communities", a.communities.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" ")),
+        PathAttribute::MpReachNlri(ref a) => match a.next_hop.is_empty() {
+            true => ("mp-reach", format!("{} {}", format_family(a.afi, a.safi), format_mp_nlri(&a.nlri))),
+            false => ("mp-reach", format!("{} next-hop {} {}", format_family(a.afi, a.safi), format_next_hop(&a.next_hop), format_mp_nlri(&a.nlri))),
+        },
+        PathAttribute::MpUnreachNlri(ref a) => ("mp-unreach", format!("{} {}", format_family(a.afi, a.safi), format_mp_nlri(&a.withdrawn_routes))),
+        PathAttribute::ExtendedCommunities(ref a) => ("extended-communities", a.communities.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" ")),
+        PathAttribute::LinkState(ref a) => ("link-state", a.tlvs.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")),
+        PathAttribute::OnlyToCustomer(ref a) => ("otc", format!("AS{}", a.asn)),
+    }
+}
+
+fn format_family(afi: u16, safi: u8) -> String {
+    match (afi, safi) {
+        (AFI_IPV4, mpls::SAFI_LABELED_UNICAST) => "ipv4 labeled-unicast".to_string(),
+        (AFI_IPV6, mpls::SAFI_LABELED_UNICAST) => "ipv6 labeled-unicast".to_string(),
+        (AFI_IPV4, vpn::SAFI_VPN) => "vpnv4".to_string(),
+        (AFI_IPV6, vpn::SAFI_VPN) => "vpnv6".to_string(),
+        (evpn::AFI_L2VPN, evpn::SAFI_EVPN) => "l2vpn evpn".to_string(),
+        (link_state::AFI_LINK_STATE, link_state::SAFI_LINK_STATE) => "link-state".to_string(),
+        (AFI_IPV4, SAFI_FLOWSPEC) => "ipv4 flowspec".to_string(),
+        (AFI_IPV6, SAFI_FLOWSPEC) => "ipv6 flowspec".to_string(),
+        _ => format!("afi {} safi {}", afi, safi),
+    }
+}
+
+// One or two addresses, the second an IPv6 link local address. For VPNs
+// each has an RD in front, always zero, which is left out.
+fn format_next_hop(next_hop: &[u8]) -> String {
+    let address = |b: &[u8]| match b.len() {
+        4 => Ipv4Addr::from([b[0], b[1], b[2], b[3]]).to_string(),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(b);
+            Ipv6Addr::from(octets).to_string()
+        },
+        _ => format_hex(b),
+    };
+
+    match next_hop.len() {
+        32 => format!("{} {}", address(&next_hop[..16]), address(&next_hop[16..])),
+        12 | 24 => address(&next_hop[8..]),
+        48 => format!("{} {}", address(&next_hop[8..24]), address(&next_hop[32..])),
+        _ => address(next_hop),
+    }
+}
+
+// Each FlowSpec rule is bracketed, as they hold spaces.
+fn format_mp_nlri(nlri: &MpNlri) -> String {
+    match *nlri {
+        MpNlri::Labeled(ref prefixes) => prefixes.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "),
+        MpNlri::Vpn(ref prefixes) => prefixes.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "),
+        MpNlri::Evpn(ref routes) => routes.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", "),
+        MpNlri::LinkState(ref nlri) => nlri.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", "),
+        MpNlri::FlowSpec(ref rules) => rules.iter().map(|r| format!("[{}]", r)).collect::<Vec<_>>().join(" "),
+        MpNlri::Unknown(ref nlri) => format_hex(nlri),
+    }
+}
+
+fn format_flags(flags: &BgpPathAttributeFlags) -> String {
+    let names = [(flags.optional, "optional"), (flags.transitive, "transitive"), (flags.partial, "partial"), (flags.extended_length, "extended-length")];
+    names.iter().filter(|f| f.0).map(|f| f.1).collect::<Vec<_>>().join(",")
+}
+
+fn format_line(message: &BgpMessage) -> String {
+    let mut s = String::new();
+
+    match *message {
+        BgpMessage::Open(ref open) => {
+            let _ = write!(s, "OPEN version {} as {} hold-time {} id {}", open.version, open.my_autonomous_system, open.hold_time, open.bgp_identifier);
+            let capabilities: Vec<String> = open.optional_parameters.iter().flat_map(|p| match *p {
+                OptionalParameter::Capability(ref c) => c.iter().map(format_capability).collect::<Vec<_>>(),
+            }).collect();
+            if !capabilities.is_empty() {
+                let _ = write!(s, " capabilities {}", capabilities.join(","));
+            }
+        },
+        BgpMessage::Update(ref update) => {
+            s.push_str("UPDATE");
+            if !update.withdrawn_routes.is_empty() {
+                let _ = write!(s, " withdrawn {}", format_prefixes(&update.withdrawn_routes));
+            }
+            if !update.nlri.is_empty() {
+                let _ = write!(s, " nlri {}", format_prefixes(&update.nlri));
+            }
+            for attribute in &update.path_attributes {
+                match format_attribute(&attribute.attribute) {
+                    (name, ref value) if value.is_empty() => { let _ = write!(s, " {}", name); },
+                    (name, value) => { let _ = write!(s, " {} {}", name, value); },
+                }
+            }
+        },
+        BgpMessage::Notification(ref notification) => {
+            let _ = write!(s, "NOTIFICATION code {} subcode {} ({})", notification.error_code, notification.error_subcode, notification.error());
+            match notification.shutdown_communication() {
+                Some(Ok(message)) => { let _ = write!(s, " message {:?}", message); },
+                Some(Err(e)) => { let _ = write!(s, " data {} ({})", format_hex(&notification.data), e); },
+                None if !notification.data.is_empty() => { let _ = write!(s, " data {}", format_hex(&notification.data)); },
+                None => {},
+            }
+        },
+        BgpMessage::Keepalive => s.push_str("KEEPALIVE"),
+    }
+
+    s
+}
+
+fn format_tree(message: &BgpMessage, length: usize) -> String {
+    let mut s = String::new();
+
+    match *message {
+        BgpMessage::Open(ref open) => {
+            let _ = writeln!(s, "OPEN, length {}", length);
+            let _ = writeln!(s, "  Version: {}", open.version);
+            let _ = writeln!(s, "  My AS: {}", open.my_autonomous_system);
+            let _ = writeln!(s, "  Hold time: {}", open.hold_time);
+            let _ = writeln!(s, "  BGP identifier: {}", open.bgp_identifier);
+            let _ = writeln!(s, "  Optional parameters:");
+            for parameter in &open.optional_parameters {
+                match *parameter {
+                    OptionalParameter::Capability(ref capabilities) => {
+                        let _ = writeln!(s, "    Capabilities:");
+                        for capability in capabilities {
+                            let _ = writeln!(s, "      {}", format_capability(capability));
+                        }
+                    },
+                }
+            }
+        },
+        BgpMessage::Update(ref update) => {
+            let _ = writeln!(s, "UPDATE, length {}", length);
+            let _ = writeln!(s, "  Withdrawn routes:");
+            for prefix in &update.withdrawn_routes {
+                let _ = writeln!(s, "    {}", prefix);
+            }
+            let _ = writeln!(s, "  Path attributes:");
+            for attribute in &update.path_attributes {
+                let (name, value) = format_attribute(&attribute.attribute);
+                let _ = writeln!(s, "    {} [{}] {}", name, format_flags(&attribute.flags), value);
+                s.truncate(s.trim_end().len());
+                s.push('\n');
+            }
+            let _ = writeln!(s, "  NLRI:");
+            for prefix in &update.nlri {
+                let _ = writeln!(s, "    {}", prefix);
+            }
+        },
+        BgpMessage::Notification(ref notification) => {
+            let _ = writeln!(s, "NOTIFICATION, length {}", length);
+            let error = notification.error();
+            let _ = writeln!(s, "  Error code: {} ({})", notification.error_code, error.name());
+            match error.subcode_name() {
+                Some(name) => { let _ = writeln!(s, "  Error subcode: {} ({})", notification.error_subcode, name); },
+                None => { let _ = writeln!(s, "  Error subcode: {}", notification.error_subcode); },
+            }
+            if !notification.data.is_empty() {
+                let _ = writeln!(s, "  Data: {}", format_hex(&notification.data));
+            }
+            match notification.shutdown_communication() {
+                Some(Ok(message)) => { let _ = writeln!(s, "  Shutdown communication: {:?}", message); },
+                Some(Err(e)) => { let _ = writeln!(s, "  Shutdown communication: {}", e); },
+                None => {},
+            }
+        },
+        BgpMessage::Keepalive => {
+            let _ = writeln!(s, "KEEPALIVE, length {}", length);
+        },
+    }
+
+    s
+}
+
+// One JSON object per line, with the message and whatever we know about
+// where it came from.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peers: Option<&'a str>,
+    offset: usize,
+    message: &'a BgpMessage,
+}
+
+#[cfg(feature = "serde")]
+fn format_json(record: &Record, message: &BgpMessage) -> String {
+    let json = JsonRecord {
+        timestamp: record.timestamp.map(|t| t.as_secs() as f64 + t.subsec_micros() as f64 / 1e6),
+        peers: record.peers.as_deref(),
+        offset: record.offset,
+        message,
+    };
+
+    format!("{}\n", serde_json::to_string(&json).unwrap_or_default())
+}
+
+#[cfg(feature = "serde")]
+fn format_topology(topology: &link_state::Topology, json: bool) -> String {
+    match json {
+        true => format!("{}\n", topology.to_json()),
+        false => topology.to_dot(),
+    }
+}
+
+#[cfg(not(feature = "serde"))]
+fn format_topology(topology: &link_state::Topology, json: bool) -> String {
+    topology.to_dot()
+}
+
+fn format_record(record: &Record, message: &BgpMessage, options: &Options) -> String {
+    // --json is rejected by parse_args() without serde.
+    #[cfg(feature = "serde")]
+    if options.json {
+        return format_json(record, message);
+    }
+
+    let mut prefix = String::new();
+    if let Some(timestamp) = record.timestamp {
+        let _ = write!(prefix, "{} ", format_timestamp(timestamp));
+    }
+    if let Some(ref peers) = record.peers {
+        let _ = write!(prefix, "{} ", peers);
+    }
+
+    match options.verbose {
+        false => format!("{}{}\n", prefix, format_line(message)),
+        true => {
+            let mut s = format!("{}offset {}\n", prefix, record.offset);
+            for line in format_tree(message, record.data.len()).lines() {
+                let _ = writeln!(s, "  {}", line);
+            }
+            s
+        },
+    }
+}
+
+// BMP.
+
+fn listen_bmp<W: Write, E: Write>(address: &str, options: &Options, out: &mut W, err: &mut E) -> i32 {
+    let listener = match BmpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            let _ = writeln!(err, "{}: {}", address, e);
+            return 1;
+        },
+    };
+
+    if let Ok(address) = listener.local_addr() {
+        let _ = writeln!(err, "listening for BMP on {}", address);
+    }
+
+    for event in listener.events() {
+        if let Some(s) = format_bmp_event(&event, options) {
+            let _ = out.write_all(s.as_bytes());
+            let _ = out.flush();
+        }
+    }
+
+    0
+}
+
+fn format_information(tlvs: &[InformationTlv]) -> String {
+    tlvs.iter().map(|tlv| match *tlv {
+        InformationTlv::String(ref v) => format!(" string {:?}", v),
+        InformationTlv::SysDescr(ref v) => format!(" sys-descr {:?}", v),
+        InformationTlv::SysName(ref v) => format!(" sys-name {:?}", v),
+        InformationTlv::Unknown(t, ref v) => format!(" tlv-{} {}", t, v.len()),
+    }).collect()
+}
+
+fn format_termination_reason(reason: &TerminationReason) -> String {
+    match *reason {
+        TerminationReason::AdministrativelyClosed => "administratively-closed".to_string(),
+        TerminationReason::Unspecified => "unspecified".to_string(),
+        TerminationReason::OutOfResources => "out-of-resources".to_string(),
+        TerminationReason::RedundantConnection => "redundant-connection".to_string(),
+        TerminationReason::PermanentlyAdministrativelyClosed => "permanently-administratively-closed".to_string(),
+        TerminationReason::Unknown(v) => v.to_string(),
+    }
+}
+
+fn format_peer(peer: &PerPeerHeader) -> String {
+    format!("{} AS{} {}", format_timestamp(peer.timestamp), peer.peer_as, peer.peer_address)
+}
+
+// One line per event, or None if the event is filtered out.
+fn format_bmp_event(event: &BmpEvent, options: &Options) -> Option<String> {
+    let line = match *event {
+        BmpEvent::Connected(router) => format!("{} connected", router),
+        BmpEvent::Disconnected(router) => format!("{} disconnected", router),
+        BmpEvent::Error(router, ref data) => format!("{} malformed BMP message, {} bytes", router, data.len()),
+        BmpEvent::Message(router, ref message) => match *message {
+            BmpMessage::Initiation(ref tlvs) => format!("{} INITIATION{}", router, format_information(tlvs)),
+            BmpMessage::Termination(ref tlvs) => format!("{} TERMINATION{}", router, tlvs.iter().map(|tlv| match *tlv {
+                TerminationTlv::String(ref v) => format!(" string {:?}", v),
+                TerminationTlv::Reason(ref v) => format!(" reason {}", format_termination_reason(v)),
+                TerminationTlv::Unknown(t, ref v) => format!(" tlv-{} {}", t, v.len()),
+            }).collect::<String>()),
+            BmpMessage::PeerUp(ref up) => format!("{} {} PEER UP local {} port {} remote port {}{}",
+                router, format_peer(&up.peer), up.local_address, up.local_port, up.remote_port, format_information(&up.information)),
+            BmpMessage::PeerDown(ref down) => format!("{} {} PEER DOWN {}", router, format_peer(&down.peer), match down.reason {
+                PeerDownReason::LocalNotification(ref m) => format!("local {}", format_line(m)),
+                PeerDownReason::LocalNoNotification(event) => format!("local fsm-event {}", event),
+                PeerDownReason::RemoteNotification(ref m) => format!("remote {}", format_line(m)),
+                PeerDownReason::RemoteNoData => "remote no-data".to_string(),
+                PeerDownReason::PeerDeconfigured => "deconfigured".to_string(),
+                PeerDownReason::Unknown(reason, _) => format!("reason {}", reason),
+            }),
+            BmpMessage::StatisticsReport(ref report) => format!("{} {} STATS{}", router, format_peer(&report.peer), report.statistics.iter().map(|stat| match *stat {
+                Statistic::Counter(t, v) => format!(" {}={}", t, v),
+                Statistic::Gauge(t, v) => format!(" {}={}", t, v),
+                Statistic::AfiSafiGauge(t, afi, safi, v) => format!(" {}({},{})={}", t, afi, safi, v),
+                Statistic::Unknown(t, ref v) => format!(" {}=<{} bytes>", t, v.len()),
+            }).collect::<String>()),
+            BmpMessage::RouteMonitoring(ref rm) => {
+                if !matches(options, &rm.message) {
+                    return None;
+                }
+                let policy = match rm.peer.post_policy {
+                    true => "post-policy",
+                    false => "pre-policy",
+                };
+                format!("{} {} {} {}", router, format_peer(&rm.peer), policy, format_line(&rm.message))
+            },
+        },
+    };
+
+    Some(line + "\n")
+}
+
+// Error reporting.
+
+fn error_name(code: u32) -> &'static str {
+    match code {
+        CONNECTION_NOT_SYNCHRONIZED => "connection not synchronized",
+        BAD_MESSAGE_LENGTH => "bad message length",
+        BAD_MESSAGE_TYPE => "bad message type",
+        UNSUPPORTED_VERSION_NUMBER => "unsupported version number",
+        BAD_PEER_AS => "bad peer AS",
+        BAD_BGP_IDENTIFIER => "bad BGP identifier",
+        UNSUPPORTED_OPTIONAL_PARAMETER => "unsupported optional parameter",
+        UNACCEPTABLE_HOLD_TIME => "unacceptable hold time",
+        UNSUPPORTED_CAPABILITY => "unsupported capability",
+        _ => "unknown error",
+    }
+}
+
+fn format_error(file: &str, record: &Record, result: &IResult<&[u8], BgpMessage>) -> String {
+    let (offset, description) = match *result {
+        Done(rest, _) => (record.data.len() - rest.len(), format!("{} trailing bytes after message", rest.len())),
+        Incomplete(_) => (record.data.len(), "message is truncated".to_string()),
+        Error(ref e) => match error_details(e) {
+            (position, Some(code)) => (position.map_or(0, |p| record.data.len() - p.len()), format!("{} (error {}, subcode {})", error_name(code), code >> 8, code & 0xff)),
+            (position, None) => (position.map_or(0, |p| record.data.len() - p.len()), "malformed message".to_string()),
+        },
+    };
+
+    // Show a few lines either side of where the error was found, with
+    // addresses relative to the start of the file or stream.
+    let start = offset.saturating_sub(16) & !15;
+    let end = ::std::cmp::min(record.data.len(), start + 64);
+
+    let mut s = format!("{}: error at offset {}: {}\n", file, record.offset + offset, description);
+    if start < end {
+        s.push_str(&record.data[start..end].to_hex_from(16, record.offset + start));
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    fn run_output(s: &str) -> (i32, String, String) {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let status = run(&args(s), &mut out, &mut err);
+        (status, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    #[test]
+    fn parse_args_test() {
+        assert_eq!(parse_args(&args("-v -t open,keepalive -p 10.0.0.0/8 -a 65000 -f pcap a b")), Ok(Some(Options {
+            format: Some(Format::Pcap),
+            verbose: true,
+            json: false,
+            types: vec![1, 4],
+            prefix: Some((Ipv4Addr::new(10, 0, 0, 0), 8)),
+            asn: Some(65000),
+            topology: false,
+            bmp_listen: None,
+            files: vec!["a".to_string(), "b".to_string()],
+        })));
+
+        assert_eq!(parse_args(&args("--bmp-listen 127.0.0.1:11019 -t update")).unwrap().unwrap().bmp_listen, Some("127.0.0.1:11019".to_string()));
+        assert!(parse_args(&args("--bmp-listen 127.0.0.1:11019 a")).is_err());
+        assert!(parse_args(&args("--bmp-listen 127.0.0.1:11019 -v")).is_err());
+        assert!(parse_args(&args("--topology a")).unwrap().unwrap().topology);
+        assert_eq!(parse_args(&args("-a 65536x")), Err("invalid AS number: 65536x".to_string()));
+        assert_eq!(parse_args(&args("--json a")).map(|o| o.unwrap().json), match cfg!(feature = "serde") {
+            true => Ok(true),
+            false => Err("built without JSON support, rebuild with --features serde".to_string()),
+        });
+        assert!(parse_args(&args("--bmp-listen 127.0.0.1:11019 --topology")).is_err());
+
+        assert_eq!(parse_args(&args("--help")), Ok(None));
+        assert!(parse_args(&args("")).is_err());
+        assert!(parse_args(&args("-t route-refresh a")).is_err());
+        assert!(parse_args(&args("-p 10.0.0.0/33 a")).is_err());
+        assert!(parse_args(&args("-a 4294967296 a")).is_err());
+        assert!(parse_args(&args("-x a")).is_err());
+        assert!(parse_args(&args("a -f")).is_err());
+    }
+
+    #[test]
+    fn dump_pcap_test() {
+        let (status, out, err) = run_output("assets/BGP_notification.cap");
+        assert_eq!(status, 0);
+        assert_eq!(err, "");
+        assert_eq!(out, "\
+1214780929.766313 2.2.2.2:12732 > 1.1.1.1:179 OPEN version 4 as 65200 hold-time 180 id 10.20.3.1 capabilities multiprotocol(1,1),unknown(128),route-refresh
+1214780929.782272 1.1.1.1:179 > 2.2.2.2:12732 NOTIFICATION code 2 subcode 2 (OPEN Message Error: Bad Peer AS) data feb0
+");
+    }
+
+    #[test]
+    fn dump_raw_test() {
+        let (status, out, _) = run_output("-f raw assets/test_bgp_update3.bin");
+        assert_eq!(status, 0);
+        assert_eq!(out, "UPDATE nlri 10.0.0.0/8 origin EGP as-path next-hop 192.168.0.33 med 0 local-pref 100 communities 65033:500 65033:600\n");
+
+        let (status, out, _) = run_output("-v assets/test_bgp_update1.bin");
+        assert_eq!(status, 0);
+        assert_eq!(out, "\
+offset 0
+  UPDATE, length 98
+    Withdrawn routes:
+    Path attributes:
+      origin [transitive] INCOMPLETE
+      as-path [transitive] {500,500} 65211
+      next-hop [transitive] 192.168.0.15
+      local-pref [transitive] 100
+      atomic-aggregate [transitive]
+      aggregator [optional,transitive] AS65210 192.168.0.10
+      communities [optional,transitive] 65215:1 790:4 340:250
+    NLRI:
+      172.16.0.0/16
+");
+    }
+
+    #[test]
+    fn dump_filter_test() {
+        let count = |s: &str| run_output(s).1.lines().count();
+
+        assert_eq!(count("assets/bgp.pcap"), 9);
+        assert_eq!(count("-t keepalive assets/bgp.pcap"), 4);
+        assert_eq!(count("-t open,notification assets/bgp.pcap"), 2);
+        assert_eq!(count("-p 172.16.0.0/12 assets/bgp.pcap"), 1);
+        assert_eq!(count("-p 192.168.0.0/16 assets/bgp.pcap"), 1);
+        assert_eq!(count("-p 0.0.0.0/0 assets/bgp.pcap"), 3);
+        assert_eq!(count("-p 10.0.0.0/16 assets/bgp.pcap"), 0);
+        assert_eq!(count("-a 65211 assets/bgp.pcap"), 2);
+        assert_eq!(count("-a 65033 assets/bgp.pcap"), 2);
+        assert_eq!(count("-a 65033 -t update assets/bgp.pcap"), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn dump_json_test() {
+        let (status, out, _) = run_output("--json -t notification assets/BGP_notification.cap");
+        assert_eq!(status, 0);
+        assert_eq!(out, concat!(r#"{"timestamp":1214780929.782272,"peers":"1.1.1.1:179 > 2.2.2.2:12732","offset":0,"#,
+            r#""message":{"type":"notification","error_code":2,"error_subcode":2,"data":"feb0"}}"#, "\n"));
+    }
+
+    #[test]
+    fn format_notification_test() {
+        let shutdown = BgpMessage::Notification(Box::new(BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, "maintenance")));
+        assert_eq!(format_line(&shutdown), r#"NOTIFICATION code 6 subcode 2 (Cease: Administrative Shutdown) message "maintenance""#);
+        assert_eq!(format_tree(&shutdown, 33), concat!(
+            "NOTIFICATION, length 33\n",
+            "  Error code: 6 (Cease)\n",
+            "  Error subcode: 2 (Administrative Shutdown)\n",
+            "  Data: 0b6d61696e74656e616e6365\n",
+            "  Shutdown communication: \"maintenance\"\n"));
+
+        let truncated = BgpMessage::Notification(Box::new(BgpNotificationMessage::cease(CeaseSubcode::AdministrativeReset, vec![9, b'a'])));
+        assert_eq!(format_line(&truncated),
+            "NOTIFICATION code 6 subcode 4 (Cease: Administrative Reset) data 0961 (shutdown communication of 9 bytes with only 1 present)");
+    }
+
+    #[test]
+    fn format_flowspec_test() {
+        let input = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x37, 0x02, 0x00, 0x00, 0x00, 0x20,
+            0x90, 14, 0x00, 0x11, 0x00, 0x01, 133, 0, 0, 0x0b, 0x01, 0x18, 0xc0, 0x00, 0x02, 0x03, 0x81, 0x06, 0x05, 0x81, 0x50,
+            0xc0, 16, 8, 0x80, 0x06, 0, 0, 0x49, 0x74, 0x24, 0x00,
+        ];
+        let message = match parse_bgp_message(&input) {
+            Done(_, message) => message,
+            other => panic!("failed to parse: {:?}", other),
+        };
+        assert_eq!(format_line(&message), "UPDATE mp-reach ipv4 flowspec [dst 192.0.2.0/24 proto =6 dport =80] extended-communities rate-limit 1000000");
+    }
+
+    #[test]
+    fn format_vpn_test() {
+        let vrf = vpn::Vrf::new("red", "65000:1".parse().unwrap(), vec![], vec!["65000:1".parse().unwrap()]);
+        let update = vrf.export(&[("10.1.0.0".parse().unwrap(), 16, mpls::Label::new(100))], "192.0.2.1".parse().unwrap());
+        assert_eq!(format_line(&BgpMessage::Update(Box::new(update))),
+            "UPDATE origin IGP as-path mp-reach vpnv4 next-hop 192.0.2.1 65000:1 10.1.0.0/16 label 100 extended-communities rt 65000:1");
+    }
+
+    #[test]
+    fn format_link_state_test() {
+        let input = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x50, 0x02, 0x00, 0x00, 0x00, 0x39,
+            0x90, 14, 0x00, 0x2c, 0x40, 0x04, 71, 4, 192, 0, 2, 1, 0,
+            0x00, 0x01, 0x00, 0x1f, 2, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x01, 0x00, 0x00, 0x12, 0x02, 0x00, 0x00, 0x04, 0x00, 0x00, 0xfd, 0xe8, 0x02, 0x03, 0x00, 0x06, 0, 0, 0, 0, 0, 1,
+            0x80, 29, 6, 0x04, 0x02, 0x00, 0x02, b'r', b'1',
+        ];
+        let message = match parse_bgp_message(&input) {
+            Done(_, message) => message,
+            other => panic!("failed to parse: {:?}", other),
+        };
+        assert_eq!(format_line(&message), "UPDATE mp-reach link-state next-hop 192.0.2.1 node isis-l2 as 65000 router 0000.0000.0001 link-state name r1");
+    }
+
+    #[test]
+    fn format_bmp_event_test() {
+        let options = parse_args(&args("--bmp-listen 127.0.0.1:0 -t update")).unwrap().unwrap();
+        let router = "10.0.0.1:4000".parse().unwrap();
+        let peer = PerPeerHeader {
+            peer_type: PeerType::Global,
+            post_policy: false,
+            legacy_as_path: true,
+            adj_rib_out: false,
+            peer_distinguisher: 0,
+            peer_address: "192.168.0.15".parse().unwrap(),
+            peer_as: 65033,
+            peer_bgp_id: Ipv4Addr::new(192, 168, 0, 15),
+            timestamp: Duration::new(954423960, 0),
+        };
+
+        let update = match parse_bgp_message(&include_bytes!("../assets/test_bgp_update3.bin")[..]) {
+            Done(_, message) => message,
+            other => panic!("failed to parse: {:?}", other),
+        };
+        let event = BmpEvent::Message(router, BmpMessage::RouteMonitoring(Box::new(RouteMonitoring { peer, message: update })));
+        assert_eq!(format_bmp_event(&event, &options), Some("10.0.0.1:4000 954423960.000000 AS65033 192.168.0.15 pre-policy \
+            UPDATE nlri 10.0.0.0/8 origin EGP as-path next-hop 192.168.0.33 med 0 local-pref 100 communities 65033:500 65033:600\n".to_string()));
+
+        let event = BmpEvent::Message(router, BmpMessage::RouteMonitoring(Box::new(RouteMonitoring {
+            peer: match event { BmpEvent::Message(_, BmpMessage::RouteMonitoring(rm)) => rm.peer, _ => unreachable!() },
+            message: BgpMessage::Keepalive,
+        })));
+        assert_eq!(format_bmp_event(&event, &options), None);
+
+        let event = BmpEvent::Message(router, BmpMessage::Initiation(vec![InformationTlv::SysName("r1".to_string())]));
+        assert_eq!(format_bmp_event(&event, &options), Some("10.0.0.1:4000 INITIATION sys-name \"r1\"\n".to_string()));
+
+        let event = BmpEvent::Message(router, BmpMessage::Termination(vec![TerminationTlv::Reason(TerminationReason::Unspecified)]));
+        assert_eq!(format_bmp_event(&event, &options), Some("10.0.0.1:4000 TERMINATION reason unspecified\n".to_string()));
+    }
+
+    #[test]
+    fn format_error_test() {
+        let mut data = include_bytes!("../assets/test_bgp_open1.bin").to_vec();
+        data[19] = 3; // version
+        let record = Record { timestamp: None, peers: None, as4: false, offset: 100, data };
+
+        let result = parse_bgp_message(&record.data);
+        let s = format_error("x.bin", &record, &result);
+        let mut lines = s.lines();
+        assert_eq!(lines.next(), Some("x.bin: error at offset 119: unsupported version number (error 2, subcode 1)"));
+        assert!(lines.next().unwrap().starts_with("00000064\tff ff ff ff"));
+        assert!(lines.next().unwrap().starts_with("00000074\t00 1d 01 03"));
+    }
+
+    #[test]
+    fn dump_error_test() {
+        let (status, out, err) = run_output("-f raw assets/test_bgp_nlri1.bin");
+        assert_eq!(status, 1);
+        assert_eq!(out, "");
+        assert!(err.starts_with("assets/test_bgp_nlri1.bin: error at offset 0: connection not synchronized (error 1, subcode 1)\n00000000\t"));
+
+        let (status, _, err) = run_output("-f pcap assets/test_bgp_nlri1.bin");
+        assert_eq!(status, 1);
+        assert_eq!(err, "assets/test_bgp_nlri1.bin: not a pcap or pcapng file\n");
+    }
+}
@@ -0,0 +1,488 @@
+// Encoding messages, the reverse of the parsers.
+//
+// Encoding is done field by field into a Vec<u8>. Lengths are worked out
+// from the encoded contents, so they can't disagree with them. Where the
+// parsed message held flags, like the extended length bit on a path
+// attribute, they are kept, except that an attribute too long for a one
+// byte length gets the extended length bit whatever the flags say.
+//
+// AS numbers in UPDATEs are encoded in two octets unless the session
+// negotiated the 4-octet AS capability (RFC 6793), with any that don't
+// fit in two sent as AS_TRANS. A prefix with a path identifier is
+// encoded with it, so it's up to the caller only to have them where
+// ADD-PATH was negotiated.
+//
+// A message that encodes to more than the maximum length is still
+// encoded. It's up to the caller not to build one, though an UPDATE can
+// be split into ones that fit.
+//
+// What can't be encoded at all is an error, saying what was wrong, rather
+// than being written with lengths that disagree with the contents. That's
+// a FlowSpec rule too long for its 12 bit length, or one with a prefix
+// that doesn't fit its address, a labeled or VPN prefix whose labels, RD
+// and prefix are more than its 8 bit length allows, an EVPN route too
+// long for its 8 bit length, and an EVPN IP Prefix route whose prefix
+// and gateway aren't the same family.
+
+use super::*;
+
+const AS_TRANS: u16 = 23456;
+
+fn two_octet_as(asn: u32) -> u16 {
+    match asn {
+        0..=0xffff => asn as u16,
+        _ => AS_TRANS,
+    }
+}
+
+fn encode_as(asn: u32, four_octet_as: bool, out: &mut Vec<u8>) {
+    match four_octet_as {
+        true => out.extend_from_slice(&asn.to_be_bytes()),
+        false => out.extend_from_slice(&two_octet_as(asn).to_be_bytes()),
+    }
+}
+
+impl BgpMessage {
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        self.encode_with_as4(false)
+    }
+
+    // For a session where both sides sent the 4-octet AS capability.
+    pub fn encode_with_as4(&self, four_octet_as: bool) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        let message_type = match *self {
+            BgpMessage::Open(ref open) => {
+                open.encode_body(&mut body);
+                1
+            },
+            BgpMessage::Update(ref update) => {
+                update.encode_body(&mut body, four_octet_as)?;
+                2
+            },
+            BgpMessage::Notification(ref notification) => {
+                notification.encode_body(&mut body);
+                3
+            },
+            BgpMessage::Keepalive => 4,
+        };
+
+        let mut out = Vec::with_capacity(19 + body.len());
+        out.extend_from_slice(&[0xff; 16]);
+        out.extend_from_slice(&((19 + body.len()) as u16).to_be_bytes());
+        out.push(message_type);
+        out.extend(body);
+        Ok(out)
+    }
+}
+
+impl BgpOpenMessage {
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.push(self.version);
+        out.extend_from_slice(&self.my_autonomous_system.to_be_bytes());
+        out.extend_from_slice(&self.hold_time.to_be_bytes());
+        out.extend_from_slice(&self.bgp_identifier.octets());
+
+        let mut parameters = Vec::new();
+        for parameter in &self.optional_parameters {
+            match *parameter {
+                OptionalParameter::Capability(ref capabilities) => {
+                    let mut value = Vec::new();
+                    for capability in capabilities {
+                        capability.encode(&mut value);
+                    }
+                    parameters.push(2);
+                    parameters.push(value.len() as u8);
+                    parameters.extend(value);
+                },
+            }
+        }
+
+        out.push(parameters.len() as u8);
+        out.extend(parameters);
+    }
+}
+
+impl CapabilityParameter {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            CapabilityParameter::MultiprotocolExtensions(ref mp) => {
+                out.extend_from_slice(&[1, 4]);
+                out.extend_from_slice(&mp.afi.to_be_bytes());
+                out.push(0);
+                out.push(mp.safi);
+            },
+            CapabilityParameter::RouteRefresh => out.extend_from_slice(&[2, 0]),
+            CapabilityParameter::ExtendedMessage => out.extend_from_slice(&[6, 0]),
+            CapabilityParameter::MultipleLabels(ref m) => {
+                out.extend_from_slice(&[8, (m.counts.len() * 4) as u8]);
+                for count in &m.counts {
+                    out.extend_from_slice(&count.afi.to_be_bytes());
+                    out.extend_from_slice(&[count.safi, count.count]);
+                }
+            },
+            CapabilityParameter::Role(ref role) => out.extend_from_slice(&[9, 1, role.role.into()]),
+            CapabilityParameter::FourOctetAs(ref four_octet_as) => {
+                out.extend_from_slice(&[65, 4]);
+                out.extend_from_slice(&four_octet_as.asn.to_be_bytes());
+            },
+            CapabilityParameter::AddPath(ref add_path) => {
+                out.extend_from_slice(&[69, (add_path.families.len() * 4) as u8]);
+                for family in &add_path.families {
+                    out.extend_from_slice(&family.afi.to_be_bytes());
+                    out.extend_from_slice(&[family.safi, (family.send as u8) << 1 | family.receive as u8]);
+                }
+            },
+            CapabilityParameter::Unknown(ref unknown) => {
+                out.extend_from_slice(&[unknown.code, unknown.data.len() as u8]);
+                out.extend_from_slice(&unknown.data);
+            },
+        }
+    }
+}
+
+impl BgpNotificationMessage {
+    fn encode_body(&self, out: &mut Vec<u8>) {
+        out.push(self.error_code);
+        out.push(self.error_subcode);
+        out.extend_from_slice(&self.data);
+    }
+}
+
+impl BgpUpdateMessage {
+    fn encode_body(&self, out: &mut Vec<u8>, four_octet_as: bool) -> Result<(), String> {
+        let mut withdrawn_routes = Vec::new();
+        for prefix in &self.withdrawn_routes {
+            prefix.encode(&mut withdrawn_routes);
+        }
+        out.extend_from_slice(&(withdrawn_routes.len() as u16).to_be_bytes());
+        out.extend(withdrawn_routes);
+
+        let mut path_attributes = Vec::new();
+        for attribute in &self.path_attributes {
+            attribute.encode(&mut path_attributes, four_octet_as)?;
+        }
+        out.extend_from_slice(&(path_attributes.len() as u16).to_be_bytes());
+        out.extend(path_attributes);
+
+        for prefix in &self.nlri {
+            prefix.encode(out);
+        }
+        Ok(())
+    }
+}
+
+impl BgpUpdateMessage {
+    // Split into UPDATEs that each encode to no more than max_length
+    // bytes. The withdrawals go first in UPDATEs of their own, then the
+    // NLRI, each UPDATE with all of the path attributes. If those leave
+    // no room for any NLRI the message is returned as it is, as it is if
+    // the attributes can't be encoded.
+    pub fn split(&self, max_length: usize, four_octet_as: bool) -> Vec<BgpUpdateMessage> {
+        let mut path_attributes = Vec::new();
+        for attribute in &self.path_attributes {
+            if attribute.encode(&mut path_attributes, four_octet_as).is_err() {
+                return vec![self.clone()];
+            }
+        }
+        let withdrawn_length: usize = self.withdrawn_routes.iter().map(|p| p.encoded_len()).sum();
+        let nlri_length: usize = self.nlri.iter().map(|p| p.encoded_len()).sum();
+        // The header, and the two length fields.
+        let empty_length = 19 + 2 + 2;
+
+        if empty_length + withdrawn_length + path_attributes.len() + nlri_length <= max_length {
+            return vec![self.clone()];
+        }
+        // The longest prefix is 5 bytes.
+        if empty_length + path_attributes.len() + 5 > max_length {
+            return vec![self.clone()];
+        }
+
+        let mut updates = Vec::new();
+        for withdrawn_routes in chunk_prefixes(&self.withdrawn_routes, max_length - empty_length) {
+            updates.push(BgpUpdateMessage { withdrawn_routes, path_attributes: vec![], nlri: vec![] });
+        }
+        for nlri in chunk_prefixes(&self.nlri, max_length - empty_length - path_attributes.len()) {
+            updates.push(BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: self.path_attributes.clone(), nlri });
+        }
+        updates
+    }
+}
+
+// Split prefixes into runs that encode to no more than space bytes.
+fn chunk_prefixes(prefixes: &[Ipv4Prefix], space: usize) -> Vec<Vec<Ipv4Prefix>> {
+    let mut chunks: Vec<Vec<Ipv4Prefix>> = Vec::new();
+    let mut used = space;
+    for prefix in prefixes {
+        if used + prefix.encoded_len() > space {
+            chunks.push(Vec::new());
+            used = 0;
+        }
+        used += prefix.encoded_len();
+        chunks.last_mut().unwrap().push(prefix.clone());
+    }
+    chunks
+}
+
+impl Ipv4Prefix {
+    fn encoded_len(&self) -> usize {
+        self.path_id.map_or(0, |_| 4) + 1 + self.prefix.len()
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        if let Some(path_id) = self.path_id {
+            out.extend_from_slice(&path_id.to_be_bytes());
+        }
+        out.push(self.length);
+        out.extend_from_slice(&self.prefix);
+    }
+}
+
+impl BgpPathAttribute {
+    fn encode(&self, out: &mut Vec<u8>, four_octet_as: bool) -> Result<(), String> {
+        let mut value = Vec::new();
+        let type_code = self.attribute.encode_value(&mut value, four_octet_as)?;
+        let extended_length = self.flags.extended_length || value.len() > 255;
+
+        out.push((self.flags.optional as u8) << 7 | (self.flags.transitive as u8) << 6 | (self.flags.partial as u8) << 5 | (extended_length as u8) << 4);
+        out.push(type_code);
+        match extended_length {
+            true => out.extend_from_slice(&(value.len() as u16).to_be_bytes()),
+            false => out.push(value.len() as u8),
+        }
+        out.extend(value);
+        Ok(())
+    }
+}
+
+impl PathAttribute {
+    // Encode the value, returning the type code.
+    fn encode_value(&self, out: &mut Vec<u8>, four_octet_as: bool) -> Result<u8, String> {
+        Ok(match *self {
+            PathAttribute::Origin(ref origin) => {
+                out.push(match origin.origin_code {
+                    BgpOriginCode::Igp => 0,
+                    BgpOriginCode::Egp => 1,
+                    BgpOriginCode::Incomplete => 2,
+                });
+                1
+            },
+            PathAttribute::AsPath(ref as_path) => {
+                for segment in &as_path.as_path {
+                    let (segment_type, asns) = match *segment {
+                        AsPathSegment::AsSet(ref v) => (1, v),
+                        AsPathSegment::AsSequence(ref v) => (2, v),
+                        AsPathSegment::AsConfedSequence(ref v) => (3, v),
+                        AsPathSegment::AsConfedSet(ref v) => (4, v),
+                    };
+                    out.push(segment_type);
+                    out.push(asns.len() as u8);
+                    for asn in asns {
+                        encode_as(*asn, four_octet_as, out);
+                    }
+                }
+                2
+            },
+            PathAttribute::NextHop(ref next_hop) => {
+                out.extend_from_slice(&next_hop.next_hop.octets());
+                3
+            },
+            PathAttribute::MultiExitDisc(ref med) => {
+                out.extend_from_slice(&med.metric.to_be_bytes());
+                4
+            },
+            PathAttribute::LocalPref(ref local_pref) => {
+                out.extend_from_slice(&local_pref.preference.to_be_bytes());
+                5
+            },
+            PathAttribute::AtomicAggregate => 6,
+            PathAttribute::Aggregator(ref aggregator) => {
+                encode_as(aggregator.aggregator_as, four_octet_as, out);
+                out.extend_from_slice(&aggregator.aggregator_id.octets());
+                7
+            },
+            PathAttribute::Communities(ref communities) => {
+                for community in &communities.communities {
+                    out.extend_from_slice(&community.asn.to_be_bytes());
+                    out.extend_from_slice(&community.value.to_be_bytes());
+                }
+                8
+            },
+            PathAttribute::MpReachNlri(ref mp) => {
+                out.extend_from_slice(&mp.afi.to_be_bytes());
+                out.push(mp.safi);
+                out.push(mp.next_hop.len() as u8);
+                out.extend_from_slice(&mp.next_hop);
+                out.push(0);
+                mp.nlri.encode(out)?;
+                14
+            },
+            PathAttribute::MpUnreachNlri(ref mp) => {
+                out.extend_from_slice(&mp.afi.to_be_bytes());
+                out.push(mp.safi);
+                mp.withdrawn_routes.encode(out)?;
+                15
+            },
+            PathAttribute::ExtendedCommunities(ref communities) => {
+                for community in &communities.communities {
+                    out.extend_from_slice(&community.to_bytes());
+                }
+                16
+            },
+            PathAttribute::LinkState(ref link_state) => {
+                for tlv in &link_state.tlvs {
+                    tlv.encode(out);
+                }
+                29
+            },
+            PathAttribute::OnlyToCustomer(ref otc) => {
+                out.extend_from_slice(&otc.asn.to_be_bytes());
+                35
+            },
+        })
+    }
+}
+
+impl MpNlri {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        match *self {
+            MpNlri::Labeled(ref prefixes) => {
+                for prefix in prefixes {
+                    prefix.encode(out)?;
+                }
+            },
+            MpNlri::Vpn(ref prefixes) => {
+                for prefix in prefixes {
+                    prefix.encode(out)?;
+                }
+            },
+            MpNlri::Evpn(ref routes) => {
+                for route in routes {
+                    route.encode(out)?;
+                }
+            },
+            MpNlri::LinkState(ref nlri) => {
+                for nlri in nlri {
+                    nlri.encode(out);
+                }
+            },
+            MpNlri::FlowSpec(ref rules) => {
+                for rule in rules {
+                    rule.encode(out)?;
+                }
+            },
+            MpNlri::Unknown(ref nlri) => out.extend_from_slice(nlri),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> BgpMessage {
+        match parse_bgp_message(input) {
+            Done(_, message) => message,
+            other => panic!("failed to parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encode_fixtures_test() {
+        let fixtures: Vec<&[u8]> = vec![
+            &include_bytes!("../assets/test_bgp_open1.bin")[..],
+            &include_bytes!("../assets/test_bgp_open2.bin")[..],
+            &include_bytes!("../assets/test_bgp_open3.bin")[..],
+            &include_bytes!("../assets/test_bgp_keepalive1.bin")[..],
+            &include_bytes!("../assets/test_bgp_notification1.bin")[..],
+            &include_bytes!("../assets/test_bgp_update3.bin")[..],
+        ];
+
+        for fixture in fixtures {
+            assert_eq!(parse(fixture).encode().unwrap(), fixture.to_vec());
+        }
+
+        // These hold things the parser doesn't keep yet, the
+        // ORIGINATOR_ID and CLUSTER_LIST attributes, so we only get back
+        // what was parsed.
+        let fixtures: Vec<&[u8]> = vec![
+            &include_bytes!("../assets/test_bgp_update1.bin")[..],
+            &include_bytes!("../assets/test_bgp_update2.bin")[..],
+        ];
+
+        for fixture in fixtures {
+            let message = parse(fixture);
+            assert_eq!(parse(&message.encode().unwrap()), message);
+        }
+    }
+
+    #[test]
+    fn encode_extended_length_test() {
+        let communities: Vec<Community> = (0..100).map(|v| Community { asn: 65000, value: v }).collect();
+        let message = BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::Communities(Box::new(CommunitiesAttribute { communities })),
+            }],
+            nlri: vec!["10.0.0.0/8".parse().unwrap()],
+        }));
+
+        let encoded = message.encode().unwrap();
+        assert_eq!(encoded.len(), 19 + 2 + 2 + 4 + 400 + 2);
+        assert_eq!(&encoded[23..27], &[0xd0, 8, 0x01, 0x90]);
+    }
+
+    #[test]
+    fn encode_four_octet_as_test() {
+        let message = BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![
+                BgpPathAttribute {
+                    flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                    attribute: PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: vec![AsPathSegment::AsSequence(vec![65001, 4200000001])] })),
+                },
+                BgpPathAttribute {
+                    flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+                    attribute: PathAttribute::Aggregator(Box::new(AggregatorAttribute { aggregator_as: 4200000001, aggregator_id: Ipv4Addr::new(192, 0, 2, 1) })),
+                },
+            ],
+            nlri: vec!["10.0.0.0/8".parse().unwrap()],
+        }));
+
+        let encoded = message.encode_with_as4(true).unwrap();
+        assert_eq!(&encoded[23..36], &[0x40, 2, 10, 2, 2, 0, 0, 0xfd, 0xe9, 0xfa, 0x56, 0xea, 0x01]);
+        match parse_bgp_message_with_encoding(&encoded, MAX_MESSAGE_LENGTH, UpdateEncoding::archived(true)) {
+            Done(&[], ref parsed) => assert_eq!(parsed, &message),
+            other => panic!("failed to parse: {:?}", other),
+        }
+
+        // Without the capability what doesn't fit is AS_TRANS.
+        let encoded = message.encode().unwrap();
+        assert_eq!(&encoded[23..32], &[0x40, 2, 6, 2, 2, 0xfd, 0xe9, 0x5b, 0xa0]);
+        assert_eq!(&encoded[32..37], &[0xc0, 7, 6, 0x5b, 0xa0]);
+    }
+
+    #[test]
+    fn encode_mp_reach_nlri_test() {
+        // The extended length bit is kept even though it isn't needed.
+        let attributes: [&[u8]; 5] = [
+            // Labeled unicast, announced with two labels and withdrawn.
+            &[0x80, 14, 0x12, 0x00, 0x01, 4, 4, 10, 0, 0, 1, 0, 64, 0x00, 0x01, 0x00, 0x00, 0x01, 0x11, 10, 1],
+            &[0x80, 15, 0x09, 0x00, 0x01, 4, 40, 0x80, 0x00, 0x00, 10, 1],
+            &[0x90, 14, 0x00, 0x11, 0x00, 0x01, 133, 0, 0, 0x0b, 0x01, 0x18, 0xc0, 0x00, 0x02, 0x03, 0x81, 0x06, 0x05, 0x81, 0x50],
+            &[0x80, 15, 0x0f, 0x00, 0x01, 133, 0x0b, 0x01, 0x18, 0xc0, 0x00, 0x02, 0x03, 0x81, 0x06, 0x05, 0x81, 0x50],
+            &[0xc0, 16, 16, 0x80, 0x06, 0, 0, 0, 0, 0, 0, 0x80, 0x08, 0xfd, 0xe8, 0, 0, 0, 0x64],
+        ];
+
+        for input in &attributes {
+            let attribute = match old_parse_bgp_path_attribute(input) {
+                Done(_, attribute) => attribute,
+                other => panic!("failed to parse: {:?}", other),
+            };
+            let mut encoded = Vec::new();
+            attribute.encode(&mut encoded, false).unwrap();
+            assert_eq!(encoded, input.to_vec());
+        }
+    }
+}
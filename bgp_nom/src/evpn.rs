@@ -0,0 +1,519 @@
+// BGP MPLS-Based Ethernet VPN (RFC 7432), AFI 25 (L2VPN) with SAFI 70,
+// including the IP Prefix route (RFC 9136).
+//
+// Each EVPN route starts with its type and length, and what follows
+// depends on the type:
+//
+//   1 Ethernet Auto-Discovery   RD, ESI, Ethernet tag, label
+//   2 MAC/IP Advertisement      RD, ESI, Ethernet tag, MAC, IP if any,
+//                               one or two labels
+//   3 Inclusive Multicast       RD, Ethernet tag, originating router
+//   4 Ethernet Segment          RD, ESI, originating router
+//   5 IP Prefix                 RD, ESI, Ethernet tag, prefix, gateway,
+//                               label
+//
+// where the ESI identifies a multihomed Ethernet segment, all zero for a
+// single homed one. The labels are read as MPLS labels, 20 bits with the
+// traffic class and bottom of stack bit. For VXLAN the field holds the
+// VNI in all 24 bits instead, so that's the three of them together. Other
+// types are kept as they are.
+//
+// The prefix and gateway of an IP Prefix route are both IPv4 or both
+// IPv6, and encoding one that mixes them is an error.
+//
+// As text each route is its type and then its fields, e.g.
+//
+//   mac-ip 65000:1 esi 0 tag 0 mac 00:11:22:33:44:55 ip 10.0.0.1 label 100
+//
+// with ESIs written as ten hex bytes separated by colons, or just 0.
+//
+// The EVPN extended communities, for MAC mobility, ESI labels and the
+// router's MAC, are in extended_community.rs.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use nom::{be_u8, be_u32};
+
+use super::*;
+use super::mpls::{Label, format_labels, label};
+use super::vpn::{RouteDistinguisher, route_distinguisher};
+
+pub const AFI_L2VPN: u16 = 25;
+pub const SAFI_EVPN: u8 = 70;
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub struct MacAddress(pub [u8; 6]);
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub struct Esi(pub [u8; 10]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_octets(&self.0))
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<MacAddress, ()> {
+        let mut mac = [0u8; 6];
+        parse_octets(s, &mut mac)?;
+        Ok(MacAddress(mac))
+    }
+}
+
+impl fmt::Display for Esi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.iter().all(|b| *b == 0) {
+            true => f.write_str("0"),
+            false => f.write_str(&format_octets(&self.0)),
+        }
+    }
+}
+
+impl FromStr for Esi {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Esi, ()> {
+        let mut esi = [0u8; 10];
+        if s != "0" {
+            parse_octets(s, &mut esi)?;
+        }
+        Ok(Esi(esi))
+    }
+}
+
+fn format_octets(octets: &[u8]) -> String {
+    octets.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+fn parse_octets(s: &str, out: &mut [u8]) -> Result<(), ()> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() != out.len() {
+        return Err(());
+    }
+    for (octet, part) in out.iter_mut().zip(parts) {
+        if part.len() != 2 {
+            return Err(());
+        }
+        *octet = u8::from_str_radix(part, 16).map_err(|_| ())?;
+    }
+    Ok(())
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum EvpnRoute {
+    EthernetAutoDiscovery {
+        rd: RouteDistinguisher,
+        esi: Esi,
+        ethernet_tag: u32,
+        label: Label,
+    },
+    MacIpAdvertisement {
+        rd: RouteDistinguisher,
+        esi: Esi,
+        ethernet_tag: u32,
+        mac: MacAddress,
+        ip: Option<IpAddr>,
+        // One, or a second for the IP VRF.
+        labels: Vec<Label>,
+    },
+    InclusiveMulticast {
+        rd: RouteDistinguisher,
+        ethernet_tag: u32,
+        originator: IpAddr,
+    },
+    EthernetSegment {
+        rd: RouteDistinguisher,
+        esi: Esi,
+        originator: IpAddr,
+    },
+    IpPrefix {
+        rd: RouteDistinguisher,
+        esi: Esi,
+        ethernet_tag: u32,
+        address: IpAddr,
+        length: u8,
+        gateway: IpAddr,
+        label: Label,
+    },
+    Unknown {
+        route_type: u8,
+        #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+        value: Vec<u8>,
+    },
+}
+
+impl EvpnRoute {
+    pub fn route_type(&self) -> u8 {
+        match *self {
+            EvpnRoute::EthernetAutoDiscovery { .. } => 1,
+            EvpnRoute::MacIpAdvertisement { .. } => 2,
+            EvpnRoute::InclusiveMulticast { .. } => 3,
+            EvpnRoute::EthernetSegment { .. } => 4,
+            EvpnRoute::IpPrefix { .. } => 5,
+            EvpnRoute::Unknown { route_type, .. } => route_type,
+        }
+    }
+}
+
+impl fmt::Display for EvpnRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvpnRoute::EthernetAutoDiscovery { rd, esi, ethernet_tag, label } => {
+                write!(f, "ad {} esi {} tag {} label {}", rd, esi, ethernet_tag, label)
+            },
+            EvpnRoute::MacIpAdvertisement { rd, esi, ethernet_tag, mac, ip, ref labels } => {
+                write!(f, "mac-ip {} esi {} tag {} mac {}", rd, esi, ethernet_tag, mac)?;
+                if let Some(ip) = ip {
+                    write!(f, " ip {}", ip)?;
+                }
+                write!(f, " label {}", format_labels(labels))
+            },
+            EvpnRoute::InclusiveMulticast { rd, ethernet_tag, originator } => {
+                write!(f, "imet {} tag {} originator {}", rd, ethernet_tag, originator)
+            },
+            EvpnRoute::EthernetSegment { rd, esi, originator } => {
+                write!(f, "es {} esi {} originator {}", rd, esi, originator)
+            },
+            EvpnRoute::IpPrefix { rd, esi, ethernet_tag, address, length, gateway, label } => {
+                write!(f, "prefix {} esi {} tag {} {}/{} gateway {} label {}", rd, esi, ethernet_tag, address, length, gateway, label)
+            },
+            EvpnRoute::Unknown { route_type, ref value } => {
+                write!(f, "type {} {}", route_type, value.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+            },
+        }
+    }
+}
+
+named!(esi<&[u8], Esi>,
+    map!(take!(10), |b: &[u8]| {
+        let mut esi = [0u8; 10];
+        esi.copy_from_slice(b);
+        Esi(esi)
+    })
+);
+
+named!(mac_address<&[u8], MacAddress>,
+    map!(take!(6), |b: &[u8]| {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(b);
+        MacAddress(mac)
+    })
+);
+
+// An address with its length in bits in front, which may be zero.
+named!(optional_address<&[u8], Option<IpAddr>>,
+    switch!(be_u8,
+        0 => value!(None) |
+        32 => map!(take!(4), |b: &[u8]| Some(prefix_address(AFI_IPV4, b))) |
+        128 => map!(take!(16), |b: &[u8]| Some(prefix_address(AFI_IPV6, b)))
+    )
+);
+
+named!(address<&[u8], IpAddr>,
+    map_opt!(optional_address, |a: Option<IpAddr>| a)
+);
+
+named!(pub evpn_route<&[u8], EvpnRoute>,
+    do_parse!(
+        route_type: be_u8 >>
+        length: be_u8 >>
+        route: flat_map!(take!(length), terminated!(switch!(value!(route_type),
+            1 => call!(ethernet_auto_discovery) |
+            2 => call!(mac_ip_advertisement) |
+            3 => call!(inclusive_multicast) |
+            4 => call!(ethernet_segment) |
+            5 => call!(ip_prefix, length) |
+            _ => map!(call!(nom::rest), |r: &[u8]| EvpnRoute::Unknown { route_type, value: r.to_vec() })
+        ), eof!())) >>
+        (route)
+    )
+);
+
+named!(ethernet_auto_discovery<&[u8], EvpnRoute>,
+    do_parse!(
+        rd: route_distinguisher >>
+        esi: esi >>
+        ethernet_tag: be_u32 >>
+        label: label >>
+        (EvpnRoute::EthernetAutoDiscovery { rd, esi, ethernet_tag, label })
+    )
+);
+
+named!(mac_ip_advertisement<&[u8], EvpnRoute>,
+    do_parse!(
+        rd: route_distinguisher >>
+        esi: esi >>
+        ethernet_tag: be_u32 >>
+        tag!([48u8]) >> // the MAC length
+        mac: mac_address >>
+        ip: optional_address >>
+        labels: many1!(complete!(label)) >>
+        (EvpnRoute::MacIpAdvertisement { rd, esi, ethernet_tag, mac, ip, labels })
+    )
+);
+
+named!(inclusive_multicast<&[u8], EvpnRoute>,
+    do_parse!(
+        rd: route_distinguisher >>
+        ethernet_tag: be_u32 >>
+        originator: address >>
+        (EvpnRoute::InclusiveMulticast { rd, ethernet_tag, originator })
+    )
+);
+
+named!(ethernet_segment<&[u8], EvpnRoute>,
+    do_parse!(
+        rd: route_distinguisher >>
+        esi: esi >>
+        originator: address >>
+        (EvpnRoute::EthernetSegment { rd, esi, originator })
+    )
+);
+
+// Whether the prefix and gateway are IPv4 or IPv6 follows from the
+// length of the route, 34 or 58 bytes.
+named_args!(ip_prefix(length: u8) <EvpnRoute>,
+    do_parse!(
+        afi: switch!(value!(length),
+            34 => value!(AFI_IPV4) |
+            58 => value!(AFI_IPV6)
+        ) >>
+        rd: route_distinguisher >>
+        esi: esi >>
+        ethernet_tag: be_u32 >>
+        prefix_length: verify!(be_u8, |v: u8| v as usize <= max_prefix_length(afi)) >>
+        address: take!(max_prefix_length(afi) / 8) >>
+        gateway: take!(max_prefix_length(afi) / 8) >>
+        label: label >>
+        (EvpnRoute::IpPrefix {
+            rd,
+            esi,
+            ethernet_tag,
+            address: prefix_address(afi, address),
+            length: prefix_length,
+            gateway: prefix_address(afi, gateway),
+            label,
+        })
+    )
+);
+
+impl EvpnRoute {
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        let mut value = Vec::new();
+        match *self {
+            EvpnRoute::EthernetAutoDiscovery { rd, esi, ethernet_tag, label } => {
+                rd.encode(&mut value);
+                value.extend_from_slice(&esi.0);
+                value.extend_from_slice(&ethernet_tag.to_be_bytes());
+                label.encode(&mut value);
+            },
+            EvpnRoute::MacIpAdvertisement { rd, esi, ethernet_tag, mac, ip, ref labels } => {
+                rd.encode(&mut value);
+                value.extend_from_slice(&esi.0);
+                value.extend_from_slice(&ethernet_tag.to_be_bytes());
+                value.push(48);
+                value.extend_from_slice(&mac.0);
+                encode_address(ip, &mut value);
+                for label in labels {
+                    label.encode(&mut value);
+                }
+            },
+            EvpnRoute::InclusiveMulticast { rd, ethernet_tag, originator } => {
+                rd.encode(&mut value);
+                value.extend_from_slice(&ethernet_tag.to_be_bytes());
+                encode_address(Some(originator), &mut value);
+            },
+            EvpnRoute::EthernetSegment { rd, esi, originator } => {
+                rd.encode(&mut value);
+                value.extend_from_slice(&esi.0);
+                encode_address(Some(originator), &mut value);
+            },
+            EvpnRoute::IpPrefix { rd, esi, ethernet_tag, address, length, gateway, label } => {
+                let max_length = match (address, gateway) {
+                    (IpAddr::V4(_), IpAddr::V4(_)) => 32,
+                    (IpAddr::V6(_), IpAddr::V6(_)) => 128,
+                    _ => return Err(format!("EVPN prefix {}/{} has gateway {}", address, length, gateway)),
+                };
+                if length > max_length {
+                    return Err(format!("EVPN prefix {}/{} is too long", address, length));
+                }
+                rd.encode(&mut value);
+                value.extend_from_slice(&esi.0);
+                value.extend_from_slice(&ethernet_tag.to_be_bytes());
+                value.push(length);
+                value.extend_from_slice(&prefix_bytes(address, 128));
+                value.extend_from_slice(&prefix_bytes(gateway, 128));
+                label.encode(&mut value);
+            },
+            EvpnRoute::Unknown { value: ref unknown, .. } => value.extend_from_slice(unknown),
+        }
+
+        if value.len() > 255 {
+            return Err(format!("EVPN route of {} bytes is too long", value.len()));
+        }
+        out.push(self.route_type());
+        out.push(value.len() as u8);
+        out.extend(value);
+        Ok(())
+    }
+}
+
+fn encode_address(address: Option<IpAddr>, out: &mut Vec<u8>) {
+    match address {
+        None => out.push(0),
+        Some(IpAddr::V4(address)) => {
+            out.push(32);
+            out.extend_from_slice(&address.octets());
+        },
+        Some(IpAddr::V6(address)) => {
+            out.push(128);
+            out.extend_from_slice(&address.octets());
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8], route: EvpnRoute, text: &str) {
+        assert_eq!(evpn_route(input), Done(&b""[..], route.clone()));
+        assert_eq!(route.to_string(), text);
+        let mut encoded = Vec::new();
+        route.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, input.to_vec());
+    }
+
+    const RD: [u8; 8] = [0x00, 0x01, 0xc0, 0x00, 0x02, 0x01, 0x00, 0x01];
+    const ESI: [u8; 10] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99];
+
+    #[test]
+    fn evpn_mac_ip_advertisement_test() {
+        let mut input = vec![2, 37];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&[0; 10]);
+        input.extend_from_slice(&[0, 0, 0, 0, 48, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 32, 10, 0, 0, 1, 0x00, 0x06, 0x41]);
+        round_trip(&input, EvpnRoute::MacIpAdvertisement {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: Esi([0; 10]),
+            ethernet_tag: 0,
+            mac: "00:11:22:33:44:55".parse().unwrap(),
+            ip: Some("10.0.0.1".parse().unwrap()),
+            labels: vec![Label::new(100)],
+        }, "mac-ip 192.0.2.1:1 esi 0 tag 0 mac 00:11:22:33:44:55 ip 10.0.0.1 label 100");
+
+        // Without an IP, and with a second label.
+        let mut input = vec![2, 36];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&ESI);
+        input.extend_from_slice(&[0, 0, 0, 10, 48, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0, 0x00, 0x06, 0x41, 0x00, 0x07, 0x51]);
+        round_trip(&input, EvpnRoute::MacIpAdvertisement {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: "00:11:22:33:44:55:66:77:88:99".parse().unwrap(),
+            ethernet_tag: 10,
+            mac: "00:11:22:33:44:55".parse().unwrap(),
+            ip: None,
+            labels: vec![Label::new(100), Label::new(117)],
+        }, "mac-ip 192.0.2.1:1 esi 00:11:22:33:44:55:66:77:88:99 tag 10 mac 00:11:22:33:44:55 label 100/117");
+    }
+
+    #[test]
+    fn evpn_route_types_test() {
+        let mut input = vec![1, 25];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&ESI);
+        input.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x01]);
+        round_trip(&input, EvpnRoute::EthernetAutoDiscovery {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: Esi(ESI),
+            ethernet_tag: 0xffff_ffff,
+            label: Label::new(0),
+        }, "ad 192.0.2.1:1 esi 00:11:22:33:44:55:66:77:88:99 tag 4294967295 label 0");
+
+        let mut input = vec![3, 17];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&[0, 0, 0, 0, 32, 192, 0, 2, 1]);
+        round_trip(&input, EvpnRoute::InclusiveMulticast {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            ethernet_tag: 0,
+            originator: "192.0.2.1".parse().unwrap(),
+        }, "imet 192.0.2.1:1 tag 0 originator 192.0.2.1");
+
+        let mut input = vec![4, 35];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&ESI);
+        input.push(128);
+        input.extend_from_slice(&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        round_trip(&input, EvpnRoute::EthernetSegment {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: Esi(ESI),
+            originator: "2001:db8::1".parse().unwrap(),
+        }, "es 192.0.2.1:1 esi 00:11:22:33:44:55:66:77:88:99 originator 2001:db8::1");
+
+        let mut input = vec![5, 34];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&[0; 10]);
+        input.extend_from_slice(&[0, 0, 0, 0, 24, 10, 1, 2, 0, 0, 0, 0, 0, 0x00, 0x27, 0x11]);
+        round_trip(&input, EvpnRoute::IpPrefix {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: Esi([0; 10]),
+            ethernet_tag: 0,
+            address: "10.1.2.0".parse().unwrap(),
+            length: 24,
+            gateway: "0.0.0.0".parse().unwrap(),
+            label: Label::new(625),
+        }, "prefix 192.0.2.1:1 esi 0 tag 0 10.1.2.0/24 gateway 0.0.0.0 label 625");
+
+        round_trip(&[9, 2, 0xab, 0xcd], EvpnRoute::Unknown { route_type: 9, value: vec![0xab, 0xcd] }, "type 9 abcd");
+    }
+
+    #[test]
+    fn evpn_route_encode_invalid_test() {
+        let route = |address: &str, length, gateway: &str| EvpnRoute::IpPrefix {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: Esi([0; 10]),
+            ethernet_tag: 0,
+            address: address.parse().unwrap(),
+            length,
+            gateway: gateway.parse().unwrap(),
+            label: Label::new(625),
+        };
+        assert!(route("10.1.2.0", 24, "::").encode(&mut Vec::new()).is_err());
+        assert!(route("2001:db8::", 32, "0.0.0.0").encode(&mut Vec::new()).is_err());
+        assert!(route("10.1.2.0", 33, "0.0.0.0").encode(&mut Vec::new()).is_err());
+        assert!(route("2001:db8::", 32, "::").encode(&mut Vec::new()).is_ok());
+
+        // Too long for the one byte route length.
+        assert!(EvpnRoute::Unknown { route_type: 9, value: vec![0; 255] }.encode(&mut Vec::new()).is_ok());
+        assert!(EvpnRoute::Unknown { route_type: 9, value: vec![0; 256] }.encode(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn evpn_route_invalid_test() {
+        // A MAC length other than 48.
+        let mut input = vec![2, 33];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&[0; 10]);
+        input.extend_from_slice(&[0, 0, 0, 0, 47, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0, 0x00, 0x06, 0x41]);
+        assert!(evpn_route(&input).is_err());
+
+        // Left over bytes.
+        let mut input = vec![3, 18];
+        input.extend_from_slice(&RD);
+        input.extend_from_slice(&[0, 0, 0, 0, 32, 192, 0, 2, 1, 0]);
+        assert!(evpn_route(&input).is_err());
+
+        // An IP Prefix route of neither length.
+        assert!(evpn_route(&[5, 2, 0, 0]).is_err());
+
+        assert_eq!("00:11:22:33:44".parse::<MacAddress>(), Err(()));
+        assert_eq!("0".parse::<Esi>(), Ok(Esi([0; 10])));
+    }
+}
@@ -0,0 +1,217 @@
+// Extended communities (RFC 4360), the EXTENDED_COMMUNITIES attribute.
+//
+// Each is 8 bytes, a type and subtype then a 6 byte value whose layout
+// depends on them. The ones we know are decoded, the rest are kept as
+// they are so they can be passed on. So are known ones with reserved bits
+// or unused bytes set, which the decoded form would lose.
+//
+// Route Targets, written "rt 65000:1", say which VRFs a VPN route goes
+// in, see vpn.rs.
+//
+// For EVPN (RFC 7432 and RFC 9135, see evpn.rs) there are:
+//
+//   mac-mobility 3 sticky     how many times the MAC has moved
+//   esi-label 100 single-active
+//   router-mac 00:11:22:33:44:55
+//
+// The FlowSpec traffic actions (RFC 8955 section 7, and RFC 7674 for the
+// redirects) say what to do with traffic matching a FlowSpec rule, see
+// flowspec.rs. As text they are:
+//
+//   rate-limit 1000           bytes per second, 0 to drop everything
+//   rate-limit-packets 1000   packets per second
+//   action sample,terminal
+//   redirect 65000:100        to the VRF with that route target
+//   mark 46                   set the DSCP
+//
+// Anything else is written as hex.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use super::*;
+use super::evpn::MacAddress;
+use super::mpls::Label;
+use super::vpn::{RouteDistinguisher, RouteTarget};
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ExtendedCommunity {
+    // See vpn.rs.
+    RouteTarget { target: RouteTarget },
+    MacMobility { sticky: bool, sequence: u32 },
+    EsiLabel { single_active: bool, label: Label },
+    RouterMac { mac: MacAddress },
+    // The AS is informational, it's the rate that counts.
+    TrafficRateBytes { asn: u16, rate: f32 },
+    TrafficRatePackets { asn: u16, rate: f32 },
+    TrafficAction { sample: bool, terminal: bool },
+    Redirect { asn: u16, value: u32 },
+    RedirectIpv4 { address: Ipv4Addr, value: u16 },
+    RedirectAs4 { asn: u32, value: u16 },
+    TrafficMarking { dscp: u8 },
+    Other { value: [u8; 8] },
+}
+
+impl ExtendedCommunity {
+    pub fn from_bytes(b: [u8; 8]) -> ExtendedCommunity {
+        let u16_at = |i: usize| u16::from_be_bytes([b[i], b[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+        let community = match (b[0], b[1]) {
+            (0x06, 0x00) => ExtendedCommunity::MacMobility { sticky: b[2] & 0x01 != 0, sequence: u32_at(4) },
+            (0x06, 0x01) => ExtendedCommunity::EsiLabel { single_active: b[2] & 0x01 != 0, label: mpls::label(&b[5..]).unwrap().1 },
+            (0x06, 0x03) => ExtendedCommunity::RouterMac { mac: MacAddress([b[2], b[3], b[4], b[5], b[6], b[7]]) },
+            (0x00..=0x02, 0x02) => ExtendedCommunity::RouteTarget { target: RouteDistinguisher::from_value_bytes(b[0], &b[2..]).unwrap() },
+            (0x80, 0x06) => ExtendedCommunity::TrafficRateBytes { asn: u16_at(2), rate: f32::from_bits(u32_at(4)) },
+            (0x80, 0x0c) => ExtendedCommunity::TrafficRatePackets { asn: u16_at(2), rate: f32::from_bits(u32_at(4)) },
+            (0x80, 0x07) => ExtendedCommunity::TrafficAction { sample: b[7] & 0x02 != 0, terminal: b[7] & 0x01 != 0 },
+            (0x80, 0x08) => ExtendedCommunity::Redirect { asn: u16_at(2), value: u32_at(4) },
+            (0x81, 0x08) => ExtendedCommunity::RedirectIpv4 { address: Ipv4Addr::from(u32_at(2)), value: u16_at(6) },
+            (0x82, 0x08) => ExtendedCommunity::RedirectAs4 { asn: u32_at(2), value: u16_at(6) },
+            (0x80, 0x09) => ExtendedCommunity::TrafficMarking { dscp: b[7] & 0x3f },
+            _ => ExtendedCommunity::Other { value: b },
+        };
+        match community.to_bytes() == b {
+            true => community,
+            false => ExtendedCommunity::Other { value: b },
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        let mut b = [0u8; 8];
+        match self {
+            ExtendedCommunity::RouteTarget { target } => {
+                b[..2].copy_from_slice(&[target.type_code(), 0x02]);
+                b[2..].copy_from_slice(&target.value_bytes());
+            },
+            ExtendedCommunity::MacMobility { sticky, sequence } => {
+                b[..3].copy_from_slice(&[0x06, 0x00, sticky as u8]);
+                b[4..].copy_from_slice(&sequence.to_be_bytes());
+            },
+            ExtendedCommunity::EsiLabel { single_active, label } => {
+                b[..3].copy_from_slice(&[0x06, 0x01, single_active as u8]);
+                let mut encoded = Vec::new();
+                label.encode(&mut encoded);
+                b[5..].copy_from_slice(&encoded);
+            },
+            ExtendedCommunity::RouterMac { mac } => {
+                b[..2].copy_from_slice(&[0x06, 0x03]);
+                b[2..].copy_from_slice(&mac.0);
+            },
+            ExtendedCommunity::TrafficRateBytes { asn, rate } => {
+                b[..4].copy_from_slice(&[0x80, 0x06, (asn >> 8) as u8, asn as u8]);
+                b[4..].copy_from_slice(&rate.to_bits().to_be_bytes());
+            },
+            ExtendedCommunity::TrafficRatePackets { asn, rate } => {
+                b[..4].copy_from_slice(&[0x80, 0x0c, (asn >> 8) as u8, asn as u8]);
+                b[4..].copy_from_slice(&rate.to_bits().to_be_bytes());
+            },
+            ExtendedCommunity::TrafficAction { sample, terminal } => {
+                b[..2].copy_from_slice(&[0x80, 0x07]);
+                b[7] = (sample as u8) << 1 | terminal as u8;
+            },
+            ExtendedCommunity::Redirect { asn, value } => {
+                b[..4].copy_from_slice(&[0x80, 0x08, (asn >> 8) as u8, asn as u8]);
+                b[4..].copy_from_slice(&value.to_be_bytes());
+            },
+            ExtendedCommunity::RedirectIpv4 { address, value } => {
+                b[..2].copy_from_slice(&[0x81, 0x08]);
+                b[2..6].copy_from_slice(&address.octets());
+                b[6..].copy_from_slice(&value.to_be_bytes());
+            },
+            ExtendedCommunity::RedirectAs4 { asn, value } => {
+                b[..2].copy_from_slice(&[0x82, 0x08]);
+                b[2..6].copy_from_slice(&asn.to_be_bytes());
+                b[6..].copy_from_slice(&value.to_be_bytes());
+            },
+            ExtendedCommunity::TrafficMarking { dscp } => {
+                b[..2].copy_from_slice(&[0x80, 0x09]);
+                b[7] = dscp & 0x3f;
+            },
+            ExtendedCommunity::Other { value } => b = value,
+        }
+        b
+    }
+}
+
+impl fmt::Display for ExtendedCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExtendedCommunity::RouteTarget { target } => write!(f, "rt {}", target),
+            ExtendedCommunity::MacMobility { sticky, sequence } => write!(f, "mac-mobility {}{}", sequence, if sticky { " sticky" } else { "" }),
+            ExtendedCommunity::EsiLabel { single_active, label } => write!(f, "esi-label {}{}", label, if single_active { " single-active" } else { "" }),
+            ExtendedCommunity::RouterMac { mac } => write!(f, "router-mac {}", mac),
+            ExtendedCommunity::TrafficRateBytes { rate, .. } => write!(f, "rate-limit {}", rate),
+            ExtendedCommunity::TrafficRatePackets { rate, .. } => write!(f, "rate-limit-packets {}", rate),
+            ExtendedCommunity::TrafficAction { sample, terminal } => {
+                let actions: Vec<&str> = [(sample, "sample"), (terminal, "terminal")].iter().filter(|a| a.0).map(|a| a.1).collect();
+                write!(f, "action {}", if actions.is_empty() { "none".to_string() } else { actions.join(",") })
+            },
+            ExtendedCommunity::Redirect { asn, value } => write!(f, "redirect {}:{}", asn, value),
+            ExtendedCommunity::RedirectIpv4 { address, value } => write!(f, "redirect {}:{}", address, value),
+            ExtendedCommunity::RedirectAs4 { asn, value } => write!(f, "redirect {}:{}", asn, value),
+            ExtendedCommunity::TrafficMarking { dscp } => write!(f, "mark {}", dscp),
+            ExtendedCommunity::Other { value } => write!(f, "0x{}", value.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        }
+    }
+}
+
+named!(pub extended_community<&[u8], ExtendedCommunity>,
+    map!(take!(8), |b: &[u8]| ExtendedCommunity::from_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_community_test() {
+        let cases = [
+            ([0x06, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03], ExtendedCommunity::MacMobility { sticky: true, sequence: 3 }, "mac-mobility 3 sticky"),
+            ([0x06, 0x01, 0x01, 0x00, 0x00, 0x00, 0x06, 0x41], ExtendedCommunity::EsiLabel { single_active: true, label: Label::new(100) }, "esi-label 100 single-active"),
+            ([0x06, 0x03, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55], ExtendedCommunity::RouterMac { mac: "00:11:22:33:44:55".parse().unwrap() }, "router-mac 00:11:22:33:44:55"),
+            ([0x00, 0x02, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x01], ExtendedCommunity::RouteTarget { target: "65000:1".parse().unwrap() }, "rt 65000:1"),
+            ([0x01, 0x02, 0xc0, 0x00, 0x02, 0x01, 0x00, 0x05], ExtendedCommunity::RouteTarget { target: "192.0.2.1:5".parse().unwrap() }, "rt 192.0.2.1:5"),
+            ([0x02, 0x02, 0xfa, 0x56, 0xea, 0x00, 0x00, 0x01], ExtendedCommunity::RouteTarget { target: "4200000000:1".parse().unwrap() }, "rt 4200000000:1"),
+            ([0x80, 0x06, 0xfd, 0xe8, 0x44, 0x7a, 0x00, 0x00], ExtendedCommunity::TrafficRateBytes { asn: 65000, rate: 1000.0 }, "rate-limit 1000"),
+            ([0x80, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], ExtendedCommunity::TrafficRateBytes { asn: 0, rate: 0.0 }, "rate-limit 0"),
+            ([0x80, 0x0c, 0x00, 0x00, 0x42, 0xc8, 0x00, 0x00], ExtendedCommunity::TrafficRatePackets { asn: 0, rate: 100.0 }, "rate-limit-packets 100"),
+            ([0x80, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03], ExtendedCommunity::TrafficAction { sample: true, terminal: true }, "action sample,terminal"),
+            ([0x80, 0x08, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x64], ExtendedCommunity::Redirect { asn: 65000, value: 100 }, "redirect 65000:100"),
+            ([0x81, 0x08, 0xc0, 0x00, 0x02, 0x01, 0x00, 0x64], ExtendedCommunity::RedirectIpv4 { address: Ipv4Addr::new(192, 0, 2, 1), value: 100 }, "redirect 192.0.2.1:100"),
+            ([0x82, 0x08, 0x00, 0x03, 0x0d, 0x40, 0x00, 0x64], ExtendedCommunity::RedirectAs4 { asn: 200_000, value: 100 }, "redirect 200000:100"),
+            ([0x80, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2e], ExtendedCommunity::TrafficMarking { dscp: 46 }, "mark 46"),
+            ([0x00, 0x03, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x64], ExtendedCommunity::Other { value: [0x00, 0x03, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x64] }, "0x0003fde800000064"),
+        ];
+
+        for &(bytes, community, text) in &cases {
+            assert_eq!(extended_community(&bytes), Done(&b""[..], community));
+            assert_eq!(community.to_bytes(), bytes);
+            assert_eq!(community.to_string(), text);
+        }
+
+        assert!(extended_community(&[0x80, 0x09, 0x00]).is_incomplete());
+    }
+
+    #[test]
+    fn extended_community_reserved_test() {
+        // Reserved bits and unused bytes set, which are kept.
+        let cases = [
+            [0x06, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x03],
+            [0x06, 0x00, 0x01, 0x80, 0x00, 0x00, 0x00, 0x03],
+            [0x06, 0x01, 0x01, 0x00, 0x01, 0x00, 0x06, 0x41],
+            [0x06, 0x01, 0x81, 0x00, 0x00, 0x00, 0x06, 0x41],
+            [0x80, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07],
+            [0x80, 0x07, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03],
+            [0x80, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0xee],
+            [0x80, 0x09, 0x00, 0x00, 0x01, 0x00, 0x00, 0x2e],
+        ];
+
+        for &bytes in &cases {
+            assert_eq!(extended_community(&bytes), Done(&b""[..], ExtendedCommunity::Other { value: bytes }));
+            assert_eq!(ExtendedCommunity::from_bytes(bytes).to_bytes(), bytes);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+// Flow Specification NLRI (RFC 8955 for IPv4, RFC 8956 for IPv6), carried
+// in MP_REACH_NLRI and MP_UNREACH_NLRI with SAFI 133.
+//
+// A FlowSpec rule matches packets rather than routing to a prefix. It is
+// a list of components, each matching one field of a packet, in order of
+// their type codes. The prefixes are like those of any NLRI, except that
+// for IPv6 there's an offset, the number of leading bits to skip before
+// the pattern starts. The other components are lists of operators, each
+// comparing the field to a value. Numeric operators compare with <, >
+// and =, and bitmask operators test bits, for the TCP flags and fragment
+// bits. The operators are ORed together, except where one has its AND bit
+// set, which binds it to the one before.
+//
+// What to do with the matching traffic is said by extended communities,
+// see extended_community.rs.
+//
+// As text a rule is its components separated by spaces, each a name and
+// its operators, e.g.
+//
+//   dst 192.0.2.0/24 proto =6 dport =80,=443 len >=64&<=1500 tcp-flags =0x02
+//
+// with "," between ORed operators and "&" between ANDed ones. Bitmask
+// values are hex, and "!" in front negates the test. An IPv6 prefix with
+// an offset is written with the offset after an "@", e.g.
+// 2001:db8::/64@8.
+//
+// The length of a rule has 12 bits, so one that encodes to more than 4095
+// bytes can't be sent, and encoding it is an error. So is a prefix longer
+// than its address, or with an offset past its length or of 128, and a
+// component without any operators.
+//
+// A rule with anything in it that doesn't parse is an error as a whole,
+// as RFC 8955 section 4.2 says, rather than being cut short to the
+// components before, which would match more than was meant.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use nom::{be_u8, Needed};
+use nom::IResult::*;
+
+use super::*;
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct FlowSpecRule {
+    pub components: Vec<FlowSpecComponent>,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value", rename_all = "snake_case"))]
+pub enum FlowSpecComponent {
+    DestinationPrefix(FlowSpecPrefix),
+    SourcePrefix(FlowSpecPrefix),
+    // The next header for IPv6.
+    Protocol(Vec<NumericMatch>),
+    Port(Vec<NumericMatch>),
+    DestinationPort(Vec<NumericMatch>),
+    SourcePort(Vec<NumericMatch>),
+    IcmpType(Vec<NumericMatch>),
+    IcmpCode(Vec<NumericMatch>),
+    TcpFlags(Vec<BitmaskMatch>),
+    PacketLength(Vec<NumericMatch>),
+    Dscp(Vec<NumericMatch>),
+    Fragment(Vec<BitmaskMatch>),
+    // IPv6 only.
+    FlowLabel(Vec<NumericMatch>),
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FlowSpecPrefix {
+    pub address: IpAddr,
+    pub length: u8,
+    // Always zero for IPv4.
+    pub offset: u8,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NumericMatch {
+    // ANDed with the operator before, rather than ORed.
+    pub and: bool,
+    pub lt: bool,
+    pub gt: bool,
+    pub eq: bool,
+    pub value: u64,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BitmaskMatch {
+    pub and: bool,
+    pub not: bool,
+    // All of the bits have to be set, rather than any of them.
+    #[cfg_attr(feature = "serde", serde(rename = "match"))]
+    pub exact: bool,
+    pub value: u64,
+}
+
+impl FlowSpecComponent {
+    pub fn type_code(&self) -> u8 {
+        match *self {
+            FlowSpecComponent::DestinationPrefix(_) => 1,
+            FlowSpecComponent::SourcePrefix(_) => 2,
+            FlowSpecComponent::Protocol(_) => 3,
+            FlowSpecComponent::Port(_) => 4,
+            FlowSpecComponent::DestinationPort(_) => 5,
+            FlowSpecComponent::SourcePort(_) => 6,
+            FlowSpecComponent::IcmpType(_) => 7,
+            FlowSpecComponent::IcmpCode(_) => 8,
+            FlowSpecComponent::TcpFlags(_) => 9,
+            FlowSpecComponent::PacketLength(_) => 10,
+            FlowSpecComponent::Dscp(_) => 11,
+            FlowSpecComponent::Fragment(_) => 12,
+            FlowSpecComponent::FlowLabel(_) => 13,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            FlowSpecComponent::DestinationPrefix(_) => "dst",
+            FlowSpecComponent::SourcePrefix(_) => "src",
+            FlowSpecComponent::Protocol(_) => "proto",
+            FlowSpecComponent::Port(_) => "port",
+            FlowSpecComponent::DestinationPort(_) => "dport",
+            FlowSpecComponent::SourcePort(_) => "sport",
+            FlowSpecComponent::IcmpType(_) => "icmp-type",
+            FlowSpecComponent::IcmpCode(_) => "icmp-code",
+            FlowSpecComponent::TcpFlags(_) => "tcp-flags",
+            FlowSpecComponent::PacketLength(_) => "len",
+            FlowSpecComponent::Dscp(_) => "dscp",
+            FlowSpecComponent::Fragment(_) => "frag",
+            FlowSpecComponent::FlowLabel(_) => "flow-label",
+        }
+    }
+}
+
+impl fmt::Display for FlowSpecRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let components: Vec<String> = self.components.iter().map(|c| c.to_string()).collect();
+        f.write_str(&components.join(" "))
+    }
+}
+
+impl fmt::Display for FlowSpecComponent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FlowSpecComponent::DestinationPrefix(ref p) | FlowSpecComponent::SourcePrefix(ref p) => write!(f, "{} {}", self.name(), p),
+            FlowSpecComponent::TcpFlags(ref m) | FlowSpecComponent::Fragment(ref m) => write!(f, "{} {}", self.name(), format_matches(m)),
+            FlowSpecComponent::Protocol(ref m) | FlowSpecComponent::Port(ref m) | FlowSpecComponent::DestinationPort(ref m) |
+            FlowSpecComponent::SourcePort(ref m) | FlowSpecComponent::IcmpType(ref m) | FlowSpecComponent::IcmpCode(ref m) |
+            FlowSpecComponent::PacketLength(ref m) | FlowSpecComponent::Dscp(ref m) | FlowSpecComponent::FlowLabel(ref m) => {
+                write!(f, "{} {}", self.name(), format_matches(m))
+            },
+        }
+    }
+}
+
+impl fmt::Display for FlowSpecPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.length)?;
+        if self.offset != 0 {
+            write!(f, "@{}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+// The operators of a component, and whether each is ANDed with the one
+// before.
+trait Operator: fmt::Display {
+    fn and(&self) -> bool;
+}
+
+impl Operator for NumericMatch {
+    fn and(&self) -> bool {
+        self.and
+    }
+}
+
+impl Operator for BitmaskMatch {
+    fn and(&self) -> bool {
+        self.and
+    }
+}
+
+fn format_matches<M: Operator>(matches: &[M]) -> String {
+    let mut s = String::new();
+    for (i, m) in matches.iter().enumerate() {
+        if i > 0 {
+            s.push(if m.and() { '&' } else { ',' });
+        }
+        s.push_str(&m.to_string());
+    }
+    s
+}
+
+impl fmt::Display for NumericMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.lt, self.gt, self.eq) {
+            (false, false, false) => f.write_str("false"),
+            (true, true, true) => f.write_str("true"),
+            (true, false, false) => write!(f, "<{}", self.value),
+            (false, true, false) => write!(f, ">{}", self.value),
+            (false, false, true) => write!(f, "={}", self.value),
+            (true, false, true) => write!(f, "<={}", self.value),
+            (false, true, true) => write!(f, ">={}", self.value),
+            (true, true, false) => write!(f, "!={}", self.value),
+        }
+    }
+}
+
+impl fmt::Display for BitmaskMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{:#04x}", if self.not { "!" } else { "" }, if self.exact { "=" } else { "" }, self.value)
+    }
+}
+
+// Parsing. The AFI is needed for the prefixes, whose encoding differs
+// between IPv4 and IPv6.
+
+named_args!(pub flowspec_rule(afi: u16) <FlowSpecRule>,
+    do_parse!(
+        first: be_u8 >>
+        // Lengths of 240 and over take two bytes, with 0xf in the top
+        // four bits.
+        length: switch!(value!(first >= 0xf0),
+            true => map!(be_u8, |second: u8| (first as u16 & 0x0f) << 8 | second as u16) |
+            false => value!(first as u16)
+        ) >>
+        components: map_opt!(
+            flat_map!(take!(length), terminated!(many1!(complete!(call!(flowspec_component, afi))), eof!())),
+            |c: Vec<FlowSpecComponent>| match c.windows(2).all(|w| w[0].type_code() < w[1].type_code()) {
+                true => Some(c),
+                false => None,
+            }
+        ) >>
+        (FlowSpecRule { components })
+    )
+);
+
+named_args!(flowspec_component(afi: u16) <FlowSpecComponent>,
+    switch!(be_u8,
+        1 => map!(call!(flowspec_prefix, afi), FlowSpecComponent::DestinationPrefix) |
+        2 => map!(call!(flowspec_prefix, afi), FlowSpecComponent::SourcePrefix) |
+        3 => map!(numeric_matches, FlowSpecComponent::Protocol) |
+        4 => map!(numeric_matches, FlowSpecComponent::Port) |
+        5 => map!(numeric_matches, FlowSpecComponent::DestinationPort) |
+        6 => map!(numeric_matches, FlowSpecComponent::SourcePort) |
+        7 => map!(numeric_matches, FlowSpecComponent::IcmpType) |
+        8 => map!(numeric_matches, FlowSpecComponent::IcmpCode) |
+        9 => map!(bitmask_matches, FlowSpecComponent::TcpFlags) |
+        10 => map!(numeric_matches, FlowSpecComponent::PacketLength) |
+        11 => map!(numeric_matches, FlowSpecComponent::Dscp) |
+        12 => map!(bitmask_matches, FlowSpecComponent::Fragment) |
+        13 => cond_reduce!(afi != AFI_IPV4, map!(numeric_matches, FlowSpecComponent::FlowLabel))
+    )
+);
+
+named_args!(flowspec_prefix(afi: u16) <FlowSpecPrefix>,
+    switch!(value!(afi == AFI_IPV4),
+        true => do_parse!(
+            length: verify!(be_u8, |v: u8| v <= 32) >>
+            pattern: take!((length as usize).div_ceil(8)) >>
+            (FlowSpecPrefix { address: IpAddr::V4(Ipv4Addr::from((pattern_bits(pattern) >> 96) as u32)), length, offset: 0 })
+        ) |
+        false => do_parse!(
+            length: verify!(be_u8, |v: u8| v <= 128) >>
+            offset: verify!(be_u8, |v: u8| v < 128 && v <= length) >>
+            pattern: take!(((length - offset) as usize).div_ceil(8)) >>
+            (FlowSpecPrefix { address: IpAddr::V6(Ipv6Addr::from(pattern_bits(pattern).checked_shr(offset.into()).unwrap_or(0))), length, offset })
+        )
+    )
+);
+
+// The bytes of a pattern as the leading bits of a u128.
+fn pattern_bits(pattern: &[u8]) -> u128 {
+    let mut octets = [0u8; 16];
+    octets[..pattern.len()].copy_from_slice(pattern);
+    u128::from_be_bytes(octets)
+}
+
+// The operators of a component, each a byte holding the flags and the
+// length of the value that follows, up to the one with the end of list
+// bit set.
+fn operators(input: &[u8]) -> IResult<&[u8], Vec<(u8, u64)>> {
+    let mut operators = Vec::new();
+    let mut rest = input;
+
+    loop {
+        let op = match rest.first() {
+            Some(op) => *op,
+            None => return Incomplete(Needed::Unknown),
+        };
+        let length = 1 << ((op >> 4) & 0x3);
+        if rest.len() < 1 + length {
+            return Incomplete(Needed::Size(input.len() - rest.len() + 1 + length));
+        }
+        let value = rest[1..1 + length].iter().fold(0u64, |v, b| v << 8 | *b as u64);
+        operators.push((op, value));
+        rest = &rest[1 + length..];
+
+        if op & 0x80 != 0 {
+            return Done(rest, operators);
+        }
+    }
+}
+
+named!(numeric_matches<Vec<NumericMatch>>,
+    map!(operators, |ops: Vec<(u8, u64)>| ops.into_iter().map(|(op, value)| NumericMatch {
+        and: op & 0x40 != 0,
+        lt: op & 0x04 != 0,
+        gt: op & 0x02 != 0,
+        eq: op & 0x01 != 0,
+        value,
+    }).collect())
+);
+
+named!(bitmask_matches<Vec<BitmaskMatch>>,
+    map!(operators, |ops: Vec<(u8, u64)>| ops.into_iter().map(|(op, value)| BitmaskMatch {
+        and: op & 0x40 != 0,
+        not: op & 0x02 != 0,
+        exact: op & 0x01 != 0,
+        value,
+    }).collect())
+);
+
+// Encoding.
+
+impl FlowSpecRule {
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        let mut components = Vec::new();
+        for component in &self.components {
+            component.encode(&mut components)?;
+        }
+
+        match components.len() {
+            length if length < 0xf0 => out.push(length as u8),
+            length if length <= 0xfff => out.extend_from_slice(&(0xf000 | length as u16).to_be_bytes()),
+            length => return Err(format!("flowspec rule of {} bytes is too long", length)),
+        }
+        out.extend(components);
+        Ok(())
+    }
+}
+
+impl FlowSpecComponent {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        out.push(self.type_code());
+        match *self {
+            FlowSpecComponent::DestinationPrefix(ref p) | FlowSpecComponent::SourcePrefix(ref p) => p.encode(out)?,
+            FlowSpecComponent::TcpFlags(ref m) | FlowSpecComponent::Fragment(ref m) => {
+                encode_operators(m.iter().map(|m| ((m.and as u8) << 6 | (m.not as u8) << 1 | m.exact as u8, m.value)), out)?;
+            },
+            FlowSpecComponent::Protocol(ref m) | FlowSpecComponent::Port(ref m) | FlowSpecComponent::DestinationPort(ref m) |
+            FlowSpecComponent::SourcePort(ref m) | FlowSpecComponent::IcmpType(ref m) | FlowSpecComponent::IcmpCode(ref m) |
+            FlowSpecComponent::PacketLength(ref m) | FlowSpecComponent::Dscp(ref m) | FlowSpecComponent::FlowLabel(ref m) => {
+                encode_operators(m.iter().map(|m| ((m.and as u8) << 6 | (m.lt as u8) << 2 | (m.gt as u8) << 1 | m.eq as u8, m.value)), out)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl FlowSpecPrefix {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        out.push(self.length);
+        match self.address {
+            IpAddr::V4(address) if self.length <= 32 && self.offset == 0 => {
+                out.extend_from_slice(&address.octets()[..(self.length as usize).div_ceil(8)]);
+            },
+            IpAddr::V6(address) if self.length <= 128 && self.offset < 128 && self.offset <= self.length => {
+                out.push(self.offset);
+                let pattern = u128::from(address).checked_shl(self.offset.into()).unwrap_or(0).to_be_bytes();
+                out.extend_from_slice(&pattern[..((self.length - self.offset) as usize).div_ceil(8)]);
+            },
+            _ => return Err(format!("flowspec prefix {} is invalid", self)),
+        }
+        Ok(())
+    }
+}
+
+// Each value takes the fewest of 1, 2, 4 or 8 bytes it fits in. There
+// has to be at least one, for the end of list bit.
+fn encode_operators<I: ExactSizeIterator<Item = (u8, u64)>>(operators: I, out: &mut Vec<u8>) -> Result<(), String> {
+    let count = operators.len();
+    if count == 0 {
+        return Err("flowspec component without operators".to_string());
+    }
+    for (i, (op, value)) in operators.enumerate() {
+        let length: u8 = match value {
+            0..=0xff => 0,
+            0x100..=0xffff => 1,
+            0x1_0000..=0xffff_ffff => 2,
+            _ => 3,
+        };
+        let end = if i + 1 == count { 0x80 } else { 0 };
+        out.push(end | op | length << 4);
+        out.extend_from_slice(&value.to_be_bytes()[8 - (1 << length)..]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eq(value: u64) -> NumericMatch {
+        NumericMatch { and: false, lt: false, gt: false, eq: true, value }
+    }
+
+    #[test]
+    fn flowspec_rule_ipv4_test() {
+        // The example from RFC 8955 section 4.2.2.3, packets to
+        // 192.0.2.0/24 and TCP port 25, from 203.0.113.0/24.
+        let input = [
+            0x10, 0x01, 0x18, 0xc0, 0x00, 0x02, 0x02, 0x18, 0xcb, 0x00, 0x71, 0x03, 0x81, 0x06, 0x04, 0x81, 0x19,
+        ];
+        let rule = FlowSpecRule { components: vec![
+            FlowSpecComponent::DestinationPrefix(FlowSpecPrefix { address: "192.0.2.0".parse().unwrap(), length: 24, offset: 0 }),
+            FlowSpecComponent::SourcePrefix(FlowSpecPrefix { address: "203.0.113.0".parse().unwrap(), length: 24, offset: 0 }),
+            FlowSpecComponent::Protocol(vec![eq(6)]),
+            FlowSpecComponent::Port(vec![eq(25)]),
+        ] };
+        assert!(flowspec_rule(&input[..1], AFI_IPV4).is_incomplete());
+        assert_eq!(flowspec_rule(&input, AFI_IPV4), Done(&b""[..], rule.clone()));
+        assert_eq!(rule.to_string(), "dst 192.0.2.0/24 src 203.0.113.0/24 proto =6 port =25");
+
+        let mut encoded = Vec::new();
+        rule.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, input.to_vec());
+    }
+
+    #[test]
+    fn flowspec_operators_test() {
+        // Ports 1024 to 2048 or 8080, TCP SYN without ACK, and not a
+        // first fragment.
+        let rule = FlowSpecRule { components: vec![
+            FlowSpecComponent::DestinationPrefix(FlowSpecPrefix { address: "10.0.0.0".parse().unwrap(), length: 8, offset: 0 }),
+            FlowSpecComponent::DestinationPort(vec![
+                NumericMatch { and: false, lt: false, gt: true, eq: true, value: 1024 },
+                NumericMatch { and: true, lt: true, gt: false, eq: true, value: 2048 },
+                eq(8080),
+            ]),
+            FlowSpecComponent::TcpFlags(vec![
+                BitmaskMatch { and: false, not: false, exact: true, value: 0x02 },
+                BitmaskMatch { and: true, not: true, exact: false, value: 0x10 },
+            ]),
+            FlowSpecComponent::PacketLength(vec![NumericMatch { and: false, lt: true, gt: true, eq: false, value: 0x1_0000 }]),
+            FlowSpecComponent::Fragment(vec![BitmaskMatch { and: false, not: true, exact: false, value: 0x04 }]),
+        ] };
+        assert_eq!(rule.to_string(), "dst 10.0.0.0/8 dport >=1024&<=2048,=8080 tcp-flags =0x02&!0x10 len !=65536 frag !0x04");
+
+        let mut encoded = Vec::new();
+        rule.encode(&mut encoded).unwrap();
+        // The port operators, with two byte values.
+        assert_eq!(&encoded[4..14], &[0x05, 0x13, 0x04, 0x00, 0x55, 0x08, 0x00, 0x91, 0x1f, 0x90]);
+        assert_eq!(flowspec_rule(&encoded, AFI_IPV4), Done(&b""[..], rule));
+    }
+
+    #[test]
+    fn flowspec_rule_ipv6_test() {
+        // The offset skips the first 8 bits of the pattern.
+        let rule = FlowSpecRule { components: vec![
+            FlowSpecComponent::DestinationPrefix(FlowSpecPrefix { address: "2001:db8::".parse().unwrap(), length: 32, offset: 0 }),
+            FlowSpecComponent::SourcePrefix(FlowSpecPrefix { address: "0:db8::".parse().unwrap(), length: 32, offset: 8 }),
+            FlowSpecComponent::FlowLabel(vec![eq(0x12345)]),
+        ] };
+        assert_eq!(rule.to_string(), "dst 2001:db8::/32 src 0:db8::/32@8 flow-label =74565");
+
+        let mut encoded = Vec::new();
+        rule.encode(&mut encoded).unwrap();
+        assert_eq!(&encoded[..8], &[19, 0x01, 32, 0, 0x20, 0x01, 0x0d, 0xb8]);
+        assert_eq!(&encoded[8..14], &[0x02, 32, 8, 0x00, 0x0d, 0xb8]);
+        assert_eq!(flowspec_rule(&encoded, AFI_IPV6), Done(&b""[..], rule));
+
+        // A flow label isn't allowed for IPv4.
+        assert!(flowspec_rule(&[3, 13, 0x81, 0x01], AFI_IPV4).is_err());
+    }
+
+    #[test]
+    fn flowspec_rule_invalid_test() {
+        // Components out of order.
+        assert!(flowspec_rule(&[6, 0x03, 0x81, 0x06, 0x01, 0x08, 0x0a], AFI_IPV4).is_err());
+        // An unknown component type.
+        assert!(flowspec_rule(&[3, 0x0e, 0x81, 0x06], AFI_IPV4).is_err());
+        // Operators running past the end.
+        assert!(flowspec_rule(&[3, 0x03, 0x01, 0x06], AFI_IPV4).is_err());
+        // An offset of the whole IPv6 address, and one past the length.
+        assert!(flowspec_rule(&[3, 0x01, 128, 128], AFI_IPV6).is_err());
+        assert!(flowspec_rule(&[4, 0x01, 8, 16, 0x20], AFI_IPV6).is_err());
+        // A valid component followed by an unknown one, or a truncated one.
+        assert!(flowspec_rule(&[6, 0x01, 8, 10, 0x0e, 0x81, 0x06], AFI_IPV4).is_err());
+        assert!(flowspec_rule(&[5, 0x01, 8, 10, 0x03, 0x01], AFI_IPV4).is_err());
+    }
+
+    #[test]
+    fn flowspec_rule_encode_invalid_test() {
+        let prefix = |address: &str, length, offset| FlowSpecRule { components: vec![
+            FlowSpecComponent::DestinationPrefix(FlowSpecPrefix { address: address.parse().unwrap(), length, offset }),
+        ] };
+        assert!(prefix("2001:db8::", 128, 128).encode(&mut Vec::new()).is_err());
+        assert!(prefix("2001:db8::", 32, 64).encode(&mut Vec::new()).is_err());
+        assert!(prefix("2001:db8::", 129, 0).encode(&mut Vec::new()).is_err());
+        assert!(prefix("192.0.2.0", 33, 0).encode(&mut Vec::new()).is_err());
+        assert!(FlowSpecRule { components: vec![FlowSpecComponent::Protocol(vec![])] }.encode(&mut Vec::new()).is_err());
+
+        // The longest rule has 4095 bytes of components.
+        let rule = |count| FlowSpecRule { components: vec![FlowSpecComponent::Port(vec![eq(0); count])] };
+        let mut encoded = Vec::new();
+        rule(2047).encode(&mut encoded).unwrap();
+        assert_eq!(&encoded[..2], &[0xff, 0xff]);
+        assert_eq!(flowspec_rule(&encoded, AFI_IPV4), Done(&b""[..], rule(2047)));
+        assert!(rule(2048).encode(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,889 @@
+// The BGP finite state machine (RFC 4271 section 8) for one connection.
+//
+// This doesn't do any IO. The speaker feeds it events, a message
+// arriving or a timer expiring, and it returns the actions to take in
+// response. That keeps all of the protocol logic somewhere it can be
+// tested without sockets or clocks.
+//
+// Making the TCP connection is left to the speaker, so the Connect and
+// Active states, which are only about that, aren't here. A session
+// starts in Idle and moves to OpenSent once it's told the connection is
+// up.
+//
+// The hold and keepalive timers are kept here too, as deadlines read
+// from a Clock (timers.rs). The speaker should call poll() once the time
+// returned by next_deadline() has passed, which handles whichever timers
+// have expired.
+//
+// Prefix limits (prefix_limit.rs) are checked here as well, since going
+// over one closes the session. So are BGP Roles (role.rs), which are
+// agreed in the OPENs, and then mark the UPDATEs going in and out.
+
+use std::time::{Duration, Instant};
+
+use super::*;
+use super::mpls;
+use super::prefix_limit::{PrefixCounter, PrefixLimit, PrefixLimitEvent};
+use super::role;
+use super::timers::{Clock, SystemClock};
+
+// The hold time to use until the OPENs have been exchanged, as suggested
+// in RFC 4271 section 8.2.2.
+const LARGE_HOLD_TIME: u16 = 240;
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct SessionConfig {
+    pub local_as: u16,
+    pub remote_as: u16,
+    pub bgp_identifier: Ipv4Addr,
+    pub hold_time: u16,
+    pub prefix_limits: Vec<PrefixLimit>,
+    // Our role to the peer, if we're sending one. In strict mode the
+    // peer has to send its role too.
+    pub role: Option<BgpRole>,
+    pub strict_role: bool,
+    // Whether to send the Extended Message capability (RFC 8654).
+    pub extended_message: bool,
+    // Whether to send the 4-octet AS capability (RFC 6793). If the peer
+    // sends it too, AS numbers in UPDATEs are four octets, and the BGP
+    // identifier rules are relaxed.
+    pub four_octet_as: bool,
+    // The most labels we can receive for labeled families, sent in the
+    // Multiple Labels capability (RFC 8277) unless empty.
+    pub multiple_labels: Vec<LabelCount>,
+    // The member ASes of our confederation (RFC 5065), if we're in one.
+    // A peer with another AS is outside it, and the confederation
+    // segments are taken out of the AS paths sent to it.
+    pub confederation_members: Vec<u16>,
+    // Our address on the connection, if known. A peer can't send it to
+    // us as a NEXT_HOP.
+    pub local_address: Option<Ipv4Addr>,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum State {
+    Idle,
+    OpenSent,
+    OpenConfirm,
+    Established,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum Event {
+    TcpConnectionConfirmed,
+    TcpConnectionFails,
+    Message(BgpMessage),
+    // A message that didn't parse, with the error as CODE<<8|SUBCODE.
+    MessageError(u32),
+    HoldTimerExpires,
+    KeepaliveTimerExpires,
+    // Take the session down, with a Shutdown Communication (RFC 8203)
+    // saying why if there's one.
+    ManualStop(Option<String>),
+    // Collision detection picked this connection to close.
+    ConnectionCollision,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum Action {
+    Send(BgpMessage),
+    Established,
+    Update(Box<BgpUpdateMessage>),
+    // A prefix limit was crossed. If it was exceeded this comes with a
+    // Close, and the speaker should keep the session down for the
+    // limit's restart interval.
+    PrefixLimit(PrefixLimitEvent),
+    // The peer sent routes that leaked, going by the OTC attribute. They
+    // come in the Update as withdrawn.
+    RouteLeak(Vec<Ipv4Prefix>),
+    // The peer sent a NOTIFICATION, which always comes with a Close.
+    Notification(Box<BgpNotificationMessage>),
+    // Close the connection. The session is back in Idle.
+    Close,
+}
+
+pub struct Session<C: Clock = SystemClock> {
+    config: SessionConfig,
+    clock: C,
+    state: State,
+    hold_time: u16,
+    hold_deadline: Option<Instant>,
+    keepalive_deadline: Option<Instant>,
+    remote_open: Option<Box<BgpOpenMessage>>,
+    remote_role: Option<BgpRole>,
+    prefixes: PrefixCounter,
+}
+
+impl Session<SystemClock> {
+    pub fn new(config: SessionConfig) -> Session<SystemClock> {
+        Session::with_clock(config, SystemClock)
+    }
+}
+
+impl<C: Clock> Session<C> {
+    pub fn with_clock(config: SessionConfig, clock: C) -> Session<C> {
+        Session {
+            prefixes: PrefixCounter::new(config.prefix_limits.clone()),
+            config,
+            clock,
+            state: State::Idle,
+            hold_time: 0,
+            hold_deadline: None,
+            keepalive_deadline: None,
+            remote_open: None,
+            remote_role: None,
+        }
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    // The hold time in seconds, zero meaning there isn't one. This is the
+    // large initial hold time until the peer's OPEN arrives, and then the
+    // lower of ours and theirs.
+    pub fn hold_time(&self) -> u16 {
+        self.hold_time
+    }
+
+    // How often to send KEEPALIVEs, a third of the hold time. Zero until
+    // the hold time has been negotiated, or if it was negotiated as zero.
+    pub fn keepalive_time(&self) -> u16 {
+        match self.state {
+            State::OpenConfirm | State::Established => self.hold_time / 3,
+            _ => 0,
+        }
+    }
+
+    pub fn remote_open(&self) -> Option<&BgpOpenMessage> {
+        self.remote_open.as_deref()
+    }
+
+    // The longest message that can be sent or received, which is raised
+    // once both sides have sent the Extended Message capability.
+    pub fn max_message_length(&self) -> u16 {
+        let remote = self.remote_open.as_ref().is_some_and(|open| open.optional_parameters.iter().any(|p| match *p {
+            OptionalParameter::Capability(ref capabilities) => capabilities.contains(&CapabilityParameter::ExtendedMessage),
+        }));
+        match self.config.extended_message && remote {
+            true => MAX_EXTENDED_MESSAGE_LENGTH,
+            false => MAX_MESSAGE_LENGTH,
+        }
+    }
+
+    // Whether AS numbers in UPDATEs are four octets, which they are once
+    // both sides have sent the 4-octet AS capability.
+    pub fn four_octet_as(&self) -> bool {
+        self.remote_open.as_ref().is_some_and(|open| UpdateEncoding::negotiated(&self.local_open(), open).four_octet_as)
+    }
+
+    // The most labels we can send with a prefix of the family, one until
+    // the peer's OPEN arrives.
+    pub fn max_labels(&self, afi: u16, safi: u8) -> u8 {
+        match self.remote_open {
+            Some(ref open) => mpls::max_labels(&self.config.multiple_labels, open, afi, safi),
+            None => 1,
+        }
+    }
+
+    // The role the peer sent, once its OPEN has arrived.
+    pub fn remote_role(&self) -> Option<BgpRole> {
+        self.remote_role
+    }
+
+    // When poll() next needs calling, if there are any timers running.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match (self.hold_deadline, self.keepalive_deadline) {
+            (Some(hold), Some(keepalive)) => Some(::std::cmp::min(hold, keepalive)),
+            (hold, keepalive) => hold.or(keepalive),
+        }
+    }
+
+    // Handle any timers that have expired.
+    pub fn poll(&mut self) -> Vec<Action> {
+        let now = self.clock.now();
+
+        if self.hold_deadline.is_some_and(|d| d <= now) {
+            return self.handle(Event::HoldTimerExpires);
+        }
+        if self.keepalive_deadline.is_some_and(|d| d <= now) {
+            return self.handle(Event::KeepaliveTimerExpires);
+        }
+        vec![]
+    }
+
+    // Send a message that didn't come from the FSM, like an UPDATE. This
+    // is only possible once the session is established. Routes the role
+    // doesn't allow to be sent are dropped from an UPDATE.
+    pub fn send(&mut self, mut message: BgpMessage) -> Vec<Action> {
+        if let BgpMessage::Update(ref mut update) = message {
+            if !self.config.confederation_members.is_empty() && !self.config.confederation_members.contains(&self.config.remote_as) {
+                for attribute in &mut update.path_attributes {
+                    if let PathAttribute::AsPath(ref mut as_path) = attribute.attribute {
+                        **as_path = as_path.without_confederation();
+                    }
+                }
+            }
+        }
+
+        if let (Some(local), BgpMessage::Update(ref mut update)) = (self.config.role, &mut message) {
+            if !role::egress(update, local, self.config.local_as as u32) {
+                update.nlri.clear();
+                update.path_attributes.clear();
+                if update.withdrawn_routes.is_empty() {
+                    return vec![];
+                }
+            }
+        }
+
+        match self.state {
+            State::Established => {
+                self.restart_keepalive_timer();
+                vec![Action::Send(message)]
+            },
+            _ => vec![],
+        }
+    }
+
+    pub fn handle(&mut self, event: Event) -> Vec<Action> {
+        let received = matches!(event, Event::Message(_));
+        let hold_time = self.hold_time;
+
+        let actions = self.transition(event);
+
+        // Any message from the peer restarts the hold timer, as does a
+        // change of hold time. The keepalive timer is restarted by
+        // anything we send.
+        if self.state == State::Idle {
+            self.hold_deadline = None;
+            self.keepalive_deadline = None;
+        } else {
+            if received || self.hold_time != hold_time {
+                self.restart_hold_timer();
+            }
+            if self.hold_time != hold_time || actions.iter().any(|a| matches!(a, Action::Send(_))) {
+                self.restart_keepalive_timer();
+            }
+        }
+
+        actions
+    }
+
+    fn restart_hold_timer(&mut self) {
+        self.hold_deadline = match self.hold_time {
+            0 => None,
+            t => Some(self.clock.now() + Duration::from_secs(t as u64)),
+        };
+    }
+
+    fn restart_keepalive_timer(&mut self) {
+        self.keepalive_deadline = match self.keepalive_time() {
+            0 => None,
+            t => Some(self.clock.now() + Duration::from_secs(t as u64)),
+        };
+    }
+
+    fn transition(&mut self, event: Event) -> Vec<Action> {
+        match (self.state, event) {
+            (State::Idle, Event::TcpConnectionConfirmed) => {
+                self.state = State::OpenSent;
+                self.hold_time = LARGE_HOLD_TIME;
+                vec![Action::Send(self.open_message())]
+            },
+            (State::Idle, _) => vec![],
+
+            (_, Event::TcpConnectionFails) => self.close(vec![]),
+            (_, Event::Message(BgpMessage::Notification(notification))) => self.close(vec![Action::Notification(notification)]),
+            (_, Event::MessageError(code)) => self.close(vec![Action::Send(notification(code))]),
+            (_, Event::HoldTimerExpires) => self.close(vec![Action::Send(notification(HOLD_TIMER_EXPIRED << 8))]),
+            (_, Event::ManualStop(message)) => {
+                let notification = match message {
+                    Some(message) => BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, &message),
+                    None => BgpNotificationMessage::cease(CeaseSubcode::AdministrativeShutdown, vec![]),
+                };
+                self.close(vec![Action::Send(BgpMessage::Notification(Box::new(notification)))])
+            },
+            (_, Event::ConnectionCollision) => self.close(vec![Action::Send(cease(CeaseSubcode::ConnectionCollisionResolution))]),
+
+            (State::OpenSent, Event::Message(BgpMessage::Open(open))) => {
+                if open.my_autonomous_system != self.config.remote_as {
+                    return self.close(vec![Action::Send(notification(BAD_PEER_AS))]);
+                }
+
+                if !self.valid_identifier(&open) {
+                    return self.close(vec![Action::Send(notification(BAD_BGP_IDENTIFIER))]);
+                }
+
+                if let Some(local) = self.config.role {
+                    match role::negotiate(local, self.config.strict_role, &open) {
+                        Ok(remote) => self.remote_role = remote,
+                        Err(()) => return self.close(vec![Action::Send(notification(ROLE_MISMATCH))]),
+                    }
+                }
+
+                self.hold_time = ::std::cmp::min(self.config.hold_time, open.hold_time);
+                self.remote_open = Some(open);
+                self.state = State::OpenConfirm;
+                vec![Action::Send(BgpMessage::Keepalive)]
+            },
+            (State::OpenConfirm, Event::Message(BgpMessage::Keepalive)) => {
+                self.state = State::Established;
+                vec![Action::Established]
+            },
+            (State::Established, Event::Message(BgpMessage::Keepalive)) => vec![],
+            (State::Established, Event::Message(BgpMessage::Update(mut update))) => {
+                if update.next_hop().is_some_and(|next_hop| !is_host_address(next_hop) || Some(next_hop) == self.config.local_address) {
+                    return self.close(vec![Action::Send(notification(INVALID_NEXT_HOP_ATTRIBUTE))]);
+                }
+
+                let mut actions = vec![];
+                if let Some(local) = self.config.role {
+                    if !role::ingress(&mut update, local, self.config.remote_as as u32) {
+                        let leaked = ::std::mem::take(&mut update.nlri);
+                        update.withdrawn_routes.extend(leaked.iter().cloned());
+                        update.path_attributes.clear();
+                        actions.push(Action::RouteLeak(leaked));
+                    }
+                }
+                for event in self.prefixes.update(&update) {
+                    if let PrefixLimitEvent::Exceeded { ref limit, .. } = event {
+                        let notification = limit.notification();
+                        return self.close(vec![Action::PrefixLimit(event), Action::Send(notification)]);
+                    }
+                    actions.push(Action::PrefixLimit(event));
+                }
+                actions.push(Action::Update(update));
+                actions
+            },
+
+            (State::OpenConfirm, Event::KeepaliveTimerExpires) |
+            (State::Established, Event::KeepaliveTimerExpires) => vec![Action::Send(BgpMessage::Keepalive)],
+            (_, Event::KeepaliveTimerExpires) => vec![],
+
+            // Anything else is unexpected in the state we're in. The
+            // subcodes for this are from RFC 6608.
+            (state, _) => {
+                let subcode = match state {
+                    State::OpenSent => 1,
+                    State::OpenConfirm => 2,
+                    _ => 3,
+                };
+                self.close(vec![Action::Send(notification(FINITE_STATE_MACHINE_ERROR << 8 | subcode))])
+            },
+        }
+    }
+
+    // RFC 4271 wants the peer's BGP identifier to be a host address, and
+    // one that's the same as ours can only be a mistake. RFC 6286 relaxes
+    // that when both speakers have 4-octet AS support: any identifier but
+    // zero will do, and only an internal peer can't have ours.
+    fn valid_identifier(&self, open: &BgpOpenMessage) -> bool {
+        let four_octet_as = UpdateEncoding::negotiated(&self.local_open(), open).four_octet_as;
+        let ours = open.bgp_identifier == self.config.bgp_identifier;
+        let internal = self.config.remote_as == self.config.local_as;
+        match four_octet_as {
+            true => !open.bgp_identifier.is_unspecified() && (!ours || !internal),
+            false => is_host_address(open.bgp_identifier) && !ours,
+        }
+    }
+
+    fn close(&mut self, mut actions: Vec<Action>) -> Vec<Action> {
+        self.state = State::Idle;
+        self.hold_time = 0;
+        self.remote_open = None;
+        self.remote_role = None;
+        self.prefixes.clear();
+        actions.push(Action::Close);
+        actions
+    }
+
+    fn open_message(&self) -> BgpMessage {
+        BgpMessage::Open(Box::new(self.local_open()))
+    }
+
+    fn local_open(&self) -> BgpOpenMessage {
+        BgpOpenMessage {
+            version: 4,
+            my_autonomous_system: self.config.local_as,
+            hold_time: self.config.hold_time,
+            bgp_identifier: self.config.bgp_identifier,
+            optional_parameters: vec![OptionalParameter::Capability(vec![
+                CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi: 1, safi: 1 })),
+                CapabilityParameter::RouteRefresh,
+            ].into_iter()
+                .chain(if self.config.extended_message { Some(CapabilityParameter::ExtendedMessage) } else { None })
+                .chain(if self.config.four_octet_as {
+                    Some(CapabilityParameter::FourOctetAs(Box::new(FourOctetAsCapability { asn: self.config.local_as as u32 })))
+                } else { None })
+                .chain(if self.config.multiple_labels.is_empty() { None } else {
+                    Some(CapabilityParameter::MultipleLabels(Box::new(MultipleLabelsCapability { counts: self.config.multiple_labels.clone() })))
+                })
+                .chain(self.config.role.map(|role| CapabilityParameter::Role(Box::new(RoleCapability { role }))))
+                .collect())],
+        }
+    }
+}
+
+// A NOTIFICATION for an error code as CODE<<8|SUBCODE.
+pub fn notification(code: u32) -> BgpMessage {
+    BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: (code >> 8) as u8, error_subcode: code as u8, data: vec![] }))
+}
+
+pub fn cease(subcode: CeaseSubcode) -> BgpMessage {
+    BgpMessage::Notification(Box::new(BgpNotificationMessage::cease(subcode, vec![])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::timers::MockClock;
+
+    fn config() -> SessionConfig {
+        SessionConfig { local_as: 65000, remote_as: 65001, bgp_identifier: Ipv4Addr::new(10, 0, 0, 1), hold_time: 90, prefix_limits: vec![], role: None, strict_role: false, extended_message: false, four_octet_as: false, multiple_labels: vec![], confederation_members: vec![], local_address: None }
+    }
+
+    fn open(asn: u16, hold_time: u16) -> BgpMessage {
+        BgpMessage::Open(Box::new(BgpOpenMessage {
+            version: 4,
+            my_autonomous_system: asn,
+            hold_time,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 2),
+            optional_parameters: vec![],
+        }))
+    }
+
+    fn established() -> Session {
+        let mut session = Session::new(config());
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open(65001, 30)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+        session
+    }
+
+    #[test]
+    fn session_establish_test() {
+        let mut session = Session::new(config());
+        assert_eq!(session.state(), State::Idle);
+
+        match session.handle(Event::TcpConnectionConfirmed).as_slice() {
+            [Action::Send(BgpMessage::Open(ref open))] => {
+                assert_eq!(open.my_autonomous_system, 65000);
+                assert_eq!(open.hold_time, 90);
+            },
+            other => panic!("expected an open: {:?}", other),
+        }
+        assert_eq!(session.state(), State::OpenSent);
+        assert_eq!(session.hold_time(), 240);
+        assert_eq!(session.keepalive_time(), 0);
+
+        assert_eq!(session.handle(Event::Message(open(65001, 30))), vec![Action::Send(BgpMessage::Keepalive)]);
+        assert_eq!(session.state(), State::OpenConfirm);
+        assert_eq!(session.hold_time(), 30);
+        assert_eq!(session.keepalive_time(), 10);
+        assert_eq!(session.remote_open().map(|o| o.bgp_identifier), Some(Ipv4Addr::new(10, 0, 0, 2)));
+
+        assert_eq!(session.handle(Event::KeepaliveTimerExpires), vec![Action::Send(BgpMessage::Keepalive)]);
+        assert_eq!(session.handle(Event::Message(BgpMessage::Keepalive)), vec![Action::Established]);
+        assert_eq!(session.state(), State::Established);
+
+        let update = BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec![] };
+        assert_eq!(session.handle(Event::Message(BgpMessage::Update(Box::new(update)))),
+            vec![Action::Update(Box::new(BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec![] }))]);
+    }
+
+    #[test]
+    fn session_bad_peer_as_test() {
+        let mut session = Session::new(config());
+        session.handle(Event::TcpConnectionConfirmed);
+        assert_eq!(session.handle(Event::Message(open(65002, 30))), vec![Action::Send(notification(BAD_PEER_AS)), Action::Close]);
+        assert_eq!(session.state(), State::Idle);
+        assert_eq!(session.hold_time(), 0);
+    }
+
+    #[test]
+    fn session_bad_identifier_test() {
+        let handle_open = |config: SessionConfig, asn: u16, identifier: [u8; 4], four_octet_as: bool| {
+            let mut session = Session::new(config);
+            session.handle(Event::TcpConnectionConfirmed);
+            let capabilities = match four_octet_as {
+                true => vec![CapabilityParameter::FourOctetAs(Box::new(FourOctetAsCapability { asn: asn as u32 }))],
+                false => vec![],
+            };
+            session.handle(Event::Message(BgpMessage::Open(Box::new(BgpOpenMessage {
+                version: 4,
+                my_autonomous_system: asn,
+                hold_time: 30,
+                bgp_identifier: Ipv4Addr::from(identifier),
+                optional_parameters: vec![OptionalParameter::Capability(capabilities)],
+            }))))
+        };
+        let bad = vec![Action::Send(notification(BAD_BGP_IDENTIFIER)), Action::Close];
+        let internal = SessionConfig { remote_as: 65000, ..config() };
+
+        // Without 4-octet AS support the identifier has to be a host
+        // address other than ours.
+        assert_eq!(handle_open(config(), 65001, [240, 0, 0, 1], false), bad);
+        assert_eq!(handle_open(config(), 65001, [127, 0, 0, 1], false), bad);
+        assert_eq!(handle_open(config(), 65001, [10, 0, 0, 1], false), bad);
+        assert_eq!(handle_open(config(), 65001, [10, 0, 0, 2], false), vec![Action::Send(BgpMessage::Keepalive)]);
+
+        // Nor with it only on the peer's side.
+        assert_eq!(handle_open(config(), 65001, [240, 0, 0, 1], true), bad);
+        assert_eq!(handle_open(config(), 65001, [10, 0, 0, 1], true), bad);
+        assert_eq!(handle_open(internal.clone(), 65000, [10, 0, 0, 1], true), bad);
+        assert_eq!(handle_open(internal, 65000, [10, 0, 0, 2], true), vec![Action::Send(BgpMessage::Keepalive)]);
+
+        // With it on both sides anything but zero will do, and an
+        // external peer can even have ours.
+        let four_octet_as = SessionConfig { four_octet_as: true, ..config() };
+        let internal = SessionConfig { remote_as: 65000, ..four_octet_as.clone() };
+        assert_eq!(handle_open(four_octet_as.clone(), 65001, [240, 0, 0, 1], false), bad);
+        assert_eq!(handle_open(four_octet_as.clone(), 65001, [240, 0, 0, 1], true), vec![Action::Send(BgpMessage::Keepalive)]);
+        assert_eq!(handle_open(four_octet_as.clone(), 65001, [127, 0, 0, 1], true), vec![Action::Send(BgpMessage::Keepalive)]);
+        assert_eq!(handle_open(four_octet_as.clone(), 65001, [10, 0, 0, 1], true), vec![Action::Send(BgpMessage::Keepalive)]);
+        assert_eq!(handle_open(four_octet_as, 65001, [0, 0, 0, 0], true), bad);
+        assert_eq!(handle_open(internal.clone(), 65000, [10, 0, 0, 1], true), bad);
+        assert_eq!(handle_open(internal, 65000, [240, 0, 0, 1], true), vec![Action::Send(BgpMessage::Keepalive)]);
+    }
+
+    #[test]
+    fn session_invalid_next_hop_test() {
+        let update = |next_hop: [u8; 4]| BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::NextHop(Box::new(NextHopAttribute { next_hop: Ipv4Addr::from(next_hop) })),
+            }],
+            nlri: vec!["192.0.2.0/24".parse().unwrap()],
+        }));
+        let invalid = vec![Action::Send(notification(INVALID_NEXT_HOP_ATTRIBUTE)), Action::Close];
+
+        for next_hop in &[[0, 0, 0, 0], [127, 0, 0, 1], [224, 0, 0, 5], [255, 255, 255, 255], [10, 0, 0, 1]] {
+            let mut session = established();
+            session.config.local_address = Some(Ipv4Addr::new(10, 0, 0, 1));
+            assert_eq!(session.handle(Event::Message(update(*next_hop))), invalid, "{:?}", next_hop);
+            assert_eq!(session.state(), State::Idle);
+        }
+
+        let mut session = established();
+        session.config.local_address = Some(Ipv4Addr::new(10, 0, 0, 1));
+        match session.handle(Event::Message(update([10, 0, 0, 2])))[..] {
+            [Action::Update(_)] => {},
+            ref actions => panic!("unexpected actions: {:?}", actions),
+        }
+    }
+
+    #[test]
+    fn session_errors_test() {
+        let mut session = established();
+        assert_eq!(session.handle(Event::HoldTimerExpires), vec![Action::Send(notification(HOLD_TIMER_EXPIRED << 8)), Action::Close]);
+        assert_eq!(session.state(), State::Idle);
+
+        let mut session = established();
+        assert_eq!(session.handle(Event::Message(open(65001, 30))), vec![Action::Send(notification(FINITE_STATE_MACHINE_ERROR << 8 | 3)), Action::Close]);
+
+        let mut session = Session::new(config());
+        session.handle(Event::TcpConnectionConfirmed);
+        assert_eq!(session.handle(Event::Message(BgpMessage::Keepalive)), vec![Action::Send(notification(FINITE_STATE_MACHINE_ERROR << 8 | 1)), Action::Close]);
+
+        let mut session = established();
+        assert_eq!(session.handle(Event::MessageError(UNSUPPORTED_VERSION_NUMBER)), vec![Action::Send(notification(UNSUPPORTED_VERSION_NUMBER)), Action::Close]);
+
+        let mut session = established();
+        let shutdown = BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, "maintenance");
+        assert_eq!(session.handle(Event::Message(BgpMessage::Notification(Box::new(shutdown)))),
+            vec![Action::Notification(Box::new(BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, "maintenance"))), Action::Close]);
+
+        let mut session = established();
+        assert_eq!(session.handle(Event::ManualStop(None)), vec![Action::Send(cease(CeaseSubcode::AdministrativeShutdown)), Action::Close]);
+
+        let mut session = established();
+        assert_eq!(session.handle(Event::ManualStop(Some("maintenance".to_string()))),
+            vec![Action::Send(BgpMessage::Notification(Box::new(BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, "maintenance")))), Action::Close]);
+
+        let mut session = Session::new(config());
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open(65001, 30)));
+        assert_eq!(session.handle(Event::ConnectionCollision), vec![Action::Send(cease(CeaseSubcode::ConnectionCollisionResolution)), Action::Close]);
+
+        assert_eq!(session.handle(Event::Message(BgpMessage::Keepalive)), vec![]);
+    }
+
+    #[test]
+    fn session_prefix_limit_test() {
+        let limit = PrefixLimit { afi: 1, safi: 1, maximum: 2, warning_threshold: 50, restart_interval: None };
+        let mut session = Session::new(SessionConfig { prefix_limits: vec![limit.clone()], ..config() });
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open(65001, 30)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+
+        let update = |prefix: &str| BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec![prefix.parse().unwrap()] };
+
+        assert_eq!(session.handle(Event::Message(BgpMessage::Update(Box::new(update("10.0.0.0/8"))))), vec![
+            Action::PrefixLimit(PrefixLimitEvent::Warning { limit: limit.clone(), count: 1 }),
+            Action::Update(Box::new(update("10.0.0.0/8"))),
+        ]);
+        assert_eq!(session.handle(Event::Message(BgpMessage::Update(Box::new(update("10.1.0.0/16"))))), vec![Action::Update(Box::new(update("10.1.0.0/16")))]);
+        assert_eq!(session.handle(Event::Message(BgpMessage::Update(Box::new(update("10.2.0.0/16"))))), vec![
+            Action::PrefixLimit(PrefixLimitEvent::Exceeded { limit: limit.clone(), count: 3 }),
+            Action::Send(limit.notification()),
+            Action::Close,
+        ]);
+        assert_eq!(session.state(), State::Idle);
+    }
+
+    #[test]
+    fn session_role_test() {
+        let open_with_role = |role: BgpRole| match open(65001, 30) {
+            BgpMessage::Open(mut open) => {
+                open.optional_parameters = vec![OptionalParameter::Capability(vec![CapabilityParameter::Role(Box::new(RoleCapability { role }))])];
+                BgpMessage::Open(open)
+            },
+            _ => unreachable!(),
+        };
+        let customer = SessionConfig { role: Some(BgpRole::Customer), ..config() };
+
+        // Our OPEN has our role in it.
+        let mut session = Session::new(customer.clone());
+        match session.handle(Event::TcpConnectionConfirmed)[..] {
+            [Action::Send(BgpMessage::Open(ref open))] => assert_eq!(role::open_role(open), Ok(Some(BgpRole::Customer))),
+            ref actions => panic!("unexpected actions: {:?}", actions),
+        }
+
+        assert_eq!(session.handle(Event::Message(open_with_role(BgpRole::Customer))), vec![Action::Send(notification(ROLE_MISMATCH)), Action::Close]);
+
+        let mut session = Session::new(SessionConfig { strict_role: true, ..customer.clone() });
+        session.handle(Event::TcpConnectionConfirmed);
+        assert_eq!(session.handle(Event::Message(open(65001, 30))), vec![Action::Send(notification(ROLE_MISMATCH)), Action::Close]);
+
+        let mut session = Session::new(customer);
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open_with_role(BgpRole::Provider)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+        assert_eq!(session.remote_role(), Some(BgpRole::Provider));
+
+        // Routes from our provider are marked as only going to customers.
+        let update = |otc: Option<u32>| {
+            let mut update = BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec!["10.0.0.0/8".parse().unwrap()] };
+            if let Some(asn) = otc {
+                update.path_attributes.push(BgpPathAttribute {
+                    flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+                    attribute: PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn })),
+                });
+            }
+            update
+        };
+        assert_eq!(session.handle(Event::Message(BgpMessage::Update(Box::new(update(None))))), vec![Action::Update(Box::new(update(Some(65001))))]);
+
+        // So they can't be sent back up again.
+        assert_eq!(session.send(BgpMessage::Update(Box::new(update(Some(65001))))), vec![]);
+        assert_eq!(session.send(BgpMessage::Update(Box::new(update(None)))), vec![Action::Send(BgpMessage::Update(Box::new(update(None))))]);
+        let mut withdrawal = update(Some(65001));
+        withdrawal.withdrawn_routes.push("10.1.0.0/16".parse().unwrap());
+        assert_eq!(session.send(BgpMessage::Update(Box::new(withdrawal))), vec![Action::Send(BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec!["10.1.0.0/16".parse().unwrap()], path_attributes: vec![], nlri: vec![],
+        })))]);
+
+        // A customer sending a marked route has leaked it.
+        let mut session = Session::new(SessionConfig { role: Some(BgpRole::Provider), ..config() });
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open_with_role(BgpRole::Customer)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+        assert_eq!(session.handle(Event::Message(BgpMessage::Update(Box::new(update(Some(65002)))))), vec![
+            Action::RouteLeak(vec!["10.0.0.0/8".parse().unwrap()]),
+            Action::Update(Box::new(BgpUpdateMessage { withdrawn_routes: vec!["10.0.0.0/8".parse().unwrap()], path_attributes: vec![], nlri: vec![] })),
+        ]);
+    }
+
+    #[test]
+    fn session_extended_message_test() {
+        let open_with = |capabilities: Vec<CapabilityParameter>| match open(65001, 30) {
+            BgpMessage::Open(mut open) => {
+                open.optional_parameters = vec![OptionalParameter::Capability(capabilities)];
+                BgpMessage::Open(open)
+            },
+            _ => unreachable!(),
+        };
+
+        let mut session = Session::new(SessionConfig { extended_message: true, ..config() });
+        match session.handle(Event::TcpConnectionConfirmed)[..] {
+            [Action::Send(BgpMessage::Open(ref open))] => assert_eq!(open.optional_parameters[0], OptionalParameter::Capability(vec![
+                CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi: 1, safi: 1 })),
+                CapabilityParameter::RouteRefresh,
+                CapabilityParameter::ExtendedMessage,
+            ])),
+            ref actions => panic!("unexpected actions: {:?}", actions),
+        }
+        assert_eq!(session.max_message_length(), MAX_MESSAGE_LENGTH);
+        session.handle(Event::Message(open_with(vec![CapabilityParameter::ExtendedMessage])));
+        assert_eq!(session.max_message_length(), MAX_EXTENDED_MESSAGE_LENGTH);
+
+        // Both sides have to send it.
+        let mut session = Session::new(SessionConfig { extended_message: true, ..config() });
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open_with(vec![CapabilityParameter::RouteRefresh])));
+        assert_eq!(session.max_message_length(), MAX_MESSAGE_LENGTH);
+
+        let mut session = Session::new(config());
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open_with(vec![CapabilityParameter::ExtendedMessage])));
+        assert_eq!(session.max_message_length(), MAX_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn session_four_octet_as_test() {
+        let four_octet_as = CapabilityParameter::FourOctetAs(Box::new(FourOctetAsCapability { asn: 65001 }));
+        let open_with = |capabilities: Vec<CapabilityParameter>| match open(65001, 30) {
+            BgpMessage::Open(mut open) => {
+                open.optional_parameters = vec![OptionalParameter::Capability(capabilities)];
+                BgpMessage::Open(open)
+            },
+            _ => unreachable!(),
+        };
+
+        let mut session = Session::new(SessionConfig { four_octet_as: true, ..config() });
+        match session.handle(Event::TcpConnectionConfirmed)[..] {
+            [Action::Send(BgpMessage::Open(ref open))] => assert_eq!(open.optional_parameters[0], OptionalParameter::Capability(vec![
+                CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi: 1, safi: 1 })),
+                CapabilityParameter::RouteRefresh,
+                CapabilityParameter::FourOctetAs(Box::new(FourOctetAsCapability { asn: 65000 })),
+            ])),
+            ref actions => panic!("unexpected actions: {:?}", actions),
+        }
+        assert!(!session.four_octet_as());
+        session.handle(Event::Message(open_with(vec![four_octet_as.clone()])));
+        assert!(session.four_octet_as());
+
+        // Both sides have to send it.
+        let mut session = Session::new(config());
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open_with(vec![four_octet_as])));
+        assert!(!session.four_octet_as());
+    }
+
+    #[test]
+    fn session_multiple_labels_test() {
+        let counts = vec![LabelCount { afi: 1, safi: 4, count: 2 }];
+        let mut session = Session::new(SessionConfig { multiple_labels: counts.clone(), ..config() });
+        match session.handle(Event::TcpConnectionConfirmed)[..] {
+            [Action::Send(BgpMessage::Open(ref open))] => match open.optional_parameters[0] {
+                OptionalParameter::Capability(ref capabilities) => assert_eq!(capabilities[2],
+                    CapabilityParameter::MultipleLabels(Box::new(MultipleLabelsCapability { counts }))),
+            },
+            ref actions => panic!("unexpected actions: {:?}", actions),
+        }
+        assert_eq!(session.max_labels(1, 4), 1);
+
+        let remote = match open(65001, 30) {
+            BgpMessage::Open(mut open) => {
+                open.optional_parameters = vec![OptionalParameter::Capability(vec![
+                    CapabilityParameter::MultipleLabels(Box::new(MultipleLabelsCapability { counts: vec![LabelCount { afi: 1, safi: 4, count: 5 }] })),
+                ])];
+                BgpMessage::Open(open)
+            },
+            _ => unreachable!(),
+        };
+        session.handle(Event::Message(remote));
+        assert_eq!(session.max_labels(1, 4), 5);
+        assert_eq!(session.max_labels(2, 4), 1);
+    }
+
+    #[test]
+    fn session_confederation_test() {
+        let update = |as_path: Vec<AsPathSegment>| BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::AsPath(Box::new(AsPathAttribute { as_path })),
+            }],
+            nlri: vec!["10.0.0.0/8".parse().unwrap()],
+        }));
+        let confederated = vec![AsPathSegment::AsConfedSequence(vec![65000]), AsPathSegment::AsSequence(vec![100])];
+        let established = |members: Vec<u16>| {
+            let mut session = Session::new(SessionConfig { confederation_members: members, ..config() });
+            session.handle(Event::TcpConnectionConfirmed);
+            session.handle(Event::Message(open(65001, 30)));
+            session.handle(Event::Message(BgpMessage::Keepalive));
+            session
+        };
+
+        // The peer is a member, so sees the whole path.
+        assert_eq!(established(vec![65000, 65001]).send(update(confederated.clone())), vec![Action::Send(update(confederated.clone()))]);
+        // It isn't, so doesn't.
+        assert_eq!(established(vec![65000, 65002]).send(update(confederated.clone())), vec![Action::Send(update(vec![AsPathSegment::AsSequence(vec![100])]))]);
+        // We're not in a confederation at all.
+        assert_eq!(established(vec![]).send(update(confederated.clone())), vec![Action::Send(update(confederated))]);
+    }
+
+    #[test]
+    fn session_hold_timer_test() {
+        let clock = MockClock::new();
+        let mut session = Session::with_clock(config(), clock.clone());
+        assert_eq!(session.next_deadline(), None);
+
+        // The large hold time applies until the OPEN arrives.
+        session.handle(Event::TcpConnectionConfirmed);
+        assert_eq!(session.next_deadline(), Some(clock.now() + Duration::from_secs(240)));
+        clock.advance(Duration::from_secs(239));
+        assert_eq!(session.poll(), vec![]);
+
+        session.handle(Event::Message(open(65001, 30)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+
+        // Messages from the peer restart the hold timer.
+        clock.advance(Duration::from_secs(29));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+        clock.advance(Duration::from_secs(29));
+        assert!(!session.poll().contains(&Action::Close));
+
+        // Whereas sending KEEPALIVEs doesn't.
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(session.poll(), vec![Action::Send(notification(HOLD_TIMER_EXPIRED << 8)), Action::Close]);
+        assert_eq!(session.state(), State::Idle);
+        assert_eq!(session.next_deadline(), None);
+    }
+
+    #[test]
+    fn session_keepalive_timer_test() {
+        let clock = MockClock::new();
+        let mut session = Session::with_clock(config(), clock.clone());
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open(65001, 30)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+
+        let mut sent = 0;
+        for _ in 0..25 {
+            clock.advance(Duration::from_secs(1));
+            session.handle(Event::Message(BgpMessage::Keepalive));
+            sent += session.poll().len();
+        }
+        assert_eq!(sent, 2);
+
+        // Sending an UPDATE restarts the keepalive timer.
+        clock.advance(Duration::from_secs(9));
+        session.send(BgpMessage::Update(Box::new(BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec![] })));
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(session.poll(), vec![]);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(session.poll(), vec![Action::Send(BgpMessage::Keepalive)]);
+    }
+
+    #[test]
+    fn session_zero_hold_time_test() {
+        let clock = MockClock::new();
+        let mut session = Session::with_clock(config(), clock.clone());
+        session.handle(Event::TcpConnectionConfirmed);
+        session.handle(Event::Message(open(65001, 0)));
+        session.handle(Event::Message(BgpMessage::Keepalive));
+
+        assert_eq!(session.hold_time(), 0);
+        assert_eq!(session.keepalive_time(), 0);
+        assert_eq!(session.next_deadline(), None);
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(session.poll(), vec![]);
+    }
+}
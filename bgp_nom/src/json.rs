@@ -0,0 +1,429 @@
+// Serialization of parsed messages, enabled with the "serde" feature.
+//
+// The JSON produced is meant to be stable so it can be fed into other
+// tools. A message is an object with a "type" of "open", "update",
+// "notification" or "keepalive" and the message fields alongside it:
+//
+//   {"type":"update","withdrawn_routes":[],"path_attributes":[...],"nlri":["172.16.0.0/16"]}
+//
+// - Prefixes are strings in CIDR notation, e.g. "192.168.4.0/22", or
+//   with ADD-PATH objects holding the "prefix" and its "path_id".
+// - Path attributes are objects holding the "flags", the attribute
+//   "type" and its "value", e.g.
+//   {"flags":{"optional":false,...},"type":"local_pref","value":100}.
+//   ATOMIC_AGGREGATE has no value.
+// - The origin is one of "igp", "egp" or "incomplete".
+// - An AS path is an array of segments. Each is an object holding either
+//   a "sequence" or a "set" of AS numbers, e.g.
+//   [{"set":[500,500]},{"sequence":[65211]}], or within a confederation
+//   a "confed_sequence" or "confed_set".
+// - Communities are strings of the form "asn:value".
+// - Addresses, including the BGP identifier, are dotted quad strings.
+// - Capabilities are objects with a "type", e.g.
+//   {"type":"multiprotocol_extensions","afi":1,"safi":1}. A BGP Role is
+//   one of "provider", "customer", "route_server", "route_server_client"
+//   or "peer".
+// - MP_REACH_NLRI and MP_UNREACH_NLRI hold the "afi" and "safi", and the
+//   NLRI as an object keyed by their kind, e.g. {"flow_spec":[...]}, or
+//   {"unknown":"<hex>"} for families that aren't decoded. A FlowSpec rule
+//   is an array of components, each with a "type" and "value".
+// - Extended communities are objects with a "type", e.g.
+//   {"type":"traffic_rate_bytes","asn":0,"rate":0.0}.
+// - Route distinguishers and route targets are strings, e.g. "65000:1"
+//   or "192.0.2.1:5". A VPN prefix holds its "labels", "rd", "address"
+//   and "length", and a labeled unicast prefix the same without the
+//   "rd". Each label is an object with its "value", "traffic_class" and
+//   "bottom" of stack bit.
+// - EVPN routes are objects with a "type", e.g. "mac_ip_advertisement",
+//   and their fields. MAC addresses and ESIs are strings of hex bytes
+//   separated by colons, with an all zero ESI written as "0".
+// - BGP-LS NLRI are objects with a "type" of "node", "link" or "prefix"
+//   and their descriptors. IGP router IDs are strings, "192.0.2.1" for
+//   OSPF or "0000.0000.0001" for IS-IS. The BGP-LS attribute is an array
+//   of TLVs, each an object with a "type", e.g.
+//   {"type":"igp_metric","metric":10}, and TLVs that aren't decoded have
+//   their "tlv_type" and hex "value".
+// - Notification data is a string of hex digits, e.g. "feb0".
+//
+// Anything that can be serialized can be deserialized again, which is
+// handy for writing test messages by hand.
+//
+// Most of this is done with derives on the types themselves. This file
+// holds the few custom implementations, and the tests.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use serde::ser::SerializeStruct;
+
+use super::{Community, Ipv4Prefix};
+use super::evpn::{Esi, MacAddress};
+use super::link_state::IgpRouterId;
+use super::vpn::RouteDistinguisher;
+
+impl Serialize for Ipv4Prefix {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.path_id {
+            None => serializer.collect_str(self),
+            Some(path_id) => {
+                let mut state = serializer.serialize_struct("Ipv4Prefix", 2)?;
+                state.serialize_field("prefix", &self.to_string())?;
+                state.serialize_field("path_id", &path_id)?;
+                state.end()
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonPrefix {
+    Prefix(String),
+    WithPathId { prefix: String, path_id: u32 },
+}
+
+impl<'de> Deserialize<'de> for Ipv4Prefix {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Ipv4Prefix, D::Error> {
+        let (s, path_id) = match JsonPrefix::deserialize(deserializer)? {
+            JsonPrefix::Prefix(s) => (s, None),
+            JsonPrefix::WithPathId { prefix, path_id } => (prefix, Some(path_id)),
+        };
+        let prefix: Ipv4Prefix = s.parse().map_err(|_| D::Error::custom(format!("invalid prefix: {}", s)))?;
+        Ok(Ipv4Prefix { path_id, ..prefix })
+    }
+}
+
+impl Serialize for Community {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Community {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Community, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| D::Error::custom(format!("invalid community: {}", s)))
+    }
+}
+
+impl Serialize for RouteDistinguisher {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RouteDistinguisher {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RouteDistinguisher, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| D::Error::custom(format!("invalid route distinguisher: {}", s)))
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<MacAddress, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| D::Error::custom(format!("invalid MAC address: {}", s)))
+    }
+}
+
+impl Serialize for Esi {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Esi {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Esi, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| D::Error::custom(format!("invalid ESI: {}", s)))
+    }
+}
+
+impl Serialize for IgpRouterId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IgpRouterId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<IgpRouterId, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| D::Error::custom(format!("invalid IGP router ID: {}", s)))
+    }
+}
+
+// For raw bytes, like the notification data.
+pub mod hex {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&value.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return Err(D::Error::custom(format!("invalid hex: {}", s)));
+        }
+        (0..s.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| D::Error::custom(format!("invalid hex: {}", s))))
+            .collect()
+    }
+}
+
+// The ASPA records written by RPKI validators, see aspa.rs.
+#[derive(Deserialize)]
+pub struct AspaFile {
+    pub aspas: Vec<AspaRecord>,
+}
+
+#[derive(Deserialize)]
+pub struct AspaRecord {
+    #[serde(alias = "customer_asid", with = "asn")]
+    pub customer: u32,
+    #[serde(deserialize_with = "asns")]
+    pub providers: Vec<u32>,
+}
+
+// An AS number, or a string of one with or without "AS" in front.
+mod asn {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub enum Asn {
+        Number(u32),
+        String(String),
+    }
+
+    impl Asn {
+        pub fn value<E: Error>(self) -> Result<u32, E> {
+            match self {
+                Asn::Number(asn) => Ok(asn),
+                Asn::String(s) => s.trim_start_matches("AS").parse().map_err(|_| E::custom(format!("invalid AS number: {}", s))),
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        Asn::deserialize(deserializer)?.value()
+    }
+}
+
+fn asns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    Vec::<asn::Asn>::deserialize(deserializer)?.into_iter().map(|asn| asn.value()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    fn parse(input: &[u8]) -> BgpMessage {
+        match parse_bgp_message(input) {
+            Done(_, message) => message,
+            other => panic!("failed to parse: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn serialize_update_test() {
+        let message = parse(&include_bytes!("../assets/test_bgp_update1.bin")[..]);
+        let json = serde_json::to_value(&message).unwrap();
+
+        assert_eq!(json["type"], "update");
+        assert_eq!(json["nlri"], serde_json::json!(["172.16.0.0/16"]));
+        assert_eq!(json["withdrawn_routes"], serde_json::json!([]));
+
+        let attributes = json["path_attributes"].as_array().unwrap();
+        assert_eq!(attributes[0], serde_json::json!({
+            "flags": { "optional": false, "transitive": true, "partial": false, "extended_length": false },
+            "type": "origin",
+            "value": "incomplete",
+        }));
+        assert_eq!(attributes[1]["type"], "as_path");
+        assert_eq!(attributes[1]["value"], serde_json::json!([{ "set": [500, 500] }, { "sequence": [65211] }]));
+        assert_eq!(attributes[2]["value"], "192.168.0.15");
+        assert_eq!(attributes[3]["value"], 100);
+        assert_eq!(attributes[4]["type"], "atomic_aggregate");
+        assert!(attributes[4].get("value").is_none());
+        assert_eq!(attributes[5]["value"], serde_json::json!({ "aggregator_as": 65210, "aggregator_id": "192.168.0.10" }));
+        assert_eq!(attributes[6]["type"], "communities");
+        assert_eq!(attributes[6]["value"], serde_json::json!(["65215:1", "790:4", "340:250"]));
+    }
+
+    #[test]
+    fn serialize_open_test() {
+        let message = parse(&include_bytes!("../assets/test_bgp_open3.bin")[..]);
+        assert_eq!(serde_json::to_string(&message).unwrap(), concat!(
+            r#"{"type":"open","version":4,"my_autonomous_system":65200,"hold_time":180,"bgp_identifier":"10.20.3.1","optional_parameters":["#,
+            r#"{"capability":[{"type":"multiprotocol_extensions","afi":1,"safi":1}]},{"capability":[{"type":"unknown","code":128,"data":""}]},{"capability":[{"type":"route_refresh"}]}]}"#));
+    }
+
+    #[test]
+    fn serialize_role_test() {
+        let role = CapabilityParameter::Role(Box::new(RoleCapability { role: BgpRole::RouteServerClient }));
+        assert_eq!(serde_json::to_string(&role).unwrap(), r#"{"type":"role","role":"route_server_client"}"#);
+        let otc = PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn: 65001 }));
+        assert_eq!(serde_json::to_string(&otc).unwrap(), r#"{"type":"only_to_customer","value":65001}"#);
+        assert_eq!(serde_json::from_str::<PathAttribute>(r#"{"type":"only_to_customer","value":65001}"#).unwrap(), otc);
+    }
+
+    #[test]
+    fn serialize_flowspec_test() {
+        let attribute = PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute {
+            afi: AFI_IPV4,
+            safi: SAFI_FLOWSPEC,
+            next_hop: vec![],
+            nlri: MpNlri::FlowSpec(vec![FlowSpecRule { components: vec![
+                flowspec::FlowSpecComponent::DestinationPrefix(flowspec::FlowSpecPrefix { address: "192.0.2.0".parse().unwrap(), length: 24, offset: 0 }),
+                flowspec::FlowSpecComponent::TcpFlags(vec![flowspec::BitmaskMatch { and: false, not: false, exact: true, value: 2 }]),
+            ] }]),
+        }));
+        let json = serde_json::to_value(&attribute).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "mp_reach_nlri", "value": {
+            "afi": 1, "safi": 133, "next_hop": "",
+            "nlri": { "flow_spec": [[
+                { "type": "destination_prefix", "value": { "address": "192.0.2.0", "length": 24, "offset": 0 } },
+                { "type": "tcp_flags", "value": [{ "and": false, "not": false, "match": true, "value": 2 }] },
+            ]] },
+        } }));
+        assert_eq!(serde_json::from_value::<PathAttribute>(json).unwrap(), attribute);
+
+        let communities = PathAttribute::ExtendedCommunities(Box::new(ExtendedCommunitiesAttribute { communities: vec![
+            ExtendedCommunity::TrafficMarking { dscp: 46 },
+        ] }));
+        assert_eq!(serde_json::to_string(&communities).unwrap(), r#"{"type":"extended_communities","value":[{"type":"traffic_marking","dscp":46}]}"#);
+    }
+
+    #[test]
+    fn serialize_vpn_test() {
+        let rt = ExtendedCommunity::RouteTarget { target: "192.0.2.1:5".parse().unwrap() };
+        assert_eq!(serde_json::to_string(&rt).unwrap(), r#"{"type":"route_target","target":"192.0.2.1:5"}"#);
+        assert_eq!(serde_json::from_str::<ExtendedCommunity>(r#"{"type":"route_target","target":"192.0.2.1:5"}"#).unwrap(), rt);
+        assert!(serde_json::from_str::<ExtendedCommunity>(r#"{"type":"route_target","target":"192.0.2.1"}"#).is_err());
+
+        let prefix = vpn::VpnPrefix { labels: vec![mpls::Label::new(100)], rd: "65000:1".parse().unwrap(), address: "10.0.0.0".parse().unwrap(), length: 8 };
+        assert_eq!(serde_json::to_string(&prefix).unwrap(),
+            r#"{"labels":[{"value":100,"traffic_class":0,"bottom":true}],"rd":"65000:1","address":"10.0.0.0","length":8}"#);
+    }
+
+    #[test]
+    fn serialize_evpn_test() {
+        let route = EvpnRoute::MacIpAdvertisement {
+            rd: "192.0.2.1:1".parse().unwrap(),
+            esi: "0".parse().unwrap(),
+            ethernet_tag: 0,
+            mac: "00:11:22:33:44:55".parse().unwrap(),
+            ip: None,
+            labels: vec![mpls::Label::new(100)],
+        };
+        let json = serde_json::to_value(&route).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "type": "mac_ip_advertisement", "rd": "192.0.2.1:1", "esi": "0", "ethernet_tag": 0, "mac": "00:11:22:33:44:55", "ip": null,
+            "labels": [{ "value": 100, "traffic_class": 0, "bottom": true }],
+        }));
+        assert_eq!(serde_json::from_value::<EvpnRoute>(json).unwrap(), route);
+        assert!(serde_json::from_str::<evpn::MacAddress>(r#""00:11:22""#).is_err());
+    }
+
+    #[test]
+    fn serialize_link_state_test() {
+        let nlri = LinkStateNlri::Node {
+            protocol: 3,
+            identifier: 0,
+            node: link_state::NodeDescriptor { asn: Some(65000), router_id: "192.0.2.1".parse().ok(), ..Default::default() },
+        };
+        let json = serde_json::to_value(&nlri).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "type": "node", "protocol": 3, "identifier": 0,
+            "node": { "asn": 65000, "bgp_ls_id": null, "ospf_area": null, "router_id": "192.0.2.1", "other": [] },
+        }));
+        assert_eq!(serde_json::from_value::<LinkStateNlri>(json).unwrap(), nlri);
+
+        let attribute = PathAttribute::LinkState(Box::new(LinkStateAttribute { tlvs: vec![
+            LinkStateTlv::IgpMetric { metric: 10 },
+            LinkStateTlv::Unknown { tlv_type: 1200, value: vec![0xab] },
+        ] }));
+        let json = serde_json::to_value(&attribute).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "link_state", "value": [
+            { "type": "igp_metric", "metric": 10 },
+            { "type": "unknown", "tlv_type": 1200, "value": "ab" },
+        ] }));
+        assert_eq!(serde_json::from_value::<PathAttribute>(json).unwrap(), attribute);
+    }
+
+    #[test]
+    fn serialize_keepalive_notification_test() {
+        assert_eq!(serde_json::to_string(&BgpMessage::Keepalive).unwrap(), r#"{"type":"keepalive"}"#);
+
+        let message = parse(&include_bytes!("../assets/test_bgp_notification1.bin")[..]);
+        assert_eq!(serde_json::to_string(&message).unwrap(), r#"{"type":"notification","error_code":2,"error_subcode":2,"data":"feb0"}"#);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let fixtures: Vec<&[u8]> = vec![
+            &include_bytes!("../assets/test_bgp_open1.bin")[..],
+            &include_bytes!("../assets/test_bgp_open3.bin")[..],
+            &include_bytes!("../assets/test_bgp_keepalive1.bin")[..],
+            &include_bytes!("../assets/test_bgp_notification1.bin")[..],
+            &include_bytes!("../assets/test_bgp_update1.bin")[..],
+            &include_bytes!("../assets/test_bgp_update2.bin")[..],
+            &include_bytes!("../assets/test_bgp_update3.bin")[..],
+        ];
+
+        for fixture in fixtures {
+            let message = parse(fixture);
+            let json = serde_json::to_string(&message).unwrap();
+            assert_eq!(serde_json::from_str::<BgpMessage>(&json).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn deserialize_hand_written_test() {
+        let message: BgpMessage = serde_json::from_str(r#"{
+            "type": "update",
+            "withdrawn_routes": ["10.0.0.0/8"],
+            "path_attributes": [
+                { "flags": { "optional": false, "transitive": true, "partial": false, "extended_length": false }, "type": "origin", "value": "igp" },
+                { "flags": { "optional": false, "transitive": true, "partial": false, "extended_length": false }, "type": "as_path", "value": [{ "sequence": [65001, 65002] }] },
+                { "flags": { "optional": true, "transitive": true, "partial": false, "extended_length": false }, "type": "communities", "value": ["65001:666"] }
+            ],
+            "nlri": ["192.0.2.0/24"]
+        }"#).unwrap();
+
+        assert_eq!(message, BgpMessage::Update(Box::new(BgpUpdateMessage {
+            withdrawn_routes: vec![Ipv4Prefix { prefix: vec![10], length: 8, path_id: None }],
+            path_attributes: vec![
+                BgpPathAttribute {
+                    flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                    attribute: PathAttribute::Origin(Box::new(OriginAttribute { origin_code: BgpOriginCode::Igp })),
+                },
+                BgpPathAttribute {
+                    flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                    attribute: PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: vec![AsPathSegment::AsSequence(vec![65001, 65002])] })),
+                },
+                BgpPathAttribute {
+                    flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+                    attribute: PathAttribute::Communities(Box::new(CommunitiesAttribute { communities: vec![Community { asn: 65001, value: 666 }] })),
+                },
+            ],
+            nlri: vec![Ipv4Prefix { prefix: vec![192, 0, 2], length: 24, path_id: None }],
+        })));
+
+        assert!(serde_json::from_str::<Ipv4Prefix>(r#""192.0.2.0""#).is_err());
+
+        let prefix = Ipv4Prefix { prefix: vec![192, 0, 2], length: 24, path_id: Some(7) };
+        assert_eq!(serde_json::to_string(&prefix).unwrap(), r#"{"prefix":"192.0.2.0/24","path_id":7}"#);
+        assert_eq!(serde_json::from_str::<Ipv4Prefix>(r#"{"prefix":"192.0.2.0/24","path_id":7}"#).unwrap(), prefix);
+        assert!(serde_json::from_str::<Community>(r#""65001""#).is_err());
+    }
+}
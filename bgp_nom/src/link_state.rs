@@ -0,0 +1,936 @@
+// BGP Link State (RFC 9552), AFI 16388 SAFI 71, for carrying the IGP
+// topology, its nodes, links and prefixes, in BGP.
+//
+// Each NLRI has a type and length, the IGP it came from and the instance
+// identifier, and then TLVs describing what it is about:
+//
+//   1 Node              the node's descriptors (TLV 256)
+//   2 Link              the local (256) and remote (257) node's
+//                       descriptors, then those of the link itself
+//   3 IPv4 Prefix       the advertising node's descriptors, then the
+//   4 IPv6 Prefix       prefix's, always including the prefix itself
+//
+// Node descriptors are sub-TLVs for the AS, the BGP-LS identifier, the
+// OSPF area and the IGP router ID. That's four bytes for OSPF, or the six
+// byte system ID for IS-IS, with a pseudonode holding a little more.
+//
+// Everything else known about them, names, metrics, bandwidths and so on,
+// comes in the BGP-LS attribute (type 29), also a list of TLVs. The TLVs
+// we don't know are kept as they are in both.
+//
+// As text an NLRI is its type, protocol and descriptors, e.g.
+//
+//   link isis-l2 as 65000 router 0000.0000.0001 to as 65000 router 0000.0000.0002 local 10.0.0.1 remote 10.0.0.2
+//
+// `Topology` puts the NLRI and attributes received together into a graph
+// of nodes and the links between them, which can be written out as
+// GraphViz DOT or, with the serde feature, as JSON.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+use nom::{be_u8, be_u16, be_u32, be_u64};
+
+use super::*;
+
+pub const AFI_LINK_STATE: u16 = 16388;
+pub const SAFI_LINK_STATE: u8 = 71;
+
+// A TLV we don't know, or that's kept as it is.
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Hash,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Tlv {
+    pub tlv_type: u16,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+    pub value: Vec<u8>,
+}
+
+impl fmt::Display for Tlv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tlv {} {}", self.tlv_type, self.value.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+}
+
+fn encode_tlv(out: &mut Vec<u8>, tlv_type: u16, value: &[u8]) {
+    out.extend_from_slice(&tlv_type.to_be_bytes());
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+// The name of the protocol an NLRI came from.
+pub fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        1 => "isis-l1".to_string(),
+        2 => "isis-l2".to_string(),
+        3 => "ospfv2".to_string(),
+        4 => "direct".to_string(),
+        5 => "static".to_string(),
+        6 => "ospfv3".to_string(),
+        7 => "bgp".to_string(),
+        _ => format!("protocol-{}", protocol),
+    }
+}
+
+// An IGP router ID: an OSPF router ID, written as an address, an IS-IS
+// system ID, written as three groups of four hex digits, or a pseudonode
+// of either, adding the designated router's interface address for OSPF
+// or the pseudonode number for IS-IS.
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Hash,Clone)]
+pub struct IgpRouterId(pub Vec<u8>);
+
+impl fmt::Display for IgpRouterId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        let system_id = |b: &[u8]| format!("{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}", b[0], b[1], b[2], b[3], b[4], b[5]);
+        match b.len() {
+            4 => write!(f, "{}", Ipv4Addr::new(b[0], b[1], b[2], b[3])),
+            6 => f.write_str(&system_id(b)),
+            7 => write!(f, "{}.{:02x}", system_id(b), b[6]),
+            8 => write!(f, "{}:{}", Ipv4Addr::new(b[0], b[1], b[2], b[3]), Ipv4Addr::new(b[4], b[5], b[6], b[7])),
+            _ => write!(f, "0x{}", b.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        }
+    }
+}
+
+impl FromStr for IgpRouterId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<IgpRouterId, ()> {
+        if let Ok(address) = s.parse::<Ipv4Addr>() {
+            return Ok(IgpRouterId(address.octets().to_vec()));
+        }
+        let parts: Vec<&str> = s.split(':').collect();
+        if let [router, interface] = parts[..] {
+            let router = router.parse::<Ipv4Addr>().map_err(|_| ())?;
+            let interface = interface.parse::<Ipv4Addr>().map_err(|_| ())?;
+            return Ok(IgpRouterId([router.octets(), interface.octets()].concat()));
+        }
+
+        let parts: Vec<&str> = s.split('.').collect();
+        let hex = match parts.len() {
+            3 if parts.iter().all(|p| p.len() == 4) => parts.concat(),
+            4 if parts[..3].iter().all(|p| p.len() == 4) && parts[3].len() == 2 => parts.concat(),
+            _ => return Err(()),
+        };
+        let bytes = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16)).collect::<Result<Vec<u8>, _>>();
+        bytes.map(IgpRouterId).map_err(|_| ())
+    }
+}
+
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Hash,Clone,Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeDescriptor {
+    pub asn: Option<u32>,
+    pub bgp_ls_id: Option<u32>,
+    pub ospf_area: Option<u32>,
+    pub router_id: Option<IgpRouterId>,
+    pub other: Vec<Tlv>,
+}
+
+impl fmt::Display for NodeDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(asn) = self.asn {
+            parts.push(format!("as {}", asn));
+        }
+        if let Some(id) = self.bgp_ls_id {
+            parts.push(format!("bgp-ls-id {}", id));
+        }
+        if let Some(area) = self.ospf_area {
+            parts.push(format!("area {}", area));
+        }
+        if let Some(ref router_id) = self.router_id {
+            parts.push(format!("router {}", router_id));
+        }
+        parts.extend(self.other.iter().map(|t| t.to_string()));
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl NodeDescriptor {
+    fn from_tlvs(tlvs: Vec<(u16, &[u8])>) -> Option<NodeDescriptor> {
+        let mut node = NodeDescriptor::default();
+        for (tlv_type, value) in tlvs {
+            match (tlv_type, value.len()) {
+                (512, 4) => node.asn = Some(u32_from(value)),
+                (513, 4) => node.bgp_ls_id = Some(u32_from(value)),
+                (514, 4) => node.ospf_area = Some(u32_from(value)),
+                (515, 4) | (515, 6..=8) => node.router_id = Some(IgpRouterId(value.to_vec())),
+                (512..=515, _) => return None,
+                _ => node.other.push(Tlv { tlv_type, value: value.to_vec() }),
+            }
+        }
+        Some(node)
+    }
+
+    // As a TLV of the given type, 256 for local and 257 for remote.
+    pub fn encode(&self, tlv_type: u16, out: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        if let Some(asn) = self.asn {
+            encode_tlv(&mut value, 512, &asn.to_be_bytes());
+        }
+        if let Some(id) = self.bgp_ls_id {
+            encode_tlv(&mut value, 513, &id.to_be_bytes());
+        }
+        if let Some(area) = self.ospf_area {
+            encode_tlv(&mut value, 514, &area.to_be_bytes());
+        }
+        if let Some(ref router_id) = self.router_id {
+            encode_tlv(&mut value, 515, &router_id.0);
+        }
+        for tlv in &self.other {
+            encode_tlv(&mut value, tlv.tlv_type, &tlv.value);
+        }
+        encode_tlv(out, tlv_type, &value);
+    }
+}
+
+// What tells apart parallel links between the same two nodes.
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Hash,Clone,Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LinkDescriptor {
+    pub local_id: Option<u32>,
+    pub remote_id: Option<u32>,
+    pub local_address: Option<IpAddr>,
+    pub remote_address: Option<IpAddr>,
+    pub mt_id: Option<u16>,
+    pub other: Vec<Tlv>,
+}
+
+impl fmt::Display for LinkDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let (Some(local), Some(remote)) = (self.local_id, self.remote_id) {
+            parts.push(format!("ids {}/{}", local, remote));
+        }
+        if let Some(address) = self.local_address {
+            parts.push(format!("local {}", address));
+        }
+        if let Some(address) = self.remote_address {
+            parts.push(format!("remote {}", address));
+        }
+        if let Some(mt_id) = self.mt_id {
+            parts.push(format!("mt {}", mt_id));
+        }
+        parts.extend(self.other.iter().map(|t| t.to_string()));
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl LinkDescriptor {
+    fn from_tlvs(tlvs: Vec<(u16, &[u8])>) -> Option<LinkDescriptor> {
+        let mut link = LinkDescriptor::default();
+        for (tlv_type, value) in tlvs {
+            match (tlv_type, value.len()) {
+                (258, 8) => {
+                    link.local_id = Some(u32_from(&value[..4]));
+                    link.remote_id = Some(u32_from(&value[4..]));
+                },
+                (259, 4) => link.local_address = Some(prefix_address(AFI_IPV4, value)),
+                (260, 4) => link.remote_address = Some(prefix_address(AFI_IPV4, value)),
+                (261, 16) => link.local_address = Some(prefix_address(AFI_IPV6, value)),
+                (262, 16) => link.remote_address = Some(prefix_address(AFI_IPV6, value)),
+                (263, 2) => link.mt_id = Some(u16::from_be_bytes([value[0], value[1]]) & 0x0fff),
+                (258..=263, _) => return None,
+                _ => link.other.push(Tlv { tlv_type, value: value.to_vec() }),
+            }
+        }
+        Some(link)
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        if let (Some(local), Some(remote)) = (self.local_id, self.remote_id) {
+            encode_tlv(out, 258, &[local.to_be_bytes(), remote.to_be_bytes()].concat());
+        }
+        match self.local_address {
+            Some(IpAddr::V4(address)) => encode_tlv(out, 259, &address.octets()),
+            Some(IpAddr::V6(address)) => encode_tlv(out, 261, &address.octets()),
+            None => {},
+        }
+        match self.remote_address {
+            Some(IpAddr::V4(address)) => encode_tlv(out, 260, &address.octets()),
+            Some(IpAddr::V6(address)) => encode_tlv(out, 262, &address.octets()),
+            None => {},
+        }
+        if let Some(mt_id) = self.mt_id {
+            encode_tlv(out, 263, &mt_id.to_be_bytes());
+        }
+        for tlv in &self.other {
+            encode_tlv(out, tlv.tlv_type, &tlv.value);
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Hash,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PrefixDescriptor {
+    pub mt_id: Option<u16>,
+    pub ospf_route_type: Option<u8>,
+    pub address: IpAddr,
+    pub length: u8,
+    pub other: Vec<Tlv>,
+}
+
+impl fmt::Display for PrefixDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.length)?;
+        if let Some(mt_id) = self.mt_id {
+            write!(f, " mt {}", mt_id)?;
+        }
+        if let Some(route_type) = self.ospf_route_type {
+            write!(f, " route-type {}", route_type)?;
+        }
+        for tlv in &self.other {
+            write!(f, " {}", tlv)?;
+        }
+        Ok(())
+    }
+}
+
+impl PrefixDescriptor {
+    fn from_tlvs(afi: u16, tlvs: Vec<(u16, &[u8])>) -> Option<PrefixDescriptor> {
+        let (mut mt_id, mut ospf_route_type, mut prefix, mut other) = (None, None, None, Vec::new());
+        for (tlv_type, value) in tlvs {
+            match (tlv_type, value.len()) {
+                (263, 2) => mt_id = Some(u16::from_be_bytes([value[0], value[1]]) & 0x0fff),
+                (264, 1) => ospf_route_type = Some(value[0]),
+                (265, 1..=17) => {
+                    let length = value[0] as usize;
+                    if length > max_prefix_length(afi) || value.len() != 1 + length.div_ceil(8) {
+                        return None;
+                    }
+                    prefix = Some((prefix_address(afi, &value[1..]), length as u8));
+                },
+                (263..=265, _) => return None,
+                _ => other.push(Tlv { tlv_type, value: value.to_vec() }),
+            }
+        }
+        prefix.map(|(address, length)| PrefixDescriptor { mt_id, ospf_route_type, address, length, other })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        if let Some(mt_id) = self.mt_id {
+            encode_tlv(out, 263, &mt_id.to_be_bytes());
+        }
+        if let Some(route_type) = self.ospf_route_type {
+            encode_tlv(out, 264, &[route_type]);
+        }
+        let mut reachability = vec![self.length];
+        reachability.extend_from_slice(&prefix_bytes(self.address, self.length));
+        encode_tlv(out, 265, &reachability);
+        for tlv in &self.other {
+            encode_tlv(out, tlv.tlv_type, &tlv.value);
+        }
+    }
+}
+
+fn u32_from(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum LinkStateNlri {
+    Node {
+        protocol: u8,
+        identifier: u64,
+        node: NodeDescriptor,
+    },
+    Link {
+        protocol: u8,
+        identifier: u64,
+        local: NodeDescriptor,
+        remote: NodeDescriptor,
+        link: LinkDescriptor,
+    },
+    Prefix {
+        protocol: u8,
+        identifier: u64,
+        node: NodeDescriptor,
+        prefix: PrefixDescriptor,
+    },
+    Unknown {
+        nlri_type: u16,
+        #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+        value: Vec<u8>,
+    },
+}
+
+impl fmt::Display for LinkStateNlri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = |f: &mut fmt::Formatter, name: &str, protocol: u8, identifier: u64| match identifier {
+            0 => write!(f, "{} {}", name, protocol_name(protocol)),
+            _ => write!(f, "{} {} id {}", name, protocol_name(protocol), identifier),
+        };
+        match *self {
+            LinkStateNlri::Node { protocol, identifier, ref node } => {
+                header(f, "node", protocol, identifier)?;
+                write!(f, " {}", node)
+            },
+            LinkStateNlri::Link { protocol, identifier, ref local, ref remote, ref link } => {
+                header(f, "link", protocol, identifier)?;
+                write!(f, " {} to {}", local, remote)?;
+                match link.to_string().as_str() {
+                    "" => Ok(()),
+                    link => write!(f, " {}", link),
+                }
+            },
+            LinkStateNlri::Prefix { protocol, identifier, ref node, ref prefix } => {
+                header(f, "prefix", protocol, identifier)?;
+                write!(f, " {} {}", node, prefix)
+            },
+            LinkStateNlri::Unknown { nlri_type, ref value } => {
+                write!(f, "type {} {}", nlri_type, value.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+            },
+        }
+    }
+}
+
+named!(tlv<&[u8], (u16, &[u8])>,
+    do_parse!(
+        tlv_type: be_u16 >>
+        value: length_bytes!(be_u16) >>
+        ((tlv_type, value))
+    )
+);
+
+named!(tlvs<&[u8], Vec<(u16, &[u8])>>,
+    terminated!(many0!(complete!(tlv)), eof!())
+);
+
+named_args!(node_descriptor(tlv_type: u16) <NodeDescriptor>,
+    flat_map!(
+        preceded!(verify!(be_u16, |t: u16| t == tlv_type), length_bytes!(be_u16)),
+        map_opt!(tlvs, NodeDescriptor::from_tlvs)
+    )
+);
+
+named!(pub link_state_nlri<&[u8], LinkStateNlri>,
+    do_parse!(
+        nlri_type: be_u16 >>
+        length: be_u16 >>
+        nlri: flat_map!(take!(length), switch!(value!(nlri_type),
+            1 => call!(node_nlri) |
+            2 => call!(link_nlri) |
+            3 => call!(prefix_nlri, AFI_IPV4) |
+            4 => call!(prefix_nlri, AFI_IPV6) |
+            _ => map!(call!(nom::rest), |r: &[u8]| LinkStateNlri::Unknown { nlri_type, value: r.to_vec() })
+        )) >>
+        (nlri)
+    )
+);
+
+named!(node_nlri<&[u8], LinkStateNlri>,
+    do_parse!(
+        protocol: be_u8 >>
+        identifier: be_u64 >>
+        node: call!(node_descriptor, 256) >>
+        eof!() >>
+        (LinkStateNlri::Node { protocol, identifier, node })
+    )
+);
+
+named!(link_nlri<&[u8], LinkStateNlri>,
+    do_parse!(
+        protocol: be_u8 >>
+        identifier: be_u64 >>
+        local: call!(node_descriptor, 256) >>
+        remote: call!(node_descriptor, 257) >>
+        link: map_opt!(tlvs, LinkDescriptor::from_tlvs) >>
+        (LinkStateNlri::Link { protocol, identifier, local, remote, link })
+    )
+);
+
+named_args!(prefix_nlri(afi: u16) <LinkStateNlri>,
+    do_parse!(
+        protocol: be_u8 >>
+        identifier: be_u64 >>
+        node: call!(node_descriptor, 256) >>
+        prefix: map_opt!(tlvs, |tlvs| PrefixDescriptor::from_tlvs(afi, tlvs)) >>
+        (LinkStateNlri::Prefix { protocol, identifier, node, prefix })
+    )
+);
+
+impl LinkStateNlri {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        let nlri_type = match *self {
+            LinkStateNlri::Node { protocol, identifier, ref node } => {
+                value.push(protocol);
+                value.extend_from_slice(&identifier.to_be_bytes());
+                node.encode(256, &mut value);
+                1
+            },
+            LinkStateNlri::Link { protocol, identifier, ref local, ref remote, ref link } => {
+                value.push(protocol);
+                value.extend_from_slice(&identifier.to_be_bytes());
+                local.encode(256, &mut value);
+                remote.encode(257, &mut value);
+                link.encode(&mut value);
+                2
+            },
+            LinkStateNlri::Prefix { protocol, identifier, ref node, ref prefix } => {
+                value.push(protocol);
+                value.extend_from_slice(&identifier.to_be_bytes());
+                node.encode(256, &mut value);
+                prefix.encode(&mut value);
+                match prefix.address {
+                    IpAddr::V4(_) => 3,
+                    IpAddr::V6(_) => 4,
+                }
+            },
+            LinkStateNlri::Unknown { nlri_type, value: ref unknown } => {
+                value.extend_from_slice(unknown);
+                nlri_type
+            },
+        };
+        encode_tlv(out, nlri_type, &value);
+    }
+}
+
+// The TLVs of the BGP-LS attribute. Bandwidths are in bytes per second.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum LinkStateTlv {
+    NodeFlags { flags: u8 },
+    NodeName { name: String },
+    IsisArea {
+        #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+        area: Vec<u8>,
+    },
+    LocalRouterId { address: IpAddr },
+    RemoteRouterId { address: IpAddr },
+    AdminGroup { groups: u32 },
+    MaxBandwidth { bandwidth: f32 },
+    MaxReservableBandwidth { bandwidth: f32 },
+    // For each of the eight priorities.
+    UnreservedBandwidth { bandwidth: Vec<f32> },
+    TeMetric { metric: u32 },
+    IgpMetric { metric: u32 },
+    LinkName { name: String },
+    IgpFlags { flags: u8 },
+    PrefixMetric { metric: u32 },
+    Unknown {
+        tlv_type: u16,
+        #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+        value: Vec<u8>,
+    },
+}
+
+impl fmt::Display for LinkStateTlv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkStateTlv::NodeFlags { flags } => write!(f, "node-flags 0x{:02x}", flags),
+            LinkStateTlv::NodeName { ref name } => write!(f, "name {}", name),
+            LinkStateTlv::IsisArea { ref area } => write!(f, "isis-area {}", area.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+            LinkStateTlv::LocalRouterId { address } => write!(f, "router-id {}", address),
+            LinkStateTlv::RemoteRouterId { address } => write!(f, "remote-router-id {}", address),
+            LinkStateTlv::AdminGroup { groups } => write!(f, "admin-group 0x{:08x}", groups),
+            LinkStateTlv::MaxBandwidth { bandwidth } => write!(f, "max-bandwidth {}", bandwidth),
+            LinkStateTlv::MaxReservableBandwidth { bandwidth } => write!(f, "max-reservable-bandwidth {}", bandwidth),
+            LinkStateTlv::UnreservedBandwidth { ref bandwidth } => {
+                write!(f, "unreserved-bandwidth {}", bandwidth.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(","))
+            },
+            LinkStateTlv::TeMetric { metric } => write!(f, "te-metric {}", metric),
+            LinkStateTlv::IgpMetric { metric } => write!(f, "metric {}", metric),
+            LinkStateTlv::LinkName { ref name } => write!(f, "link-name {}", name),
+            LinkStateTlv::IgpFlags { flags } => write!(f, "igp-flags 0x{:02x}", flags),
+            LinkStateTlv::PrefixMetric { metric } => write!(f, "prefix-metric {}", metric),
+            LinkStateTlv::Unknown { tlv_type, ref value } => {
+                write!(f, "tlv {} {}", tlv_type, value.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+            },
+        }
+    }
+}
+
+impl LinkStateTlv {
+    // Those that are the wrong length are kept as unknown.
+    fn from_tlv(tlv_type: u16, value: &[u8]) -> LinkStateTlv {
+        let f32_from = |b: &[u8]| f32::from_bits(u32_from(b));
+        match (tlv_type, value.len()) {
+            (1024, 1) => LinkStateTlv::NodeFlags { flags: value[0] },
+            (1026, _) if std::str::from_utf8(value).is_ok() => LinkStateTlv::NodeName { name: String::from_utf8_lossy(value).into_owned() },
+            (1027, 1..=13) => LinkStateTlv::IsisArea { area: value.to_vec() },
+            (1028, 4) | (1029, 16) => LinkStateTlv::LocalRouterId { address: prefix_address(if value.len() == 4 { AFI_IPV4 } else { AFI_IPV6 }, value) },
+            (1030, 4) | (1031, 16) => LinkStateTlv::RemoteRouterId { address: prefix_address(if value.len() == 4 { AFI_IPV4 } else { AFI_IPV6 }, value) },
+            (1088, 4) => LinkStateTlv::AdminGroup { groups: u32_from(value) },
+            (1089, 4) => LinkStateTlv::MaxBandwidth { bandwidth: f32_from(value) },
+            (1090, 4) => LinkStateTlv::MaxReservableBandwidth { bandwidth: f32_from(value) },
+            (1091, 32) => LinkStateTlv::UnreservedBandwidth { bandwidth: value.chunks(4).map(f32_from).collect() },
+            (1092, 4) => LinkStateTlv::TeMetric { metric: u32_from(value) },
+            (1095, 1..=3) => LinkStateTlv::IgpMetric { metric: value.iter().fold(0, |m, b| m << 8 | *b as u32) },
+            (1098, _) if std::str::from_utf8(value).is_ok() => LinkStateTlv::LinkName { name: String::from_utf8_lossy(value).into_owned() },
+            (1152, 1) => LinkStateTlv::IgpFlags { flags: value[0] },
+            (1155, 4) => LinkStateTlv::PrefixMetric { metric: u32_from(value) },
+            _ => LinkStateTlv::Unknown { tlv_type, value: value.to_vec() },
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let address_tlv = |v4: u16, address: IpAddr| match address {
+            IpAddr::V4(address) => (v4, address.octets().to_vec()),
+            IpAddr::V6(address) => (v4 + 1, address.octets().to_vec()),
+        };
+        let (tlv_type, value) = match *self {
+            LinkStateTlv::NodeFlags { flags } => (1024, vec![flags]),
+            LinkStateTlv::NodeName { ref name } => (1026, name.as_bytes().to_vec()),
+            LinkStateTlv::IsisArea { ref area } => (1027, area.clone()),
+            LinkStateTlv::LocalRouterId { address } => address_tlv(1028, address),
+            LinkStateTlv::RemoteRouterId { address } => address_tlv(1030, address),
+            LinkStateTlv::AdminGroup { groups } => (1088, groups.to_be_bytes().to_vec()),
+            LinkStateTlv::MaxBandwidth { bandwidth } => (1089, bandwidth.to_bits().to_be_bytes().to_vec()),
+            LinkStateTlv::MaxReservableBandwidth { bandwidth } => (1090, bandwidth.to_bits().to_be_bytes().to_vec()),
+            LinkStateTlv::UnreservedBandwidth { ref bandwidth } => (1091, bandwidth.iter().flat_map(|b| b.to_bits().to_be_bytes().to_vec()).collect()),
+            LinkStateTlv::TeMetric { metric } => (1092, metric.to_be_bytes().to_vec()),
+            // The IGP metric is one byte for IS-IS narrow metrics, two for
+            // OSPF and three for IS-IS wide metrics. Which isn't kept, so
+            // it's sent in as few of two or three bytes as it fits.
+            LinkStateTlv::IgpMetric { metric } => match metric {
+                0..=0xffff => (1095, (metric as u16).to_be_bytes().to_vec()),
+                _ => (1095, metric.to_be_bytes()[1..].to_vec()),
+            },
+            LinkStateTlv::LinkName { ref name } => (1098, name.as_bytes().to_vec()),
+            LinkStateTlv::IgpFlags { flags } => (1152, vec![flags]),
+            LinkStateTlv::PrefixMetric { metric } => (1155, metric.to_be_bytes().to_vec()),
+            LinkStateTlv::Unknown { tlv_type, ref value } => (tlv_type, value.clone()),
+        };
+        encode_tlv(out, tlv_type, &value);
+    }
+}
+
+named!(pub link_state_tlvs<&[u8], Vec<LinkStateTlv>>,
+    map!(tlvs, |tlvs: Vec<(u16, &[u8])>| tlvs.into_iter().map(|(t, v)| LinkStateTlv::from_tlv(t, v)).collect())
+);
+
+// A node of the topology with what its Node NLRI's attribute said about
+// it, and the prefixes it advertises.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TopologyNode {
+    pub protocol: u8,
+    pub node: NodeDescriptor,
+    pub attributes: Vec<LinkStateTlv>,
+    pub prefixes: Vec<TopologyPrefix>,
+}
+
+impl TopologyNode {
+    fn new(protocol: u8, node: &NodeDescriptor) -> TopologyNode {
+        TopologyNode { protocol, node: node.clone(), attributes: vec![], prefixes: vec![] }
+    }
+
+    // The node's name if it has one, otherwise its descriptors.
+    pub fn name(&self) -> String {
+        self.attributes.iter().filter_map(|a| match *a {
+            LinkStateTlv::NodeName { ref name } => Some(name.clone()),
+            _ => None,
+        }).next().unwrap_or_else(|| self.node.to_string())
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TopologyPrefix {
+    pub prefix: PrefixDescriptor,
+    pub attributes: Vec<LinkStateTlv>,
+}
+
+// A link, in one direction: each end advertises its own.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TopologyLink {
+    pub protocol: u8,
+    pub local: NodeDescriptor,
+    pub remote: NodeDescriptor,
+    pub link: LinkDescriptor,
+    pub attributes: Vec<LinkStateTlv>,
+}
+
+impl TopologyLink {
+    pub fn metric(&self) -> Option<u32> {
+        self.attributes.iter().filter_map(|a| match *a {
+            LinkStateTlv::IgpMetric { metric } => Some(metric),
+            _ => None,
+        }).next()
+    }
+}
+
+// The graph built from the BGP-LS routes received. A node only heard of
+// through links or prefixes, without a Node NLRI of its own yet, is
+// there without attributes.
+#[derive(Debug,Default)]
+pub struct Topology {
+    nodes: BTreeMap<NodeDescriptor, TopologyNode>,
+    links: BTreeMap<(NodeDescriptor, NodeDescriptor, LinkDescriptor), TopologyLink>,
+}
+
+impl Topology {
+    pub fn new() -> Topology {
+        Topology::default()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &TopologyNode> {
+        self.nodes.values()
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = &TopologyLink> {
+        self.links.values()
+    }
+
+    pub fn node(&self, node: &NodeDescriptor) -> Option<&TopologyNode> {
+        self.nodes.get(node)
+    }
+
+    // Add the BGP-LS routes announced in an UPDATE, with the attribute
+    // that came with them, and remove the withdrawn ones.
+    pub fn update(&mut self, update: &BgpUpdateMessage) {
+        let attributes = update.path_attributes.iter().filter_map(|a| match a.attribute {
+            PathAttribute::LinkState(ref a) => Some(a.tlvs.clone()),
+            _ => None,
+        }).next().unwrap_or_default();
+
+        for attribute in &update.path_attributes {
+            match attribute.attribute {
+                PathAttribute::MpReachNlri(ref mp) => {
+                    if let MpNlri::LinkState(ref nlri) = mp.nlri {
+                        for nlri in nlri {
+                            self.add(nlri, &attributes);
+                        }
+                    }
+                },
+                PathAttribute::MpUnreachNlri(ref mp) => {
+                    if let MpNlri::LinkState(ref nlri) = mp.withdrawn_routes {
+                        for nlri in nlri {
+                            self.remove(nlri);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn add(&mut self, nlri: &LinkStateNlri, attributes: &[LinkStateTlv]) {
+        match *nlri {
+            LinkStateNlri::Node { protocol, ref node, .. } => {
+                let entry = self.nodes.entry(node.clone()).or_insert_with(|| TopologyNode::new(protocol, node));
+                entry.protocol = protocol;
+                entry.attributes = attributes.to_vec();
+            },
+            LinkStateNlri::Link { protocol, ref local, ref remote, ref link, .. } => {
+                for node in &[local, remote] {
+                    self.nodes.entry((*node).clone()).or_insert_with(|| TopologyNode::new(protocol, node));
+                }
+                let link = TopologyLink { protocol, local: local.clone(), remote: remote.clone(), link: link.clone(), attributes: attributes.to_vec() };
+                self.links.insert((local.clone(), remote.clone(), link.link.clone()), link);
+            },
+            LinkStateNlri::Prefix { protocol, ref node, ref prefix, .. } => {
+                let entry = self.nodes.entry(node.clone()).or_insert_with(|| TopologyNode::new(protocol, node));
+                entry.prefixes.retain(|p| p.prefix != *prefix);
+                entry.prefixes.push(TopologyPrefix { prefix: prefix.clone(), attributes: attributes.to_vec() });
+            },
+            LinkStateNlri::Unknown { .. } => {},
+        }
+    }
+
+    fn remove(&mut self, nlri: &LinkStateNlri) {
+        match *nlri {
+            LinkStateNlri::Node { ref node, .. } => {
+                self.nodes.remove(node);
+                self.links.retain(|_, l| l.local != *node && l.remote != *node);
+            },
+            LinkStateNlri::Link { ref local, ref remote, ref link, .. } => {
+                self.links.remove(&(local.clone(), remote.clone(), link.clone()));
+            },
+            LinkStateNlri::Prefix { ref node, ref prefix, .. } => {
+                if let Some(entry) = self.nodes.get_mut(node) {
+                    entry.prefixes.retain(|p| p.prefix != *prefix);
+                }
+            },
+            LinkStateNlri::Unknown { .. } => {},
+        }
+    }
+
+    // The graph in GraphViz DOT, the nodes labelled with their names and
+    // the links with their IGP metrics.
+    pub fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = "digraph bgp_ls {\n".to_string();
+        for node in self.nodes.values() {
+            let _ = writeln!(dot, "    {} [label={}];", quote(&node.node.to_string()), quote(&node.name()));
+        }
+        for link in self.links.values() {
+            let _ = write!(dot, "    {} -> {}", quote(&link.local.to_string()), quote(&link.remote.to_string()));
+            match link.metric() {
+                Some(metric) => { let _ = writeln!(dot, " [label={}];", quote(&metric.to_string())); },
+                None => dot.push_str(";\n"),
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // The graph as a JSON object holding arrays of "nodes" and "links".
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct JsonTopology<'a> {
+            nodes: Vec<&'a TopologyNode>,
+            links: Vec<&'a TopologyLink>,
+        }
+
+        let json = JsonTopology { nodes: self.nodes.values().collect(), links: self.links.values().collect() };
+        serde_json::to_string(&json).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(router_id: &[u8]) -> NodeDescriptor {
+        NodeDescriptor { asn: Some(65000), router_id: Some(IgpRouterId(router_id.to_vec())), ..NodeDescriptor::default() }
+    }
+
+    #[test]
+    fn link_state_nlri_test() {
+        let node_input = [
+            0x00, 0x01, 0x00, 0x1f, 2, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x01, 0x00, 0x00, 0x12, 0x02, 0x00, 0x00, 0x04, 0x00, 0x00, 0xfd, 0xe8, 0x02, 0x03, 0x00, 0x06, 0, 0, 0, 0, 0, 1,
+        ];
+        let nlri = LinkStateNlri::Node { protocol: 2, identifier: 0, node: node(&[0, 0, 0, 0, 0, 1]) };
+        assert_eq!(link_state_nlri(&node_input), Done(&b""[..], nlri.clone()));
+        assert_eq!(nlri.to_string(), "node isis-l2 as 65000 router 0000.0000.0001");
+        let mut encoded = Vec::new();
+        nlri.encode(&mut encoded);
+        assert_eq!(encoded, node_input.to_vec());
+
+        let nlri = LinkStateNlri::Link {
+            protocol: 3,
+            identifier: 7,
+            local: node(&[192, 0, 2, 1]),
+            remote: node(&[192, 0, 2, 2]),
+            link: LinkDescriptor { local_address: "10.0.0.1".parse().ok(), remote_address: "10.0.0.2".parse().ok(), ..LinkDescriptor::default() },
+        };
+        assert_eq!(nlri.to_string(), "link ospfv2 id 7 as 65000 router 192.0.2.1 to as 65000 router 192.0.2.2 local 10.0.0.1 remote 10.0.0.2");
+        let mut encoded = Vec::new();
+        nlri.encode(&mut encoded);
+        assert_eq!(&encoded[..4], &[0x00, 0x02, 0x00, 0x41]);
+        assert_eq!(link_state_nlri(&encoded), Done(&b""[..], nlri));
+
+        let nlri = LinkStateNlri::Prefix {
+            protocol: 2,
+            identifier: 0,
+            node: node(&[0, 0, 0, 0, 0, 1]),
+            prefix: PrefixDescriptor { mt_id: Some(2), ospf_route_type: None, address: "2001:db8::".parse().unwrap(), length: 32, other: vec![] },
+        };
+        assert_eq!(nlri.to_string(), "prefix isis-l2 as 65000 router 0000.0000.0001 2001:db8::/32 mt 2");
+        let mut encoded = Vec::new();
+        nlri.encode(&mut encoded);
+        assert_eq!(&encoded[..2], &[0x00, 0x04]);
+        assert_eq!(link_state_nlri(&encoded), Done(&b""[..], nlri));
+    }
+
+    #[test]
+    fn link_state_nlri_invalid_test() {
+        // An AS number of three bytes.
+        let input = [0x00, 0x01, 0x00, 0x14, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00, 0x07, 0x02, 0x00, 0x00, 0x03, 0x00, 0xfd, 0xe8];
+        assert!(link_state_nlri(&input).is_err());
+
+        // A prefix NLRI without the prefix.
+        let input = [0x00, 0x03, 0x00, 0x0d, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00, 0x00];
+        assert!(link_state_nlri(&input).is_err());
+
+        // Types we don't know are kept.
+        assert_eq!(link_state_nlri(&[0x00, 0x09, 0x00, 0x02, 0xab, 0xcd]), Done(&b""[..],
+            LinkStateNlri::Unknown { nlri_type: 9, value: vec![0xab, 0xcd] }));
+    }
+
+    #[test]
+    fn link_state_tlvs_test() {
+        let input = [
+            0x04, 0x02, 0x00, 0x02, b'r', b'1',
+            0x04, 0x04, 0x00, 0x04, 192, 0, 2, 1,
+            0x04, 0x41, 0x00, 0x04, 0x4e, 0x95, 0x02, 0xf9,
+            0x04, 0x47, 0x00, 0x03, 0x00, 0x00, 0x0a,
+            0x12, 0x34, 0x00, 0x01, 0xff,
+        ];
+        let tlvs = vec![
+            LinkStateTlv::NodeName { name: "r1".to_string() },
+            LinkStateTlv::LocalRouterId { address: "192.0.2.1".parse().unwrap() },
+            LinkStateTlv::MaxBandwidth { bandwidth: 1.25e9 },
+            LinkStateTlv::IgpMetric { metric: 10 },
+            LinkStateTlv::Unknown { tlv_type: 0x1234, value: vec![0xff] },
+        ];
+        assert_eq!(link_state_tlvs(&input), Done(&b""[..], tlvs.clone()));
+        assert_eq!(tlvs.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" "),
+            "name r1 router-id 192.0.2.1 max-bandwidth 1250000000 metric 10 tlv 4660 ff");
+
+        let mut encoded = Vec::new();
+        for tlv in &tlvs {
+            tlv.encode(&mut encoded);
+        }
+        // The metric goes back in two bytes.
+        assert_eq!(link_state_tlvs(&encoded), Done(&b""[..], tlvs));
+
+        assert!(link_state_tlvs(&input[..5]).is_err());
+    }
+
+    #[test]
+    fn igp_router_id_test() {
+        for s in &["192.0.2.1", "0000.0000.0001", "0000.0000.0001.02", "192.0.2.1:10.0.0.1"] {
+            assert_eq!(s.parse::<IgpRouterId>().unwrap().to_string(), *s);
+        }
+        assert!("0000.0000".parse::<IgpRouterId>().is_err());
+        assert!("0000.0000.000g".parse::<IgpRouterId>().is_err());
+    }
+
+    #[test]
+    fn topology_test() {
+        let (r1, r2) = (node(&[0, 0, 0, 0, 0, 1]), node(&[0, 0, 0, 0, 0, 2]));
+        let link = |local: &NodeDescriptor, remote: &NodeDescriptor| LinkStateNlri::Link {
+            protocol: 2, identifier: 0, local: local.clone(), remote: remote.clone(), link: LinkDescriptor::default(),
+        };
+        let update = |reach: Vec<LinkStateNlri>, unreach: Vec<LinkStateNlri>, tlvs: Vec<LinkStateTlv>| {
+            let flags = BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false };
+            BgpUpdateMessage { withdrawn_routes: vec![], nlri: vec![], path_attributes: vec![
+                BgpPathAttribute { flags: flags.clone(), attribute: PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute {
+                    afi: AFI_LINK_STATE, safi: SAFI_LINK_STATE, next_hop: vec![192, 0, 2, 1], nlri: MpNlri::LinkState(reach),
+                })) },
+                BgpPathAttribute { flags: flags.clone(), attribute: PathAttribute::MpUnreachNlri(Box::new(MpUnreachNlriAttribute {
+                    afi: AFI_LINK_STATE, safi: SAFI_LINK_STATE, withdrawn_routes: MpNlri::LinkState(unreach),
+                })) },
+                BgpPathAttribute { flags, attribute: PathAttribute::LinkState(Box::new(LinkStateAttribute { tlvs })) },
+            ] }
+        };
+
+        let mut topology = Topology::new();
+        topology.update(&update(vec![LinkStateNlri::Node { protocol: 2, identifier: 0, node: r1.clone() }], vec![],
+            vec![LinkStateTlv::NodeName { name: "r1".to_string() }]));
+        topology.update(&update(vec![link(&r1, &r2)], vec![], vec![LinkStateTlv::IgpMetric { metric: 10 }]));
+        topology.update(&update(vec![link(&r2, &r1)], vec![], vec![]));
+
+        assert_eq!(topology.nodes().count(), 2);
+        assert_eq!(topology.node(&r1).unwrap().name(), "r1");
+        assert_eq!(topology.node(&r2).unwrap().name(), "as 65000 router 0000.0000.0002");
+        assert_eq!(topology.to_dot(), "\
+digraph bgp_ls {
+    \"as 65000 router 0000.0000.0001\" [label=\"r1\"];
+    \"as 65000 router 0000.0000.0002\" [label=\"as 65000 router 0000.0000.0002\"];
+    \"as 65000 router 0000.0000.0001\" -> \"as 65000 router 0000.0000.0002\" [label=\"10\"];
+    \"as 65000 router 0000.0000.0002\" -> \"as 65000 router 0000.0000.0001\";
+}
+");
+
+        topology.update(&update(vec![], vec![link(&r1, &r2)], vec![]));
+        assert_eq!(topology.links().count(), 1);
+        // Removing a node takes its links with it.
+        topology.update(&update(vec![], vec![LinkStateNlri::Node { protocol: 2, identifier: 0, node: r2.clone() }], vec![]));
+        assert_eq!(topology.nodes().count(), 1);
+        assert_eq!(topology.links().count(), 0);
+    }
+}
@@ -4,24 +4,68 @@
 #![allow(unused_variables)]
 
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::Cursor;
 use std::path::Path;
+use std::str::FromStr;
 
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 extern crate byteorder;
 use byteorder::{BigEndian, ReadBytesExt};
 
 #[macro_use]
 extern crate nom;
+
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 use nom::{be_u8, be_u16, be_u32, error_to_list};
 use nom::{IResult, IError, Needed, ErrorKind};
 use nom::IResult::*;
 
-#[cfg(feature = "verbose-errors")]
-use nom::Err::*;
+mod as_path;
+mod as_path_regex;
+mod aspa;
+mod bmp;
+mod codec;
+mod collision;
+mod damping;
+mod dump;
+mod encode;
+mod evpn;
+mod extended_community;
+mod flowspec;
+mod fsm;
+#[cfg(feature = "serde")]
+mod json;
+mod link_state;
+mod message_ref;
+mod mpls;
+mod mrt;
+mod notification;
+mod pcap;
+mod prefix_limit;
+mod role;
+mod rtr;
+mod speaker;
+mod timers;
+mod vpn;
+
+use notification::{CeaseSubcode, NotificationError};
+use evpn::EvpnRoute;
+use extended_community::ExtendedCommunity;
+use flowspec::FlowSpecRule;
+use link_state::{LinkStateNlri, LinkStateTlv};
+use role::BgpRole;
+use mpls::{LabelCount, LabeledPrefix};
+use vpn::VpnPrefix;
 
 // We have one top level parser that calls each of the message specific
 // parsers based on a switch. When required it passes the length field
@@ -71,7 +115,9 @@ use nom::Err::*;
 
 // BGP messages.
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 enum BgpMessage {
     Open(Box<BgpOpenMessage>),
     Update(Box<BgpUpdateMessage>),
@@ -79,16 +125,18 @@ enum BgpMessage {
     Keepalive,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct BgpOpenMessage {
     version: u8,
     my_autonomous_system: u16,
     hold_time: u16,
-    bgp_identifier: u32,
+    bgp_identifier: Ipv4Addr,
     optional_parameters: Vec<OptionalParameter>,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct BgpUpdateMessage {
     withdrawn_routes: Vec<Ipv4Prefix>, // TODO: make this an Option?
     path_attributes: Vec<BgpPathAttribute>,
@@ -96,17 +144,22 @@ struct BgpUpdateMessage {
     nlri: Vec<Ipv4Prefix>, // TODO: make this an Option?
 }
 
-#[derive(Debug, PartialEq)]
+// The path identifier is only there when ADD-PATH (RFC 7911) has been
+// negotiated for IPv4 unicast, see UpdateEncoding.
+#[derive(Debug,PartialEq,Eq,Hash,Clone)]
 struct Ipv4Prefix {
     prefix: Vec<u8>,
     length: u8,
+    path_id: Option<u32>,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct BgpNotificationMessage {
     error_code: u8,
     error_subcode: u8,
-    // TODO: Implement the data field.
+    #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+    data: Vec<u8>,
 }
 
 // Top level parser to parse all BGP messages.
@@ -123,17 +176,36 @@ const BAD_MESSAGE_LENGTH: u32 = MESSAGE_HEADER_ERROR << 8 | 2;
 const BAD_MESSAGE_TYPE: u32 = MESSAGE_HEADER_ERROR << 8 | 3;
 
 named!(bgp_header_marker, return_error!(ErrorKind::Custom(CONNECTION_NOT_SYNCHRONIZED), tag!([0xff; 16])));
-named!(bgp_header_length<u16>, return_error!(ErrorKind::Custom(BAD_MESSAGE_LENGTH), verify!(be_u16, |v: u16| v >= 19 && v <= 4096)));
-named!(bgp_header_type<u8>, return_error!(ErrorKind::Custom(BAD_MESSAGE_TYPE), verify!(be_u8, |v: u8| v >= 1 && v <= 5)));
+// The longest a message can be is 4096 bytes, unless both speakers have
+// sent the Extended Message capability (RFC 8654), when it's 65535. Even
+// then an OPEN can't be longer than 4096, since it's sent before the
+// capabilities are known.
+//
+// The limit is a parameter of the parsers, so that a session can raise
+// it once it's been negotiated. Captures and archives of messages are
+// read with the extended limit, as we can't know what was negotiated.
+const MAX_MESSAGE_LENGTH: u16 = 4096;
+const MAX_EXTENDED_MESSAGE_LENGTH: u16 = 65535;
+
+named_args!(bgp_header_length(max_length: u16) <u16>, return_error!(ErrorKind::Custom(BAD_MESSAGE_LENGTH), verify!(be_u16, |v: u16| v >= 19 && v <= max_length)));
+named!(bgp_header_type<u8>, return_error!(ErrorKind::Custom(BAD_MESSAGE_TYPE), verify!(be_u8, |v: u8| (1..=5).contains(&v))));
 
-named!(parse_bgp_message<BgpMessage>,
+named!(parse_bgp_message<BgpMessage>, call!(parse_bgp_message_with_max, MAX_MESSAGE_LENGTH));
+named!(parse_extended_bgp_message<BgpMessage>, call!(parse_bgp_message_with_max, MAX_EXTENDED_MESSAGE_LENGTH));
+
+named_args!(parse_bgp_message_with_max(max_length: u16) <BgpMessage>, call!(parse_bgp_message_with_encoding, max_length, &DEFAULT_UPDATE_ENCODING));
+
+named_args!(parse_bgp_message_with_encoding<'a>(max_length: u16, encoding: &'a UpdateEncoding) <BgpMessage>,
     do_parse!(
         bgp_header_marker >>
-        length: bgp_header_length >>
+        length: call!(bgp_header_length, max_length) >>
         message_type: bgp_header_type >>
         message: switch!(value!(message_type),
-            1u8 => call!(parse_bgp_open, length) |
-            2u8 => call!(parse_bgp_update, length) |
+            1u8 => preceded!(
+                return_error!(ErrorKind::Custom(BAD_MESSAGE_LENGTH), verify!(value!(length), |v: u16| v <= MAX_MESSAGE_LENGTH)),
+                call!(parse_bgp_open, length)
+            ) |
+            2u8 => call!(parse_bgp_update, length, encoding) |
             3u8 => call!(parse_bgp_notification, length) |
             4u8 => call!(parse_bgp_keepalive, length)
         ) >>
@@ -141,9 +213,26 @@ named!(parse_bgp_message<BgpMessage>,
     )
 );
 
+// Split one message off the front of a stream of BGP messages without
+// parsing it. This is the framer used when reading from a byte stream,
+// e.g. a reassembled TCP stream, where the input may hold many messages
+// or only part of one. The marker and length are validated so a corrupt
+// stream is reported as an error rather than an endless run of frames.
+
+named!(bgp_message_frame, call!(bgp_message_frame_with_max, MAX_MESSAGE_LENGTH));
+named!(extended_bgp_message_frame, call!(bgp_message_frame_with_max, MAX_EXTENDED_MESSAGE_LENGTH));
+
+named_args!(bgp_message_frame_with_max(max_length: u16) <&[u8]>,
+    do_parse!(
+        length: peek!(preceded!(bgp_header_marker, call!(bgp_header_length, max_length))) >>
+        frame: take!(length) >>
+        (frame)
+    )
+);
+
 // Let's try out a design using peek!().
 
-named!(peek_for_type<u8>, do_parse!(res: peek!(preceded!(bgp_header_length, bgp_header_type)) >> (res)));
+named!(peek_for_type<u8>, do_parse!(res: peek!(preceded!(call!(bgp_header_length, MAX_MESSAGE_LENGTH), bgp_header_type)) >> (res)));
 
 named!(new_parse_bgp_message<BgpMessage>,
     do_parse!(
@@ -175,8 +264,10 @@ named!(new_parse_bgp_message<BgpMessage>,
 // the parser to do this kind of verification. That might be considered
 // in a later revision.
 //
-// BAD_BGP_IDENTIFIER: This must be a valid unicast IP host address. We
-// can validate that here, so we will.
+// BAD_BGP_IDENTIFIER: RFC 6286 allows any identifier but zero, and that
+// much we can validate here. The rest, like it having to be a host
+// address unless both speakers have 4-octet AS support, is checked by the
+// FSM.
 //
 // UNSUPPORTED_OPTIONAL_PARAMETER: not implemented yet.
 //
@@ -197,11 +288,11 @@ const BAD_BGP_IDENTIFIER: u32 = OPEN_MESSAGE_ERROR << 8 | 3;
 const UNSUPPORTED_OPTIONAL_PARAMETER: u32 = OPEN_MESSAGE_ERROR << 8 | 4;
 const UNACCEPTABLE_HOLD_TIME: u32 = OPEN_MESSAGE_ERROR << 8 | 6;
 const UNSUPPORTED_CAPABILITY: u32 = OPEN_MESSAGE_ERROR << 8 | 7;
+const ROLE_MISMATCH: u32 = OPEN_MESSAGE_ERROR << 8 | 11;
 
 named!(bgp_version<u8>, return_error!(ErrorKind::Custom(UNSUPPORTED_VERSION_NUMBER), verify!(be_u8, |v: u8| v == 4)));
 named!(bgp_hold_time<u16>, return_error!(ErrorKind::Custom(UNACCEPTABLE_HOLD_TIME), verify!(be_u16, |v: u16| v == 0 || v >= 3))); // Hold Time can be zero
-// TODO: This should return an IPAddr type.
-named!(bgp_identifier<u32>, return_error!(ErrorKind::Custom(BAD_BGP_IDENTIFIER), verify!(be_u32, |v: u32| v < 0xE0000000)));
+named!(bgp_identifier<Ipv4Addr>, return_error!(ErrorKind::Custom(BAD_BGP_IDENTIFIER), map!(verify!(be_u32, |v: u32| v != 0), Ipv4Addr::from)));
 
 named_args!(parse_bgp_open(length: u16) <BgpMessage>,
     do_parse!(
@@ -214,11 +305,11 @@ named_args!(parse_bgp_open(length: u16) <BgpMessage>,
         optional_parameters: flat_map!(take!(optional_parameters_length), complete!(many0!(parse_optional_parameter))) >>
         (BgpMessage::Open(
             Box::new(BgpOpenMessage{
-                version: version,
-                my_autonomous_system: my_autonomous_system,
-                hold_time: hold_time,
-                bgp_identifier: bgp_identifier,
-                optional_parameters: optional_parameters,
+                version,
+                my_autonomous_system,
+                hold_time,
+                bgp_identifier,
+                optional_parameters,
             })
         ))
     )
@@ -236,33 +327,94 @@ named!(new_parse_bgp_open<BgpMessage>,
         optional_parameters: flat_map!(take!(optional_parameters_length), complete!(many0!(parse_optional_parameter))) >>
         (BgpMessage::Open(
             Box::new(BgpOpenMessage{
-                version: version,
-                my_autonomous_system: my_autonomous_system,
-                hold_time: hold_time,
-                bgp_identifier: bgp_identifier,
-                optional_parameters: optional_parameters,
+                version,
+                my_autonomous_system,
+                hold_time,
+                bgp_identifier,
+                optional_parameters,
             })
         ))
     )
 );
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 enum OptionalParameter {
     Capability(Vec<CapabilityParameter>),
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
 enum CapabilityParameter {
     MultiprotocolExtensions(Box<MultiprotocolExtensionsCapability>),
     RouteRefresh,
+    ExtendedMessage,
+    MultipleLabels(Box<MultipleLabelsCapability>),
+    Role(Box<RoleCapability>),
+    FourOctetAs(Box<FourOctetAsCapability>),
+    AddPath(Box<AddPathCapability>),
+    Unknown(Box<UnknownCapability>),
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct MultiprotocolExtensionsCapability {
     afi: u16,
     safi: u8,
 }
 
+// The Multiple Labels capability (RFC 8277), see mpls.rs.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct MultipleLabelsCapability {
+    counts: Vec<LabelCount>,
+}
+
+// The BGP Role capability (RFC 9234), see role.rs.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct RoleCapability {
+    role: BgpRole,
+}
+
+// The 4-octet AS capability (RFC 6793). A session sends it if configured
+// to, and with it on both sides AS numbers in UPDATEs are four octets and
+// the relaxed RFC 6286 rules for the BGP identifier apply.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct FourOctetAsCapability {
+    asn: u32,
+}
+
+// The ADD-PATH capability (RFC 7911) lists the families a speaker can
+// send and/or receive more than one path for, see UpdateEncoding.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct AddPathCapability {
+    families: Vec<AddPathFamily>,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct AddPathFamily {
+    afi: u16,
+    safi: u8,
+    receive: bool,
+    send: bool,
+}
+
+// Any other capability, kept as it was sent. A speaker ignores the ones
+// it doesn't recognize (RFC 5492).
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct UnknownCapability {
+    code: u8,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+    data: Vec<u8>,
+}
+
 // This indirection is redundant since there is only one current type of
 // optional parameter, but for completeness we'll do it this way.
 named!(parse_optional_parameter<OptionalParameter>,
@@ -278,11 +430,12 @@ named!(optional_parameter_capability<OptionalParameter>,
     )
 );
 
-// TODO: For some reason the UNSUPPORTED_CAPABILITY error isn't returned
-// when the alt!() doesn't have a match. This leads to just returning an
-// empty Capability. Might be possible Nom bug, raised on gitter.
+// Capabilities we don't know, or whose value doesn't have the form we
+// expect, are skipped over by their length and kept as unknown, so the
+// rest of the parameter is still read. Whether one is required is for
+// the calling code to decide.
 named!(parse_capability_parameter<CapabilityParameter>,
-    return_error!(ErrorKind::Custom(UNSUPPORTED_CAPABILITY), alt!(multiprotocol_extensions_capability | route_refresh_capability))
+    return_error!(ErrorKind::Custom(UNSUPPORTED_CAPABILITY), alt!(multiprotocol_extensions_capability | route_refresh_capability | extended_message_capability | multiple_labels_capability | role_capability | four_octet_as_capability | add_path_capability | unknown_capability))
 );
 
 // TODO: Validate the AFI and SAFI here?
@@ -293,7 +446,7 @@ named!(multiprotocol_extensions_capability<CapabilityParameter>,
         afi: be_u16 >> // TODO: Validation.
         take!(1) >> // reserved byte
         safi: be_u8 >> // TODO: Validation.
-        (CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi: afi, safi: safi })))
+        (CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi, safi })))
     )
 );
 
@@ -305,6 +458,68 @@ named!(route_refresh_capability<CapabilityParameter>,
     )
 );
 
+named!(extended_message_capability<CapabilityParameter>,
+    do_parse!(
+        tag!([6u8]) >> // type code
+        tag!([0u8]) >> // length should always be zero
+        (CapabilityParameter::ExtendedMessage)
+    )
+);
+
+named!(multiple_labels_capability<CapabilityParameter>,
+    do_parse!(
+        tag!([8u8]) >> // type code
+        length: verify!(be_u8, |v: u8| v & 3 == 0) >>
+        counts: flat_map!(take!(length), complete!(many0!(do_parse!(
+            afi: be_u16 >>
+            safi: be_u8 >>
+            count: be_u8 >>
+            (LabelCount { afi, safi, count })
+        )))) >>
+        (CapabilityParameter::MultipleLabels(Box::new(MultipleLabelsCapability { counts })))
+    )
+);
+
+named!(role_capability<CapabilityParameter>,
+    do_parse!(
+        tag!([9u8]) >> // type code
+        tag!([1u8]) >> // length should always be one
+        role: be_u8 >>
+        (CapabilityParameter::Role(Box::new(RoleCapability { role: BgpRole::from(role) })))
+    )
+);
+
+named!(four_octet_as_capability<CapabilityParameter>,
+    do_parse!(
+        tag!([65u8]) >> // type code
+        tag!([4u8]) >> // length should always be four
+        asn: be_u32 >>
+        (CapabilityParameter::FourOctetAs(Box::new(FourOctetAsCapability { asn })))
+    )
+);
+
+named!(add_path_capability<CapabilityParameter>,
+    do_parse!(
+        tag!([69u8]) >> // type code
+        length: verify!(be_u8, |v: u8| v & 3 == 0) >>
+        families: flat_map!(take!(length), complete!(many0!(do_parse!(
+            afi: be_u16 >>
+            safi: be_u8 >>
+            send_receive: verify!(be_u8, |v: u8| (1..=3).contains(&v)) >>
+            (AddPathFamily { afi, safi, receive: send_receive & 1 != 0, send: send_receive & 2 != 0 })
+        )))) >>
+        (CapabilityParameter::AddPath(Box::new(AddPathCapability { families })))
+    )
+);
+
+named!(unknown_capability<CapabilityParameter>,
+    do_parse!(
+        code: be_u8 >>
+        data: length_bytes!(be_u8) >>
+        (CapabilityParameter::Unknown(Box::new(UnknownCapability { code, data: data.to_vec() })))
+    )
+);
+
 // Parse BGP Keepalive message.
 //
 // The length of a Keepalive must always be 19. The calling code will
@@ -329,38 +544,146 @@ named!(new_parse_bgp_keepalive<BgpMessage>,
 
 // Parse BGP Notification message.
 
+// The error codes without parsers to go with them. These are raised by
+// the session rather than while parsing a message.
+const HOLD_TIMER_EXPIRED: u32 = 4;
+const FINITE_STATE_MACHINE_ERROR: u32 = 5;
+const CEASE: u32 = 6;
+const ROUTE_REFRESH_MESSAGE_ERROR: u32 = 7;
+
 named_args!(parse_bgp_notification(length: u16) <BgpMessage>,
     do_parse!(
         return_error!(ErrorKind::Custom(BAD_MESSAGE_LENGTH), verify!(value!(length), |v: u16| v >= 21)) >>
-        error_code: verify!(be_u8, |v: u8| v >= 1 && v <= 6) >>
-        // TODO: The possible error_subcodes depend on the error_code.
-        error_subcode: verify!(be_u8, |v: u8| v >= 1 && v <= 11) >>
+        // Any code and subcode is kept, see notification.rs.
+        error_code: be_u8 >>
+        error_subcode: be_u8 >>
         data: take!(length - 21) >>
-        (BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: error_code, error_subcode: error_subcode })))
+        (BgpMessage::Notification(Box::new(BgpNotificationMessage {
+            error_code,
+            error_subcode,
+            data: data.to_vec(),
+        })))
     )
 );
 
 // Parse BGP Update message.
 
-named_args!(parse_bgp_update(length: u16) <BgpMessage>,
+const UPDATE_MESSAGE_ERROR: u32 = 3;
+const MALFORMED_ATTRIBUTE_LIST: u32 = UPDATE_MESSAGE_ERROR << 8 | 1;
+const INVALID_NEXT_HOP_ATTRIBUTE: u32 = UPDATE_MESSAGE_ERROR << 8 | 8;
+const INVALID_NETWORK_FIELD: u32 = UPDATE_MESSAGE_ERROR << 8 | 10;
+
+// How an UPDATE is encoded depends on what the speakers negotiated in
+// their OPENs: whether AS numbers in the AS_PATH and AGGREGATOR are four
+// octets (RFC 6793), and the families whose NLRI carry a path identifier
+// (RFC 7911). Without a session, e.g. when reading an archive, neither
+// is assumed.
+#[derive(Debug,PartialEq,Clone,Default)]
+struct UpdateEncoding {
+    four_octet_as: bool,
+    add_path: Vec<(u16, u8)>,
+}
+
+static DEFAULT_UPDATE_ENCODING: UpdateEncoding = UpdateEncoding { four_octet_as: false, add_path: Vec::new() };
+static FOUR_OCTET_AS_UPDATE_ENCODING: UpdateEncoding = UpdateEncoding { four_octet_as: true, add_path: Vec::new() };
+
+impl UpdateEncoding {
+    // For a message read from an archive, which says whether AS numbers
+    // are four octets but nothing else of what was negotiated.
+    fn archived(four_octet_as: bool) -> &'static UpdateEncoding {
+        match four_octet_as {
+            true => &FOUR_OCTET_AS_UPDATE_ENCODING,
+            false => &DEFAULT_UPDATE_ENCODING,
+        }
+    }
+
+    // For the UPDATEs sent by the speaker that sent the first OPEN to the
+    // one that sent the second. ADD-PATH is used for a family only when
+    // the sender can send and the receiver can receive.
+    fn negotiated(sender: &BgpOpenMessage, receiver: &BgpOpenMessage) -> UpdateEncoding {
+        let four_octet_as = |open: &BgpOpenMessage| open.capabilities().any(|c| matches!(*c, CapabilityParameter::FourOctetAs(_)));
+        let add_path = |open: &BgpOpenMessage| -> Vec<AddPathFamily> {
+            open.capabilities().filter_map(|c| match *c {
+                CapabilityParameter::AddPath(ref a) => Some(a.families.clone()),
+                _ => None,
+            }).flatten().collect()
+        };
+
+        let receiver_families = add_path(receiver);
+        UpdateEncoding {
+            four_octet_as: four_octet_as(sender) && four_octet_as(receiver),
+            add_path: add_path(sender).iter()
+                .filter(|s| s.send && receiver_families.iter().any(|r| r.receive && r.afi == s.afi && r.safi == s.safi))
+                .map(|s| (s.afi, s.safi))
+                .collect(),
+        }
+    }
+
+    fn add_path(&self, afi: u16, safi: u8) -> bool {
+        self.add_path.contains(&(afi, safi))
+    }
+}
+
+impl BgpOpenMessage {
+    fn capabilities(&self) -> impl Iterator<Item = &CapabilityParameter> {
+        self.optional_parameters.iter().flat_map(|p| match *p {
+            OptionalParameter::Capability(ref capabilities) => capabilities.iter(),
+        })
+    }
+}
+
+named_args!(parse_bgp_update<'a>(length: u16, encoding: &'a UpdateEncoding) <BgpMessage>,
     do_parse!(
         withdrawn_routes_length: be_u16 >>
-        withdrawn_routes: flat_map!(take!(withdrawn_routes_length), complete!(many0!(parse_bgp_prefix))) >>
+        withdrawn_routes: flat_map!(take!(withdrawn_routes_length), complete!(many0!(call!(parse_nlri_prefix, encoding.add_path(AFI_IPV4, SAFI_UNICAST))))) >>
         total_path_attributes_length: be_u16 >>
-        path_attributes: flat_map!(take!(total_path_attributes_length), complete!(many0!(old_parse_bgp_path_attribute))) >>
+        path_attributes: flat_map!(take!(total_path_attributes_length), complete!(many0!(call!(parse_bgp_path_attribute_with_encoding, encoding)))) >>
         //path_attributes: flat_map!(take!(total_path_attributes_length), complete!(many0!(new_parse_bgp_path_attribute))) >>
         nlri_length: value!(length - 23 - total_path_attributes_length - withdrawn_routes_length) >>
-        nlri: flat_map!(take!(nlri_length), complete!(many0!(parse_bgp_prefix))) >>
+        nlri: flat_map!(take!(nlri_length), complete!(many0!(call!(parse_nlri_prefix, encoding.add_path(AFI_IPV4, SAFI_UNICAST))))) >>
         (BgpMessage::Update(
             Box::new(BgpUpdateMessage{
-                withdrawn_routes: withdrawn_routes,
-                path_attributes: path_attributes,
-                nlri: nlri
+                withdrawn_routes,
+                path_attributes,
+                nlri
             })
         ))
     )
 );
 
+impl Ipv4Prefix {
+    // The prefix padded out to a full address.
+    fn addr(&self) -> Ipv4Addr {
+        let mut octets = [0u8; 4];
+        for (o, p) in octets.iter_mut().zip(self.prefix.iter()) {
+            *o = *p;
+        }
+        Ipv4Addr::from(octets)
+    }
+}
+
+impl fmt::Display for Ipv4Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr(), self.length)
+    }
+}
+
+// Parse a prefix in CIDR notation. Only the octets covered by the length
+// are kept, as they would be on the wire.
+impl FromStr for Ipv4Prefix {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Ipv4Prefix, ()> {
+        let mut parts = s.splitn(2, '/');
+        let address = parts.next().ok_or(())?.parse::<Ipv4Addr>().map_err(|_| ())?;
+        let length = parts.next().ok_or(())?.parse::<u8>().map_err(|_| ())?;
+        if length > 32 {
+            return Err(());
+        }
+        Ok(Ipv4Prefix { prefix: address.octets()[..(length as usize).div_ceil(8)].to_vec(), length, path_id: None })
+    }
+}
+
 // Parse a BGP prefix found in withdrawn routes and NLRI.
 
 // TODO: This is currently not padding out to four octects. And maybe
@@ -369,20 +692,32 @@ named_args!(parse_bgp_update(length: u16) <BgpMessage>,
 named!(parse_bgp_prefix<&[u8], Ipv4Prefix>,
     do_parse!(
         len_bits: be_u8 >>
-        prefix: take!((len_bits + 7) / 8) >>
-        (Ipv4Prefix { prefix: prefix.to_vec(), length: len_bits })
+        prefix: take!(len_bits.div_ceil(8)) >>
+        (Ipv4Prefix { prefix: prefix.to_vec(), length: len_bits, path_id: None })
+    )
+);
+
+// With ADD-PATH each prefix is preceded by its path identifier.
+named_args!(parse_nlri_prefix(add_path: bool) <Ipv4Prefix>,
+    do_parse!(
+        path_id: cond!(add_path, be_u32) >>
+        prefix: parse_bgp_prefix >>
+        (Ipv4Prefix { path_id, ..prefix })
     )
 );
 
 // BGP Path Attributes.
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct BgpPathAttribute {
     flags: BgpPathAttributeFlags,
+    #[cfg_attr(feature = "serde", serde(flatten))]
     attribute: PathAttribute,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct BgpPathAttributeFlags {
     optional: bool,
     transitive: bool,
@@ -390,7 +725,9 @@ struct BgpPathAttributeFlags {
     extended_length: bool
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value", rename_all = "snake_case"))]
 enum PathAttribute {
     Origin(Box<OriginAttribute>),
     AsPath(Box<AsPathAttribute>),
@@ -399,9 +736,17 @@ enum PathAttribute {
     LocalPref(Box<LocalPrefAttribute>),
     AtomicAggregate,
     Aggregator(Box<AggregatorAttribute>),
+    Communities(Box<CommunitiesAttribute>),
+    MpReachNlri(Box<MpReachNlriAttribute>),
+    MpUnreachNlri(Box<MpUnreachNlriAttribute>),
+    ExtendedCommunities(Box<ExtendedCommunitiesAttribute>),
+    LinkState(Box<LinkStateAttribute>),
+    OnlyToCustomer(Box<OnlyToCustomerAttribute>),
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 enum BgpOriginCode {
     Igp,
     Egp,
@@ -419,43 +764,230 @@ impl From<u8> for BgpOriginCode {
     }
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct OriginAttribute {
     origin_code: BgpOriginCode,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(clippy::enum_variant_names)]
 enum AsPathSegment {
-    AsSet(Vec<u16>),
-    AsSequence(Vec<u16>),
+    #[cfg_attr(feature = "serde", serde(rename = "set"))]
+    AsSet(Vec<u32>),
+    #[cfg_attr(feature = "serde", serde(rename = "sequence"))]
+    AsSequence(Vec<u32>),
+    // The member ASes a route has been through within a confederation
+    // (RFC 5065).
+    #[cfg_attr(feature = "serde", serde(rename = "confed_sequence"))]
+    AsConfedSequence(Vec<u32>),
+    #[cfg_attr(feature = "serde", serde(rename = "confed_set"))]
+    AsConfedSet(Vec<u32>),
 }
 
-#[derive(Debug,PartialEq)]
+// See as_path.rs.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct AsPathAttribute {
     as_path: Vec<AsPathSegment>,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct NextHopAttribute {
     next_hop: Ipv4Addr,
 }
 
-#[derive(Debug,PartialEq)]
+// Whether the address could be a host's, so not 0.0.0.0, loopback,
+// multicast or reserved. A NEXT_HOP has to be one, as does the BGP
+// identifier of a peer without 4-octet AS support.
+fn is_host_address(address: Ipv4Addr) -> bool {
+    !address.is_unspecified() && !address.is_loopback() && u32::from(address) < 0xE0000000
+}
+
+impl BgpUpdateMessage {
+    pub fn next_hop(&self) -> Option<Ipv4Addr> {
+        self.path_attributes.iter().filter_map(|a| match a.attribute {
+            PathAttribute::NextHop(ref next_hop) => Some(next_hop.next_hop),
+            _ => None,
+        }).next()
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct MultiExitDiscAttribute {
     metric: u32,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct LocalPrefAttribute {
     preference: u32,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct AggregatorAttribute {
-    aggregator_as: u16,
+    aggregator_as: u32,
     aggregator_id: Ipv4Addr,
 }
 
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+struct CommunitiesAttribute {
+    communities: Vec<Community>,
+}
+
+// MP_REACH_NLRI and MP_UNREACH_NLRI (RFC 4760) carry the routes of the
+// address families other than IPv4 unicast, which has the fields of the
+// UPDATE itself. How the NLRI are encoded depends on the AFI and SAFI,
+// and those we don't decode are kept as they are. The next hop is kept
+// as bytes too, since its length and form also depend on the family.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct MpReachNlriAttribute {
+    afi: u16,
+    safi: u8,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex"))]
+    next_hop: Vec<u8>,
+    nlri: MpNlri,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct MpUnreachNlriAttribute {
+    afi: u16,
+    safi: u8,
+    withdrawn_routes: MpNlri,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+enum MpNlri {
+    // SAFI 4, see mpls.rs.
+    Labeled(Vec<LabeledPrefix>),
+    // SAFI 128, see vpn.rs.
+    Vpn(Vec<VpnPrefix>),
+    // AFI 25 SAFI 70, see evpn.rs.
+    Evpn(Vec<EvpnRoute>),
+    // AFI 16388 SAFI 71, see link_state.rs.
+    LinkState(Vec<LinkStateNlri>),
+    // SAFI 133, see flowspec.rs.
+    FlowSpec(Vec<FlowSpecRule>),
+    Unknown(#[cfg_attr(feature = "serde", serde(with = "json::hex"))] Vec<u8>),
+}
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+const SAFI_MULTICAST: u8 = 2;
+const SAFI_FLOWSPEC: u8 = 133;
+
+fn max_prefix_length(afi: u16) -> usize {
+    match afi {
+        AFI_IPV4 => 32,
+        _ => 128,
+    }
+}
+
+// The address of a prefix in NLRI, from the bytes that were sent.
+fn prefix_address(afi: u16, bytes: &[u8]) -> IpAddr {
+    match afi {
+        AFI_IPV4 => {
+            let mut octets = [0u8; 4];
+            octets[..bytes.len().min(4)].copy_from_slice(&bytes[..bytes.len().min(4)]);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        },
+        _ => {
+            let mut octets = [0u8; 16];
+            octets[..bytes.len().min(16)].copy_from_slice(&bytes[..bytes.len().min(16)]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        },
+    }
+}
+
+// The reverse, just the bytes the length covers.
+fn prefix_bytes(address: IpAddr, length: u8) -> Vec<u8> {
+    let bytes = (length as usize).div_ceil(8);
+    match address {
+        IpAddr::V4(address) => address.octets()[..bytes.min(4)].to_vec(),
+        IpAddr::V6(address) => address.octets()[..bytes.min(16)].to_vec(),
+    }
+}
+
+// The NLRI of families that are nothing but prefixes, like unicast and
+// multicast, which MpNlri leaves undecoded.
+named_args!(plain_prefixes(afi: u16) <Vec<(IpAddr, u8)>>,
+    many0!(complete!(call!(plain_prefix, afi)))
+);
+
+named_args!(plain_prefix(afi: u16) <(IpAddr, u8)>,
+    do_parse!(
+        length: verify!(be_u8, |l: u8| l as usize <= max_prefix_length(afi)) >>
+        prefix: take!((length as usize).div_ceil(8)) >>
+        (prefix_address(afi, prefix), length)
+    )
+);
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+struct ExtendedCommunitiesAttribute {
+    communities: Vec<ExtendedCommunity>,
+}
+
+// The BGP-LS attribute (RFC 9552), see link_state.rs.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+struct LinkStateAttribute {
+    tlvs: Vec<LinkStateTlv>,
+}
+
+// The Only to Customer attribute (RFC 9234), see role.rs.
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+struct OnlyToCustomerAttribute {
+    asn: u32,
+}
+
+// A community (RFC 1997) is conventionally split into the AS number of
+// whoever defined it and a value, and written as "asn:value".
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+struct Community {
+    asn: u16,
+    value: u16,
+}
+
+impl fmt::Display for Community {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.asn, self.value)
+    }
+}
+
+impl FromStr for Community {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Community, ()> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+            (Some(Ok(asn)), Some(Ok(value))) => Ok(Community { asn, value }),
+            _ => Err(()),
+        }
+    }
+}
+
 // Extract the BGP Path Attribute Flags. Is there a nicer way to do
 // this?
 //
@@ -516,14 +1048,16 @@ named!(parse_bgp_path_attribute_flags<&[u8], BgpPathAttributeFlags>,
 // the flags field must be empty and ignored. There's four extra bits
 // that could've been used right there. Conveniently a 12 bit length
 // field would give us lengths up to 4096 bytes, which is more than
-// enough given the maximum length of a BGP message is also 4096 bytes.
+// enough given the maximum length of a BGP message was also 4096 bytes,
+// before Extended Messages (RFC 8654).
+
+// The child parsers are passed the extended length flag and read the
+// length accordingly. Those with a fixed length still assume the short
+// form for now.
 
-// TODO: Make child parsers aware of extended length. Either we pass the
-// flags to the chlid parsers and the child parsers then validate both
-// the flags and length according to the standard, or we pass the length
-// and do the flags verification in the parent parser.
+named!(old_parse_bgp_path_attribute<&[u8], BgpPathAttribute>, call!(parse_bgp_path_attribute_with_encoding, &DEFAULT_UPDATE_ENCODING));
 
-named!(old_parse_bgp_path_attribute<&[u8], BgpPathAttribute>,
+named_args!(parse_bgp_path_attribute_with_encoding<'a>(encoding: &'a UpdateEncoding) <BgpPathAttribute>,
     do_parse!(
         flags: parse_bgp_path_attribute_flags >>
         type_code: be_u8 >>
@@ -533,14 +1067,20 @@ named!(old_parse_bgp_path_attribute<&[u8], BgpPathAttribute>,
         ) >>*/
         attribute: switch!(value!(type_code),
             1 => call!(origin_attribute) |
-            2 => call!(as_path_attribute) |
+            2 => call!(as_path_attribute, flags.extended_length, encoding.four_octet_as) |
             3 => call!(next_hop_attribute) |
             4 => call!(multi_exit_disc_attribute) |
             5 => call!(local_pref_attribute) |
             6 => call!(atomic_aggregate_attribute) |
-            7 => call!(aggregator_attribute)
+            7 => call!(aggregator_attribute, encoding.four_octet_as) |
+            8 => call!(communities_attribute, flags.extended_length) |
+            14 => call!(mp_reach_nlri_attribute, flags.extended_length, encoding) |
+            15 => call!(mp_unreach_nlri_attribute, flags.extended_length, encoding) |
+            16 => call!(extended_communities_attribute, flags.extended_length) |
+            29 => call!(link_state_attribute, flags.extended_length) |
+            35 => call!(only_to_customer_attribute, flags.extended_length)
         ) >>
-        (BgpPathAttribute { flags: flags, attribute: attribute })
+        (BgpPathAttribute { flags, attribute })
     )
 );
 
@@ -557,29 +1097,34 @@ named!(origin_attribute<&[u8], PathAttribute>,
 //named!(as_path_segment_as_vec1<&[u8], Vec<u16>>, preceded!(alt!(tag!([1u8]) | tag!([2u8])), length_count!(be_u8, be_u16)));
 //named!(as_path_segment_as_vec2<&[u8], Vec<u16>>, alt!(as_set | as_sequence));
 
-named!(as_path_segment<&[u8], AsPathSegment>,
+named_args!(as_path_segment(four_octet_as: bool) <AsPathSegment>,
     do_parse!(
-        type_code: verify!(be_u8, |v: u8| v >= 1 && v <= 2) >> // TODO: or use alt!() or one_of!()?
-        seg: length_count!(be_u8, be_u16) >>
+        type_code: verify!(be_u8, |v: u8| (1..=4).contains(&v)) >> // TODO: or use alt!() or one_of!()?
+        seg: switch!(value!(four_octet_as),
+            true => length_count!(be_u8, be_u32) |
+            false => length_count!(be_u8, map!(be_u16, u32::from))
+        ) >>
         (match type_code {
             1u8 => AsPathSegment::AsSet(seg),
             2u8 => AsPathSegment::AsSequence(seg),
+            3u8 => AsPathSegment::AsConfedSequence(seg),
+            4u8 => AsPathSegment::AsConfedSet(seg),
             _ => unreachable!(),
         })
     )
 );
 
-named!(as_path_attribute<&[u8], PathAttribute>,
+named_args!(as_path_attribute(extended_length: bool, four_octet_as: bool) <PathAttribute>,
     do_parse!(
-        length: be_u8 >> // TODO: need to recognize extended length flag
-        as_path_segments: flat_map!(take!(length), complete!(many0!(as_path_segment))) >>
+        length: call!(attribute_length, extended_length) >>
+        as_path_segments: flat_map!(take!(length), complete!(many0!(call!(as_path_segment, four_octet_as)))) >>
         (PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: as_path_segments })))
     )
 );
 
 named!(next_hop_attribute<&[u8], PathAttribute>,
     do_parse!(
-        tag!([4u8]) >> // length should always be 4 (TODO: confirm)
+        tag!([4u8]) >> // length is always 4 (RFC 4271 section 5.1.3)
         next_hop: take!(4) >>
         (PathAttribute::NextHop(Box::new(NextHopAttribute { next_hop: Ipv4Addr::new(next_hop[0], next_hop[1], next_hop[2], next_hop[3]) })))
     )
@@ -589,7 +1134,7 @@ named!(multi_exit_disc_attribute<&[u8], PathAttribute>,
     do_parse!(
         tag!([4u8]) >> // length should always be 4
         metric: be_u32 >>
-        (PathAttribute::MultiExitDisc(Box::new(MultiExitDiscAttribute { metric: metric })))
+        (PathAttribute::MultiExitDisc(Box::new(MultiExitDiscAttribute { metric })))
     )
 );
 
@@ -597,7 +1142,7 @@ named!(local_pref_attribute<&[u8], PathAttribute>,
     do_parse!(
         tag!([4u8]) >> // length should always be 4
         preference: be_u32 >>
-        (PathAttribute::LocalPref(Box::new(LocalPrefAttribute { preference: preference })))
+        (PathAttribute::LocalPref(Box::new(LocalPrefAttribute { preference })))
     )
 );
 
@@ -608,12 +1153,109 @@ named!(atomic_aggregate_attribute<&[u8], PathAttribute>,
     )
 );
 
-named!(aggregator_attribute<&[u8], PathAttribute>,
+named_args!(aggregator_attribute(four_octet_as: bool) <PathAttribute>,
     do_parse!(
-        tag!([6u8]) >> // length should always be 6
-        aggregator_as: be_u16 >>
+        aggregator_as: switch!(value!(four_octet_as),
+            true => preceded!(tag!([8u8]), be_u32) |
+            false => preceded!(tag!([6u8]), map!(be_u16, u32::from))
+        ) >>
         aggregator_id: take!(4) >>
-        (PathAttribute::Aggregator(Box::new(AggregatorAttribute { aggregator_as: aggregator_as, aggregator_id: Ipv4Addr::new(aggregator_id[0], aggregator_id[1], aggregator_id[2], aggregator_id[3]) })))
+        (PathAttribute::Aggregator(Box::new(AggregatorAttribute { aggregator_as, aggregator_id: Ipv4Addr::new(aggregator_id[0], aggregator_id[1], aggregator_id[2], aggregator_id[3]) })))
+    )
+);
+
+named!(community<&[u8], Community>,
+    do_parse!(
+        asn: be_u16 >>
+        value: be_u16 >>
+        (Community { asn, value })
+    )
+);
+
+named_args!(communities_attribute(extended_length: bool) <PathAttribute>,
+    do_parse!(
+        length: verify!(call!(attribute_length, extended_length), |v: u16| v & 3 == 0) >>
+        communities: flat_map!(take!(length), complete!(many0!(community))) >>
+        (PathAttribute::Communities(Box::new(CommunitiesAttribute { communities })))
+    )
+);
+
+// The length is one or two bytes depending on the extended length flag.
+// Any attribute may be sent with it set, and the MP ones in particular
+// are often longer than 255 bytes.
+named_args!(attribute_length(extended_length: bool) <u16>,
+    switch!(value!(extended_length),
+        true => call!(be_u16) |
+        false => map!(be_u8, |v: u8| v as u16)
+    )
+);
+
+named_args!(mp_reach_nlri_attribute<'a>(extended_length: bool, encoding: &'a UpdateEncoding) <PathAttribute>,
+    do_parse!(
+        length: call!(attribute_length, extended_length) >>
+        attribute: flat_map!(take!(length), do_parse!(
+            afi: be_u16 >>
+            safi: be_u8 >>
+            next_hop: length_bytes!(be_u8) >>
+            be_u8 >> // reserved
+            nlri: call!(mp_nlri, afi, safi, false, encoding.add_path(afi, safi)) >>
+            (PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute { afi, safi, next_hop: next_hop.to_vec(), nlri })))
+        )) >>
+        (attribute)
+    )
+);
+
+named_args!(mp_unreach_nlri_attribute<'a>(extended_length: bool, encoding: &'a UpdateEncoding) <PathAttribute>,
+    do_parse!(
+        length: call!(attribute_length, extended_length) >>
+        attribute: flat_map!(take!(length), do_parse!(
+            afi: be_u16 >>
+            safi: be_u8 >>
+            withdrawn_routes: call!(mp_nlri, afi, safi, true, encoding.add_path(afi, safi)) >>
+            (PathAttribute::MpUnreachNlri(Box::new(MpUnreachNlriAttribute { afi, safi, withdrawn_routes })))
+        )) >>
+        (attribute)
+    )
+);
+
+// The rest of the attribute, all of which has to be NLRI of the family.
+// Some are encoded differently when withdrawn. NLRI with path identifiers
+// aren't decoded, and are kept as they are.
+named_args!(mp_nlri(afi: u16, safi: u8, withdrawn: bool, add_path: bool) <MpNlri>,
+    switch!(value!(if add_path { (0, 0) } else { (afi, safi) }),
+        (AFI_IPV4, mpls::SAFI_LABELED_UNICAST) => map!(terminated!(many0!(complete!(call!(mpls::labeled_prefix, afi, withdrawn))), eof!()), MpNlri::Labeled) |
+        (AFI_IPV6, mpls::SAFI_LABELED_UNICAST) => map!(terminated!(many0!(complete!(call!(mpls::labeled_prefix, afi, withdrawn))), eof!()), MpNlri::Labeled) |
+        (AFI_IPV4, vpn::SAFI_VPN) => map!(terminated!(many0!(complete!(call!(vpn::vpn_prefix, afi, withdrawn))), eof!()), MpNlri::Vpn) |
+        (AFI_IPV6, vpn::SAFI_VPN) => map!(terminated!(many0!(complete!(call!(vpn::vpn_prefix, afi, withdrawn))), eof!()), MpNlri::Vpn) |
+        (evpn::AFI_L2VPN, evpn::SAFI_EVPN) => map!(terminated!(many0!(complete!(evpn::evpn_route)), eof!()), MpNlri::Evpn) |
+        (link_state::AFI_LINK_STATE, link_state::SAFI_LINK_STATE) => map!(terminated!(many0!(complete!(link_state::link_state_nlri)), eof!()), MpNlri::LinkState) |
+        (AFI_IPV4, SAFI_FLOWSPEC) => map!(terminated!(many0!(complete!(call!(flowspec::flowspec_rule, afi))), eof!()), MpNlri::FlowSpec) |
+        (AFI_IPV6, SAFI_FLOWSPEC) => map!(terminated!(many0!(complete!(call!(flowspec::flowspec_rule, afi))), eof!()), MpNlri::FlowSpec) |
+        _ => map!(call!(nom::rest), |r: &[u8]| MpNlri::Unknown(r.to_vec()))
+    )
+);
+
+named_args!(extended_communities_attribute(extended_length: bool) <PathAttribute>,
+    do_parse!(
+        length: verify!(call!(attribute_length, extended_length), |v: u16| v & 7 == 0) >>
+        communities: flat_map!(take!(length), complete!(many0!(extended_community::extended_community))) >>
+        (PathAttribute::ExtendedCommunities(Box::new(ExtendedCommunitiesAttribute { communities })))
+    )
+);
+
+named_args!(link_state_attribute(extended_length: bool) <PathAttribute>,
+    do_parse!(
+        length: call!(attribute_length, extended_length) >>
+        tlvs: flat_map!(take!(length), link_state::link_state_tlvs) >>
+        (PathAttribute::LinkState(Box::new(LinkStateAttribute { tlvs })))
+    )
+);
+
+named_args!(only_to_customer_attribute(extended_length: bool) <PathAttribute>,
+    do_parse!(
+        verify!(call!(attribute_length, extended_length), |v: u16| v == 4) >>
+        asn: be_u32 >>
+        (PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn })))
     )
 );
 
@@ -630,7 +1272,7 @@ named!(aggregator_attribute<&[u8], PathAttribute>,
 named!(new_parse_bgp_path_attribute<&[u8], PathAttribute>,
     alt!(new_origin_attribute | new_as_path_attribute | new_next_hop_attribute | 
         new_multi_exit_disc_attribute | new_local_pref_attribute | new_atomic_aggregate_attribute |
-        new_aggregator_attribute)
+        new_aggregator_attribute | new_communities_attribute | new_only_to_customer_attribute)
 );
 
 named!(new_origin_attribute<&[u8], PathAttribute>,
@@ -646,7 +1288,7 @@ named!(new_as_path_attribute<&[u8], PathAttribute>,
     do_parse!(
         bits!(tag_bits!(u8, 8, 0b0100_0000)) >>
         tag!([2u8]) >> // as_path type code is 2
-        attr: as_path_attribute >>
+        attr: call!(as_path_attribute, false, false) >>
         (attr)
     )
 );
@@ -691,16 +1333,81 @@ named!(new_aggregator_attribute<&[u8], PathAttribute>,
     do_parse!(
         bits!(tag_bits!(u8, 8, 0b1100_0000)) >>
         tag!([7u8]) >> // type code 5
-        attr: aggregator_attribute >>
+        attr: call!(aggregator_attribute, false) >>
         (attr)
     )
 );
 
+named!(new_communities_attribute<&[u8], PathAttribute>,
+    do_parse!(
+        bits!(tag_bits!(u8, 8, 0b1100_0000)) >>
+        tag!([8u8]) >> // type code 8
+        attr: call!(communities_attribute, false) >>
+        (attr)
+    )
+);
+
+named!(new_only_to_customer_attribute<&[u8], PathAttribute>,
+    do_parse!(
+        bits!(tag_bits!(u8, 8, 0b1100_0000)) >>
+        tag!([35u8]) >> // type code 35
+        attr: call!(only_to_customer_attribute, false) >>
+        (attr)
+    )
+);
+
+// Find the innermost error position, and the BGP error code if any of
+// the parsers on the way down returned one. The code is CODE<<8|SUBCODE
+// as used with return_error!() above.
+fn error_details<'a>(e: &nom::Err<&'a [u8]>) -> (Option<&'a [u8]>, Option<u32>) {
+    let (kind, position, children) = match *e {
+        nom::Err::Code(ref kind) => (kind, None, None),
+        nom::Err::Node(ref kind, ref children) => (kind, None, Some(children)),
+        nom::Err::Position(ref kind, position) => (kind, Some(position), None),
+        nom::Err::NodePosition(ref kind, position, ref children) => (kind, Some(position), Some(children)),
+    };
+
+    let code = match *kind {
+        ErrorKind::Custom(code) => Some(code),
+        _ => None,
+    };
+
+    match children.and_then(|c| c.last()).map(error_details) {
+        Some((child_position, child_code)) => (child_position.or(position), code.or(child_code)),
+        None => (position, code),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let stdout = std::io::stdout();
+    let stderr = std::io::stderr();
+    std::process::exit(dump::run(&args, &mut stdout.lock(), &mut stderr.lock()));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use nom::{HexDisplay, IResult};
 
+    // Message framing
+
+    #[test]
+    fn bgp_message_frame_test() {
+        let open = include_bytes!("../assets/test_bgp_open1.bin");
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+
+        let mut stream = Vec::from(&open[..]);
+        stream.extend_from_slice(&keepalive[..]);
+
+        assert_eq!(bgp_message_frame(&stream), Done(&keepalive[..], &open[..]));
+        assert_eq!(bgp_message_frame(&keepalive[..]), Done(&b""[..], &keepalive[..]));
+        assert_eq!(bgp_message_frame(&open[..20]), Incomplete(Needed::Size(29)));
+
+        stream[17] = 18;
+        match bgp_message_frame(&stream) { IResult::Error(_) => {}, other => panic!("expected error: {:?}", other), }
+    }
+
     // Open message
 
     #[test]
@@ -709,7 +1416,7 @@ mod tests {
         let slice = &input[..];
 
         let msg = BgpMessage::Open(Box::new(
-            BgpOpenMessage { version: 4, my_autonomous_system: 65033, hold_time: 180, bgp_identifier: 3232235535, optional_parameters: vec![] }
+            BgpOpenMessage { version: 4, my_autonomous_system: 65033, hold_time: 180, bgp_identifier: Ipv4Addr::new(192, 168, 0, 15), optional_parameters: vec![] }
         ));
 
         let res = Done(&b""[..], msg);
@@ -734,12 +1441,12 @@ mod tests {
 
         let optional_parameters = vec![
             OptionalParameter::Capability(vec![CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi: 1, safi: 1 }))]),
-            OptionalParameter::Capability(vec![]),
+            OptionalParameter::Capability(vec![CapabilityParameter::Unknown(Box::new(UnknownCapability { code: 128, data: vec![] }))]),
             OptionalParameter::Capability(vec![CapabilityParameter::RouteRefresh])
         ];
 
         let msg = BgpMessage::Open(Box::new(
-            BgpOpenMessage { version: 4, my_autonomous_system: 65200, hold_time: 180, bgp_identifier: 169083649, optional_parameters: optional_parameters }
+            BgpOpenMessage { version: 4, my_autonomous_system: 65200, hold_time: 180, bgp_identifier: Ipv4Addr::new(10, 20, 3, 1), optional_parameters }
         ));
 
         let res = Done(&b""[..], msg);
@@ -759,7 +1466,7 @@ mod tests {
 
         let optional_parameters = vec![
             OptionalParameter::Capability(vec![CapabilityParameter::MultiprotocolExtensions(Box::new(MultiprotocolExtensionsCapability { afi: 1, safi: 1 }))]),
-            OptionalParameter::Capability(vec![]),
+            OptionalParameter::Capability(vec![CapabilityParameter::Unknown(Box::new(UnknownCapability { code: 128, data: vec![] }))]),
             OptionalParameter::Capability(vec![CapabilityParameter::RouteRefresh])
         ];
         
@@ -802,6 +1509,84 @@ mod tests {
             CapabilityParameter::RouteRefresh));
     }
 
+    #[test]
+    fn extended_message_capability_test() {
+        assert_eq!(parse_capability_parameter(&[6, 0]), Done(&b""[..], CapabilityParameter::ExtendedMessage));
+        assert!(extended_message_capability(&[6, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn four_octet_as_capability_test() {
+        assert_eq!(parse_capability_parameter(&[65, 4, 0, 0x03, 0x0d, 0x40]),
+            Done(&b""[..], CapabilityParameter::FourOctetAs(Box::new(FourOctetAsCapability { asn: 200000 }))));
+    }
+
+    #[test]
+    fn unknown_capability_test() {
+        // Graceful Restart, which we don't decode, ahead of a Role.
+        assert_eq!(optional_parameter_capability(&[2, 7, 64, 2, 0, 120, 9, 1, 3]), Done(&b""[..], OptionalParameter::Capability(vec![
+            CapabilityParameter::Unknown(Box::new(UnknownCapability { code: 64, data: vec![0, 120] })),
+            CapabilityParameter::Role(Box::new(RoleCapability { role: BgpRole::Customer })),
+        ])));
+
+        // A known capability with a length we don't expect.
+        assert_eq!(parse_capability_parameter(&[9, 2, 3, 0]),
+            Done(&b""[..], CapabilityParameter::Unknown(Box::new(UnknownCapability { code: 9, data: vec![3, 0] }))));
+        assert!(optional_parameter_capability(&[2, 4, 64, 3, 0, 120]).is_err());
+    }
+
+    #[test]
+    fn bgp_identifier_test() {
+        assert_eq!(bgp_identifier(&[10, 0, 0, 1]), Done(&b""[..], Ipv4Addr::new(10, 0, 0, 1)));
+        // Not host addresses, but RFC 6286 allows them.
+        assert_eq!(bgp_identifier(&[240, 0, 0, 1]), Done(&b""[..], Ipv4Addr::new(240, 0, 0, 1)));
+        assert_eq!(bgp_identifier(&[127, 0, 0, 1]), Done(&b""[..], Ipv4Addr::new(127, 0, 0, 1)));
+        match bgp_identifier(&[0, 0, 0, 0]) {
+            IResult::Error(ref e) => assert_eq!(error_details(e).1, Some(BAD_BGP_IDENTIFIER)),
+            other => panic!("expected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn extended_message_length_test() {
+        // The header of a KEEPALIVE claiming to be 5000 bytes long.
+        let mut message = vec![0xff; 16];
+        message.extend_from_slice(&[0x13, 0x88, 4]);
+        let code = |result: IResult<&[u8], BgpMessage>| match result {
+            IResult::Error(ref e) => error_details(e).1,
+            other => panic!("expected error: {:?}", other),
+        };
+        assert_eq!(code(parse_bgp_message(&message)), Some(BAD_MESSAGE_LENGTH));
+        match bgp_message_frame(&message) {
+            IResult::Error(ref e) => assert_eq!(error_details(e).1, Some(BAD_MESSAGE_LENGTH)),
+            other => panic!("expected error: {:?}", other),
+        }
+        assert_eq!(extended_bgp_message_frame(&message), Incomplete(Needed::Size(5000)));
+
+        // An OPEN can't be longer than 4096 even so.
+        message[18] = 1;
+        assert_eq!(code(parse_extended_bgp_message(&message)), Some(BAD_MESSAGE_LENGTH));
+    }
+
+    #[test]
+    fn multiple_labels_capability_test() {
+        assert_eq!(parse_capability_parameter(&[8, 8, 0, 1, 4, 3, 0, 2, 128, 2]), Done(&b""[..],
+            CapabilityParameter::MultipleLabels(Box::new(MultipleLabelsCapability { counts: vec![
+                LabelCount { afi: 1, safi: 4, count: 3 },
+                LabelCount { afi: 2, safi: 128, count: 2 },
+            ] }))));
+        assert!(multiple_labels_capability(&[8, 3, 0, 1, 4]).is_err());
+    }
+
+    #[test]
+    fn role_capability_test() {
+        assert_eq!(role_capability(&[9, 1, 3]), Done(&b""[..],
+            CapabilityParameter::Role(Box::new(RoleCapability { role: BgpRole::Customer }))));
+        assert_eq!(parse_capability_parameter(&[9, 1, 7]), Done(&b""[..],
+            CapabilityParameter::Role(Box::new(RoleCapability { role: BgpRole::Unknown(7) }))));
+        assert!(role_capability(&[9, 2, 3, 0]).is_err());
+    }
+
     // Keepalive message
     
     #[test]
@@ -828,14 +1613,37 @@ mod tests {
     fn parse_bgp_notification_test() {
         let input = include_bytes!("../assets/test_bgp_notification1.bin");
         let slice = &input[19..];
-        assert_eq!(parse_bgp_notification(slice, 23), IResult::Done(&b""[..], BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 2, error_subcode: 2 }))));
+        assert_eq!(parse_bgp_notification(slice, 23), IResult::Done(&b""[..], BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 2, error_subcode: 2, data: vec![0xfe, 0xb0] }))));
     }
 
     #[test]
     fn parse_bgp_notification_full_test() {
         let input = include_bytes!("../assets/test_bgp_notification1.bin");
         let slice = &input[..];
-        assert_eq!(parse_bgp_message(slice), IResult::Done(&b""[..], BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 2, error_subcode: 2 }))));
+        assert_eq!(parse_bgp_message(slice), IResult::Done(&b""[..], BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 2, error_subcode: 2, data: vec![0xfe, 0xb0] }))));
+    }
+
+    #[test]
+    fn parse_bgp_notification_subcode_test() {
+        // Unspecific, and codes and subcodes we don't know, are all kept.
+        for &(code, subcode) in &[(4, 0), (6, 0), (4, 1), (6, 11), (1, 4), (7, 1), (42, 3)] {
+            match parse_bgp_notification(&[code, subcode], 21) {
+                IResult::Done(_, BgpMessage::Notification(notification)) => {
+                    assert_eq!((notification.error_code, notification.error_subcode), (code, subcode));
+                    assert_eq!(notification.error(), NotificationError::new(code, subcode));
+                },
+                other => panic!("failed to parse: {:?}", other),
+            }
+        }
+
+        let input = [6, 1, 0, 1, 1, 0, 0, 0x03, 0xe8];
+        match parse_bgp_notification(&input, 28) {
+            IResult::Done(_, BgpMessage::Notification(notification)) => {
+                assert_eq!(notification.cease_subcode(), Some(CeaseSubcode::MaximumNumberOfPrefixesReached));
+                assert_eq!(notification.data, vec![0, 1, 1, 0, 0, 0x03, 0xe8]);
+            },
+            other => panic!("failed to parse: {:?}", other),
+        }
     }
 
     #[test]
@@ -867,7 +1675,7 @@ mod tests {
     #[test]
     fn parse_bgp_prefix_test() {
         let input = include_bytes!("../assets/test_bgp_nlri2.bin");
-        assert_eq!(parse_bgp_prefix(input), IResult::Done(&b""[..], Ipv4Prefix { prefix: vec![192u8, 168, 4], length: 22 }));
+        assert_eq!(parse_bgp_prefix(input), IResult::Done(&b""[..], Ipv4Prefix { prefix: vec![192u8, 168, 4], length: 22, path_id: None }));
     }
 
     #[test]
@@ -943,6 +1751,28 @@ mod tests {
         assert_eq!(new_as_path_attribute(slice), IResult::Done(&b""[..], PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: vec![AsPathSegment::AsSet(vec![500, 500]), AsPathSegment::AsSequence(vec![65211])] }))));
     }
 
+    #[test]
+    fn as_path_confederation_test() {
+        // A confederation sequence and set ahead of the path from outside.
+        let input = [0x40, 2, 16, 3, 2, 0xfd, 0xe9, 0xfd, 0xea, 4, 1, 0xfd, 0xeb, 2, 2, 0x00, 0x64, 0x00, 0xc8];
+        let as_path = AsPathAttribute { as_path: vec![
+            AsPathSegment::AsConfedSequence(vec![65001, 65002]),
+            AsPathSegment::AsConfedSet(vec![65003]),
+            AsPathSegment::AsSequence(vec![100, 200]),
+        ] };
+        assert_eq!(old_parse_bgp_path_attribute(&input), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+            attribute: PathAttribute::AsPath(Box::new(as_path.clone())),
+        }));
+        assert_eq!(as_path.path_length(), 2);
+        assert_eq!(as_path.without_confederation(), AsPathAttribute { as_path: vec![AsPathSegment::AsSequence(vec![100, 200])] });
+
+        let as_path = AsPathAttribute { as_path: vec![AsPathSegment::AsSequence(vec![100, 200]), AsPathSegment::AsSet(vec![300, 400])] };
+        assert_eq!(as_path.path_length(), 3);
+
+        assert!(as_path_segment(&[5, 1, 0x00, 0x64], false).is_err());
+    }
+
     #[test]
     fn new_next_hop_attribute_test() {
         let input = include_bytes!("../assets/test_bgp_path_attribute_next_hop1.bin");
@@ -975,6 +1805,127 @@ mod tests {
         assert_eq!(new_atomic_aggregate_attribute(slice), IResult::Done(&b""[..], PathAttribute::AtomicAggregate));
     }
 
+    #[test]
+    fn new_communities_attribute_test() {
+        let input = include_bytes!("../assets/test_bgp_path_attribute_communities1.bin");
+        let slice = &input[..];
+        assert_eq!(new_communities_attribute(slice), IResult::Done(&b""[..], PathAttribute::Communities(Box::new(CommunitiesAttribute { communities: vec![
+            Community { asn: 65215, value: 1 }, Community { asn: 790, value: 4 }, Community { asn: 340, value: 250 }
+        ] }))));
+
+        let input = include_bytes!("../assets/test_bgp_path_attribute_communities2.bin");
+        let slice = &input[..];
+        assert_eq!(new_communities_attribute(slice), IResult::Done(&b""[..], PathAttribute::Communities(Box::new(CommunitiesAttribute { communities: vec![
+            Community { asn: 65033, value: 500 }, Community { asn: 65033, value: 600 }
+        ] }))));
+    }
+
+    #[test]
+    fn extended_length_attribute_test() {
+        let flags = BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: true };
+        assert_eq!(old_parse_bgp_path_attribute(&[0x50, 2, 0, 6, 2, 2, 0x00, 0x64, 0x00, 0xc8]), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: flags.clone(),
+            attribute: PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: vec![AsPathSegment::AsSequence(vec![100, 200])] })),
+        }));
+
+        let flags = BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: true };
+        assert_eq!(old_parse_bgp_path_attribute(&[0xd0, 8, 0, 4, 0xfd, 0xe9, 0, 100]), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: flags.clone(),
+            attribute: PathAttribute::Communities(Box::new(CommunitiesAttribute { communities: vec![Community { asn: 65001, value: 100 }] })),
+        }));
+        assert_eq!(old_parse_bgp_path_attribute(&[0xd0, 35, 0, 4, 0, 0, 0xfd, 0xe9]), IResult::Done(&b""[..], BgpPathAttribute {
+            flags,
+            attribute: PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn: 65001 })),
+        }));
+        assert!(old_parse_bgp_path_attribute(&[0xd0, 35, 0, 2, 0xfd, 0xe9]).is_err());
+    }
+
+    #[test]
+    fn new_only_to_customer_attribute_test() {
+        let slice = &[0xc0, 35, 4, 0, 0, 0xfd, 0xe9][..];
+        assert_eq!(new_only_to_customer_attribute(slice), IResult::Done(&b""[..], PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn: 65001 }))));
+        assert_eq!(old_parse_bgp_path_attribute(slice), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+            attribute: PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn: 65001 })),
+        }));
+        assert!(new_only_to_customer_attribute(&[0xc0, 35, 2, 0xfd, 0xe9]).is_err());
+    }
+
+    #[test]
+    fn mp_reach_nlri_flowspec_test() {
+        // An IPv4 FlowSpec rule, with the extended length bit set.
+        let slice = &[
+            0x90, 14, 0x00, 0x11, 0x00, 0x01, 133, 0, 0,
+            0x0b, 0x01, 0x18, 0xc0, 0x00, 0x02, 0x03, 0x81, 0x06, 0x05, 0x81, 0x50,
+        ][..];
+        let rule = FlowSpecRule { components: vec![
+            flowspec::FlowSpecComponent::DestinationPrefix(flowspec::FlowSpecPrefix { address: "192.0.2.0".parse().unwrap(), length: 24, offset: 0 }),
+            flowspec::FlowSpecComponent::Protocol(vec![flowspec::NumericMatch { and: false, lt: false, gt: false, eq: true, value: 6 }]),
+            flowspec::FlowSpecComponent::DestinationPort(vec![flowspec::NumericMatch { and: false, lt: false, gt: false, eq: true, value: 80 }]),
+        ] };
+        assert_eq!(rule.to_string(), "dst 192.0.2.0/24 proto =6 dport =80");
+        assert_eq!(old_parse_bgp_path_attribute(slice), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: true },
+            attribute: PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute { afi: AFI_IPV4, safi: SAFI_FLOWSPEC, next_hop: vec![], nlri: MpNlri::FlowSpec(vec![rule.clone()]) })),
+        }));
+
+        // Withdrawn, without the extended length.
+        let slice = &[0x80, 15, 0x0f, 0x00, 0x01, 133, 0x0b, 0x01, 0x18, 0xc0, 0x00, 0x02, 0x03, 0x81, 0x06, 0x05, 0x81, 0x50][..];
+        assert_eq!(old_parse_bgp_path_attribute(slice), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+            attribute: PathAttribute::MpUnreachNlri(Box::new(MpUnreachNlriAttribute { afi: AFI_IPV4, safi: SAFI_FLOWSPEC, withdrawn_routes: MpNlri::FlowSpec(vec![rule]) })),
+        }));
+
+        // Other families are kept as they are.
+        let slice = &[0x80, 14, 0x0e, 0x00, 0x01, 0x01, 4, 10, 0, 0, 1, 0, 8, 10, 16, 172, 16][..];
+        assert_eq!(old_parse_bgp_path_attribute(slice), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+            attribute: PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute { afi: AFI_IPV4, safi: 1, next_hop: vec![10, 0, 0, 1], nlri: MpNlri::Unknown(vec![8, 10, 16, 172, 16]) })),
+        }));
+
+        // A rule running past the end of the attribute.
+        assert!(old_parse_bgp_path_attribute(&[0x80, 15, 0x06, 0x00, 0x01, 133, 0x05, 0x03, 0x81][..]).is_err());
+
+        // An UPDATE with an IPv6 prefix whose offset is its whole length
+        // is parsed without its MP_REACH_NLRI, rather than panicking.
+        let mut update = vec![0xff; 16];
+        update.extend_from_slice(&[0, 36, 2, 0, 0, 0, 13, 0x90, 14, 0x00, 0x09, 0x00, 0x02, 133, 0, 0, 0x03, 0x01, 128, 128]);
+        match parse_bgp_message(&update) {
+            IResult::Done(_, BgpMessage::Update(update)) => assert_eq!(update.path_attributes, vec![]),
+            other => panic!("expected an UPDATE: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn extended_communities_attribute_test() {
+        let slice = &[0xc0, 16, 16, 0x80, 0x06, 0, 0, 0, 0, 0, 0, 0x80, 0x09, 0, 0, 0, 0, 0, 0x2e][..];
+        assert_eq!(old_parse_bgp_path_attribute(slice), IResult::Done(&b""[..], BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+            attribute: PathAttribute::ExtendedCommunities(Box::new(ExtendedCommunitiesAttribute { communities: vec![
+                ExtendedCommunity::TrafficRateBytes { asn: 0, rate: 0.0 },
+                ExtendedCommunity::TrafficMarking { dscp: 46 },
+            ] })),
+        }));
+        assert!(old_parse_bgp_path_attribute(&[0xc0, 16, 4, 0x80, 0x09, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn community_from_str_test() {
+        assert_eq!("65033:500".parse::<Community>(), Ok(Community { asn: 65033, value: 500 }));
+        assert_eq!(Community { asn: 65033, value: 500 }.to_string(), "65033:500");
+        assert!("65033".parse::<Community>().is_err());
+        assert!("65536:1".parse::<Community>().is_err());
+    }
+
+    #[test]
+    fn ipv4_prefix_from_str_test() {
+        assert_eq!("192.168.4.0/22".parse::<Ipv4Prefix>(), Ok(Ipv4Prefix { prefix: vec![192, 168, 4], length: 22, path_id: None }));
+        assert_eq!("0.0.0.0/0".parse::<Ipv4Prefix>(), Ok(Ipv4Prefix { prefix: vec![], length: 0, path_id: None }));
+        assert_eq!(Ipv4Prefix { prefix: vec![172, 16], length: 16, path_id: None }.to_string(), "172.16.0.0/16");
+        assert!("10.0.0.0".parse::<Ipv4Prefix>().is_err());
+        assert!("10.0.0.0/33".parse::<Ipv4Prefix>().is_err());
+    }
+
     #[test]
     fn new_aggregator_attribute_test() {
         let input = include_bytes!("../assets/test_bgp_path_attribute_aggregator1.bin");
@@ -0,0 +1,489 @@
+// A borrowed view of BGP messages, for reading them without decoding
+// everything in them.
+//
+// Parsing into a BgpMessage allocates for every prefix, attribute and AS
+// path. A BgpMessageRef points into the input instead. Parsing one only checks
+// the framing, which for an UPDATE includes the prefixes and attributes
+// fitting where they should, and the withdrawn routes, attributes and
+// NLRI are then read with iterators over the input. Each of those can be
+// decoded into the owned type on its own, or the whole message with
+// to_message().
+//
+// The NEXT_HOP and AS_PATH, being what's most often looked at, can be
+// read without decoding anything else. OPENs and NOTIFICATIONs are rare
+// enough that they're just kept as their bodies.
+//
+// Like the parsers for archives and captures this allows extended
+// messages (RFC 8654), as we can't know what was negotiated. Whether AS
+// numbers are four octets is said when parsing, as an MRT archive knows
+// it, and is used for the AS_PATH and for decoding attributes.
+//
+// The benchmark at the end compares this with parse_bgp_message on the
+// whole messages in assets/. It's ignored as it's slow in a debug build,
+// run it with:
+//
+//   cargo test --release message_ref_benchmark -- --ignored --nocapture
+
+use std::fmt;
+
+use super::*;
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum BgpMessageRef<'a> {
+    Open(&'a [u8]),
+    Update(BgpUpdateRef<'a>),
+    Notification(&'a [u8]),
+    Keepalive,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct BgpUpdateRef<'a> {
+    withdrawn_routes: &'a [u8],
+    path_attributes: &'a [u8],
+    nlri: &'a [u8],
+    four_octet_as: bool,
+}
+
+named!(pub parse_bgp_message_ref<BgpMessageRef>, call!(parse_bgp_message_ref_with_as4, false));
+
+named_args!(pub parse_bgp_message_ref_with_as4(four_octet_as: bool) <BgpMessageRef>,
+    do_parse!(
+        bgp_header_marker >>
+        length: call!(bgp_header_length, MAX_EXTENDED_MESSAGE_LENGTH) >>
+        message_type: bgp_header_type >>
+        message: switch!(value!(message_type),
+            1u8 => map!(take!(length - 19), BgpMessageRef::Open) |
+            2u8 => call!(update_ref, length, four_octet_as) |
+            3u8 => map!(take!(length - 19), BgpMessageRef::Notification) |
+            4u8 => value!(BgpMessageRef::Keepalive, return_error!(ErrorKind::Custom(BAD_MESSAGE_LENGTH), verify!(value!(length), |v: u16| v == 19)))
+        ) >>
+        (message)
+    )
+);
+
+// The length is of the whole message, which is what tells us where the
+// NLRI end.
+fn update_ref(input: &[u8], length: u16, four_octet_as: bool) -> IResult<&[u8], BgpMessageRef<'_>> {
+    do_parse!(input,
+        withdrawn_routes: return_error!(ErrorKind::Custom(MALFORMED_ATTRIBUTE_LIST), verify!(length_bytes!(be_u16), valid_prefixes)) >>
+        path_attributes: return_error!(ErrorKind::Custom(MALFORMED_ATTRIBUTE_LIST), verify!(length_bytes!(be_u16), valid_path_attributes)) >>
+        nlri_length: return_error!(ErrorKind::Custom(MALFORMED_ATTRIBUTE_LIST), expr_opt!((length as usize).checked_sub(23 + withdrawn_routes.len() + path_attributes.len()))) >>
+        nlri: return_error!(ErrorKind::Custom(INVALID_NETWORK_FIELD), verify!(take!(nlri_length), valid_prefixes)) >>
+        (BgpMessageRef::Update(BgpUpdateRef { withdrawn_routes, path_attributes, nlri, four_octet_as }))
+    )
+}
+
+fn valid_prefixes(data: &[u8]) -> bool {
+    let mut prefixes = PrefixIter { data };
+    prefixes.all(|p| p.length <= 32) && prefixes.data.is_empty()
+}
+
+fn valid_path_attributes(data: &[u8]) -> bool {
+    let mut attributes = PathAttributeIter { data, four_octet_as: false };
+    attributes.by_ref().count();
+    attributes.data.is_empty()
+}
+
+impl<'a> BgpMessageRef<'a> {
+    // The owned message, or None if the body doesn't parse.
+    pub fn to_message(self) -> Option<BgpMessage> {
+        let result = match self {
+            BgpMessageRef::Open(body) => parse_bgp_open(body, body.len() as u16 + 19),
+            BgpMessageRef::Update(update) => return Some(BgpMessage::Update(Box::new(update.to_update()))),
+            BgpMessageRef::Notification(body) => parse_bgp_notification(body, body.len() as u16 + 19),
+            BgpMessageRef::Keepalive => return Some(BgpMessage::Keepalive),
+        };
+        match result {
+            Done(_, message) => Some(message),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> BgpUpdateRef<'a> {
+    pub fn withdrawn_routes(&self) -> PrefixIter<'a> {
+        PrefixIter { data: self.withdrawn_routes }
+    }
+
+    pub fn path_attributes(&self) -> PathAttributeIter<'a> {
+        PathAttributeIter { data: self.path_attributes, four_octet_as: self.four_octet_as }
+    }
+
+    pub fn nlri(&self) -> PrefixIter<'a> {
+        PrefixIter { data: self.nlri }
+    }
+
+    pub fn path_attribute(&self, type_code: u8) -> Option<PathAttributeRef<'a>> {
+        self.path_attributes().find(|a| a.type_code == type_code)
+    }
+
+    pub fn next_hop(&self) -> Option<Ipv4Addr> {
+        match self.path_attribute(3)?.value {
+            &[a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+            _ => None,
+        }
+    }
+
+    pub fn as_path(&self) -> Option<AsPathRef<'a>> {
+        self.path_attribute(2).map(|a| AsPathRef { data: a.value, four_octet_as: self.four_octet_as })
+    }
+
+    // Attributes we don't decode are left out, where the owned parser
+    // would stop at them.
+    pub fn to_update(self) -> BgpUpdateMessage {
+        BgpUpdateMessage {
+            withdrawn_routes: self.withdrawn_routes().map(|p| p.to_prefix()).collect(),
+            path_attributes: self.path_attributes().filter_map(|a| a.decode()).collect(),
+            nlri: self.nlri().map(|p| p.to_prefix()).collect(),
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct PrefixRef<'a> {
+    prefix: &'a [u8],
+    length: u8,
+}
+
+impl<'a> PrefixRef<'a> {
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    pub fn addr(&self) -> Ipv4Addr {
+        let mut octets = [0u8; 4];
+        for (o, p) in octets.iter_mut().zip(self.prefix.iter()) {
+            *o = *p;
+        }
+        Ipv4Addr::from(octets)
+    }
+
+    pub fn to_prefix(self) -> Ipv4Prefix {
+        Ipv4Prefix { prefix: self.prefix.to_vec(), length: self.length, path_id: None }
+    }
+}
+
+impl<'a> fmt::Display for PrefixRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr(), self.length)
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct PrefixIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for PrefixIter<'a> {
+    type Item = PrefixRef<'a>;
+
+    fn next(&mut self) -> Option<PrefixRef<'a>> {
+        let (&length, rest) = self.data.split_first()?;
+        let octets = (length as usize).div_ceil(8);
+        let prefix = rest.get(..octets)?;
+        self.data = &rest[octets..];
+        Some(PrefixRef { prefix, length })
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct PathAttributeRef<'a> {
+    flags: u8,
+    type_code: u8,
+    value: &'a [u8],
+    // The whole attribute, header and all.
+    data: &'a [u8],
+    four_octet_as: bool,
+}
+
+impl<'a> PathAttributeRef<'a> {
+    pub fn flags(&self) -> BgpPathAttributeFlags {
+        BgpPathAttributeFlags {
+            optional: self.flags & 0x80 != 0,
+            transitive: self.flags & 0x40 != 0,
+            partial: self.flags & 0x20 != 0,
+            extended_length: self.flags & 0x10 != 0,
+        }
+    }
+
+    pub fn type_code(&self) -> u8 {
+        self.type_code
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    // The owned attribute, or None for one we don't decode.
+    pub fn decode(&self) -> Option<BgpPathAttribute> {
+        match parse_bgp_path_attribute_with_encoding(self.data, UpdateEncoding::archived(self.four_octet_as)) {
+            Done(_, attribute) => Some(attribute),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct PathAttributeIter<'a> {
+    data: &'a [u8],
+    four_octet_as: bool,
+}
+
+impl<'a> Iterator for PathAttributeIter<'a> {
+    type Item = PathAttributeRef<'a>;
+
+    fn next(&mut self) -> Option<PathAttributeRef<'a>> {
+        let (flags, type_code) = (*self.data.first()?, *self.data.get(1)?);
+        let (header, length) = match flags & 0x10 {
+            0 => (3, *self.data.get(2)? as usize),
+            _ => (4, (*self.data.get(2)? as usize) << 8 | *self.data.get(3)? as usize),
+        };
+        let data = self.data.get(..header + length)?;
+        self.data = &self.data[header + length..];
+        Some(PathAttributeRef { flags, type_code, value: &data[header..], data, four_octet_as: self.four_octet_as })
+    }
+}
+
+// An AS_PATH read from the input. A segment that runs past the end of
+// the attribute ends the segments.
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct AsPathRef<'a> {
+    data: &'a [u8],
+    four_octet_as: bool,
+}
+
+impl<'a> AsPathRef<'a> {
+    pub fn segments(&self) -> AsPathSegmentIter<'a> {
+        AsPathSegmentIter { data: self.data, asn_length: if self.four_octet_as { 4 } else { 2 } }
+    }
+
+    // As AsPathAttribute::origin_as().
+    pub fn origin_as(&self) -> Option<u32> {
+        match self.segments().last() {
+            Some(segment) if segment.segment_type == 2 => segment.asns().last(),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, asn: u32) -> bool {
+        self.segments().any(|s| s.asns().any(|a| a == asn))
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct AsPathSegmentRef<'a> {
+    segment_type: u8,
+    asns: &'a [u8],
+    asn_length: usize,
+}
+
+impl<'a> AsPathSegmentRef<'a> {
+    // 1 for an AS_SET, 2 an AS_SEQUENCE, and 3 and 4 for the
+    // confederation segments.
+    pub fn segment_type(&self) -> u8 {
+        self.segment_type
+    }
+
+    pub fn asns(&self) -> impl DoubleEndedIterator<Item = u32> + 'a {
+        self.asns.chunks(self.asn_length).map(|c| c.iter().fold(0, |asn, b| asn << 8 | *b as u32))
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct AsPathSegmentIter<'a> {
+    data: &'a [u8],
+    // 2 or 4 bytes for each AS.
+    asn_length: usize,
+}
+
+impl<'a> Iterator for AsPathSegmentIter<'a> {
+    type Item = AsPathSegmentRef<'a>;
+
+    fn next(&mut self) -> Option<AsPathSegmentRef<'a>> {
+        let (segment_type, count) = (*self.data.first()?, *self.data.get(1)? as usize);
+        let asns = self.data.get(2..2 + count * self.asn_length)?;
+        self.data = &self.data[2 + count * self.asn_length..];
+        Some(AsPathSegmentRef { segment_type, asns, asn_length: self.asn_length })
+    }
+}
+
+impl mrt::MrtMessage {
+    pub fn parse_ref(&self) -> IResult<&[u8], BgpMessageRef<'_>> {
+        parse_bgp_message_ref_with_as4(&self.data, self.as4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn fixtures() -> Vec<&'static [u8]> {
+        vec![
+            &include_bytes!("../assets/test_bgp_open1.bin")[..],
+            &include_bytes!("../assets/test_bgp_open2.bin")[..],
+            &include_bytes!("../assets/test_bgp_open3.bin")[..],
+            &include_bytes!("../assets/test_bgp_keepalive1.bin")[..],
+            &include_bytes!("../assets/test_bgp_notification1.bin")[..],
+            &include_bytes!("../assets/test_bgp_update1.bin")[..],
+            &include_bytes!("../assets/test_bgp_update2.bin")[..],
+            &include_bytes!("../assets/test_bgp_update3.bin")[..],
+        ]
+    }
+
+    fn update_ref(input: &[u8]) -> BgpUpdateRef<'_> {
+        match parse_bgp_message_ref(input) {
+            Done(_, BgpMessageRef::Update(update)) => update,
+            other => panic!("expected an update: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn message_ref_fixtures_test() {
+        for input in fixtures() {
+            match (parse_bgp_message_ref(input), parse_extended_bgp_message(input)) {
+                (Done(rest, message_ref), Done(_, message)) => {
+                    assert!(rest.is_empty());
+                    assert_eq!(message_ref.to_message(), Some(message));
+                },
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn message_ref_update_test() {
+        let input = &include_bytes!("../assets/test_bgp_update1.bin")[..];
+        let update = update_ref(input);
+        let owned = match parse_bgp_message(input) {
+            Done(_, BgpMessage::Update(update)) => update,
+            other => panic!("expected an update: {:?}", other),
+        };
+
+        assert_eq!(update.nlri().map(|p| p.to_string()).collect::<Vec<_>>(), owned.nlri.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+        assert_eq!(update.withdrawn_routes().count(), owned.withdrawn_routes.len());
+        // The owned parser stops at the ORIGINATOR_ID and CLUSTER_LIST,
+        // which it doesn't decode, but they're still there to read.
+        assert_eq!(update.path_attributes().map(|a| a.type_code()).collect::<Vec<_>>(), vec![1, 2, 3, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(owned.path_attributes.len(), 7);
+        assert_eq!(update.path_attribute(9).map(|a| a.value()), Some(&[192, 168, 0, 15][..]));
+        for (a, b) in update.path_attributes().zip(owned.path_attributes.iter()) {
+            assert_eq!(a.flags(), b.flags);
+            assert_eq!(a.decode().as_ref(), Some(b));
+        }
+        assert_eq!(update.next_hop(), owned.next_hop());
+
+        let as_path = update.as_path().unwrap();
+        let owned_as_path = owned.as_path().unwrap();
+        assert_eq!(as_path.origin_as(), owned_as_path.origin_as());
+        assert_eq!(as_path.segments().count(), owned_as_path.segments().len());
+        for (a, b) in as_path.segments().zip(owned_as_path.segments()) {
+            assert_eq!(a.asns().collect::<Vec<_>>(), b.asns());
+        }
+        assert!(as_path.segments().flat_map(|s| s.asns()).all(|asn| as_path.contains(asn)));
+    }
+
+    #[test]
+    fn message_ref_as4_test() {
+        // An UPDATE with an AS_PATH of 65001 4200000001.
+        let mut input = vec![0xff; 16];
+        input.extend_from_slice(&[0, 51, 2, 0, 0, 0, 24]);
+        input.extend_from_slice(&[0x40, 1, 1, 0, 0x40, 2, 10, 2, 2, 0, 0, 0xfd, 0xe9, 0xfa, 0x56, 0xea, 0x01, 0x40, 3, 4, 192, 0, 2, 1]);
+        input.extend_from_slice(&[24, 198, 51, 100]);
+
+        let update = match parse_bgp_message_ref_with_as4(&input, true) {
+            Done(_, BgpMessageRef::Update(update)) => update,
+            other => panic!("expected an update: {:?}", other),
+        };
+        let as_path = update.as_path().unwrap();
+        assert_eq!(as_path.segments().flat_map(|s| s.asns()).collect::<Vec<_>>(), vec![65001, 4200000001]);
+        assert_eq!(as_path.origin_as(), Some(4200000001));
+        assert!(as_path.contains(65001));
+        assert!(!as_path.contains(0));
+        let owned = match update.path_attribute(2).and_then(|a| a.decode()) {
+            Some(BgpPathAttribute { attribute: PathAttribute::AsPath(as_path), .. }) => as_path,
+            other => panic!("expected an AS_PATH: {:?}", other),
+        };
+        assert_eq!(owned.to_string(), "65001 4200000001");
+
+        // Read as two octet AS numbers the segment is the first four bytes.
+        assert_eq!(update_ref(&input).as_path().unwrap().segments().flat_map(|s| s.asns()).collect::<Vec<_>>(), vec![0, 65001]);
+    }
+
+    #[test]
+    fn message_ref_invalid_test() {
+        let code = |input: &[u8]| match parse_bgp_message_ref(input) {
+            IResult::Error(ref e) => error_details(e).1,
+            other => panic!("expected error: {:?}", other),
+        };
+        let message = |message_type: u8, body: &[u8]| {
+            let mut message = vec![0xff; 16];
+            message.extend_from_slice(&((body.len() + 19) as u16).to_be_bytes());
+            message.push(message_type);
+            message.extend_from_slice(body);
+            message
+        };
+
+        assert_eq!(code(&message(4, &[0])), Some(BAD_MESSAGE_LENGTH));
+        // A withdrawn route longer than 32 bits, and one running past the
+        // withdrawn routes.
+        assert_eq!(code(&message(2, &[0, 2, 33, 10, 0, 0])), Some(MALFORMED_ATTRIBUTE_LIST));
+        assert_eq!(code(&message(2, &[0, 2, 16, 10, 0, 0])), Some(MALFORMED_ATTRIBUTE_LIST));
+        // An attribute longer than the attributes.
+        assert_eq!(code(&message(2, &[0, 0, 0, 4, 0x40, 3, 4, 10])), Some(MALFORMED_ATTRIBUTE_LIST));
+        // NLRI cut short.
+        assert_eq!(code(&message(2, &[0, 0, 0, 0, 24, 10, 0])), Some(INVALID_NETWORK_FIELD));
+
+        // An attribute we don't decode is still there to read.
+        let update = message(2, &[0, 0, 0, 5, 0xc0, 99, 2, 1, 2, 8, 10]);
+        let update = update_ref(&update);
+        let attribute = update.path_attribute(99).unwrap();
+        assert_eq!(attribute.value(), &[1, 2]);
+        assert_eq!(attribute.decode(), None);
+        assert_eq!(update.nlri().map(|p| p.to_string()).collect::<Vec<_>>(), vec!["10.0.0.0/8"]);
+    }
+
+    // Throughput of the owned parser against the borrowed one, parsing
+    // alone and reading every prefix and attribute, and converting it to
+    // the owned types.
+    #[test]
+    #[ignore]
+    fn message_ref_benchmark_test() {
+        const ITERATIONS: usize = 200_000;
+        let fixtures = fixtures();
+        let bytes = fixtures.iter().map(|f| f.len()).sum::<usize>() * ITERATIONS;
+
+        let run = |name: &str, f: &dyn Fn(&[u8]) -> usize| {
+            let start = Instant::now();
+            let mut total = 0;
+            for _ in 0..ITERATIONS {
+                for input in &fixtures {
+                    total += f(input);
+                }
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+            println!("{:<24} {:>8.1} MB/s {:>10.0} messages/s ({})", name, bytes as f64 / elapsed / 1e6, (ITERATIONS * fixtures.len()) as f64 / elapsed, total);
+        };
+
+        run("owned", &|input| match parse_bgp_message(input) {
+            Done(_, BgpMessage::Update(update)) => update.nlri.len() + update.path_attributes.len(),
+            Done(_, _) => 1,
+            _ => 0,
+        });
+        run("borrowed", &|input| match parse_bgp_message_ref(input) {
+            Done(_, _) => 1,
+            _ => 0,
+        });
+        run("borrowed, iterated", &|input| match parse_bgp_message_ref(input) {
+            Done(_, BgpMessageRef::Update(update)) => {
+                update.nlri().count() + update.path_attributes().count() + update.as_path().and_then(|p| p.origin_as()).map_or(0, |_| 1)
+            },
+            Done(_, _) => 1,
+            _ => 0,
+        });
+        run("borrowed, to owned", &|input| match parse_bgp_message_ref(input) {
+            Done(_, message) => message.to_message().map_or(0, |_| 1),
+            _ => 0,
+        });
+    }
+}
@@ -0,0 +1,262 @@
+// MPLS labels, as carried in front of the prefix in labeled NLRI: for
+// labeled unicast (RFC 8277), SAFI 4, here, and for L3VPNs (RFC 4364),
+// see vpn.rs.
+//
+// Each label takes three bytes: the 20 bit label itself, three bits of
+// traffic class, and the bottom of stack bit, set on the last of them.
+// Only one label is sent unless both speakers have sent the Multiple
+// Labels capability for the family, giving the most each can receive.
+//
+// A withdrawal needn't bother with real labels. It has a single label
+// field however many were announced, often holding the withdrawal value
+// 0x800000 (RFC 3107) rather than a label, which isn't the bottom of the
+// stack but still ends it.
+
+use std::fmt;
+use std::net::IpAddr;
+
+use nom::{be_u8, Needed};
+use nom::IResult::*;
+
+use super::*;
+
+pub const WITHDRAWAL_LABEL: u32 = 0x80_0000;
+
+pub const SAFI_LABELED_UNICAST: u8 = 4;
+
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Label {
+    pub value: u32,
+    pub traffic_class: u8,
+    pub bottom: bool,
+}
+
+impl Label {
+    pub fn new(value: u32) -> Label {
+        Label { value, traffic_class: 0, bottom: true }
+    }
+
+    fn from_u32(raw: u32) -> Label {
+        Label { value: raw >> 4, traffic_class: (raw >> 1 & 0x7) as u8, bottom: raw & 1 != 0 }
+    }
+
+    fn to_u32(self) -> u32 {
+        self.value << 4 | (self.traffic_class as u32 & 0x7) << 1 | self.bottom as u32
+    }
+
+    // The label field of a withdrawal.
+    pub fn withdrawal() -> Label {
+        Label::from_u32(WITHDRAWAL_LABEL)
+    }
+
+    pub fn is_withdrawal(&self) -> bool {
+        self.to_u32() == WITHDRAWAL_LABEL
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_u32().to_be_bytes()[1..]);
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.is_withdrawal() {
+            true => f.write_str("withdrawn"),
+            false => write!(f, "{}", self.value),
+        }
+    }
+}
+
+pub fn format_labels(labels: &[Label]) -> String {
+    labels.iter().map(|l| l.to_string()).collect::<Vec<_>>().join("/")
+}
+
+// The labels up to and including the bottom of the stack.
+pub fn label_stack(input: &[u8]) -> IResult<&[u8], Vec<Label>> {
+    let mut labels = Vec::new();
+    let mut rest = input;
+
+    loop {
+        if rest.len() < 3 {
+            return Incomplete(Needed::Size(input.len() - rest.len() + 3));
+        }
+        let label = Label::from_u32(u32::from_be_bytes([0, rest[0], rest[1], rest[2]]));
+        labels.push(label);
+        rest = &rest[3..];
+
+        if label.bottom || label.is_withdrawal() {
+            return Done(rest, labels);
+        }
+    }
+}
+
+named!(pub label<&[u8], Label>,
+    map!(take!(3), |b: &[u8]| Label::from_u32(u32::from_be_bytes([0, b[0], b[1], b[2]])))
+);
+
+// The labels of a prefix, a single one when withdrawn.
+named_args!(pub labels(withdrawn: bool) <Vec<Label>>,
+    switch!(value!(withdrawn),
+        true => map!(label, |l: Label| vec![l]) |
+        false => call!(label_stack)
+    )
+);
+
+// A count from the Multiple Labels capability.
+#[derive(Debug,PartialEq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LabelCount {
+    pub afi: u16,
+    pub safi: u8,
+    pub count: u8,
+}
+
+// The most labels that can be sent to a peer for the family, given what
+// we and it sent in our OPENs.
+pub fn max_labels(local: &[LabelCount], remote: &BgpOpenMessage, afi: u16, safi: u8) -> u8 {
+    let remote = remote.optional_parameters.iter().flat_map(|p| match *p {
+        OptionalParameter::Capability(ref capabilities) => capabilities.iter(),
+    }).filter_map(|c| match *c {
+        CapabilityParameter::MultipleLabels(ref m) => m.counts.iter().find(|c| c.afi == afi && c.safi == safi),
+        _ => None,
+    }).next();
+
+    match (local.iter().any(|c| c.afi == afi && c.safi == safi), remote) {
+        (true, Some(remote)) => remote.count.max(1),
+        _ => 1,
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LabeledPrefix {
+    pub labels: Vec<Label>,
+    pub address: IpAddr,
+    pub length: u8,
+}
+
+impl fmt::Display for LabeledPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{} label {}", self.address, self.length, format_labels(&self.labels))
+    }
+}
+
+// The length covers the labels as well as the prefix.
+named_args!(pub labeled_prefix(afi: u16, withdrawn: bool) <LabeledPrefix>,
+    do_parse!(
+        length: be_u8 >>
+        labels: call!(labels, withdrawn) >>
+        prefix_length: expr_opt!((length as usize).checked_sub(labels.len() * 24).filter(|l| *l <= max_prefix_length(afi))) >>
+        prefix: take!(prefix_length.div_ceil(8)) >>
+        (LabeledPrefix { labels, address: prefix_address(afi, prefix), length: prefix_length as u8 })
+    )
+);
+
+impl LabeledPrefix {
+    // The labels and prefix have to fit in a length of 255 bits.
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        let length = self.labels.len() * 24 + self.length as usize;
+        if length > 255 {
+            return Err(format!("labeled prefix {} is too long", self));
+        }
+        out.push(length as u8);
+        for label in &self.labels {
+            label.encode(out);
+        }
+        out.extend_from_slice(&prefix_bytes(self.address, self.length));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_stack_test() {
+        let input = [0x00, 0x06, 0x40, 0x00, 0x3e, 0x81, 0x0a];
+        let labels = vec![
+            Label { value: 100, traffic_class: 0, bottom: false },
+            Label { value: 1000, traffic_class: 0, bottom: true },
+        ];
+        assert_eq!(label_stack(&input), Done(&[0x0a][..], labels.clone()));
+        assert_eq!(format_labels(&labels), "100/1000");
+
+        let mut encoded = Vec::new();
+        for label in &labels {
+            label.encode(&mut encoded);
+        }
+        assert_eq!(encoded, input[..6].to_vec());
+
+        // The withdrawal value ends the stack without the bottom bit.
+        let withdrawal = Label { value: 0x8_0000, traffic_class: 0, bottom: false };
+        assert!(withdrawal.is_withdrawal());
+        assert_eq!(label_stack(&[0x80, 0x00, 0x00, 0x0a]), Done(&[0x0a][..], vec![withdrawal]));
+        assert_eq!(withdrawal.to_string(), "withdrawn");
+
+        assert!(label_stack(&[0x00, 0x06, 0x40, 0x00]).is_incomplete());
+    }
+
+    #[test]
+    fn labeled_prefix_test() {
+        // 10.1.0.0/16 with labels 16 and 17.
+        let input = [64, 0x00, 0x01, 0x00, 0x00, 0x01, 0x11, 10, 1];
+        let prefix = LabeledPrefix {
+            labels: vec![Label { value: 16, traffic_class: 0, bottom: false }, Label::new(17)],
+            address: "10.1.0.0".parse().unwrap(),
+            length: 16,
+        };
+        assert_eq!(labeled_prefix(&input, AFI_IPV4, false), Done(&b""[..], prefix.clone()));
+        assert_eq!(prefix.to_string(), "10.1.0.0/16 label 16/17");
+        let mut encoded = Vec::new();
+        prefix.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, input.to_vec());
+
+        // Withdrawn, with a single label field whatever it holds.
+        let input = [24 + 16, 0x00, 0x00, 0x00, 10, 1];
+        assert_eq!(labeled_prefix(&input, AFI_IPV4, true), Done(&b""[..], LabeledPrefix {
+            labels: vec![Label { value: 0, traffic_class: 0, bottom: false }],
+            address: "10.1.0.0".parse().unwrap(),
+            length: 16,
+        }));
+        let withdrawal = LabeledPrefix { labels: vec![Label::withdrawal()], address: "2001:db8::".parse().unwrap(), length: 32 };
+        assert_eq!(withdrawal.to_string(), "2001:db8::/32 label withdrawn");
+        let mut encoded = Vec::new();
+        withdrawal.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, vec![56, 0x80, 0x00, 0x00, 0x20, 0x01, 0x0d, 0xb8]);
+        assert_eq!(labeled_prefix(&encoded, AFI_IPV6, true), Done(&b""[..], withdrawal));
+
+        // An IPv6 /128 has room for five labels but not six.
+        let labeled = |count: usize| LabeledPrefix { labels: vec![Label::new(16); count], address: "2001:db8::1".parse().unwrap(), length: 128 };
+        assert!(labeled(5).encode(&mut Vec::new()).is_ok());
+        assert!(labeled(6).encode(&mut Vec::new()).is_err());
+
+        // Too long for IPv4 once the label is taken off.
+        assert!(labeled_prefix(&[24 + 33, 0x00, 0x01, 0x01, 10, 1, 0, 0, 0], AFI_IPV4, false).is_err());
+    }
+
+    #[test]
+    fn max_labels_test() {
+        let open = |counts: Vec<LabelCount>| BgpOpenMessage {
+            version: 4,
+            my_autonomous_system: 65001,
+            hold_time: 90,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 2),
+            optional_parameters: vec![OptionalParameter::Capability(vec![
+                CapabilityParameter::MultipleLabels(Box::new(MultipleLabelsCapability { counts })),
+            ])],
+        };
+        let local = [LabelCount { afi: AFI_IPV4, safi: SAFI_LABELED_UNICAST, count: 4 }];
+        let remote = open(vec![
+            LabelCount { afi: AFI_IPV4, safi: SAFI_LABELED_UNICAST, count: 3 },
+            LabelCount { afi: AFI_IPV6, safi: SAFI_LABELED_UNICAST, count: 2 },
+        ]);
+
+        assert_eq!(max_labels(&local, &remote, AFI_IPV4, SAFI_LABELED_UNICAST), 3);
+        // Only if we sent it for the family too.
+        assert_eq!(max_labels(&local, &remote, AFI_IPV6, SAFI_LABELED_UNICAST), 1);
+        assert_eq!(max_labels(&[], &remote, AFI_IPV4, SAFI_LABELED_UNICAST), 1);
+        assert_eq!(max_labels(&local, &open(vec![]), AFI_IPV4, SAFI_LABELED_UNICAST), 1);
+    }
+}
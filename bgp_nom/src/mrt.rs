@@ -0,0 +1,262 @@
+// Read BGP messages out of MRT (RFC 6396) archives.
+//
+// Only the BGP4MP and BGP4MP_ET record types carry whole BGP messages,
+// so those are the only ones we return. TABLE_DUMP and TABLE_DUMP_V2
+// records hold RIB entries rather than messages, and state changes carry
+// no message at all, so those are skipped.
+//
+// The MESSAGE_AS4 subtypes are returned too. The messages in them were
+// sent on a session with four octet AS numbers negotiated, so the AS_PATH
+// and AGGREGATOR attributes are in the four octet form, and are parsed
+// that way.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use nom::{be_u16, be_u32};
+use nom::IResult;
+use nom::IResult::*;
+
+use super::{BgpMessage, MAX_EXTENDED_MESSAGE_LENGTH, UpdateEncoding, parse_bgp_message_with_encoding};
+
+const MRT_TYPE_BGP4MP: u16 = 16;
+const MRT_TYPE_BGP4MP_ET: u16 = 17;
+
+const BGP4MP_MESSAGE: u16 = 1;
+const BGP4MP_MESSAGE_AS4: u16 = 4;
+const BGP4MP_MESSAGE_LOCAL: u16 = 6;
+const BGP4MP_MESSAGE_AS4_LOCAL: u16 = 7;
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+
+#[derive(Debug,PartialEq)]
+pub enum MrtError {
+    // The record starting at this offset runs past the end of the input.
+    Truncated(usize),
+    // The record starting at this offset is a BGP4MP message with a
+    // header we couldn't make sense of.
+    Malformed(usize),
+}
+
+// A BGP message taken from a BGP4MP record. The offset is the position
+// of the BGP message itself, not the MRT record, within the input.
+
+#[derive(Debug,PartialEq)]
+pub struct MrtMessage {
+    pub timestamp: Duration,
+    pub peer_as: u32,
+    pub local_as: u32,
+    pub peer_address: IpAddr,
+    pub local_address: IpAddr,
+    // Whether the session had four octet AS numbers.
+    pub as4: bool,
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl MrtMessage {
+    pub fn parse(&self) -> IResult<&[u8], BgpMessage> {
+        parse_bgp_message_with_encoding(&self.data, MAX_EXTENDED_MESSAGE_LENGTH, UpdateEncoding::archived(self.as4))
+    }
+}
+
+pub fn read_mrt(data: &[u8]) -> Result<Vec<MrtMessage>, MrtError> {
+    let mut messages = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (record_length, header) = match mrt_header(&data[pos..]) {
+            Done(rest, header) => (data.len() - pos - rest.len() + header.length as usize, header),
+            _ => return Err(MrtError::Truncated(pos)),
+        };
+
+        if data.len() - pos < record_length {
+            return Err(MrtError::Truncated(pos));
+        }
+
+        let body_offset = pos + record_length - header.length as usize;
+        let body = &data[body_offset..pos + record_length];
+
+        let (microseconds, body, body_offset) = match header.record_type {
+            MRT_TYPE_BGP4MP_ET if body.len() >= 4 => ((body[0] as u32) << 24 | (body[1] as u32) << 16 | (body[2] as u32) << 8 | body[3] as u32, &body[4..], body_offset + 4),
+            MRT_TYPE_BGP4MP_ET => return Err(MrtError::Malformed(pos)),
+            _ => (0, body, body_offset),
+        };
+
+        let as4 = match (header.record_type, header.subtype) {
+            (MRT_TYPE_BGP4MP, BGP4MP_MESSAGE) | (MRT_TYPE_BGP4MP, BGP4MP_MESSAGE_LOCAL) |
+            (MRT_TYPE_BGP4MP_ET, BGP4MP_MESSAGE) | (MRT_TYPE_BGP4MP_ET, BGP4MP_MESSAGE_LOCAL) => Some(false),
+            (MRT_TYPE_BGP4MP, BGP4MP_MESSAGE_AS4) | (MRT_TYPE_BGP4MP, BGP4MP_MESSAGE_AS4_LOCAL) |
+            (MRT_TYPE_BGP4MP_ET, BGP4MP_MESSAGE_AS4) | (MRT_TYPE_BGP4MP_ET, BGP4MP_MESSAGE_AS4_LOCAL) => Some(true),
+            _ => None,
+        };
+
+        if let Some(as4) = as4 {
+            match bgp4mp_message(body, as4) {
+                Done(rest, (peer_as, local_as, peer_address, local_address)) => {
+                    messages.push(MrtMessage {
+                        timestamp: Duration::new(header.timestamp as u64, microseconds.saturating_mul(1_000)),
+                        peer_as,
+                        local_as,
+                        peer_address,
+                        local_address,
+                        as4,
+                        offset: body_offset + body.len() - rest.len(),
+                        data: rest.to_vec(),
+                    });
+                },
+                _ => return Err(MrtError::Malformed(pos)),
+            }
+        }
+
+        pos += record_length;
+    }
+
+    Ok(messages)
+}
+
+struct MrtHeader {
+    timestamp: u32,
+    record_type: u16,
+    subtype: u16,
+    length: u32,
+}
+
+named!(mrt_header<&[u8], MrtHeader>,
+    do_parse!(
+        timestamp: be_u32 >>
+        record_type: be_u16 >>
+        subtype: be_u16 >>
+        length: be_u32 >>
+        (MrtHeader { timestamp, record_type, subtype, length })
+    )
+);
+
+// The BGP4MP message header. Returns the peer and local AS numbers and
+// addresses, leaving the BGP message as the remaining input.
+
+named_args!(bgp4mp_message(as4: bool) <(u32, u32, IpAddr, IpAddr)>,
+    do_parse!(
+        peer_as: call!(mrt_as_number, as4) >>
+        local_as: call!(mrt_as_number, as4) >>
+        take!(2) >> // interface index
+        afi: verify!(be_u16, |v: u16| v == AFI_IPV4 || v == AFI_IPV6) >>
+        peer_address: call!(mrt_ip_address, afi) >>
+        local_address: call!(mrt_ip_address, afi) >>
+        (peer_as, local_as, peer_address, local_address)
+    )
+);
+
+named_args!(mrt_as_number(as4: bool) <u32>,
+    switch!(value!(as4),
+        true => call!(be_u32) |
+        false => map!(be_u16, |v: u16| v as u32)
+    )
+);
+
+named_args!(mrt_ip_address(afi: u16) <IpAddr>,
+    switch!(value!(afi),
+        AFI_IPV4 => map!(be_u32, |v: u32| IpAddr::V4(Ipv4Addr::from(v))) |
+        AFI_IPV6 => map!(take!(16), |v: &[u8]| {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(v);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        })
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(record_type: u16, subtype: u16, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x38, 0xe3, 0x5a, 0x98]; // 954423960
+        out.extend_from_slice(&[(record_type >> 8) as u8, record_type as u8, (subtype >> 8) as u8, subtype as u8]);
+        let length = body.len() as u32;
+        out.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn read_mrt_bgp4mp_message_test() {
+        let update = include_bytes!("../assets/test_bgp_update1.bin");
+
+        let mut body = vec![0xfe, 0x09, 0xfe, 0xbb, 0, 1, 0, 1, 192, 168, 0, 15, 192, 168, 0, 33];
+        body.extend_from_slice(&update[..]);
+
+        // A state change record that should be skipped, then the message.
+        let mut data = record(MRT_TYPE_BGP4MP, 0, &[0xfe, 0x09, 0xfe, 0xbb, 0, 1, 0, 1, 192, 168, 0, 15, 192, 168, 0, 33, 0, 1, 0, 2]);
+        let offset = data.len() + 12 + 16;
+        data.extend(record(MRT_TYPE_BGP4MP, BGP4MP_MESSAGE, &body));
+
+        let messages = read_mrt(&data).unwrap();
+        assert_eq!(messages, vec![MrtMessage {
+            timestamp: Duration::new(954423960, 0),
+            peer_as: 65033,
+            local_as: 65211,
+            peer_address: "192.168.0.15".parse().unwrap(),
+            local_address: "192.168.0.33".parse().unwrap(),
+            as4: false,
+            offset,
+            data: update.to_vec(),
+        }]);
+
+        match messages[0].parse() {
+            Done(rest, BgpMessage::Update(_)) => assert_eq!(rest, &b""[..]),
+            other => panic!("expected an update message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_mrt_bgp4mp_et_as4_ipv6_test() {
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+
+        let mut body = vec![0, 0x07, 0xa1, 0x20, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 2];
+        body.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        body.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        body.extend_from_slice(&keepalive[..]);
+
+        let messages = read_mrt(&record(MRT_TYPE_BGP4MP_ET, BGP4MP_MESSAGE_AS4, &body)).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].timestamp, Duration::new(954423960, 500_000_000));
+        assert_eq!(messages[0].peer_as, 1);
+        assert_eq!(messages[0].local_as, 2);
+        assert_eq!(messages[0].peer_address, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(messages[0].offset, 12 + 4 + 44);
+        assert_eq!(messages[0].parse(), Done(&b""[..], BgpMessage::Keepalive));
+    }
+
+    #[test]
+    fn read_mrt_bgp4mp_as4_update_test() {
+        // An UPDATE with an AS_PATH of 65001 4200000001.
+        let mut update = vec![0xff; 16];
+        update.extend_from_slice(&[0, 51, 2, 0, 0, 0, 24]);
+        update.extend_from_slice(&[0x40, 1, 1, 0, 0x40, 2, 10, 2, 2, 0, 0, 0xfd, 0xe9, 0xfa, 0x56, 0xea, 0x01, 0x40, 3, 4, 192, 0, 2, 1]);
+        update.extend_from_slice(&[24, 198, 51, 100]);
+
+        let mut body = vec![0xfa, 0x56, 0xea, 0x01, 0, 0, 0xfe, 0xbb, 0, 1, 0, 1, 192, 168, 0, 15, 192, 168, 0, 33];
+        body.extend_from_slice(&update);
+
+        let messages = read_mrt(&record(MRT_TYPE_BGP4MP, BGP4MP_MESSAGE_AS4, &body)).unwrap();
+        assert_eq!(messages[0].peer_as, 4200000001);
+        assert!(messages[0].as4);
+        match messages[0].parse() {
+            Done(&[], BgpMessage::Update(ref update)) => assert_eq!(update.as_path().unwrap().to_string(), "65001 4200000001"),
+            other => panic!("expected an update message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_mrt_errors_test() {
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+        let mut data = record(MRT_TYPE_BGP4MP, BGP4MP_MESSAGE, &keepalive[..]);
+        assert_eq!(read_mrt(&data), Err(MrtError::Malformed(0)));
+
+        data.truncate(20);
+        assert_eq!(read_mrt(&data), Err(MrtError::Truncated(0)));
+
+        assert_eq!(read_mrt(&[0, 0, 0]), Err(MrtError::Truncated(0)));
+    }
+}
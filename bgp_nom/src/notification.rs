@@ -0,0 +1,613 @@
+// The errors a NOTIFICATION can carry, from the IANA "BGP Error (Notification)
+// Codes" and "BGP Error Subcodes" registries.
+//
+// BgpNotificationMessage keeps the error code and subcode as they were
+// on the wire. NotificationError is the typed view of them, for matching
+// on and for printing with the names the RFCs give them. The conversion
+// is lossless both ways: codes and subcodes we don't know are kept as
+// Unknown, so a NOTIFICATION can always be parsed and encoded again.
+//
+// Subcode 0 is Unspecific for every code (RFC 4271 section 6). Hold
+// Timer Expired has no subcodes, so it only keeps the number.
+//
+// A Cease with the Administrative Shutdown or Administrative Reset
+// subcode can carry a Shutdown Communication (RFC 8203, extended to 255
+// bytes by RFC 9003): a length octet and a UTF-8 message, for operators
+// to say why the session was taken down. We check the length and the
+// UTF-8 when decoding it, since it's text from the network that ends up
+// in logs, and cut long messages short on a character boundary when
+// encoding one.
+
+use std::fmt;
+
+use super::*;
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum NotificationError {
+    MessageHeader(MessageHeaderError),
+    OpenMessage(OpenMessageError),
+    UpdateMessage(UpdateMessageError),
+    HoldTimerExpired(u8),
+    FiniteStateMachine(FiniteStateMachineError),
+    Cease(CeaseSubcode),
+    RouteRefreshMessage(RouteRefreshMessageError), // RFC 7313
+    Unknown(u8, u8),
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum MessageHeaderError {
+    Unspecific,
+    ConnectionNotSynchronized,
+    BadMessageLength,
+    BadMessageType,
+    Unknown(u8),
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum OpenMessageError {
+    Unspecific,
+    UnsupportedVersionNumber,
+    BadPeerAs,
+    BadBgpIdentifier,
+    UnsupportedOptionalParameter,
+    AuthenticationFailure, // deprecated, RFC 1771
+    UnacceptableHoldTime,
+    UnsupportedCapability, // RFC 5492
+    RoleMismatch, // RFC 9234
+    Unknown(u8),
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum UpdateMessageError {
+    Unspecific,
+    MalformedAttributeList,
+    UnrecognizedWellKnownAttribute,
+    MissingWellKnownAttribute,
+    AttributeFlagsError,
+    AttributeLengthError,
+    InvalidOriginAttribute,
+    AsRoutingLoop, // deprecated, RFC 1771
+    InvalidNextHopAttribute,
+    OptionalAttributeError,
+    InvalidNetworkField,
+    MalformedAsPath,
+    Unknown(u8),
+}
+
+// RFC 6608.
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum FiniteStateMachineError {
+    Unspecific,
+    UnexpectedMessageInOpenSent,
+    UnexpectedMessageInOpenConfirm,
+    UnexpectedMessageInEstablished,
+    Unknown(u8),
+}
+
+// RFC 4486 and the RFCs that have added to it since.
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum CeaseSubcode {
+    Unspecific,
+    MaximumNumberOfPrefixesReached,
+    AdministrativeShutdown,
+    PeerDeconfigured,
+    AdministrativeReset,
+    ConnectionRejected,
+    OtherConfigurationChange,
+    ConnectionCollisionResolution,
+    OutOfResources,
+    HardReset, // RFC 8538
+    BfdDown, // RFC 9384
+    Unknown(u8),
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum RouteRefreshMessageError {
+    Unspecific,
+    InvalidMessageLength,
+    Unknown(u8),
+}
+
+impl NotificationError {
+    pub fn new(error_code: u8, error_subcode: u8) -> NotificationError {
+        match error_code as u32 {
+            MESSAGE_HEADER_ERROR => NotificationError::MessageHeader(error_subcode.into()),
+            OPEN_MESSAGE_ERROR => NotificationError::OpenMessage(error_subcode.into()),
+            UPDATE_MESSAGE_ERROR => NotificationError::UpdateMessage(error_subcode.into()),
+            HOLD_TIMER_EXPIRED => NotificationError::HoldTimerExpired(error_subcode),
+            FINITE_STATE_MACHINE_ERROR => NotificationError::FiniteStateMachine(error_subcode.into()),
+            CEASE => NotificationError::Cease(error_subcode.into()),
+            ROUTE_REFRESH_MESSAGE_ERROR => NotificationError::RouteRefreshMessage(error_subcode.into()),
+            _ => NotificationError::Unknown(error_code, error_subcode),
+        }
+    }
+
+    pub fn error_code(&self) -> u8 {
+        let code = match *self {
+            NotificationError::MessageHeader(_) => MESSAGE_HEADER_ERROR,
+            NotificationError::OpenMessage(_) => OPEN_MESSAGE_ERROR,
+            NotificationError::UpdateMessage(_) => UPDATE_MESSAGE_ERROR,
+            NotificationError::HoldTimerExpired(_) => HOLD_TIMER_EXPIRED,
+            NotificationError::FiniteStateMachine(_) => FINITE_STATE_MACHINE_ERROR,
+            NotificationError::Cease(_) => CEASE,
+            NotificationError::RouteRefreshMessage(_) => ROUTE_REFRESH_MESSAGE_ERROR,
+            NotificationError::Unknown(code, _) => code as u32,
+        };
+        code as u8
+    }
+
+    pub fn error_subcode(&self) -> u8 {
+        match *self {
+            NotificationError::MessageHeader(s) => s.into(),
+            NotificationError::OpenMessage(s) => s.into(),
+            NotificationError::UpdateMessage(s) => s.into(),
+            NotificationError::HoldTimerExpired(s) => s,
+            NotificationError::FiniteStateMachine(s) => s.into(),
+            NotificationError::Cease(s) => s.into(),
+            NotificationError::RouteRefreshMessage(s) => s.into(),
+            NotificationError::Unknown(_, s) => s,
+        }
+    }
+
+    // The name of the error code alone.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NotificationError::MessageHeader(_) => "Message Header Error",
+            NotificationError::OpenMessage(_) => "OPEN Message Error",
+            NotificationError::UpdateMessage(_) => "UPDATE Message Error",
+            NotificationError::HoldTimerExpired(_) => "Hold Timer Expired",
+            NotificationError::FiniteStateMachine(_) => "Finite State Machine Error",
+            NotificationError::Cease(_) => "Cease",
+            NotificationError::RouteRefreshMessage(_) => "ROUTE-REFRESH Message Error",
+            NotificationError::Unknown(..) => "Unknown",
+        }
+    }
+
+    // The name of the subcode alone, or None if it's Unspecific or the
+    // code doesn't have subcodes.
+    pub fn subcode_name(&self) -> Option<String> {
+        let name = match *self {
+            NotificationError::MessageHeader(s) => s.to_string(),
+            NotificationError::OpenMessage(s) => s.to_string(),
+            NotificationError::UpdateMessage(s) => s.to_string(),
+            NotificationError::FiniteStateMachine(s) => s.to_string(),
+            NotificationError::Cease(s) => s.to_string(),
+            NotificationError::RouteRefreshMessage(s) => s.to_string(),
+            NotificationError::HoldTimerExpired(0) => return None,
+            NotificationError::HoldTimerExpired(s) |
+            NotificationError::Unknown(_, s) => format!("subcode {}", s),
+        };
+        match name.as_str() {
+            "Unspecific" => None,
+            _ => Some(name),
+        }
+    }
+}
+
+// "Cease: Administrative Shutdown", or just "Hold Timer Expired" where
+// there's no subcode to give.
+impl fmt::Display for NotificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NotificationError::Unknown(code, _) => write!(f, "Unknown error code {}", code)?,
+            _ => f.write_str(self.name())?,
+        }
+        match self.subcode_name() {
+            Some(name) => write!(f, ": {}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<u8> for MessageHeaderError {
+    fn from(v: u8) -> MessageHeaderError {
+        match v {
+            0 => MessageHeaderError::Unspecific,
+            1 => MessageHeaderError::ConnectionNotSynchronized,
+            2 => MessageHeaderError::BadMessageLength,
+            3 => MessageHeaderError::BadMessageType,
+            v => MessageHeaderError::Unknown(v),
+        }
+    }
+}
+
+impl From<MessageHeaderError> for u8 {
+    fn from(e: MessageHeaderError) -> u8 {
+        match e {
+            MessageHeaderError::Unspecific => 0,
+            MessageHeaderError::ConnectionNotSynchronized => 1,
+            MessageHeaderError::BadMessageLength => 2,
+            MessageHeaderError::BadMessageType => 3,
+            MessageHeaderError::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for MessageHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MessageHeaderError::Unspecific => f.write_str("Unspecific"),
+            MessageHeaderError::ConnectionNotSynchronized => f.write_str("Connection Not Synchronized"),
+            MessageHeaderError::BadMessageLength => f.write_str("Bad Message Length"),
+            MessageHeaderError::BadMessageType => f.write_str("Bad Message Type"),
+            MessageHeaderError::Unknown(v) => write!(f, "subcode {}", v),
+        }
+    }
+}
+
+impl From<u8> for OpenMessageError {
+    fn from(v: u8) -> OpenMessageError {
+        match v {
+            0 => OpenMessageError::Unspecific,
+            1 => OpenMessageError::UnsupportedVersionNumber,
+            2 => OpenMessageError::BadPeerAs,
+            3 => OpenMessageError::BadBgpIdentifier,
+            4 => OpenMessageError::UnsupportedOptionalParameter,
+            5 => OpenMessageError::AuthenticationFailure,
+            6 => OpenMessageError::UnacceptableHoldTime,
+            7 => OpenMessageError::UnsupportedCapability,
+            11 => OpenMessageError::RoleMismatch,
+            v => OpenMessageError::Unknown(v),
+        }
+    }
+}
+
+impl From<OpenMessageError> for u8 {
+    fn from(e: OpenMessageError) -> u8 {
+        match e {
+            OpenMessageError::Unspecific => 0,
+            OpenMessageError::UnsupportedVersionNumber => 1,
+            OpenMessageError::BadPeerAs => 2,
+            OpenMessageError::BadBgpIdentifier => 3,
+            OpenMessageError::UnsupportedOptionalParameter => 4,
+            OpenMessageError::AuthenticationFailure => 5,
+            OpenMessageError::UnacceptableHoldTime => 6,
+            OpenMessageError::UnsupportedCapability => 7,
+            OpenMessageError::RoleMismatch => 11,
+            OpenMessageError::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for OpenMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpenMessageError::Unspecific => f.write_str("Unspecific"),
+            OpenMessageError::UnsupportedVersionNumber => f.write_str("Unsupported Version Number"),
+            OpenMessageError::BadPeerAs => f.write_str("Bad Peer AS"),
+            OpenMessageError::BadBgpIdentifier => f.write_str("Bad BGP Identifier"),
+            OpenMessageError::UnsupportedOptionalParameter => f.write_str("Unsupported Optional Parameter"),
+            OpenMessageError::AuthenticationFailure => f.write_str("Authentication Failure"),
+            OpenMessageError::UnacceptableHoldTime => f.write_str("Unacceptable Hold Time"),
+            OpenMessageError::UnsupportedCapability => f.write_str("Unsupported Capability"),
+            OpenMessageError::RoleMismatch => f.write_str("Role Mismatch"),
+            OpenMessageError::Unknown(v) => write!(f, "subcode {}", v),
+        }
+    }
+}
+
+impl From<u8> for UpdateMessageError {
+    fn from(v: u8) -> UpdateMessageError {
+        match v {
+            0 => UpdateMessageError::Unspecific,
+            1 => UpdateMessageError::MalformedAttributeList,
+            2 => UpdateMessageError::UnrecognizedWellKnownAttribute,
+            3 => UpdateMessageError::MissingWellKnownAttribute,
+            4 => UpdateMessageError::AttributeFlagsError,
+            5 => UpdateMessageError::AttributeLengthError,
+            6 => UpdateMessageError::InvalidOriginAttribute,
+            7 => UpdateMessageError::AsRoutingLoop,
+            8 => UpdateMessageError::InvalidNextHopAttribute,
+            9 => UpdateMessageError::OptionalAttributeError,
+            10 => UpdateMessageError::InvalidNetworkField,
+            11 => UpdateMessageError::MalformedAsPath,
+            v => UpdateMessageError::Unknown(v),
+        }
+    }
+}
+
+impl From<UpdateMessageError> for u8 {
+    fn from(e: UpdateMessageError) -> u8 {
+        match e {
+            UpdateMessageError::Unspecific => 0,
+            UpdateMessageError::MalformedAttributeList => 1,
+            UpdateMessageError::UnrecognizedWellKnownAttribute => 2,
+            UpdateMessageError::MissingWellKnownAttribute => 3,
+            UpdateMessageError::AttributeFlagsError => 4,
+            UpdateMessageError::AttributeLengthError => 5,
+            UpdateMessageError::InvalidOriginAttribute => 6,
+            UpdateMessageError::AsRoutingLoop => 7,
+            UpdateMessageError::InvalidNextHopAttribute => 8,
+            UpdateMessageError::OptionalAttributeError => 9,
+            UpdateMessageError::InvalidNetworkField => 10,
+            UpdateMessageError::MalformedAsPath => 11,
+            UpdateMessageError::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for UpdateMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UpdateMessageError::Unspecific => f.write_str("Unspecific"),
+            UpdateMessageError::MalformedAttributeList => f.write_str("Malformed Attribute List"),
+            UpdateMessageError::UnrecognizedWellKnownAttribute => f.write_str("Unrecognized Well-known Attribute"),
+            UpdateMessageError::MissingWellKnownAttribute => f.write_str("Missing Well-known Attribute"),
+            UpdateMessageError::AttributeFlagsError => f.write_str("Attribute Flags Error"),
+            UpdateMessageError::AttributeLengthError => f.write_str("Attribute Length Error"),
+            UpdateMessageError::InvalidOriginAttribute => f.write_str("Invalid ORIGIN Attribute"),
+            UpdateMessageError::AsRoutingLoop => f.write_str("AS Routing Loop"),
+            UpdateMessageError::InvalidNextHopAttribute => f.write_str("Invalid NEXT_HOP Attribute"),
+            UpdateMessageError::OptionalAttributeError => f.write_str("Optional Attribute Error"),
+            UpdateMessageError::InvalidNetworkField => f.write_str("Invalid Network Field"),
+            UpdateMessageError::MalformedAsPath => f.write_str("Malformed AS_PATH"),
+            UpdateMessageError::Unknown(v) => write!(f, "subcode {}", v),
+        }
+    }
+}
+
+impl From<u8> for FiniteStateMachineError {
+    fn from(v: u8) -> FiniteStateMachineError {
+        match v {
+            0 => FiniteStateMachineError::Unspecific,
+            1 => FiniteStateMachineError::UnexpectedMessageInOpenSent,
+            2 => FiniteStateMachineError::UnexpectedMessageInOpenConfirm,
+            3 => FiniteStateMachineError::UnexpectedMessageInEstablished,
+            v => FiniteStateMachineError::Unknown(v),
+        }
+    }
+}
+
+impl From<FiniteStateMachineError> for u8 {
+    fn from(e: FiniteStateMachineError) -> u8 {
+        match e {
+            FiniteStateMachineError::Unspecific => 0,
+            FiniteStateMachineError::UnexpectedMessageInOpenSent => 1,
+            FiniteStateMachineError::UnexpectedMessageInOpenConfirm => 2,
+            FiniteStateMachineError::UnexpectedMessageInEstablished => 3,
+            FiniteStateMachineError::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for FiniteStateMachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FiniteStateMachineError::Unspecific => f.write_str("Unspecific"),
+            FiniteStateMachineError::UnexpectedMessageInOpenSent => f.write_str("Receive Unexpected Message in OpenSent State"),
+            FiniteStateMachineError::UnexpectedMessageInOpenConfirm => f.write_str("Receive Unexpected Message in OpenConfirm State"),
+            FiniteStateMachineError::UnexpectedMessageInEstablished => f.write_str("Receive Unexpected Message in Established State"),
+            FiniteStateMachineError::Unknown(v) => write!(f, "subcode {}", v),
+        }
+    }
+}
+
+impl From<u8> for CeaseSubcode {
+    fn from(v: u8) -> CeaseSubcode {
+        match v {
+            0 => CeaseSubcode::Unspecific,
+            1 => CeaseSubcode::MaximumNumberOfPrefixesReached,
+            2 => CeaseSubcode::AdministrativeShutdown,
+            3 => CeaseSubcode::PeerDeconfigured,
+            4 => CeaseSubcode::AdministrativeReset,
+            5 => CeaseSubcode::ConnectionRejected,
+            6 => CeaseSubcode::OtherConfigurationChange,
+            7 => CeaseSubcode::ConnectionCollisionResolution,
+            8 => CeaseSubcode::OutOfResources,
+            9 => CeaseSubcode::HardReset,
+            10 => CeaseSubcode::BfdDown,
+            v => CeaseSubcode::Unknown(v),
+        }
+    }
+}
+
+impl From<CeaseSubcode> for u8 {
+    fn from(s: CeaseSubcode) -> u8 {
+        match s {
+            CeaseSubcode::Unspecific => 0,
+            CeaseSubcode::MaximumNumberOfPrefixesReached => 1,
+            CeaseSubcode::AdministrativeShutdown => 2,
+            CeaseSubcode::PeerDeconfigured => 3,
+            CeaseSubcode::AdministrativeReset => 4,
+            CeaseSubcode::ConnectionRejected => 5,
+            CeaseSubcode::OtherConfigurationChange => 6,
+            CeaseSubcode::ConnectionCollisionResolution => 7,
+            CeaseSubcode::OutOfResources => 8,
+            CeaseSubcode::HardReset => 9,
+            CeaseSubcode::BfdDown => 10,
+            CeaseSubcode::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for CeaseSubcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CeaseSubcode::Unspecific => f.write_str("Unspecific"),
+            CeaseSubcode::MaximumNumberOfPrefixesReached => f.write_str("Maximum Number of Prefixes Reached"),
+            CeaseSubcode::AdministrativeShutdown => f.write_str("Administrative Shutdown"),
+            CeaseSubcode::PeerDeconfigured => f.write_str("Peer De-configured"),
+            CeaseSubcode::AdministrativeReset => f.write_str("Administrative Reset"),
+            CeaseSubcode::ConnectionRejected => f.write_str("Connection Rejected"),
+            CeaseSubcode::OtherConfigurationChange => f.write_str("Other Configuration Change"),
+            CeaseSubcode::ConnectionCollisionResolution => f.write_str("Connection Collision Resolution"),
+            CeaseSubcode::OutOfResources => f.write_str("Out of Resources"),
+            CeaseSubcode::HardReset => f.write_str("Hard Reset"),
+            CeaseSubcode::BfdDown => f.write_str("BFD Down"),
+            CeaseSubcode::Unknown(v) => write!(f, "subcode {}", v),
+        }
+    }
+}
+
+impl From<u8> for RouteRefreshMessageError {
+    fn from(v: u8) -> RouteRefreshMessageError {
+        match v {
+            0 => RouteRefreshMessageError::Unspecific,
+            1 => RouteRefreshMessageError::InvalidMessageLength,
+            v => RouteRefreshMessageError::Unknown(v),
+        }
+    }
+}
+
+impl From<RouteRefreshMessageError> for u8 {
+    fn from(e: RouteRefreshMessageError) -> u8 {
+        match e {
+            RouteRefreshMessageError::Unspecific => 0,
+            RouteRefreshMessageError::InvalidMessageLength => 1,
+            RouteRefreshMessageError::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for RouteRefreshMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteRefreshMessageError::Unspecific => f.write_str("Unspecific"),
+            RouteRefreshMessageError::InvalidMessageLength => f.write_str("Invalid Message Length"),
+            RouteRefreshMessageError::Unknown(v) => write!(f, "subcode {}", v),
+        }
+    }
+}
+
+// The longest Shutdown Communication, in bytes.
+const MAX_SHUTDOWN_COMMUNICATION: usize = 255;
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum ShutdownCommunicationError {
+    // The length octet says there's more than there is.
+    Truncated { length: usize, available: usize },
+    InvalidUtf8,
+}
+
+impl fmt::Display for ShutdownCommunicationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShutdownCommunicationError::Truncated { length, available } =>
+                write!(f, "shutdown communication of {} bytes with only {} present", length, available),
+            ShutdownCommunicationError::InvalidUtf8 => f.write_str("shutdown communication is not valid UTF-8"),
+        }
+    }
+}
+
+impl BgpNotificationMessage {
+    // A Cease carrying a Shutdown Communication. The subcode should be
+    // AdministrativeShutdown or AdministrativeReset. Messages over 255
+    // bytes are truncated.
+    pub fn shutdown(subcode: CeaseSubcode, message: &str) -> BgpNotificationMessage {
+        let mut end = ::std::cmp::min(message.len(), MAX_SHUTDOWN_COMMUNICATION);
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let mut data = Vec::with_capacity(1 + end);
+        data.push(end as u8);
+        data.extend_from_slice(&message.as_bytes()[..end]);
+        BgpNotificationMessage::cease(subcode, data)
+    }
+
+    // The Shutdown Communication, if this is a Cease that can carry one
+    // and it does. Anything after the message is ignored.
+    pub fn shutdown_communication(&self) -> Option<Result<String, ShutdownCommunicationError>> {
+        match self.cease_subcode() {
+            Some(CeaseSubcode::AdministrativeShutdown) | Some(CeaseSubcode::AdministrativeReset) => {},
+            _ => return None,
+        }
+
+        let (&length, rest) = self.data.split_first()?;
+        let length = length as usize;
+        if length > rest.len() {
+            return Some(Err(ShutdownCommunicationError::Truncated { length, available: rest.len() }));
+        }
+        Some(String::from_utf8(rest[..length].to_vec()).map_err(|_| ShutdownCommunicationError::InvalidUtf8))
+    }
+
+    pub fn new(error: NotificationError, data: Vec<u8>) -> BgpNotificationMessage {
+        BgpNotificationMessage { error_code: error.error_code(), error_subcode: error.error_subcode(), data }
+    }
+
+    pub fn cease(subcode: CeaseSubcode, data: Vec<u8>) -> BgpNotificationMessage {
+        BgpNotificationMessage::new(NotificationError::Cease(subcode), data)
+    }
+
+    pub fn error(&self) -> NotificationError {
+        NotificationError::new(self.error_code, self.error_subcode)
+    }
+
+    // The Cease subcode, if this is a Cease.
+    pub fn cease_subcode(&self) -> Option<CeaseSubcode> {
+        match self.error() {
+            NotificationError::Cease(subcode) => Some(subcode),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notification_error_round_trip_test() {
+        // Every code and subcode comes back as it was, known or not.
+        for code in 0..=255u8 {
+            for subcode in 0..=255u8 {
+                let error = NotificationError::new(code, subcode);
+                assert_eq!((error.error_code(), error.error_subcode()), (code, subcode));
+            }
+        }
+
+        assert_eq!(NotificationError::new(2, 2), NotificationError::OpenMessage(OpenMessageError::BadPeerAs));
+        assert_eq!(NotificationError::new(3, 12), NotificationError::UpdateMessage(UpdateMessageError::Unknown(12)));
+        assert_eq!(NotificationError::new(6, 0), NotificationError::Cease(CeaseSubcode::Unspecific));
+        assert_eq!(NotificationError::new(7, 1), NotificationError::RouteRefreshMessage(RouteRefreshMessageError::InvalidMessageLength));
+        assert_eq!(NotificationError::new(9, 1), NotificationError::Unknown(9, 1));
+    }
+
+    #[test]
+    fn notification_error_display_test() {
+        let display = |code, subcode| NotificationError::new(code, subcode).to_string();
+
+        assert_eq!(display(1, 1), "Message Header Error: Connection Not Synchronized");
+        assert_eq!(display(2, 2), "OPEN Message Error: Bad Peer AS");
+        assert_eq!(display(2, 0), "OPEN Message Error");
+        assert_eq!(display(3, 11), "UPDATE Message Error: Malformed AS_PATH");
+        assert_eq!(display(3, 42), "UPDATE Message Error: subcode 42");
+        assert_eq!(display(4, 0), "Hold Timer Expired");
+        assert_eq!(display(4, 1), "Hold Timer Expired: subcode 1");
+        assert_eq!(display(5, 3), "Finite State Machine Error: Receive Unexpected Message in Established State");
+        assert_eq!(display(6, 1), "Cease: Maximum Number of Prefixes Reached");
+        assert_eq!(display(7, 1), "ROUTE-REFRESH Message Error: Invalid Message Length");
+        assert_eq!(display(42, 3), "Unknown error code 42: subcode 3");
+    }
+
+    #[test]
+    fn shutdown_communication_test() {
+        let notification = BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, "maintenance, back at 14:00");
+        assert_eq!(notification.data[0], 26);
+        assert_eq!(notification.shutdown_communication(), Some(Ok("maintenance, back at 14:00".to_string())));
+
+        let reset = BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeReset, "");
+        assert_eq!(reset.data, vec![0]);
+        assert_eq!(reset.shutdown_communication(), Some(Ok(String::new())));
+
+        // Without any data there's no communication, and other Ceases
+        // never have one.
+        assert_eq!(BgpNotificationMessage::cease(CeaseSubcode::AdministrativeShutdown, vec![]).shutdown_communication(), None);
+        assert_eq!(BgpNotificationMessage::cease(CeaseSubcode::PeerDeconfigured, vec![1, b'a']).shutdown_communication(), None);
+    }
+
+    #[test]
+    fn shutdown_communication_invalid_test() {
+        let notification = BgpNotificationMessage::cease(CeaseSubcode::AdministrativeShutdown, vec![5, b'a', b'b']);
+        assert_eq!(notification.shutdown_communication(), Some(Err(ShutdownCommunicationError::Truncated { length: 5, available: 2 })));
+
+        let notification = BgpNotificationMessage::cease(CeaseSubcode::AdministrativeShutdown, vec![2, 0xc3, 0x28]);
+        assert_eq!(notification.shutdown_communication(), Some(Err(ShutdownCommunicationError::InvalidUtf8)));
+
+        // Long messages are cut short without splitting a character.
+        let message = "\u{e9}".repeat(200);
+        let notification = BgpNotificationMessage::shutdown(CeaseSubcode::AdministrativeShutdown, &message);
+        assert_eq!(notification.data.len(), 1 + 254);
+        assert_eq!(notification.shutdown_communication(), Some(Ok("\u{e9}".repeat(127))));
+    }
+}
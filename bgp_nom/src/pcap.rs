@@ -0,0 +1,835 @@
+// Read BGP messages out of packet captures.
+//
+// Both the classic libpcap format and pcapng are supported. Each packet
+// is decoded down through the link layer (Ethernet with optional 802.1Q
+// tags, Linux cooked capture, BSD loopback or raw IP), IPv4 or IPv6, and
+// TCP. Segments to or from port 179 are added to a stream per direction
+// which is reassembled in sequence order, and the stream is then cut up
+// into messages by bgp_message_frame().
+//
+// This is not a general purpose TCP reassembler. IP fragments are
+// skipped, and a stream that is picked up mid-way through is resynced by
+// searching for the next BGP marker. That is more than good enough for
+// captures of BGP sessions.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use nom::{be_u8, be_u16, be_u32};
+use nom::IResult;
+use nom::IResult::*;
+
+use super::{BgpMessage, extended_bgp_message_frame, parse_extended_bgp_message};
+
+const BGP_PORT: u16 = 179;
+
+#[derive(Debug)]
+pub enum PcapError {
+    Io(io::Error),
+    UnknownFormat,
+    Truncated,
+    Malformed,
+}
+
+impl From<io::Error> for PcapError {
+    fn from(e: io::Error) -> PcapError {
+        PcapError::Io(e)
+    }
+}
+
+// A BGP message cut out of a reassembled TCP stream. The message is kept
+// as raw bytes so callers can report where a parse error occurred. The
+// offset is the position of the message within its TCP stream, and the
+// timestamp is that of the packet which completed the message.
+
+#[derive(Debug,PartialEq)]
+pub struct CapturedMessage {
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl CapturedMessage {
+    pub fn parse(&self) -> IResult<&[u8], BgpMessage> {
+        parse_extended_bgp_message(&self.data)
+    }
+}
+
+pub fn read_pcap_file<P: AsRef<Path>>(path: P) -> Result<Vec<CapturedMessage>, PcapError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    read_pcap(&data)
+}
+
+// Extract every BGP message from a capture held in memory, in the order
+// the messages were completed on the wire.
+
+pub fn read_pcap(data: &[u8]) -> Result<Vec<CapturedMessage>, PcapError> {
+    let packets = read_packets(data)?;
+    let mut streams: HashMap<(SocketAddr, SocketAddr), TcpStream> = HashMap::new();
+    let mut messages = Vec::new();
+
+    for packet in packets {
+        let segment = match decode_link_layer(packet.link_type, &packet.data) {
+            Some(segment) => segment,
+            None => continue,
+        };
+
+        if segment.source.port() != BGP_PORT && segment.destination.port() != BGP_PORT {
+            continue;
+        }
+
+        let stream = streams.entry((segment.source, segment.destination)).or_insert_with(TcpStream::new);
+        for (offset, data) in stream.add_segment(segment.seq, segment.syn, segment.payload) {
+            messages.push(CapturedMessage {
+                timestamp: packet.timestamp,
+                source: segment.source,
+                destination: segment.destination,
+                offset,
+                data,
+            });
+        }
+    }
+
+    Ok(messages)
+}
+
+// Capture file formats.
+
+#[derive(Debug,PartialEq)]
+struct Packet {
+    timestamp: Duration,
+    link_type: u32,
+    data: Vec<u8>,
+}
+
+const PCAP_MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOSECONDS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+pub fn is_pcap(data: &[u8]) -> bool {
+    if data.len() < 4 {
+        return false;
+    }
+
+    matches!((LittleEndian::read_u32(data), BigEndian::read_u32(data)),
+        (PCAP_MAGIC_MICROSECONDS, _) | (PCAP_MAGIC_NANOSECONDS, _) |
+        (_, PCAP_MAGIC_MICROSECONDS) | (_, PCAP_MAGIC_NANOSECONDS) |
+        (PCAPNG_SECTION_HEADER_BLOCK, _))
+}
+
+fn read_packets(data: &[u8]) -> Result<Vec<Packet>, PcapError> {
+    if data.len() < 4 {
+        return Err(PcapError::UnknownFormat);
+    }
+
+    match (LittleEndian::read_u32(data), BigEndian::read_u32(data)) {
+        (PCAP_MAGIC_MICROSECONDS, _) => read_pcap_packets::<LittleEndian>(data, 1_000_000),
+        (PCAP_MAGIC_NANOSECONDS, _) => read_pcap_packets::<LittleEndian>(data, 1_000_000_000),
+        (_, PCAP_MAGIC_MICROSECONDS) => read_pcap_packets::<BigEndian>(data, 1_000_000),
+        (_, PCAP_MAGIC_NANOSECONDS) => read_pcap_packets::<BigEndian>(data, 1_000_000_000),
+        (PCAPNG_SECTION_HEADER_BLOCK, _) => read_pcapng_packets(data),
+        _ => Err(PcapError::UnknownFormat),
+    }
+}
+
+// The classic format is a 24 byte file header followed by records, each
+// with a 16 byte header. The byte order is given by the magic number, as
+// is whether the fractional part of the timestamp is in micro or nano
+// seconds. A fraction of a second or more is malformed.
+
+fn read_pcap_packets<B: ByteOrder>(data: &[u8], ticks_per_second: u64) -> Result<Vec<Packet>, PcapError> {
+    if data.len() < 24 {
+        return Err(PcapError::Truncated);
+    }
+
+    let link_type = B::read_u32(&data[20..]);
+    let mut packets = Vec::new();
+    let mut pos = 24;
+
+    while pos < data.len() {
+        if data.len() - pos < 16 {
+            return Err(PcapError::Truncated);
+        }
+
+        let seconds = B::read_u32(&data[pos..]);
+        let fraction = B::read_u32(&data[pos + 4..]);
+        let captured_length = B::read_u32(&data[pos + 8..]) as usize;
+        pos += 16;
+
+        if fraction as u64 >= ticks_per_second {
+            return Err(PcapError::Malformed);
+        }
+
+        if data.len() - pos < captured_length {
+            return Err(PcapError::Truncated);
+        }
+
+        packets.push(Packet {
+            timestamp: ticks_to_duration(seconds as u64 * ticks_per_second + fraction as u64, ticks_per_second),
+            link_type,
+            data: data[pos..pos + captured_length].to_vec(),
+        });
+        pos += captured_length;
+    }
+
+    Ok(packets)
+}
+
+// pcapng is a sequence of blocks. Each section starts with a Section
+// Header Block that sets the byte order for the rest of the section, and
+// each interface is described by an Interface Description Block giving
+// its link type and timestamp resolution. Packets are carried in
+// Enhanced, Simple or (obsolete) Packet Blocks. Anything else is skipped.
+
+const PCAPNG_INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const PCAPNG_PACKET_BLOCK: u32 = 2;
+const PCAPNG_SIMPLE_PACKET_BLOCK: u32 = 3;
+const PCAPNG_ENHANCED_PACKET_BLOCK: u32 = 6;
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+
+struct Interface {
+    link_type: u32,
+    snap_length: u32,
+    ticks_per_second: u64,
+}
+
+fn read_pcapng_packets(data: &[u8]) -> Result<Vec<Packet>, PcapError> {
+    let mut packets = Vec::new();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut big_endian = false;
+    let mut pos = 0;
+
+    while pos < data.len() {
+        if data.len() - pos < 12 {
+            return Err(PcapError::Truncated);
+        }
+
+        let block = &data[pos..];
+
+        // The block type of the Section Header Block is a palindrome so
+        // it can be recognized before the byte order is known.
+        let block_type = read_u32(big_endian, block);
+        if block_type == PCAPNG_SECTION_HEADER_BLOCK {
+            big_endian = match LittleEndian::read_u32(&block[8..]) {
+                PCAPNG_BYTE_ORDER_MAGIC => false,
+                _ if BigEndian::read_u32(&block[8..]) == PCAPNG_BYTE_ORDER_MAGIC => true,
+                _ => return Err(PcapError::UnknownFormat),
+            };
+            interfaces.clear();
+        }
+
+        let block_length = read_u32(big_endian, &block[4..]) as usize;
+        if block_length < 12 || block_length & 3 != 0 || block_length > block.len() {
+            return Err(PcapError::Truncated);
+        }
+
+        let body = &block[8..block_length - 4];
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION_BLOCK => {
+                if body.len() < 8 {
+                    return Err(PcapError::Truncated);
+                }
+                interfaces.push(Interface {
+                    link_type: read_u16(big_endian, body) as u32,
+                    snap_length: read_u32(big_endian, &body[4..]),
+                    ticks_per_second: pcapng_ticks_per_second(big_endian, &body[8..]),
+                });
+            },
+            PCAPNG_ENHANCED_PACKET_BLOCK | PCAPNG_PACKET_BLOCK => {
+                if body.len() < 20 {
+                    return Err(PcapError::Truncated);
+                }
+                let interface_id = match block_type {
+                    PCAPNG_ENHANCED_PACKET_BLOCK => read_u32(big_endian, body) as usize,
+                    _ => read_u16(big_endian, body) as usize,
+                };
+                let interface = interfaces.get(interface_id).ok_or(PcapError::UnknownFormat)?;
+                let ticks = (read_u32(big_endian, &body[4..]) as u64) << 32 | read_u32(big_endian, &body[8..]) as u64;
+                let captured_length = read_u32(big_endian, &body[12..]) as usize;
+                if body.len() - 20 < captured_length {
+                    return Err(PcapError::Truncated);
+                }
+                packets.push(Packet {
+                    timestamp: ticks_to_duration(ticks, interface.ticks_per_second),
+                    link_type: interface.link_type,
+                    data: body[20..20 + captured_length].to_vec(),
+                });
+            },
+            PCAPNG_SIMPLE_PACKET_BLOCK => {
+                // Simple Packet Blocks have no timestamp and always refer
+                // to the first interface. The captured length is implied
+                // by the block length and the interface snap length.
+                if body.len() < 4 {
+                    return Err(PcapError::Truncated);
+                }
+                let interface = interfaces.first().ok_or(PcapError::UnknownFormat)?;
+                let original_length = read_u32(big_endian, body) as usize;
+                let mut captured_length = ::std::cmp::min(original_length, body.len() - 4);
+                if interface.snap_length != 0 {
+                    captured_length = ::std::cmp::min(captured_length, interface.snap_length as usize);
+                }
+                packets.push(Packet {
+                    timestamp: Duration::new(0, 0),
+                    link_type: interface.link_type,
+                    data: body[4..4 + captured_length].to_vec(),
+                });
+            },
+            _ => {},
+        }
+
+        pos += block_length;
+    }
+
+    Ok(packets)
+}
+
+// The if_tsresol option gives the timestamp resolution as a negative
+// power of ten, or of two if the top bit is set. The default is micro
+// seconds.
+
+fn pcapng_ticks_per_second(big_endian: bool, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = read_u16(big_endian, options);
+        let length = read_u16(big_endian, &options[2..]) as usize;
+        let padded_length = (length + 3) & !3;
+
+        if options.len() - 4 < padded_length {
+            break;
+        }
+
+        if code == PCAPNG_OPTION_IF_TSRESOL && length == 1 {
+            let resolution = options[4];
+            let exponent = (resolution & 0x7f) as u32;
+            return match resolution & 0x80 {
+                0 => 10u64.checked_pow(exponent).unwrap_or(1_000_000),
+                _ => 2u64.checked_pow(exponent).unwrap_or(1_000_000),
+            };
+        }
+
+        if code == 0 {
+            break;
+        }
+
+        options = &options[4 + padded_length..];
+    }
+
+    1_000_000
+}
+
+fn ticks_to_duration(ticks: u64, ticks_per_second: u64) -> Duration {
+    let seconds = ticks / ticks_per_second;
+    let remainder = ticks % ticks_per_second;
+    Duration::new(seconds, (remainder as u128 * 1_000_000_000 / ticks_per_second as u128) as u32)
+}
+
+fn read_u16(big_endian: bool, buf: &[u8]) -> u16 {
+    match big_endian {
+        true => BigEndian::read_u16(buf),
+        false => LittleEndian::read_u16(buf),
+    }
+}
+
+fn read_u32(big_endian: bool, buf: &[u8]) -> u32 {
+    match big_endian {
+        true => BigEndian::read_u32(buf),
+        false => LittleEndian::read_u32(buf),
+    }
+}
+
+// Link, network and transport layer decoding.
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+
+#[derive(Debug,PartialEq)]
+struct TcpSegment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    seq: u32,
+    syn: bool,
+    payload: &'a [u8],
+}
+
+fn decode_link_layer<'a>(link_type: u32, data: &'a [u8]) -> Option<TcpSegment<'a>> {
+    match link_type {
+        LINKTYPE_ETHERNET => ethernet_frame(data).to_full_result().ok().and_then(|(ethertype, payload)| decode_ethertype(ethertype, payload)),
+        LINKTYPE_LINUX_SLL => linux_sll_frame(data).to_full_result().ok().and_then(|(ethertype, payload)| decode_ethertype(ethertype, payload)),
+        LINKTYPE_NULL if data.len() >= 4 => {
+            // The address family is in the byte order of the host that
+            // wrote the capture. AF_INET is 2 everywhere, AF_INET6 varies.
+            let family = match LittleEndian::read_u32(data) {
+                f if f > 0xffff => BigEndian::read_u32(data),
+                f => f,
+            };
+            match family {
+                2 => decode_ethertype(ETHERTYPE_IPV4, &data[4..]),
+                24 | 28 | 30 => decode_ethertype(ETHERTYPE_IPV6, &data[4..]),
+                _ => None,
+            }
+        },
+        LINKTYPE_RAW => match data.first().map(|v| v >> 4) {
+            Some(4) => decode_ethertype(ETHERTYPE_IPV4, data),
+            Some(6) => decode_ethertype(ETHERTYPE_IPV6, data),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn decode_ethertype<'a>(ethertype: u16, data: &'a [u8]) -> Option<TcpSegment<'a>> {
+    let (source, destination, payload) = match ethertype {
+        ETHERTYPE_IPV4 => ipv4_packet(data).to_full_result().ok()?,
+        ETHERTYPE_IPV6 => ipv6_packet(data).to_full_result().ok()?,
+        _ => return None,
+    };
+
+    let (source_port, destination_port, seq, flags, payload) = tcp_segment(payload).to_full_result().ok()?;
+
+    Some(TcpSegment {
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        seq,
+        syn: flags & 0x02 != 0,
+        payload,
+    })
+}
+
+// Ethernet II, skipping any number of 802.1Q or 802.1ad tags.
+
+named!(ethernet_frame<&[u8], (u16, &[u8])>,
+    do_parse!(
+        take!(12) >> // destination and source MAC
+        outer_ethertype: be_u16 >>
+        ethertype: call!(skip_vlan_tags, outer_ethertype) >>
+        payload: call!(rest) >>
+        ((ethertype, payload))
+    )
+);
+
+fn skip_vlan_tags(mut input: &[u8], mut ethertype: u16) -> IResult<&[u8], u16> {
+    while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+        match preceded!(input, take!(2), be_u16) {
+            Done(i, o) => { input = i; ethertype = o; },
+            Error(e) => return Error(e),
+            Incomplete(n) => return Incomplete(n),
+        }
+    }
+    Done(input, ethertype)
+}
+
+// Linux cooked capture, used when capturing on the "any" interface.
+
+named!(linux_sll_frame<&[u8], (u16, &[u8])>,
+    do_parse!(
+        take!(14) >> // packet type, address type, address length and address
+        ethertype: be_u16 >>
+        payload: call!(rest) >>
+        ((ethertype, payload))
+    )
+);
+
+// IPv4, ignoring options. Fragments are rejected because reassembling
+// them isn't worth it for BGP, which sets DF on almost all packets.
+
+named!(ipv4_packet<&[u8], (IpAddr, IpAddr, &[u8])>,
+    do_parse!(
+        version_ihl: verify!(be_u8, |v: u8| v >> 4 == 4 && v & 0x0f >= 5) >>
+        take!(1) >> // DSCP and ECN
+        total_length: verify!(be_u16, |v: u16| v as usize >= (version_ihl as usize & 0x0f) * 4) >>
+        take!(2) >> // identification
+        verify!(be_u16, |v: u16| v & 0x3fff == 0) >> // MF flag and fragment offset
+        take!(1) >> // TTL
+        tag!([IP_PROTOCOL_TCP]) >>
+        take!(2) >> // checksum
+        source: be_u32 >>
+        destination: be_u32 >>
+        take!((version_ihl as usize & 0x0f) * 4 - 20) >> // options
+        payload: take!(total_length as usize - (version_ihl as usize & 0x0f) * 4) >>
+        (IpAddr::V4(Ipv4Addr::from(source)), IpAddr::V4(Ipv4Addr::from(destination)), payload)
+    )
+);
+
+// IPv6, skipping the hop-by-hop, routing and destination options
+// extension headers. As with IPv4, fragments are rejected.
+
+named!(ipv6_packet<&[u8], (IpAddr, IpAddr, &[u8])>,
+    do_parse!(
+        verify!(be_u8, |v: u8| v >> 4 == 6) >>
+        take!(3) >> // traffic class and flow label
+        payload_length: be_u16 >>
+        next_header: be_u8 >>
+        take!(1) >> // hop limit
+        source: take!(16) >>
+        destination: take!(16) >>
+        payload: flat_map!(take!(payload_length), call!(skip_ipv6_extension_headers, next_header)) >>
+        (IpAddr::V6(ipv6_address(source)), IpAddr::V6(ipv6_address(destination)), payload)
+    )
+);
+
+fn skip_ipv6_extension_headers(mut input: &[u8], mut next_header: u8) -> IResult<&[u8], &[u8]> {
+    while next_header != IP_PROTOCOL_TCP {
+        match next_header {
+            0 | 43 | 60 => match do_parse!(input, next: be_u8 >> length: be_u8 >> take!(length as usize * 8 + 6) >> (next)) {
+                Done(i, o) => { input = i; next_header = o; },
+                Error(e) => return Error(e),
+                Incomplete(n) => return Incomplete(n),
+            },
+            _ => return Error(error_position!(::nom::ErrorKind::Tag, input)),
+        }
+    }
+    Done(&input[input.len()..], input)
+}
+
+fn ipv6_address(octets: &[u8]) -> Ipv6Addr {
+    let mut address = [0u8; 16];
+    address.copy_from_slice(octets);
+    Ipv6Addr::from(address)
+}
+
+// Returns the ports, sequence number, flags and payload of a TCP segment.
+
+named!(tcp_segment<&[u8], (u16, u16, u32, u8, &[u8])>,
+    do_parse!(
+        source_port: be_u16 >>
+        destination_port: be_u16 >>
+        seq: be_u32 >>
+        take!(4) >> // acknowledgment number
+        data_offset: verify!(map!(be_u8, |v: u8| (v >> 4) as usize * 4), |v: usize| v >= 20) >>
+        flags: be_u8 >>
+        take!(data_offset - 14) >> // window, checksum, urgent pointer and options
+        payload: call!(rest) >>
+        (source_port, destination_port, seq, flags, payload)
+    )
+);
+
+fn rest(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    Done(&input[input.len()..], input)
+}
+
+// Reassembly of one direction of a TCP connection.
+//
+// Data is appended to the buffer in sequence order. Segments that arrive
+// early are held until the gap before them is filled, and retransmitted
+// data that has already been seen is trimmed off. Sequence numbers are
+// tracked relative to the first one seen so wrapping is not a concern.
+
+struct TcpStream {
+    initial_seq: Option<u32>,
+    received: u32,
+    pending: BTreeMap<u32, Vec<u8>>,
+    buffer: Vec<u8>,
+    buffer_offset: usize,
+}
+
+impl TcpStream {
+    fn new() -> TcpStream {
+        TcpStream {
+            initial_seq: None,
+            received: 0,
+            pending: BTreeMap::new(),
+            buffer: Vec::new(),
+            buffer_offset: 0,
+        }
+    }
+
+    // Add a segment to the stream and return any messages it completes,
+    // along with their offset in the stream.
+    fn add_segment(&mut self, seq: u32, syn: bool, payload: &[u8]) -> Vec<(usize, Vec<u8>)> {
+        // A SYN starts a new connection, so anything from an earlier
+        // connection between the same ports is thrown away. The SYN
+        // itself consumes one sequence number.
+        let seq = match syn {
+            true => {
+                *self = TcpStream::new();
+                self.initial_seq = Some(seq.wrapping_add(1));
+                seq.wrapping_add(1)
+            },
+            false => seq,
+        };
+
+        let initial_seq = *self.initial_seq.get_or_insert(seq);
+        let relative_seq = seq.wrapping_sub(initial_seq);
+
+        // Anything more than half the sequence space behind us is old.
+        if payload.is_empty() || relative_seq > u32::MAX / 2 {
+            return Vec::new();
+        }
+
+        if relative_seq > self.received {
+            self.pending.insert(relative_seq, payload.to_vec());
+            return Vec::new();
+        }
+
+        self.append(relative_seq, payload);
+
+        while let Some(&next) = self.pending.keys().next() {
+            if next > self.received {
+                break;
+            }
+            let payload = self.pending.remove(&next).unwrap();
+            self.append(next, &payload);
+        }
+
+        self.frame_messages()
+    }
+
+    fn append(&mut self, relative_seq: u32, payload: &[u8]) {
+        let overlap = (self.received - relative_seq) as usize;
+        if overlap < payload.len() {
+            self.buffer.extend_from_slice(&payload[overlap..]);
+            self.received += (payload.len() - overlap) as u32;
+        }
+    }
+
+    fn frame_messages(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let mut messages = Vec::new();
+        let mut pos = 0;
+
+        loop {
+            match extended_bgp_message_frame(&self.buffer[pos..]) {
+                Done(_, frame) => {
+                    messages.push((self.buffer_offset + pos, frame.to_vec()));
+                    pos += frame.len();
+                },
+                Incomplete(_) => break,
+                Error(_) => {
+                    // Lost our place in the stream, so skip ahead to the
+                    // next thing that looks like a marker.
+                    pos += 1;
+                    match self.buffer[pos..].windows(16).position(|w| w == [0xff; 16]) {
+                        Some(p) => pos += p,
+                        None => pos = self.buffer.len().saturating_sub(15).max(pos),
+                    }
+                },
+            }
+        }
+
+        self.buffer.drain(..pos);
+        self.buffer_offset += pos;
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::BgpNotificationMessage;
+
+    fn bgp_pcap() -> &'static [u8] {
+        &include_bytes!("../assets/bgp.pcap")[..]
+    }
+
+    fn bgp_notification_cap() -> &'static [u8] {
+        &include_bytes!("../assets/BGP_notification.cap")[..]
+    }
+
+    fn message_type(message: &CapturedMessage) -> u8 {
+        message.data[18]
+    }
+
+    #[test]
+    fn read_pcap_bgp_test() {
+        let messages = read_pcap(bgp_pcap()).unwrap();
+
+        let client: SocketAddr = "192.168.0.15:2124".parse().unwrap();
+        let server: SocketAddr = "192.168.0.33:179".parse().unwrap();
+
+        let summary: Vec<(SocketAddr, u8)> = messages.iter().map(|m| (m.source, message_type(m))).collect();
+        assert_eq!(summary, vec![
+            (client, 1), (server, 1), (client, 4), (server, 4), (client, 4),
+            (client, 2), (client, 2), (server, 2), (server, 4),
+        ]);
+
+        assert_eq!(messages[0].destination, server);
+        assert_eq!(messages[0].timestamp, Duration::new(954392216, 957322000));
+        assert_eq!(messages[0].offset, 0);
+        assert_eq!(messages[6].offset, 29 + 19 + 19 + 98);
+
+        // Every message in the capture must parse and consume all input.
+        for message in &messages {
+            match message.parse() {
+                Done(rest, _) => assert_eq!(rest, &b""[..]),
+                other => panic!("failed to parse message at offset {}: {:?}", message.offset, other),
+            }
+        }
+    }
+
+    #[test]
+    fn read_pcap_notification_test() {
+        let messages = read_pcap(bgp_notification_cap()).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].source, "2.2.2.2:12732".parse().unwrap());
+        assert_eq!(messages[1].source, "1.1.1.1:179".parse().unwrap());
+
+        match messages[0].parse() {
+            Done(rest, BgpMessage::Open(open)) => {
+                assert_eq!(rest, &b""[..]);
+                assert_eq!(open.my_autonomous_system, 65200);
+            },
+            other => panic!("expected an open message: {:?}", other),
+        }
+
+        assert_eq!(messages[1].parse(), Done(&b""[..], BgpMessage::Notification(Box::new(BgpNotificationMessage { error_code: 2, error_subcode: 2, data: vec![0xfe, 0xb0] }))));
+    }
+
+    #[test]
+    fn read_pcap_matches_fixtures_test() {
+        // The .bin fixtures were originally cut out of these captures by
+        // hand, so check the reader agrees with them.
+        let messages = read_pcap(bgp_pcap()).unwrap();
+        assert_eq!(&messages[0].data[..], &include_bytes!("../assets/test_bgp_open1.bin")[..]);
+        assert_eq!(&messages[1].data[..], &include_bytes!("../assets/test_bgp_open2.bin")[..]);
+        assert_eq!(&messages[5].data[..], &include_bytes!("../assets/test_bgp_update1.bin")[..]);
+        assert_eq!(&messages[6].data[..], &include_bytes!("../assets/test_bgp_update2.bin")[..]);
+        assert_eq!(&messages[7].data[..], &include_bytes!("../assets/test_bgp_update3.bin")[..]);
+
+        let messages = read_pcap(bgp_notification_cap()).unwrap();
+        assert_eq!(&messages[0].data[..], &include_bytes!("../assets/test_bgp_open3.bin")[..]);
+        assert_eq!(&messages[1].data[..], &include_bytes!("../assets/test_bgp_notification1.bin")[..]);
+    }
+
+    // Rewrite a classic little endian capture as pcapng, with big endian
+    // byte order and nanosecond timestamps to exercise both.
+    fn pcap_to_pcapng(pcap: &[u8]) -> Vec<u8> {
+        fn block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+            let length = 12 + ((body.len() + 3) & !3);
+            let mut buf = [0u8; 4];
+            BigEndian::write_u32(&mut buf, block_type);
+            out.extend_from_slice(&buf);
+            BigEndian::write_u32(&mut buf, length as u32);
+            out.extend_from_slice(&buf);
+            out.extend_from_slice(body);
+            out.resize(out.len() + (length - 12 - body.len()), 0);
+            out.extend_from_slice(&buf);
+        }
+
+        let mut out = Vec::new();
+        block(&mut out, PCAPNG_SECTION_HEADER_BLOCK, &[0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        block(&mut out, PCAPNG_INTERFACE_DESCRIPTION_BLOCK, &[0, 1, 0, 0, 0, 0, 0xff, 0xff, 0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+        for packet in read_packets(pcap).unwrap() {
+            let ticks = packet.timestamp.as_secs() * 1_000_000_000 + packet.timestamp.subsec_nanos() as u64;
+            let mut body = vec![0u8; 20];
+            BigEndian::write_u32(&mut body[4..], (ticks >> 32) as u32);
+            BigEndian::write_u32(&mut body[8..], ticks as u32);
+            BigEndian::write_u32(&mut body[12..], packet.data.len() as u32);
+            BigEndian::write_u32(&mut body[16..], packet.data.len() as u32);
+            body.extend_from_slice(&packet.data);
+            block(&mut out, PCAPNG_ENHANCED_PACKET_BLOCK, &body);
+        }
+
+        out
+    }
+
+    #[test]
+    fn read_pcapng_test() {
+        let pcapng = pcap_to_pcapng(bgp_pcap());
+        assert_eq!(read_pcap(&pcapng).unwrap(), read_pcap(bgp_pcap()).unwrap());
+    }
+
+    #[test]
+    fn read_pcap_unknown_format_test() {
+        match read_pcap(&[0u8; 24]) {
+            Err(PcapError::UnknownFormat) => {},
+            other => panic!("expected UnknownFormat: {:?}", other),
+        }
+
+        match read_pcap(&bgp_pcap()[..90]) {
+            Err(PcapError::Truncated) => {},
+            other => panic!("expected Truncated: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_pcap_timestamp_test() {
+        // A nanosecond capture with a fraction just under a second, which
+        // overflowed when multiplied out in 32 bits, and then one second.
+        let mut pcap = bgp_pcap()[..24].to_vec();
+        LittleEndian::write_u32(&mut pcap, PCAP_MAGIC_NANOSECONDS);
+        pcap.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xc9, 0x9a, 0x3b, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let packets = read_packets(&pcap).unwrap();
+        assert_eq!(packets[0].timestamp, Duration::new(0xffffffff, 999_999_999));
+
+        pcap[28..32].copy_from_slice(&[0x00, 0xca, 0x9a, 0x3b]);
+        match read_packets(&pcap) {
+            Err(PcapError::Malformed) => {},
+            other => panic!("expected Malformed: {:?}", other),
+        }
+
+        let mut pcap = bgp_pcap()[..24].to_vec();
+        pcap.extend_from_slice(&[0, 0, 0, 0, 0x40, 0x42, 0x0f, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        match read_packets(&pcap) {
+            Err(PcapError::Malformed) => {},
+            other => panic!("expected Malformed: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ipv6_packet_test() {
+        let mut packet = vec![0x60, 0, 0, 0, 0, 28, IP_PROTOCOL_TCP, 64];
+        packet.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        packet.extend_from_slice(&[0x30, 0x39, 0, 179, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let segment = decode_link_layer(LINKTYPE_RAW, &packet).unwrap();
+        assert_eq!(segment.source, "[2001:db8::1]:12345".parse().unwrap());
+        assert_eq!(segment.destination, "[2001:db8::2]:179".parse().unwrap());
+        assert_eq!(segment.seq, 1);
+        assert_eq!(segment.payload, &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn tcp_stream_reassembly_test() {
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+        let mut data = Vec::new();
+        data.extend_from_slice(&keepalive[..]);
+        data.extend_from_slice(&keepalive[..]);
+
+        let mut stream = TcpStream::new();
+        assert!(stream.add_segment(1000, true, &[]).is_empty());
+
+        // Second half arrives first and is held.
+        assert!(stream.add_segment(1011, false, &data[10..]).is_empty());
+
+        // A retransmit overlapping what is held completes both messages.
+        let messages = stream.add_segment(1001, false, &data[..15]);
+        assert_eq!(messages, vec![(0, keepalive.to_vec()), (19, keepalive.to_vec())]);
+
+        // Duplicate data is ignored.
+        assert!(stream.add_segment(1001, false, &data[..]).is_empty());
+    }
+
+    #[test]
+    fn tcp_stream_resync_test() {
+        let keepalive = include_bytes!("../assets/test_bgp_keepalive1.bin");
+
+        // Picked up part way through a message.
+        let mut data = vec![0xff, 0xff, 0, 19, 4];
+        data.extend_from_slice(&keepalive[..]);
+
+        let mut stream = TcpStream::new();
+        assert_eq!(stream.add_segment(5000, false, &data), vec![(5, keepalive.to_vec())]);
+    }
+}
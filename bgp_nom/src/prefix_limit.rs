@@ -0,0 +1,286 @@
+// Maximum prefix limits (RFC 4486 section 4).
+//
+// A peer that suddenly sends far more routes than it should, often a full
+// table leaked by mistake, can be cut off before it does any harm. Each
+// limit is for one AFI/SAFI. Once a percentage of the maximum is reached
+// there is a warning, and going over the maximum closes the session with
+// a Cease, Maximum Number of Prefixes Reached, holding the AFI, SAFI and
+// the limit in its data. The session is kept down for the restart
+// interval, or until it is restarted by hand if there isn't one.
+//
+// The same prefix can be announced again with new attributes, and a
+// withdrawal can be for a prefix that was never announced, so counting
+// needs to know which prefixes the peer has. The set of prefixes is kept
+// for each limit. IPv4 unicast is counted from the UPDATE's own fields
+// as well as MP_REACH_NLRI and MP_UNREACH_NLRI, and every other family
+// from those. A route is known by what identifies it in its family, so
+// the labels it was sent with don't count, while a FlowSpec rule or a
+// BGP-LS object is the whole of it. The NLRI of families we don't decode
+// are read as plain prefixes for unicast and multicast, and not counted
+// otherwise.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use super::*;
+use super::evpn::EvpnRoute;
+use super::mpls::Label;
+use super::vpn::RouteDistinguisher;
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct PrefixLimit {
+    pub afi: u16,
+    pub safi: u8,
+    pub maximum: u32,
+    // Warn once the count reaches this percentage of the maximum.
+    pub warning_threshold: u8,
+    // How long to keep the session down after going over the maximum,
+    // or None to keep it down until it's restarted by hand.
+    pub restart_interval: Option<Duration>,
+}
+
+impl PrefixLimit {
+    // The Cease to send when the limit is exceeded.
+    pub fn notification(&self) -> BgpMessage {
+        let mut data = Vec::with_capacity(7);
+        data.extend_from_slice(&self.afi.to_be_bytes());
+        data.push(self.safi);
+        data.extend_from_slice(&self.maximum.to_be_bytes());
+        BgpMessage::Notification(Box::new(BgpNotificationMessage::cease(CeaseSubcode::MaximumNumberOfPrefixesReached, data)))
+    }
+
+    fn warning_count(&self) -> u64 {
+        (self.maximum as u64 * self.warning_threshold as u64).div_ceil(100)
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum PrefixLimitEvent {
+    Warning { limit: PrefixLimit, count: u32 },
+    Exceeded { limit: PrefixLimit, count: u32 },
+}
+
+// What a route is known by within its family.
+#[derive(Debug,PartialEq,Eq,Hash,Clone)]
+enum RouteKey {
+    Prefix(IpAddr, u8),
+    Vpn(RouteDistinguisher, IpAddr, u8),
+    // The route as it's encoded.
+    Encoded(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Counter {
+    limit: PrefixLimit,
+    prefixes: HashSet<RouteKey>,
+    warned: bool,
+}
+
+// Counts the prefixes received from one peer against its limits.
+#[derive(Debug)]
+pub struct PrefixCounter {
+    counters: Vec<Counter>,
+}
+
+impl PrefixCounter {
+    pub fn new(limits: Vec<PrefixLimit>) -> PrefixCounter {
+        PrefixCounter {
+            counters: limits.into_iter().map(|l| Counter { limit: l, prefixes: HashSet::new(), warned: false }).collect(),
+        }
+    }
+
+    // Count the prefixes in an UPDATE, returning any limits crossed. The
+    // warning is only given once, until the count drops below the
+    // threshold again.
+    pub fn update(&mut self, update: &BgpUpdateMessage) -> Vec<PrefixLimitEvent> {
+        let mut events = vec![];
+
+        for counter in &mut self.counters {
+            let (withdrawn, announced) = routes(update, counter.limit.afi, counter.limit.safi);
+            for route in &withdrawn {
+                counter.prefixes.remove(route);
+            }
+            counter.prefixes.extend(announced);
+
+            let count = counter.prefixes.len() as u32;
+            if count > counter.limit.maximum {
+                events.push(PrefixLimitEvent::Exceeded { limit: counter.limit.clone(), count });
+            } else if count as u64 >= counter.limit.warning_count() {
+                if !counter.warned {
+                    counter.warned = true;
+                    events.push(PrefixLimitEvent::Warning { limit: counter.limit.clone(), count });
+                }
+            } else {
+                counter.warned = false;
+            }
+        }
+
+        events
+    }
+
+    // The number of prefixes counted for a limit, or None if there's no
+    // limit for the AFI/SAFI.
+    pub fn count(&self, afi: u16, safi: u8) -> Option<u32> {
+        self.counters.iter().find(|c| c.limit.afi == afi && c.limit.safi == safi).map(|c| c.prefixes.len() as u32)
+    }
+
+    // Forget everything counted, for when the session goes down.
+    pub fn clear(&mut self) {
+        for counter in &mut self.counters {
+            counter.prefixes.clear();
+            counter.warned = false;
+        }
+    }
+}
+
+// The routes of the family an UPDATE withdraws and announces.
+fn routes(update: &BgpUpdateMessage, afi: u16, safi: u8) -> (Vec<RouteKey>, Vec<RouteKey>) {
+    let ipv4_key = |p: &Ipv4Prefix| RouteKey::Prefix(IpAddr::V4(p.addr()), p.length);
+    let (mut withdrawn, mut announced) = match (afi, safi) {
+        (AFI_IPV4, SAFI_UNICAST) => (update.withdrawn_routes.iter().map(ipv4_key).collect(), update.nlri.iter().map(ipv4_key).collect()),
+        _ => (vec![], vec![]),
+    };
+
+    for attribute in &update.path_attributes {
+        match attribute.attribute {
+            PathAttribute::MpUnreachNlri(ref mp) if mp.afi == afi && mp.safi == safi => withdrawn.extend(route_keys(&mp.withdrawn_routes, afi, safi)),
+            PathAttribute::MpReachNlri(ref mp) if mp.afi == afi && mp.safi == safi => announced.extend(route_keys(&mp.nlri, afi, safi)),
+            _ => {},
+        }
+    }
+    (withdrawn, announced)
+}
+
+fn route_keys(nlri: &MpNlri, afi: u16, safi: u8) -> Vec<RouteKey> {
+    // A route that was parsed can be encoded again.
+    let encoded = |encode: &dyn Fn(&mut Vec<u8>) -> Result<(), String>| {
+        let mut out = Vec::new();
+        let _ = encode(&mut out);
+        RouteKey::Encoded(out)
+    };
+
+    match *nlri {
+        MpNlri::Labeled(ref prefixes) => prefixes.iter().map(|p| RouteKey::Prefix(p.address, p.length)).collect(),
+        MpNlri::Vpn(ref prefixes) => prefixes.iter().map(|p| RouteKey::Vpn(p.rd, p.address, p.length)).collect(),
+        MpNlri::Evpn(ref routes) => routes.iter().map(|r| encoded(&|out| without_labels(r).encode(out))).collect(),
+        MpNlri::LinkState(ref nlri) => nlri.iter().map(|n| encoded(&|out| {
+            n.encode(out);
+            Ok(())
+        })).collect(),
+        MpNlri::FlowSpec(ref rules) => rules.iter().map(|r| encoded(&|out| r.encode(out))).collect(),
+        MpNlri::Unknown(ref nlri) => match (afi, safi) {
+            (AFI_IPV4, SAFI_UNICAST) | (AFI_IPV4, SAFI_MULTICAST) | (AFI_IPV6, SAFI_UNICAST) | (AFI_IPV6, SAFI_MULTICAST) => {
+                match plain_prefixes(nlri, afi) {
+                    Done(_, prefixes) => prefixes.into_iter().map(|(address, length)| RouteKey::Prefix(address, length)).collect(),
+                    _ => vec![],
+                }
+            },
+            _ => vec![],
+        },
+    }
+}
+
+
+// An EVPN route with its labels cleared, as a withdrawal's needn't be
+// those it was announced with.
+fn without_labels(route: &EvpnRoute) -> EvpnRoute {
+    let mut route = route.clone();
+    match route {
+        EvpnRoute::EthernetAutoDiscovery { ref mut label, .. } | EvpnRoute::IpPrefix { ref mut label, .. } => *label = Label::new(0),
+        EvpnRoute::MacIpAdvertisement { ref mut labels, .. } => labels.clear(),
+        EvpnRoute::InclusiveMulticast { .. } | EvpnRoute::EthernetSegment { .. } | EvpnRoute::Unknown { .. } => {},
+    }
+    route
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mpls::LabeledPrefix;
+
+    fn limit(maximum: u32) -> PrefixLimit {
+        PrefixLimit { afi: 1, safi: 1, maximum, warning_threshold: 75, restart_interval: None }
+    }
+
+    fn update(withdrawn: &[&str], nlri: &[&str]) -> BgpUpdateMessage {
+        BgpUpdateMessage {
+            withdrawn_routes: withdrawn.iter().map(|p| p.parse().unwrap()).collect(),
+            path_attributes: vec![],
+            nlri: nlri.iter().map(|p| p.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn prefix_counter_test() {
+        let mut counter = PrefixCounter::new(vec![limit(4)]);
+        assert_eq!(counter.update(&update(&[], &["10.0.0.0/8", "10.0.0.0/16"])), vec![]);
+
+        // Announcing a prefix again, or withdrawing one that isn't there,
+        // doesn't change the count.
+        assert_eq!(counter.update(&update(&["192.0.2.0/24"], &["10.0.0.0/8"])), vec![]);
+        assert_eq!(counter.count(1, 1), Some(2));
+        assert_eq!(counter.count(2, 1), None);
+
+        assert_eq!(counter.update(&update(&[], &["10.1.0.0/16"])), vec![PrefixLimitEvent::Warning { limit: limit(4), count: 3 }]);
+        assert_eq!(counter.update(&update(&[], &["10.2.0.0/16"])), vec![]);
+
+        // Dropping below the threshold means the next crossing warns
+        // again.
+        counter.update(&update(&["10.1.0.0/16", "10.2.0.0/16"], &[]));
+        assert_eq!(counter.update(&update(&[], &["10.1.0.0/16"])), vec![PrefixLimitEvent::Warning { limit: limit(4), count: 3 }]);
+
+        assert_eq!(counter.update(&update(&[], &["10.2.0.0/16", "10.3.0.0/16"])), vec![PrefixLimitEvent::Exceeded { limit: limit(4), count: 5 }]);
+
+        counter.clear();
+        assert_eq!(counter.count(1, 1), Some(0));
+    }
+
+    #[test]
+    fn prefix_counter_multiprotocol_test() {
+        let mp = |attribute| BgpUpdateMessage {
+            withdrawn_routes: vec![],
+            path_attributes: vec![BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+                attribute,
+            }],
+            nlri: vec![],
+        };
+        let reach = |afi, safi, nlri| mp(PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute { afi, safi, next_hop: vec![], nlri })));
+        let unreach = |afi, safi, withdrawn_routes| mp(PathAttribute::MpUnreachNlri(Box::new(MpUnreachNlriAttribute { afi, safi, withdrawn_routes })));
+        let ipv6 = PrefixLimit { afi: 2, ..limit(4) };
+        let mut counter = PrefixCounter::new(vec![limit(4), ipv6.clone(), PrefixLimit { safi: 4, ..limit(4) }]);
+
+        // IPv6 unicast, 2001:db8::/32 and 2001:db8:1::/48, which doesn't
+        // count for IPv4.
+        assert_eq!(counter.update(&reach(2, 1, MpNlri::Unknown(vec![32, 0x20, 0x01, 0x0d, 0xb8, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 1]))), vec![]);
+        assert_eq!(counter.count(2, 1), Some(2));
+        assert_eq!(counter.count(1, 1), Some(0));
+        counter.update(&unreach(2, 1, MpNlri::Unknown(vec![32, 0x20, 0x01, 0x0d, 0xb8])));
+        assert_eq!(counter.count(2, 1), Some(1));
+
+        let more = MpNlri::Unknown(vec![48, 0x20, 0x01, 0x0d, 0xb8, 0, 2, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 3, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 4, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 5]);
+        assert_eq!(counter.update(&reach(2, 1, more)), vec![PrefixLimitEvent::Exceeded { limit: ipv6, count: 5 }]);
+
+        // IPv4 unicast is the same prefix whichever way it's sent.
+        counter.update(&reach(1, 1, MpNlri::Unknown(vec![8, 10])));
+        counter.update(&update(&[], &["10.0.0.0/8", "192.0.2.0/24"]));
+        assert_eq!(counter.count(1, 1), Some(2));
+
+        // Labeled unicast is withdrawn whatever the label.
+        let labeled = |label| MpNlri::Labeled(vec![LabeledPrefix { labels: vec![Label::new(label)], address: "10.0.0.0".parse().unwrap(), length: 8 }]);
+        counter.update(&reach(1, 4, labeled(100)));
+        assert_eq!(counter.count(1, 4), Some(1));
+        counter.update(&unreach(1, 4, labeled(0)));
+        assert_eq!(counter.count(1, 4), Some(0));
+    }
+
+    #[test]
+    fn prefix_limit_notification_test() {
+        let notification = limit(1000).notification();
+        assert_eq!(notification.encode().unwrap()[19..], [6, 1, 0, 1, 1, 0, 0, 0x03, 0xe8]);
+        match notification {
+            BgpMessage::Notification(n) => assert_eq!(n.cease_subcode(), Some(CeaseSubcode::MaximumNumberOfPrefixesReached)),
+            other => panic!("expected a notification: {:?}", other),
+        }
+    }
+}
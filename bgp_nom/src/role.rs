@@ -0,0 +1,254 @@
+// BGP Roles and the Only to Customer (OTC) attribute (RFC 9234), for
+// preventing route leaks.
+//
+// Each side of a session says in its OPEN what it is to the other, and
+// the two have to agree: Provider with Customer, Route Server with Route
+// Server Client, or Peer with Peer. Anything else is closed with a Role
+// Mismatch. A peer not sending a role at all is allowed, unless strict
+// mode is on.
+//
+// With the roles known, routes are marked with the OTC attribute as they
+// go to customers, or come in from providers and peers, holding the AS
+// that did it. A route with OTC can only go down to customers from then
+// on, so one coming in from a customer, or from a peer other than the
+// one that marked it, has leaked. Those are treated as withdrawn.
+
+use std::fmt;
+
+use super::*;
+
+// The role we are to the peer, or it to us. The values are from the BGP
+// Role capability.
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BgpRole {
+    Provider,
+    RouteServer,
+    RouteServerClient,
+    Customer,
+    Peer,
+    Unknown(u8),
+}
+
+impl From<u8> for BgpRole {
+    fn from(v: u8) -> BgpRole {
+        match v {
+            0 => BgpRole::Provider,
+            1 => BgpRole::RouteServer,
+            2 => BgpRole::RouteServerClient,
+            3 => BgpRole::Customer,
+            4 => BgpRole::Peer,
+            v => BgpRole::Unknown(v),
+        }
+    }
+}
+
+impl From<BgpRole> for u8 {
+    fn from(role: BgpRole) -> u8 {
+        match role {
+            BgpRole::Provider => 0,
+            BgpRole::RouteServer => 1,
+            BgpRole::RouteServerClient => 2,
+            BgpRole::Customer => 3,
+            BgpRole::Peer => 4,
+            BgpRole::Unknown(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for BgpRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BgpRole::Provider => f.write_str("provider"),
+            BgpRole::RouteServer => f.write_str("rs"),
+            BgpRole::RouteServerClient => f.write_str("rs-client"),
+            BgpRole::Customer => f.write_str("customer"),
+            BgpRole::Peer => f.write_str("peer"),
+            BgpRole::Unknown(v) => write!(f, "role {}", v),
+        }
+    }
+}
+
+impl BgpRole {
+    // The role the other side of a session has to have.
+    pub fn opposite(self) -> Option<BgpRole> {
+        match self {
+            BgpRole::Provider => Some(BgpRole::Customer),
+            BgpRole::Customer => Some(BgpRole::Provider),
+            BgpRole::RouteServer => Some(BgpRole::RouteServerClient),
+            BgpRole::RouteServerClient => Some(BgpRole::RouteServer),
+            BgpRole::Peer => Some(BgpRole::Peer),
+            BgpRole::Unknown(_) => None,
+        }
+    }
+}
+
+// The role in an OPEN. More than one is fine as long as they agree.
+pub fn open_role(open: &BgpOpenMessage) -> Result<Option<BgpRole>, ()> {
+    let mut role = None;
+    for parameter in &open.optional_parameters {
+        let OptionalParameter::Capability(ref capabilities) = *parameter;
+        for capability in capabilities {
+            if let CapabilityParameter::Role(ref r) = *capability {
+                if role.is_some_and(|role| role != r.role) {
+                    return Err(());
+                }
+                role = Some(r.role);
+            }
+        }
+    }
+    Ok(role)
+}
+
+// Check the role in the peer's OPEN against ours, returning the peer's
+// role, or Err if the session has to be closed with a Role Mismatch.
+pub fn negotiate(local: BgpRole, strict: bool, open: &BgpOpenMessage) -> Result<Option<BgpRole>, ()> {
+    match open_role(open)? {
+        Some(remote) if local.opposite() == Some(remote) => Ok(Some(remote)),
+        Some(_) => Err(()),
+        None if strict => Err(()),
+        None => Ok(None),
+    }
+}
+
+pub fn only_to_customer(update: &BgpUpdateMessage) -> Option<u32> {
+    update.path_attributes.iter().filter_map(|a| match a.attribute {
+        PathAttribute::OnlyToCustomer(ref otc) => Some(otc.asn),
+        _ => None,
+    }).next()
+}
+
+fn add_only_to_customer(update: &mut BgpUpdateMessage, asn: u32) {
+    update.path_attributes.push(BgpPathAttribute {
+        flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+        attribute: PathAttribute::OnlyToCustomer(Box::new(OnlyToCustomerAttribute { asn })),
+    });
+}
+
+// The ingress procedure, for an UPDATE received on a session where we
+// have the local role. Returns false if its routes have leaked.
+pub fn ingress(update: &mut BgpUpdateMessage, local: BgpRole, remote_as: u32) -> bool {
+    if update.nlri.is_empty() {
+        return true;
+    }
+
+    match (local, only_to_customer(update)) {
+        // From a customer or route server client.
+        (BgpRole::Provider, Some(_)) | (BgpRole::RouteServer, Some(_)) => false,
+        (BgpRole::Peer, Some(asn)) => asn == remote_as,
+        // From a provider, peer or route server.
+        (BgpRole::Customer, None) | (BgpRole::Peer, None) | (BgpRole::RouteServerClient, None) => {
+            add_only_to_customer(update, remote_as);
+            true
+        },
+        _ => true,
+    }
+}
+
+// The egress procedure, for an UPDATE we're about to send on a session
+// where we have the local role. Returns false if its routes mustn't be
+// sent.
+pub fn egress(update: &mut BgpUpdateMessage, local: BgpRole, local_as: u32) -> bool {
+    if update.nlri.is_empty() {
+        return true;
+    }
+
+    match (local, only_to_customer(update)) {
+        // To a provider, peer or route server.
+        (BgpRole::Customer, Some(_)) | (BgpRole::Peer, Some(_)) | (BgpRole::RouteServerClient, Some(_)) => false,
+        // To a customer, peer or route server client.
+        (BgpRole::Provider, None) | (BgpRole::Peer, None) | (BgpRole::RouteServer, None) => {
+            add_only_to_customer(update, local_as);
+            true
+        },
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(roles: &[u8]) -> BgpOpenMessage {
+        BgpOpenMessage {
+            version: 4,
+            my_autonomous_system: 65001,
+            hold_time: 90,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 2),
+            optional_parameters: vec![OptionalParameter::Capability(roles.iter().map(|r| {
+                CapabilityParameter::Role(Box::new(RoleCapability { role: BgpRole::from(*r) }))
+            }).collect())],
+        }
+    }
+
+    fn update(otc: Option<u32>) -> BgpUpdateMessage {
+        let mut update = BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri: vec!["10.0.0.0/8".parse().unwrap()] };
+        if let Some(asn) = otc {
+            add_only_to_customer(&mut update, asn);
+        }
+        update
+    }
+
+    #[test]
+    fn role_negotiate_test() {
+        assert_eq!(negotiate(BgpRole::Provider, false, &open(&[3])), Ok(Some(BgpRole::Customer)));
+        assert_eq!(negotiate(BgpRole::RouteServerClient, false, &open(&[1, 1])), Ok(Some(BgpRole::RouteServer)));
+        assert_eq!(negotiate(BgpRole::Peer, false, &open(&[4])), Ok(Some(BgpRole::Peer)));
+        assert_eq!(negotiate(BgpRole::Peer, false, &open(&[])), Ok(None));
+
+        assert_eq!(negotiate(BgpRole::Provider, false, &open(&[0])), Err(()));
+        assert_eq!(negotiate(BgpRole::Peer, false, &open(&[4, 3])), Err(()));
+        assert_eq!(negotiate(BgpRole::Peer, false, &open(&[9])), Err(()));
+        assert_eq!(negotiate(BgpRole::Peer, true, &open(&[])), Err(()));
+    }
+
+    #[test]
+    fn role_ingress_test() {
+        // From a provider, marked as only going to customers.
+        let mut u = update(None);
+        assert!(ingress(&mut u, BgpRole::Customer, 65001));
+        assert_eq!(only_to_customer(&u), Some(65001));
+
+        // From a customer, it can't have been marked.
+        assert!(ingress(&mut update(None), BgpRole::Provider, 65001));
+        assert!(!ingress(&mut update(Some(65002)), BgpRole::Provider, 65001));
+        assert!(!ingress(&mut update(Some(65002)), BgpRole::RouteServer, 65001));
+
+        // From a peer, only by that peer.
+        assert!(ingress(&mut update(Some(65001)), BgpRole::Peer, 65001));
+        assert!(!ingress(&mut update(Some(65002)), BgpRole::Peer, 65001));
+        let mut u = update(None);
+        assert!(ingress(&mut u, BgpRole::Peer, 65001));
+        assert_eq!(only_to_customer(&u), Some(65001));
+
+        // Withdrawals are never a leak.
+        let mut u = update(Some(65002));
+        u.nlri.clear();
+        assert!(ingress(&mut u, BgpRole::Provider, 65001));
+    }
+
+    #[test]
+    fn role_egress_test() {
+        // To a customer, marked by us.
+        let mut u = update(None);
+        assert!(egress(&mut u, BgpRole::Provider, 65000));
+        assert_eq!(only_to_customer(&u), Some(65000));
+        // Keeping the mark of whoever made it.
+        let mut u = update(Some(65002));
+        assert!(egress(&mut u, BgpRole::Provider, 65000));
+        assert_eq!(only_to_customer(&u), Some(65002));
+
+        // Not up to providers or across to peers once marked.
+        assert!(!egress(&mut update(Some(65002)), BgpRole::Customer, 65000));
+        assert!(!egress(&mut update(Some(65002)), BgpRole::Peer, 65000));
+        assert!(!egress(&mut update(Some(65002)), BgpRole::RouteServerClient, 65000));
+
+        let mut u = update(None);
+        assert!(egress(&mut u, BgpRole::Customer, 65000));
+        assert_eq!(only_to_customer(&u), None);
+        let mut u = update(None);
+        assert!(egress(&mut u, BgpRole::RouteServerClient, 65000));
+        assert_eq!(only_to_customer(&u), None);
+    }
+}
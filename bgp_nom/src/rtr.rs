@@ -0,0 +1,966 @@
+// The RPKI to Router protocol (RFC 8210), and Route Origin Validation
+// (RFC 6811) with what it fetches.
+//
+// An RTR cache serves Validated ROA Payloads (VRPs): a prefix, the
+// longest prefix length allowed within it, and the AS allowed to
+// originate it. The client asks for everything with a Reset Query, and
+// afterwards only for what has changed since the serial number it has
+// with a Serial Query. It does that when the cache sends a Serial Notify,
+// and otherwise every refresh interval. A query that isn't answered is
+// sent again after the retry interval. A Cache Reset from the cache means
+// it can't do incremental updates, so the client starts again from a
+// Reset Query. If the data isn't refreshed within the expire interval the
+// table is emptied, as stale data is worse than none.
+//
+// Changes between a Cache Response and the End of Data are applied in one
+// go, so the table is never seen half updated.
+//
+// We speak version 1 unless asked for version 2, which adds ASPA PDUs
+// (draft-ietf-sidrops-8210bis) for AS path verification, see aspa.rs.
+// Version 0 PDUs are understood too, the only difference being the End
+// of Data without the intervals. Router Key PDUs (for BGPsec) are parsed
+// but not used.
+//
+// Validating a route looks for the VRPs covering its prefix. With none it
+// is NotFound. It is Valid if one of them has the origin AS and allows
+// the prefix length, and Invalid otherwise. The origin AS is the last AS
+// in the AS_PATH, if that ends in an AS_SEQUENCE. If it ends in an AS_SET
+// there is no origin AS, so the route can't be Valid.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use futures::{SinkExt, StreamExt};
+use nom::{be_u8, be_u16, be_u32};
+use nom::IResult::*;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::time::{Instant, sleep_until};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use super::*;
+use super::aspa::{AspaState, AspaTable, Relation};
+
+const RTR_VERSION: u8 = 1;
+const MAX_RTR_VERSION: u8 = 2;
+const RTR_HEADER_LENGTH: u32 = 8;
+// Far more than any PDU needs, to stop a bad length making us buffer
+// forever.
+const MAX_RTR_PDU_LENGTH: u32 = 65536;
+
+// The intervals a cache suggests, in seconds. These are the defaults from
+// RFC 8210 section 6, used until the cache says otherwise.
+const DEFAULT_REFRESH_INTERVAL: u32 = 3600;
+const DEFAULT_RETRY_INTERVAL: u32 = 600;
+const DEFAULT_EXPIRE_INTERVAL: u32 = 7200;
+
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub struct Vrp {
+    pub prefix: IpAddr,
+    pub length: u8,
+    pub max_length: u8,
+    pub asn: u32,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct Intervals {
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum RtrPdu {
+    SerialNotify { session_id: u16, serial: u32 },
+    SerialQuery { session_id: u16, serial: u32 },
+    ResetQuery,
+    CacheResponse { session_id: u16 },
+    // IPv4 and IPv6 Prefix PDUs, announcing or withdrawing a VRP.
+    Prefix { announce: bool, vrp: Vrp },
+    // The intervals are missing in version 0.
+    EndOfData { session_id: u16, serial: u32, intervals: Option<Intervals> },
+    CacheReset,
+    RouterKey { announce: bool, subject_key_identifier: Vec<u8>, asn: u32, subject_public_key_info: Vec<u8> },
+    ErrorReport { error_code: u16, pdu: Vec<u8>, text: String },
+    // Version 2 only. A withdrawal has no providers.
+    Aspa { announce: bool, customer: u32, providers: Vec<u32> },
+}
+
+// Error Report codes, RFC 8210 section 12.
+const CORRUPT_DATA: u16 = 0;
+const INVALID_REQUEST: u16 = 3;
+const UNSUPPORTED_PDU_TYPE: u16 = 5;
+
+// Split one PDU off the front of a stream.
+named!(pub rtr_pdu_frame,
+    do_parse!(
+        length: peek!(preceded!(take!(4), verify!(be_u32, |v: u32| (RTR_HEADER_LENGTH..=MAX_RTR_PDU_LENGTH).contains(&v)))) >>
+        frame: take!(length) >>
+        (frame)
+    )
+);
+
+named!(pub parse_rtr_pdu<RtrPdu>,
+    do_parse!(
+        verify!(be_u8, |v: u8| v <= MAX_RTR_VERSION) >>
+        pdu_type: be_u8 >>
+        session_id: be_u16 >>
+        length: verify!(be_u32, |v: u32| v >= RTR_HEADER_LENGTH) >>
+        pdu: flat_map!(take!(length - RTR_HEADER_LENGTH), switch!(value!(pdu_type),
+            0u8 => map!(be_u32, |serial| RtrPdu::SerialNotify { session_id, serial }) |
+            1u8 => map!(be_u32, |serial| RtrPdu::SerialQuery { session_id, serial }) |
+            2u8 => value!(RtrPdu::ResetQuery) |
+            3u8 => value!(RtrPdu::CacheResponse { session_id }) |
+            4u8 => call!(ipv4_prefix) |
+            6u8 => call!(ipv6_prefix) |
+            7u8 => call!(end_of_data, session_id) |
+            8u8 => value!(RtrPdu::CacheReset) |
+            9u8 => call!(router_key, (session_id >> 8) as u8) |
+            10u8 => call!(error_report, session_id) |
+            11u8 => call!(aspa, (session_id >> 8) as u8)
+        )) >>
+        (pdu)
+    )
+);
+
+named!(ipv4_prefix<RtrPdu>,
+    do_parse!(
+        flags: be_u8 >>
+        length: verify!(be_u8, |v: u8| v <= 32) >>
+        max_length: verify!(be_u8, |v: u8| v >= length && v <= 32) >>
+        take!(1) >>
+        prefix: be_u32 >>
+        asn: be_u32 >>
+        (RtrPdu::Prefix {
+            announce: flags & 1 != 0,
+            vrp: Vrp { prefix: IpAddr::V4(Ipv4Addr::from(prefix)), length, max_length, asn },
+        })
+    )
+);
+
+named!(ipv6_prefix<RtrPdu>,
+    do_parse!(
+        flags: be_u8 >>
+        length: verify!(be_u8, |v: u8| v <= 128) >>
+        max_length: verify!(be_u8, |v: u8| v >= length && v <= 128) >>
+        take!(1) >>
+        prefix: take!(16) >>
+        asn: be_u32 >>
+        (RtrPdu::Prefix {
+            announce: flags & 1 != 0,
+            vrp: Vrp { prefix: IpAddr::V6(Ipv6Addr::from(ipv6_octets(prefix))), length, max_length, asn },
+        })
+    )
+);
+
+fn ipv6_octets(v: &[u8]) -> [u8; 16] {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(v);
+    octets
+}
+
+named_args!(end_of_data(session_id: u16) <RtrPdu>,
+    do_parse!(
+        serial: be_u32 >>
+        intervals: opt!(complete!(do_parse!(
+            refresh: be_u32 >>
+            retry: be_u32 >>
+            expire: be_u32 >>
+            (Intervals { refresh, retry, expire })
+        ))) >>
+        (RtrPdu::EndOfData { session_id, serial, intervals })
+    )
+);
+
+named_args!(router_key(flags: u8) <RtrPdu>,
+    do_parse!(
+        subject_key_identifier: take!(20) >>
+        asn: be_u32 >>
+        subject_public_key_info: call!(nom::rest) >>
+        (RtrPdu::RouterKey {
+            announce: flags & 1 != 0,
+            subject_key_identifier: subject_key_identifier.to_vec(),
+            asn,
+            subject_public_key_info: subject_public_key_info.to_vec(),
+        })
+    )
+);
+
+named_args!(error_report(error_code: u16) <RtrPdu>,
+    do_parse!(
+        pdu: length_bytes!(be_u32) >>
+        text: length_bytes!(be_u32) >>
+        (RtrPdu::ErrorReport { error_code, pdu: pdu.to_vec(), text: String::from_utf8_lossy(text).into_owned() })
+    )
+);
+
+named_args!(aspa(flags: u8) <RtrPdu>,
+    do_parse!(
+        customer: be_u32 >>
+        providers: many0!(complete!(be_u32)) >>
+        eof!() >>
+        (RtrPdu::Aspa { announce: flags & 1 != 0, customer, providers })
+    )
+);
+
+impl RtrPdu {
+    pub fn encode(&self, version: u8) -> Vec<u8> {
+        let mut body = Vec::new();
+        let (pdu_type, field) = match *self {
+            RtrPdu::SerialNotify { session_id, serial } => {
+                body.extend_from_slice(&serial.to_be_bytes());
+                (0, session_id)
+            },
+            RtrPdu::SerialQuery { session_id, serial } => {
+                body.extend_from_slice(&serial.to_be_bytes());
+                (1, session_id)
+            },
+            RtrPdu::ResetQuery => (2, 0),
+            RtrPdu::CacheResponse { session_id } => (3, session_id),
+            RtrPdu::Prefix { announce, ref vrp } => {
+                body.extend_from_slice(&[announce as u8, vrp.length, vrp.max_length, 0]);
+                let pdu_type = match vrp.prefix {
+                    IpAddr::V4(prefix) => {
+                        body.extend_from_slice(&prefix.octets());
+                        4
+                    },
+                    IpAddr::V6(prefix) => {
+                        body.extend_from_slice(&prefix.octets());
+                        6
+                    },
+                };
+                body.extend_from_slice(&vrp.asn.to_be_bytes());
+                (pdu_type, 0)
+            },
+            RtrPdu::EndOfData { session_id, serial, intervals } => {
+                body.extend_from_slice(&serial.to_be_bytes());
+                if let Some(intervals) = intervals {
+                    body.extend_from_slice(&intervals.refresh.to_be_bytes());
+                    body.extend_from_slice(&intervals.retry.to_be_bytes());
+                    body.extend_from_slice(&intervals.expire.to_be_bytes());
+                }
+                (7, session_id)
+            },
+            RtrPdu::CacheReset => (8, 0),
+            RtrPdu::RouterKey { announce, ref subject_key_identifier, asn, ref subject_public_key_info } => {
+                body.extend_from_slice(subject_key_identifier);
+                body.extend_from_slice(&asn.to_be_bytes());
+                body.extend_from_slice(subject_public_key_info);
+                (9, (announce as u16) << 8)
+            },
+            RtrPdu::ErrorReport { error_code, ref pdu, ref text } => {
+                body.extend_from_slice(&(pdu.len() as u32).to_be_bytes());
+                body.extend_from_slice(pdu);
+                body.extend_from_slice(&(text.len() as u32).to_be_bytes());
+                body.extend_from_slice(text.as_bytes());
+                (10, error_code)
+            },
+            RtrPdu::Aspa { announce, customer, ref providers } => {
+                body.extend_from_slice(&customer.to_be_bytes());
+                for provider in providers {
+                    body.extend_from_slice(&provider.to_be_bytes());
+                }
+                (11, (announce as u16) << 8)
+            },
+        };
+
+        let mut out = Vec::with_capacity(8 + body.len());
+        out.push(version);
+        out.push(pdu_type);
+        out.extend_from_slice(&field.to_be_bytes());
+        out.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+        out.extend(body);
+        out
+    }
+}
+
+// Route Origin Validation.
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum RovState {
+    Valid,
+    Invalid,
+    NotFound,
+}
+
+impl fmt::Display for RovState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RovState::Valid => "valid",
+            RovState::Invalid => "invalid",
+            RovState::NotFound => "not-found",
+        })
+    }
+}
+
+// The VRPs, indexed by prefix so that finding those covering a route
+// only takes a lookup for each prefix length.
+#[derive(Debug,Default,Clone)]
+pub struct VrpTable {
+    vrps: HashMap<(IpAddr, u8), Vec<(u8, u32)>>,
+    len: usize,
+}
+
+fn mask(address: IpAddr, length: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(a) => IpAddr::V4(Ipv4Addr::from(u32::from(a) & u32::MAX.checked_shl(32 - length as u32).unwrap_or(0))),
+        IpAddr::V6(a) => IpAddr::V6(Ipv6Addr::from(u128::from(a) & u128::MAX.checked_shl(128 - length as u32).unwrap_or(0))),
+    }
+}
+
+impl VrpTable {
+    pub fn new() -> VrpTable {
+        VrpTable::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Returns false if the VRP was already there.
+    pub fn insert(&mut self, vrp: Vrp) -> bool {
+        let entries = self.vrps.entry((mask(vrp.prefix, vrp.length), vrp.length)).or_default();
+        if entries.contains(&(vrp.max_length, vrp.asn)) {
+            return false;
+        }
+        entries.push((vrp.max_length, vrp.asn));
+        self.len += 1;
+        true
+    }
+
+    // Returns false if the VRP wasn't there.
+    pub fn remove(&mut self, vrp: &Vrp) -> bool {
+        let key = (mask(vrp.prefix, vrp.length), vrp.length);
+        let removed = match self.vrps.get_mut(&key) {
+            Some(entries) => match entries.iter().position(|e| *e == (vrp.max_length, vrp.asn)) {
+                Some(i) => {
+                    entries.swap_remove(i);
+                    true
+                },
+                None => false,
+            },
+            None => false,
+        };
+
+        if removed {
+            self.len -= 1;
+            if self.vrps.get(&key).is_some_and(|e| e.is_empty()) {
+                self.vrps.remove(&key);
+            }
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.vrps.clear();
+        self.len = 0;
+    }
+
+    // Validate a route by its prefix and origin AS, None meaning it
+    // doesn't have one.
+    pub fn validate(&self, prefix: IpAddr, length: u8, origin: Option<u32>) -> RovState {
+        let mut covered = false;
+
+        for covering in 0..=length {
+            if let Some(entries) = self.vrps.get(&(mask(prefix, covering), covering)) {
+                covered = true;
+                if entries.iter().any(|&(max_length, asn)| length <= max_length && Some(asn) == origin && asn != 0) {
+                    return RovState::Valid;
+                }
+            }
+        }
+
+        match covered {
+            true => RovState::Invalid,
+            false => RovState::NotFound,
+        }
+    }
+
+    // Validate each prefix announced in an UPDATE, in its NLRI and in
+    // MP_REACH_NLRI. Only unicast, multicast and labeled unicast prefixes
+    // are validated there, VPN routes and other families being left out.
+    pub fn validate_update(&self, update: &BgpUpdateMessage) -> Vec<(IpAddr, u8, RovState)> {
+        let origin = update.as_path().and_then(|as_path| as_path.origin_as());
+        update.nlri.iter().map(|p| (IpAddr::V4(p.addr()), p.length))
+            .chain(update.path_attributes.iter().flat_map(|a| match a.attribute {
+                PathAttribute::MpReachNlri(ref mp) => mp_reach_prefixes(mp),
+                _ => vec![],
+            }))
+            .map(|(prefix, length)| (prefix, length, self.validate(prefix, length, origin)))
+            .collect()
+    }
+}
+
+fn mp_reach_prefixes(mp: &MpReachNlriAttribute) -> Vec<(IpAddr, u8)> {
+    match (mp.afi, mp.safi, &mp.nlri) {
+        (_, _, MpNlri::Labeled(prefixes)) => prefixes.iter().map(|p| (p.address, p.length)).collect(),
+        (AFI_IPV4, SAFI_UNICAST, MpNlri::Unknown(nlri)) | (AFI_IPV4, SAFI_MULTICAST, MpNlri::Unknown(nlri)) |
+        (AFI_IPV6, SAFI_UNICAST, MpNlri::Unknown(nlri)) | (AFI_IPV6, SAFI_MULTICAST, MpNlri::Unknown(nlri)) => match plain_prefixes(nlri, mp.afi) {
+            Done(_, prefixes) => prefixes,
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+// The client.
+
+pub(crate) struct RtrCodec {
+    // The version PDUs are sent with. Any we understand are received.
+    version: u8,
+}
+
+impl Decoder for RtrCodec {
+    type Item = RtrPdu;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<RtrPdu>> {
+        let length = match rtr_pdu_frame(src) {
+            Done(_, frame) => frame.len(),
+            Incomplete(_) => return Ok(None),
+            Error(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad RTR PDU length")),
+        };
+
+        let result = match parse_rtr_pdu(&src[..length]) {
+            Done(_, pdu) => Ok(Some(pdu)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad RTR PDU")),
+        };
+
+        src.advance(length);
+        result
+    }
+}
+
+impl Encoder<RtrPdu> for RtrCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, pdu: RtrPdu, dst: &mut BytesMut) -> io::Result<()> {
+        dst.extend_from_slice(&pdu.encode(self.version));
+        Ok(())
+    }
+}
+
+#[derive(Debug,PartialEq)]
+pub enum RtrEvent {
+    // The tables are up to date with the cache.
+    Synchronized { session_id: u16, serial: u32, vrps: usize, aspas: usize },
+    // The data expired without being refreshed, so the tables were
+    // emptied.
+    Expired,
+    // The cache sent an Error Report, or something we couldn't parse.
+    Error(String),
+    Closed,
+}
+
+pub struct RtrClient {
+    stream: TcpStream,
+    version: u8,
+}
+
+#[derive(Default)]
+struct Tables {
+    vrps: VrpTable,
+    aspas: AspaTable,
+}
+
+impl Tables {
+    fn clear(&mut self) {
+        self.vrps.clear();
+        self.aspas.clear();
+    }
+}
+
+// For validating routes against the client's tables, which can be shared
+// between tasks.
+#[derive(Clone)]
+pub struct RtrHandle {
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl RtrClient {
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> io::Result<RtrClient> {
+        Ok(RtrClient { stream: TcpStream::connect(address).await?, version: RTR_VERSION })
+    }
+
+    // Speak another version, 2 for ASPA records. There's no falling back
+    // if the cache doesn't support it, it will send an Error Report.
+    pub fn version(self, version: u8) -> RtrClient {
+        RtrClient { stream: self.stream, version }
+    }
+
+    // Start fetching VRPs. This has to be called from within a tokio
+    // runtime. The connection isn't retried if it closes.
+    pub fn start(self) -> (RtrHandle, UnboundedReceiver<RtrEvent>) {
+        let (events, receiver) = unbounded_channel();
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        tokio::spawn(run_client(self.stream, self.version, tables.clone(), events));
+        (RtrHandle { tables }, receiver)
+    }
+}
+
+impl RtrHandle {
+    pub fn validate(&self, prefix: IpAddr, length: u8, origin: Option<u32>) -> RovState {
+        self.tables.lock().unwrap().vrps.validate(prefix, length, origin)
+    }
+
+    pub fn validate_update(&self, update: &BgpUpdateMessage) -> Vec<(IpAddr, u8, RovState)> {
+        self.tables.lock().unwrap().vrps.validate_update(update)
+    }
+
+    pub fn verify(&self, as_path: &[AsPathSegment], relation: Relation) -> AspaState {
+        self.tables.lock().unwrap().aspas.verify(as_path, relation)
+    }
+
+    pub fn verify_update(&self, update: &BgpUpdateMessage, relation: Relation) -> AspaState {
+        self.tables.lock().unwrap().aspas.verify_update(update, relation)
+    }
+
+    // The number of VRPs.
+    pub fn len(&self) -> usize {
+        self.tables.lock().unwrap().vrps.len()
+    }
+
+    // The number of ASPA records.
+    pub fn aspas(&self) -> usize {
+        self.tables.lock().unwrap().aspas.len()
+    }
+}
+
+// A Serial or Reset Query being answered.
+struct Response {
+    reset: bool,
+    changes: Vec<(bool, Vrp)>,
+    aspas: Vec<(bool, u32, Vec<u32>)>,
+}
+
+async fn run_client<S: AsyncRead + AsyncWrite + Unpin>(stream: S, version: u8, tables: Arc<Mutex<Tables>>, events: UnboundedSender<RtrEvent>) {
+    let mut framed = Framed::new(stream, RtrCodec { version });
+    // The session ID and serial of what we have.
+    let mut state: Option<(u16, u32)> = None;
+    let mut reset = true;
+    let mut response: Option<Response> = None;
+    let mut intervals = Intervals { refresh: DEFAULT_REFRESH_INTERVAL, retry: DEFAULT_RETRY_INTERVAL, expire: DEFAULT_EXPIRE_INTERVAL };
+    // When to send the next query. Until a query is answered that's the
+    // retry interval after it was sent.
+    let mut refresh = Instant::now() + Duration::from_secs(intervals.retry as u64);
+    let mut expire: Option<Instant> = None;
+
+    if framed.send(RtrPdu::ResetQuery).await.is_err() {
+        let _ = events.send(RtrEvent::Closed);
+        return;
+    }
+
+    loop {
+        let pdu = tokio::select! {
+            pdu = framed.next() => pdu,
+            _ = sleep_until(refresh) => {
+                refresh = Instant::now() + Duration::from_secs(intervals.retry as u64);
+                if framed.send(query(state, reset)).await.is_err() {
+                    break;
+                }
+                continue;
+            },
+            _ = sleep_until(expire.unwrap_or_else(Instant::now)), if expire.is_some() => {
+                expire = None;
+                tables.lock().unwrap().clear();
+                let _ = events.send(RtrEvent::Expired);
+                continue;
+            },
+        };
+
+        let pdu = match pdu {
+            Some(Ok(pdu)) => pdu,
+            Some(Err(e)) => {
+                let _ = framed.send(RtrPdu::ErrorReport { error_code: CORRUPT_DATA, pdu: vec![], text: e.to_string() }).await;
+                let _ = events.send(RtrEvent::Error(e.to_string()));
+                break;
+            },
+            None => break,
+        };
+
+        match pdu {
+            RtrPdu::SerialNotify { session_id, serial } => {
+                if response.is_none() && state.is_some_and(|s| s.0 == session_id && s.1 != serial) {
+                    if framed.send(query(state, reset)).await.is_err() {
+                        break;
+                    }
+                    refresh = Instant::now() + Duration::from_secs(intervals.retry as u64);
+                }
+            },
+            RtrPdu::CacheResponse { session_id } => {
+                // A new session ID means the cache restarted, and what
+                // we have is no good.
+                if state.is_some_and(|s| s.0 != session_id) {
+                    reset = true;
+                }
+                response = Some(Response { reset, changes: vec![], aspas: vec![] });
+            },
+            RtrPdu::Prefix { .. } | RtrPdu::Aspa { .. } if response.is_none() => {
+                let text = "data outside a cache response".to_string();
+                let _ = framed.send(RtrPdu::ErrorReport { error_code: INVALID_REQUEST, pdu: vec![], text: text.clone() }).await;
+                let _ = events.send(RtrEvent::Error(text));
+                break;
+            },
+            RtrPdu::Prefix { announce, vrp } => {
+                if let Some(ref mut response) = response {
+                    response.changes.push((announce, vrp));
+                }
+            },
+            RtrPdu::Aspa { announce, customer, providers } => {
+                if let Some(ref mut response) = response {
+                    response.aspas.push((announce, customer, providers));
+                }
+            },
+            RtrPdu::EndOfData { session_id, serial, intervals: new_intervals } => {
+                if let Some(response) = response.take() {
+                    let (vrps, aspas) = {
+                        let mut tables = tables.lock().unwrap();
+                        if response.reset {
+                            tables.clear();
+                        }
+                        for (announce, vrp) in response.changes {
+                            match announce {
+                                true => tables.vrps.insert(vrp),
+                                false => tables.vrps.remove(&vrp),
+                            };
+                        }
+                        for (announce, customer, providers) in response.aspas {
+                            match announce {
+                                true => tables.aspas.insert(customer, providers),
+                                false => {
+                                    tables.aspas.remove(customer);
+                                },
+                            }
+                        }
+                        (tables.vrps.len(), tables.aspas.len())
+                    };
+
+                    intervals = new_intervals.unwrap_or(intervals);
+                    state = Some((session_id, serial));
+                    reset = false;
+                    refresh = Instant::now() + Duration::from_secs(intervals.refresh as u64);
+                    expire = Some(Instant::now() + Duration::from_secs(intervals.expire as u64));
+                    let _ = events.send(RtrEvent::Synchronized { session_id, serial, vrps, aspas });
+                }
+            },
+            RtrPdu::CacheReset => {
+                reset = true;
+                response = None;
+                if framed.send(RtrPdu::ResetQuery).await.is_err() {
+                    break;
+                }
+                refresh = Instant::now() + Duration::from_secs(intervals.retry as u64);
+            },
+            RtrPdu::ErrorReport { text, .. } => {
+                let _ = events.send(RtrEvent::Error(text));
+                break;
+            },
+            RtrPdu::RouterKey { .. } => {},
+            RtrPdu::SerialQuery { .. } | RtrPdu::ResetQuery => {
+                let _ = framed.send(RtrPdu::ErrorReport { error_code: UNSUPPORTED_PDU_TYPE, pdu: vec![], text: "query sent to a router".to_string() }).await;
+            },
+        }
+    }
+
+    let _ = events.send(RtrEvent::Closed);
+}
+
+// Ask for the changes since what we have, or for everything if we don't
+// have anything.
+fn query(state: Option<(u16, u32)>, reset: bool) -> RtrPdu {
+    match state {
+        Some((session_id, serial)) if !reset => RtrPdu::SerialQuery { session_id, serial },
+        _ => RtrPdu::ResetQuery,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mpls::Label;
+    use tokio::net::TcpListener;
+
+    fn vrp(prefix: &str, length: u8, max_length: u8, asn: u32) -> Vrp {
+        Vrp { prefix: prefix.parse().unwrap(), length, max_length, asn }
+    }
+
+    #[test]
+    fn rtr_pdu_round_trip_test() {
+        let pdus = vec![
+            RtrPdu::SerialNotify { session_id: 7, serial: 42 },
+            RtrPdu::SerialQuery { session_id: 7, serial: 42 },
+            RtrPdu::ResetQuery,
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Prefix { announce: true, vrp: vrp("192.0.2.0", 24, 24, 65000) },
+            RtrPdu::Prefix { announce: false, vrp: vrp("2001:db8::", 32, 48, 65001) },
+            RtrPdu::EndOfData { session_id: 7, serial: 42, intervals: Some(Intervals { refresh: 3600, retry: 600, expire: 7200 }) },
+            RtrPdu::EndOfData { session_id: 7, serial: 42, intervals: None },
+            RtrPdu::CacheReset,
+            RtrPdu::RouterKey { announce: true, subject_key_identifier: vec![1; 20], asn: 65000, subject_public_key_info: vec![2; 91] },
+            RtrPdu::ErrorReport { error_code: 2, pdu: vec![1, 2, 0, 0, 0, 0, 0, 8], text: "No Data Available".to_string() },
+            RtrPdu::Aspa { announce: true, customer: 64496, providers: vec![64497, 64498] },
+            RtrPdu::Aspa { announce: false, customer: 64496, providers: vec![] },
+        ];
+
+        for pdu in pdus {
+            let encoded = pdu.encode(RTR_VERSION);
+            assert_eq!(rtr_pdu_frame(&encoded), Done(&b""[..], &encoded[..]));
+            assert_eq!(parse_rtr_pdu(&encoded), Done(&b""[..], pdu));
+        }
+
+        // An IPv4 Prefix PDU, byte by byte.
+        let encoded = RtrPdu::Prefix { announce: true, vrp: vrp("192.0.2.0", 24, 28, 65000) }.encode(1);
+        assert_eq!(encoded, vec![1, 4, 0, 0, 0, 0, 0, 20, 1, 24, 28, 0, 192, 0, 2, 0, 0, 0, 0xfd, 0xe8]);
+    }
+
+    #[test]
+    fn rtr_pdu_invalid_test() {
+        // A maximum length shorter than the prefix length.
+        let encoded = RtrPdu::Prefix { announce: true, vrp: vrp("192.0.2.0", 24, 16, 65000) }.encode(1);
+        assert!(parse_rtr_pdu(&encoded).is_err());
+
+        // Version 3 isn't supported.
+        assert!(parse_rtr_pdu(&RtrPdu::ResetQuery.encode(3)).is_err());
+
+        // A provider AS cut short.
+        let mut encoded = RtrPdu::Aspa { announce: true, customer: 64496, providers: vec![64497] }.encode(2);
+        encoded.pop();
+        encoded[7] -= 1;
+        assert!(parse_rtr_pdu(&encoded).is_err());
+
+        assert!(rtr_pdu_frame(&[1, 2, 0, 0, 0, 0, 0, 4]).is_err());
+        assert!(rtr_pdu_frame(&[1, 2, 0, 0, 0, 0, 0]).is_incomplete());
+    }
+
+    #[test]
+    fn vrp_table_validate_test() {
+        let mut table = VrpTable::new();
+        assert!(table.insert(vrp("10.0.0.0", 8, 16, 65000)));
+        assert!(table.insert(vrp("10.1.0.0", 16, 24, 65001)));
+        assert!(table.insert(vrp("2001:db8::", 32, 48, 65000)));
+        assert!(!table.insert(vrp("10.0.0.0", 8, 16, 65000)));
+        assert_eq!(table.len(), 3);
+
+        let v4 = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(table.validate(v4("10.0.0.0"), 8, Some(65000)), RovState::Valid);
+        assert_eq!(table.validate(v4("10.2.0.0"), 16, Some(65000)), RovState::Valid);
+        // Too long for the VRP.
+        assert_eq!(table.validate(v4("10.2.3.0"), 24, Some(65000)), RovState::Invalid);
+        // The wrong origin.
+        assert_eq!(table.validate(v4("10.2.0.0"), 16, Some(65002)), RovState::Invalid);
+        // Covered by both, and valid for the more specific.
+        assert_eq!(table.validate(v4("10.1.2.0"), 24, Some(65001)), RovState::Valid);
+        assert_eq!(table.validate(v4("10.1.0.0"), 16, None), RovState::Invalid);
+        assert_eq!(table.validate(v4("192.0.2.0"), 24, Some(65000)), RovState::NotFound);
+        assert_eq!(table.validate(v4("2001:db8:1::"), 48, Some(65000)), RovState::Valid);
+        assert_eq!(table.validate(v4("2001:db9::"), 32, Some(65000)), RovState::NotFound);
+
+        assert!(table.remove(&vrp("10.0.0.0", 8, 16, 65000)));
+        assert!(!table.remove(&vrp("10.0.0.0", 8, 16, 65000)));
+        assert_eq!(table.validate(v4("10.2.0.0"), 16, Some(65000)), RovState::NotFound);
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn validate_update_test() {
+        let update = match parse_bgp_message(&include_bytes!("../assets/test_bgp_update1.bin")[..]) {
+            Done(_, BgpMessage::Update(update)) => update,
+            other => panic!("failed to parse: {:?}", other),
+        };
+
+        let v4 = |a: &str| a.parse::<IpAddr>().unwrap();
+
+        // The AS_PATH is {500,500} 65211, so the origin is 65211.
+        let mut table = VrpTable::new();
+        assert_eq!(table.validate_update(&update), vec![(v4("172.16.0.0"), 16, RovState::NotFound)]);
+        table.insert(vrp("172.16.0.0", 12, 16, 65211));
+        assert_eq!(table.validate_update(&update), vec![(v4("172.16.0.0"), 16, RovState::Valid)]);
+
+        // Prefixes in MP_REACH_NLRI are validated too, unicast and
+        // labeled, but not VPN ones.
+        let mut update = update;
+        let mp_reach = |afi, safi, nlri| BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+            attribute: PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute { afi, safi, next_hop: vec![], nlri })),
+        };
+        update.path_attributes.push(mp_reach(2, 1, MpNlri::Unknown(vec![32, 0x20, 0x01, 0x0d, 0xb8, 48, 0x20, 0x01, 0x0d, 0xb8, 0, 1])));
+        update.path_attributes.push(mp_reach(1, 4, MpNlri::Labeled(vec![LabeledPrefix { labels: vec![Label::new(16)], address: v4("10.0.0.0"), length: 8 }])));
+        update.path_attributes.push(mp_reach(1, 128, MpNlri::Vpn(vec![VpnPrefix {
+            labels: vec![Label::new(16)],
+            rd: "65000:1".parse().unwrap(),
+            address: v4("10.0.0.0"),
+            length: 8,
+        }])));
+        table.insert(vrp("2001:db8::", 32, 32, 65211));
+        assert_eq!(table.validate_update(&update), vec![
+            (v4("172.16.0.0"), 16, RovState::Valid),
+            (v4("2001:db8::"), 32, RovState::Valid),
+            (v4("2001:db8:1::"), 48, RovState::Invalid),
+            (v4("10.0.0.0"), 8, RovState::NotFound),
+        ]);
+
+        // An AS_SET at the end means there's no origin.
+        if let PathAttribute::AsPath(ref mut as_path) = update.path_attributes[1].attribute {
+            as_path.as_path.reverse();
+        }
+        assert_eq!(table.validate_update(&update)[0], (v4("172.16.0.0"), 16, RovState::Invalid));
+    }
+
+    async fn next_event(events: &mut UnboundedReceiver<RtrEvent>) -> RtrEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+    }
+
+    async fn next_pdu(cache: &mut Framed<tokio::net::TcpStream, RtrCodec>) -> RtrPdu {
+        tokio::time::timeout(Duration::from_secs(5), cache.next()).await.unwrap().unwrap().unwrap()
+    }
+
+    // Run the client against a stand-in cache, through a full load, an
+    // incremental update and a cache reset.
+    #[tokio::test]
+    async fn rtr_client_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = RtrClient::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (handle, mut events) = client.start();
+        let mut cache = Framed::new(listener.accept().await.unwrap().0, RtrCodec { version: RTR_VERSION });
+        let intervals = Some(Intervals { refresh: 3600, retry: 600, expire: 7200 });
+        let v4 = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert_eq!(next_pdu(&mut cache).await, RtrPdu::ResetQuery);
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Prefix { announce: true, vrp: vrp("192.0.2.0", 24, 24, 65000) },
+            RtrPdu::Prefix { announce: true, vrp: vrp("198.51.100.0", 24, 24, 65001) },
+            RtrPdu::EndOfData { session_id: 7, serial: 1, intervals },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert_eq!(next_event(&mut events).await, RtrEvent::Synchronized { session_id: 7, serial: 1, vrps: 2, aspas: 0 });
+        assert_eq!(handle.validate(v4("192.0.2.0"), 24, Some(65000)), RovState::Valid);
+
+        // A Serial Notify gets a Serial Query for what's changed.
+        cache.send(RtrPdu::SerialNotify { session_id: 7, serial: 2 }).await.unwrap();
+        assert_eq!(next_pdu(&mut cache).await, RtrPdu::SerialQuery { session_id: 7, serial: 1 });
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Prefix { announce: false, vrp: vrp("192.0.2.0", 24, 24, 65000) },
+            RtrPdu::Prefix { announce: true, vrp: vrp("192.0.2.0", 24, 24, 65002) },
+            RtrPdu::EndOfData { session_id: 7, serial: 2, intervals },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert_eq!(next_event(&mut events).await, RtrEvent::Synchronized { session_id: 7, serial: 2, vrps: 2, aspas: 0 });
+        assert_eq!(handle.validate(v4("192.0.2.0"), 24, Some(65000)), RovState::Invalid);
+        assert_eq!(handle.validate(v4("192.0.2.0"), 24, Some(65002)), RovState::Valid);
+
+        // A Cache Reset starts again from the beginning.
+        cache.send(RtrPdu::CacheReset).await.unwrap();
+        assert_eq!(next_pdu(&mut cache).await, RtrPdu::ResetQuery);
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 8 },
+            RtrPdu::Prefix { announce: true, vrp: vrp("203.0.113.0", 24, 24, 65003) },
+            RtrPdu::EndOfData { session_id: 8, serial: 1, intervals },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert_eq!(next_event(&mut events).await, RtrEvent::Synchronized { session_id: 8, serial: 1, vrps: 1, aspas: 0 });
+        assert_eq!(handle.validate(v4("192.0.2.0"), 24, Some(65002)), RovState::NotFound);
+
+        cache.send(RtrPdu::ErrorReport { error_code: 1, pdu: vec![], text: "Internal Error".to_string() }).await.unwrap();
+        assert_eq!(next_event(&mut events).await, RtrEvent::Error("Internal Error".to_string()));
+        assert_eq!(next_event(&mut events).await, RtrEvent::Closed);
+    }
+
+    // ASPA records over version 2.
+    #[tokio::test]
+    async fn rtr_client_aspa_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = RtrClient::connect(listener.local_addr().unwrap()).await.unwrap().version(2);
+        let (handle, mut events) = client.start();
+        let mut cache = Framed::new(listener.accept().await.unwrap().0, RtrCodec { version: 2 });
+        let path = vec![AsPathSegment::AsSequence(vec![3, 2, 1])];
+
+        assert_eq!(next_pdu(&mut cache).await, RtrPdu::ResetQuery);
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Aspa { announce: true, customer: 1, providers: vec![2] },
+            RtrPdu::Aspa { announce: true, customer: 2, providers: vec![3] },
+            RtrPdu::EndOfData { session_id: 7, serial: 1, intervals: None },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert_eq!(next_event(&mut events).await, RtrEvent::Synchronized { session_id: 7, serial: 1, vrps: 0, aspas: 2 });
+        assert_eq!(handle.verify(&path, Relation::Customer), AspaState::Valid);
+
+        // A new record for 2 replaces the old one.
+        cache.send(RtrPdu::SerialNotify { session_id: 7, serial: 2 }).await.unwrap();
+        assert_eq!(next_pdu(&mut cache).await, RtrPdu::SerialQuery { session_id: 7, serial: 1 });
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Aspa { announce: true, customer: 2, providers: vec![4] },
+            RtrPdu::EndOfData { session_id: 7, serial: 2, intervals: None },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert_eq!(next_event(&mut events).await, RtrEvent::Synchronized { session_id: 7, serial: 2, vrps: 0, aspas: 2 });
+        assert_eq!(handle.verify(&path, Relation::Customer), AspaState::Invalid);
+
+        cache.send(RtrPdu::SerialNotify { session_id: 7, serial: 3 }).await.unwrap();
+        assert_eq!(next_pdu(&mut cache).await, RtrPdu::SerialQuery { session_id: 7, serial: 2 });
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Aspa { announce: false, customer: 2, providers: vec![] },
+            RtrPdu::EndOfData { session_id: 7, serial: 3, intervals: None },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert_eq!(next_event(&mut events).await, RtrEvent::Synchronized { session_id: 7, serial: 3, vrps: 0, aspas: 1 });
+        assert_eq!(handle.verify(&path, Relation::Customer), AspaState::Unknown);
+    }
+
+    // The refresh, retry and expire intervals, with tokio's clock paused
+    // so they don't have to be waited for. The cache is at the other end
+    // of an in-memory stream, as the paused clock can run ahead of what's
+    // arrived on a TCP connection.
+    #[tokio::test(start_paused = true)]
+    async fn rtr_client_timers_test() {
+        let (stream, cache) = tokio::io::duplex(4096);
+        let (sender, mut events) = unbounded_channel();
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        tokio::spawn(run_client(stream, RTR_VERSION, tables.clone(), sender));
+        let handle = RtrHandle { tables };
+        let mut cache = Framed::new(cache, RtrCodec { version: RTR_VERSION });
+
+        assert_eq!(cache.next().await.unwrap().unwrap(), RtrPdu::ResetQuery);
+        for pdu in [
+            RtrPdu::CacheResponse { session_id: 7 },
+            RtrPdu::Prefix { announce: true, vrp: vrp("192.0.2.0", 24, 24, 65000) },
+            RtrPdu::EndOfData { session_id: 7, serial: 1, intervals: Some(Intervals { refresh: 60, retry: 10, expire: 600 }) },
+        ] {
+            cache.send(pdu).await.unwrap();
+        }
+        assert!(matches!(events.recv().await.unwrap(), RtrEvent::Synchronized { .. }));
+        let synchronized = Instant::now();
+
+        // The first query comes after the refresh interval, and without an
+        // answer the rest keep coming every retry interval, until the data
+        // expires. The paused clock runs ahead whenever everything's
+        // waiting, so there's no timeout on these.
+        assert_eq!(cache.next().await.unwrap().unwrap(), RtrPdu::SerialQuery { session_id: 7, serial: 1 });
+        assert_eq!(synchronized.elapsed(), Duration::from_secs(60));
+        assert_eq!(cache.next().await.unwrap().unwrap(), RtrPdu::SerialQuery { session_id: 7, serial: 1 });
+        assert_eq!(synchronized.elapsed(), Duration::from_secs(70));
+        assert_eq!(events.recv().await.unwrap(), RtrEvent::Expired);
+        assert_eq!(synchronized.elapsed(), Duration::from_secs(600));
+        assert_eq!(handle.len(), 0);
+    }
+}
@@ -0,0 +1,609 @@
+// An async BGP speaker built on tokio.
+//
+// The speaker listens for connections from its neighbors and connects
+// out to those that aren't passive. Each connection runs a Session from
+// fsm.rs, with this file doing the IO and timers for it. What the
+// sessions do is reported as SpeakerEvents on a channel: a session
+// coming up, each UPDATE received, and a session going down.
+//
+// UPDATEs can be sent to an established neighbor through the
+// SpeakerHandle.
+//
+// Hold and keepalive timers are run by the Session, this just wakes it
+// up when they are due.
+//
+// Neighbors are identified by address. A connection from an address that
+// isn't a configured neighbor is dropped. There can be two connections
+// to a neighbor for a while if we both connect at once, until collision
+// detection (collision.rs) closes one of them.
+//
+// A neighbor that goes over one of its prefix limits is held down: we
+// don't connect to it or accept its connections until the limit's restart
+// interval has passed, or until SpeakerHandle::restart() if there isn't
+// one. SpeakerHandle::shutdown() holds a neighbor down until it's
+// restarted too, telling it why with a Shutdown Communication.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::time::{Instant, sleep, sleep_until};
+use tokio_util::codec::Framed;
+
+use super::*;
+use super::codec::{BgpCodec, CodecError};
+use super::collision::{CollisionResolver, ConnectionId, Direction};
+use super::fsm::{Action, Event, Session, SessionConfig, State};
+use super::prefix_limit::{PrefixLimit, PrefixLimitEvent};
+use super::timers::{ConnectRetry, Jitter};
+
+#[derive(Debug,Clone)]
+pub struct SpeakerConfig {
+    pub local_as: u16,
+    pub bgp_identifier: Ipv4Addr,
+    pub hold_time: u16,
+    // Where to listen, or None to only make outgoing connections.
+    pub listen: Option<SocketAddr>,
+    pub connect_retry_time: Duration,
+    // Whether to send the Extended Message capability (RFC 8654),
+    // allowing messages up to 65535 bytes with neighbors that send it
+    // too.
+    pub extended_message: bool,
+    // Whether to send the 4-octet AS capability (RFC 6793), using four
+    // octet AS numbers in UPDATEs with neighbors that send it too.
+    pub four_octet_as: bool,
+    // The most labels we can receive for labeled families (RFC 8277).
+    pub multiple_labels: Vec<LabelCount>,
+    // The member ASes of our confederation (RFC 5065), empty if we
+    // aren't in one.
+    pub confederation_members: Vec<u16>,
+    pub neighbors: Vec<NeighborConfig>,
+}
+
+#[derive(Debug,Clone)]
+pub struct NeighborConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    pub remote_as: u16,
+    // A passive neighbor is never connected to, we wait for it to
+    // connect to us.
+    pub passive: bool,
+    pub prefix_limits: Vec<PrefixLimit>,
+    // Our role to the neighbor (RFC 9234), see role.rs.
+    pub role: Option<BgpRole>,
+    pub strict_role: bool,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum SpeakerEvent {
+    Established(IpAddr, Box<BgpOpenMessage>),
+    Update(IpAddr, Box<BgpUpdateMessage>),
+    PrefixLimit(IpAddr, PrefixLimitEvent),
+    // Routes from the neighbor that leaked, which are treated as
+    // withdrawn.
+    RouteLeak(IpAddr, Vec<Ipv4Prefix>),
+    // The neighbor sent a NOTIFICATION. A Closed follows if the session
+    // was established.
+    Notification(IpAddr, Box<BgpNotificationMessage>),
+    Closed(IpAddr),
+}
+
+pub struct Speaker {
+    config: Arc<SpeakerConfig>,
+    listener: Option<TcpListener>,
+}
+
+// For sending messages to established neighbors.
+#[derive(Clone)]
+pub struct SpeakerHandle {
+    connections: Connections,
+}
+
+// What the connection tasks share: collision detection, a way to reach
+// each connection, which connection is established for each neighbor,
+// and the neighbors held down after going over a prefix limit, with when
+// they can come back up.
+struct ConnectionTable {
+    resolver: CollisionResolver,
+    commands: HashMap<ConnectionId, UnboundedSender<Command>>,
+    established: HashMap<IpAddr, ConnectionId>,
+    held_down: HashMap<IpAddr, Option<Instant>>,
+}
+
+impl ConnectionTable {
+    fn held_down(&mut self, neighbor: IpAddr) -> bool {
+        match self.held_down.get(&neighbor) {
+            Some(Some(until)) if *until <= Instant::now() => {
+                self.held_down.remove(&neighbor);
+                false
+            },
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+type Connections = Arc<Mutex<ConnectionTable>>;
+
+enum Command {
+    Send(BgpMessage),
+    Shutdown(Option<String>),
+    // Collision detection picked this connection to close.
+    Collision,
+}
+
+impl Speaker {
+    pub async fn bind(config: SpeakerConfig) -> io::Result<Speaker> {
+        let listener = match config.listen {
+            Some(address) => Some(TcpListener::bind(address).await?),
+            None => None,
+        };
+
+        Ok(Speaker { config: Arc::new(config), listener })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().and_then(|l| l.local_addr().ok())
+    }
+
+    // Start listening and connecting. This has to be called from within
+    // a tokio runtime.
+    pub fn start(self) -> (SpeakerHandle, UnboundedReceiver<SpeakerEvent>) {
+        let (events, receiver) = unbounded_channel();
+        let connections = Arc::new(Mutex::new(ConnectionTable {
+            resolver: CollisionResolver::new(self.config.bgp_identifier, self.config.local_as),
+            commands: HashMap::new(),
+            established: HashMap::new(),
+            held_down: HashMap::new(),
+        }));
+
+        if let Some(listener) = self.listener {
+            tokio::spawn(accept(listener, self.config.clone(), connections.clone(), events.clone()));
+        }
+
+        for neighbor in self.config.neighbors.iter().filter(|n| !n.passive) {
+            tokio::spawn(connect(neighbor.clone(), self.config.clone(), connections.clone(), events.clone()));
+        }
+
+        (SpeakerHandle { connections }, receiver)
+    }
+}
+
+impl SpeakerHandle {
+    // Send a message to a neighbor. Returns false if there is no
+    // established session with it.
+    pub fn send(&self, neighbor: IpAddr, message: BgpMessage) -> bool {
+        let connections = self.connections.lock().unwrap();
+        match connections.established.get(&neighbor).and_then(|id| connections.commands.get(id)) {
+            Some(sender) => sender.send(Command::Send(message)).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn established(&self) -> Vec<IpAddr> {
+        self.connections.lock().unwrap().established.keys().cloned().collect()
+    }
+
+    // Take the session with a neighbor down and keep it down until
+    // restart() is called. The message, if given, is sent to the
+    // neighbor as a Shutdown Communication. Returns false if there was
+    // no established session to take down.
+    pub fn shutdown(&self, neighbor: IpAddr, message: Option<&str>) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        connections.held_down.insert(neighbor, None);
+        match connections.established.get(&neighbor).and_then(|id| connections.commands.get(id)) {
+            Some(sender) => sender.send(Command::Shutdown(message.map(|m| m.to_string()))).is_ok(),
+            None => false,
+        }
+    }
+
+    // Let a neighbor held down by a prefix limit or a shutdown come back
+    // up.
+    pub fn restart(&self, neighbor: IpAddr) {
+        self.connections.lock().unwrap().held_down.remove(&neighbor);
+    }
+}
+
+fn session_config(config: &SpeakerConfig, neighbor: &NeighborConfig, stream: &TcpStream) -> SessionConfig {
+    SessionConfig {
+        local_as: config.local_as,
+        remote_as: neighbor.remote_as,
+        bgp_identifier: config.bgp_identifier,
+        hold_time: config.hold_time,
+        prefix_limits: neighbor.prefix_limits.clone(),
+        role: neighbor.role,
+        strict_role: neighbor.strict_role,
+        extended_message: config.extended_message,
+        four_octet_as: config.four_octet_as,
+        multiple_labels: config.multiple_labels.clone(),
+        confederation_members: config.confederation_members.clone(),
+        local_address: match stream.local_addr() {
+            Ok(SocketAddr::V4(address)) => Some(*address.ip()),
+            _ => None,
+        },
+    }
+}
+
+async fn accept(listener: TcpListener, config: Arc<SpeakerConfig>, connections: Connections, events: UnboundedSender<SpeakerEvent>) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(_) => continue,
+        };
+
+        if let Some(neighbor) = config.neighbors.iter().find(|n| n.address == address.ip()) {
+            if connections.lock().unwrap().held_down(neighbor.address) {
+                continue;
+            }
+            let session = Session::new(session_config(&config, neighbor, &stream));
+            tokio::spawn(run_session(stream, session, neighbor.address, Direction::Inbound, connections.clone(), events.clone()));
+        }
+    }
+}
+
+// Keep a connection to the neighbor up. After a failed attempt, or the
+// session closing, we wait for the ConnectRetry time before trying again,
+// backing off while the neighbor can't be reached.
+async fn connect(neighbor: NeighborConfig, config: Arc<SpeakerConfig>, connections: Connections, events: UnboundedSender<SpeakerEvent>) {
+    let mut retry = ConnectRetry::new(config.connect_retry_time, Jitter::from_time());
+
+    loop {
+        while connections.lock().unwrap().held_down(neighbor.address) {
+            if events.is_closed() {
+                return;
+            }
+            sleep(config.connect_retry_time).await;
+        }
+
+        if let Ok(stream) = TcpStream::connect((neighbor.address, neighbor.port)).await {
+            let session = Session::new(session_config(&config, &neighbor, &stream));
+            if run_session(stream, session, neighbor.address, Direction::Outbound, connections.clone(), events.clone()).await {
+                retry.reset();
+            }
+        }
+
+        if events.is_closed() {
+            return;
+        }
+        sleep(retry.next_delay()).await;
+    }
+}
+
+// Run a session on a connection until it closes, returning whether it
+// was established.
+async fn run_session(stream: TcpStream, mut session: Session, neighbor: IpAddr, direction: Direction, connections: Connections, events: UnboundedSender<SpeakerEvent>) -> bool {
+    let mut framed = Framed::new(stream, BgpCodec::new());
+    let (sender, mut commands) = unbounded_channel();
+    let id = {
+        let mut connections = connections.lock().unwrap();
+        let id = connections.resolver.connected(neighbor, direction);
+        connections.commands.insert(id, sender);
+        id
+    };
+    let mut established = false;
+    let mut open_received = false;
+
+    let mut actions = session.handle(Event::TcpConnectionConfirmed);
+
+    loop {
+        let mut close = false;
+        for action in actions {
+            match action {
+                Action::Send(message) => {
+                    if framed.send(message).await.is_err() {
+                        close = true;
+                    }
+                },
+                Action::Established => {
+                    established = true;
+                    {
+                        let mut connections = connections.lock().unwrap();
+                        connections.resolver.established(neighbor, id);
+                        connections.established.insert(neighbor, id);
+                    }
+                    let open = session.remote_open().map(|o| Box::new(o.clone())).unwrap();
+                    let _ = events.send(SpeakerEvent::Established(neighbor, open));
+                },
+                Action::Update(update) => {
+                    let _ = events.send(SpeakerEvent::Update(neighbor, update));
+                },
+                Action::PrefixLimit(event) => {
+                    if let PrefixLimitEvent::Exceeded { ref limit, .. } = event {
+                        let until = limit.restart_interval.map(|i| Instant::now() + i);
+                        connections.lock().unwrap().held_down.insert(neighbor, until);
+                    }
+                    let _ = events.send(SpeakerEvent::PrefixLimit(neighbor, event));
+                },
+                Action::RouteLeak(prefixes) => {
+                    let _ = events.send(SpeakerEvent::RouteLeak(neighbor, prefixes));
+                },
+                Action::Notification(notification) => {
+                    let _ = events.send(SpeakerEvent::Notification(neighbor, notification));
+                },
+                Action::Close => close = true,
+            }
+        }
+
+        if close {
+            break;
+        }
+
+        // Now we know the peer's BGP identifier, check for a collision.
+        if session.state() == State::OpenConfirm && !open_received {
+            open_received = true;
+            let (remote_identifier, remote_as) = session.remote_open().map_or((Ipv4Addr::UNSPECIFIED, 0), |o| (o.bgp_identifier, o.my_autonomous_system));
+            let mut connections = connections.lock().unwrap();
+            match connections.resolver.open_received(neighbor, id, remote_identifier, remote_as) {
+                Some(loser) if loser == id => {
+                    actions = session.handle(Event::ConnectionCollision);
+                    continue;
+                },
+                Some(loser) => {
+                    if let Some(sender) = connections.commands.get(&loser) {
+                        let _ = sender.send(Command::Collision);
+                    }
+                },
+                None => {},
+            }
+        }
+
+        framed.codec_mut().set_max_length(session.max_message_length());
+        framed.codec_mut().set_four_octet_as(session.four_octet_as());
+        let deadline = session.next_deadline().map(Instant::from_std);
+
+        actions = tokio::select! {
+            message = framed.next() => session.handle(match message {
+                Some(Ok(message)) => Event::Message(message),
+                Some(Err(CodecError::Message(code))) => Event::MessageError(code),
+                Some(Err(CodecError::Io(_))) | None => Event::TcpConnectionFails,
+            }),
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => session.poll(),
+            Some(command) = commands.recv() => match command {
+                Command::Send(message) => session.send(message),
+                Command::Shutdown(message) => session.handle(Event::ManualStop(message)),
+                Command::Collision => session.handle(Event::ConnectionCollision),
+            },
+        };
+    }
+
+    let mut connections = connections.lock().unwrap();
+    connections.resolver.closed(neighbor, id);
+    connections.commands.remove(&id);
+    if connections.established.get(&neighbor) == Some(&id) {
+        connections.established.remove(&neighbor);
+        let _ = events.send(SpeakerEvent::Closed(neighbor));
+    }
+
+    established
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update() -> BgpUpdateMessage {
+        match parse_bgp_message(&include_bytes!("../assets/test_bgp_update1.bin")[..]) {
+            Done(_, BgpMessage::Update(update)) => *update,
+            other => panic!("failed to parse: {:?}", other),
+        }
+    }
+
+    async fn next_event(events: &mut UnboundedReceiver<SpeakerEvent>) -> SpeakerEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn speaker_loopback_test() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+
+        // A waits for B to connect.
+        let a = Speaker::bind(SpeakerConfig {
+            local_as: 65000,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 1),
+            hold_time: 90,
+            listen: Some("127.0.0.1:0".parse().unwrap()),
+            connect_retry_time: Duration::from_secs(1),
+            extended_message: true,
+            four_octet_as: true,
+            multiple_labels: vec![],
+            confederation_members: vec![],
+            neighbors: vec![NeighborConfig { address: localhost, port: 179, remote_as: 65001, passive: true, prefix_limits: vec![], role: None, strict_role: false }],
+        }).await.unwrap();
+        let port = a.local_addr().unwrap().port();
+
+        let b = Speaker::bind(SpeakerConfig {
+            local_as: 65001,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 2),
+            hold_time: 30,
+            listen: None,
+            connect_retry_time: Duration::from_secs(1),
+            extended_message: true,
+            four_octet_as: true,
+            multiple_labels: vec![],
+            confederation_members: vec![],
+            neighbors: vec![NeighborConfig { address: localhost, port, remote_as: 65000, passive: false, prefix_limits: vec![], role: None, strict_role: false }],
+        }).await.unwrap();
+
+        let (a_handle, mut a_events) = a.start();
+        let (b_handle, mut b_events) = b.start();
+
+        match next_event(&mut a_events).await {
+            SpeakerEvent::Established(address, open) => {
+                assert_eq!(address, localhost);
+                assert_eq!(open.my_autonomous_system, 65001);
+                assert_eq!(open.hold_time, 30);
+            },
+            other => panic!("expected established: {:?}", other),
+        }
+        match next_event(&mut b_events).await {
+            SpeakerEvent::Established(_, open) => assert_eq!(open.bgp_identifier, Ipv4Addr::new(10, 0, 0, 1)),
+            other => panic!("expected established: {:?}", other),
+        }
+
+        assert!(b_handle.send(localhost, BgpMessage::Update(Box::new(update()))));
+        assert_eq!(next_event(&mut a_events).await, SpeakerEvent::Update(localhost, Box::new(update())));
+
+        // Both sent Extended Message, so a long UPDATE goes in one piece.
+        let nlri: Vec<Ipv4Prefix> = (0..1200).map(|i| format!("10.{}.{}.0/24", i / 256, i % 256).parse().unwrap()).collect();
+        let long = BgpUpdateMessage { withdrawn_routes: vec![], path_attributes: vec![], nlri };
+        assert!(b_handle.send(localhost, BgpMessage::Update(Box::new(long.clone()))));
+        assert_eq!(next_event(&mut a_events).await, SpeakerEvent::Update(localhost, Box::new(long)));
+
+        assert!(a_handle.shutdown(localhost, Some("maintenance")));
+        assert_eq!(next_event(&mut a_events).await, SpeakerEvent::Closed(localhost));
+        match next_event(&mut b_events).await {
+            SpeakerEvent::Notification(_, notification) => assert_eq!(notification.shutdown_communication(), Some(Ok("maintenance".to_string()))),
+            other => panic!("expected a notification: {:?}", other),
+        }
+        assert_eq!(next_event(&mut b_events).await, SpeakerEvent::Closed(localhost));
+
+        // A stays down until it's restarted, even though B keeps trying.
+        sleep(Duration::from_millis(1500)).await;
+        assert!(a_handle.established().is_empty());
+        a_handle.restart(localhost);
+        assert!(matches!(next_event(&mut a_events).await, SpeakerEvent::Established(..)));
+    }
+
+    // Both speakers connect to each other, and collision detection has to
+    // pick one of the two connections. Whichever it is, they should end
+    // up with one session between them.
+    #[tokio::test]
+    async fn speaker_collision_test() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+
+        let config = |local_as: u16, remote_as: u16, bgp_identifier: Ipv4Addr| SpeakerConfig {
+            local_as,
+            bgp_identifier,
+            hold_time: 90,
+            listen: Some("127.0.0.1:0".parse().unwrap()),
+            connect_retry_time: Duration::from_millis(200),
+            extended_message: false,
+            four_octet_as: false,
+            multiple_labels: vec![],
+            confederation_members: vec![],
+            neighbors: vec![NeighborConfig { address: localhost, port: 0, remote_as, passive: false, prefix_limits: vec![], role: None, strict_role: false }],
+        };
+
+        // Bind both first so each knows the other's port.
+        let mut a_config = config(65000, 65001, Ipv4Addr::new(10, 0, 0, 1));
+        let mut b_config = config(65001, 65000, Ipv4Addr::new(10, 0, 0, 2));
+        let a_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let b_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        a_config.neighbors[0].port = b_listener.local_addr().unwrap().port();
+        b_config.neighbors[0].port = a_listener.local_addr().unwrap().port();
+        let a = Speaker { config: Arc::new(a_config), listener: Some(a_listener) };
+        let b = Speaker { config: Arc::new(b_config), listener: Some(b_listener) };
+
+        let (a_handle, mut a_events) = a.start();
+        let (b_handle, _b_events) = b.start();
+
+        // A collision can make a session go down and come back up again,
+        // so wait for both ends to settle on one.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while a_handle.established().is_empty() || b_handle.established().is_empty() {
+            assert!(Instant::now() < deadline, "sessions not established");
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(b_handle.send(localhost, BgpMessage::Update(Box::new(update()))));
+        loop {
+            if let SpeakerEvent::Update(address, _) = next_event(&mut a_events).await {
+                assert_eq!(address, localhost);
+                break;
+            }
+        }
+
+        assert_eq!(a_handle.connections.lock().unwrap().resolver.connections(localhost), 1);
+        assert_eq!(b_handle.connections.lock().unwrap().resolver.connections(localhost), 1);
+    }
+
+    // A goes over its limit for B, sends it a Cease and holds it down
+    // until the restart interval has passed.
+    #[tokio::test]
+    async fn speaker_prefix_limit_test() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        let limit = PrefixLimit { afi: 1, safi: 1, maximum: 1, warning_threshold: 100, restart_interval: Some(Duration::from_millis(500)) };
+
+        let a = Speaker::bind(SpeakerConfig {
+            local_as: 65000,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 1),
+            hold_time: 90,
+            listen: Some("127.0.0.1:0".parse().unwrap()),
+            connect_retry_time: Duration::from_secs(1),
+            extended_message: false,
+            four_octet_as: false,
+            multiple_labels: vec![],
+            confederation_members: vec![],
+            neighbors: vec![NeighborConfig { address: localhost, port: 179, remote_as: 65001, passive: true, prefix_limits: vec![limit.clone()], role: None, strict_role: false }],
+        }).await.unwrap();
+        let port = a.local_addr().unwrap().port();
+
+        let b = Speaker::bind(SpeakerConfig {
+            local_as: 65001,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 2),
+            hold_time: 90,
+            listen: None,
+            connect_retry_time: Duration::from_millis(100),
+            extended_message: false,
+            four_octet_as: false,
+            multiple_labels: vec![],
+            confederation_members: vec![],
+            neighbors: vec![NeighborConfig { address: localhost, port, remote_as: 65000, passive: false, prefix_limits: vec![], role: None, strict_role: false }],
+        }).await.unwrap();
+
+        let (a_handle, mut a_events) = a.start();
+        let (b_handle, mut b_events) = b.start();
+        assert!(matches!(next_event(&mut a_events).await, SpeakerEvent::Established(..)));
+        assert!(matches!(next_event(&mut b_events).await, SpeakerEvent::Established(..)));
+
+        assert!(b_handle.send(localhost, BgpMessage::Update(Box::new(update()))));
+        assert_eq!(next_event(&mut a_events).await, SpeakerEvent::PrefixLimit(localhost, PrefixLimitEvent::Warning { limit: limit.clone(), count: 1 }));
+        assert!(matches!(next_event(&mut a_events).await, SpeakerEvent::Update(..)));
+
+        let mut update = update();
+        update.nlri.push("10.0.0.0/8".parse().unwrap());
+        let sent = Instant::now();
+        assert!(b_handle.send(localhost, BgpMessage::Update(Box::new(update))));
+        assert_eq!(next_event(&mut a_events).await, SpeakerEvent::PrefixLimit(localhost, PrefixLimitEvent::Exceeded { limit: limit.clone(), count: 2 }));
+        assert_eq!(next_event(&mut a_events).await, SpeakerEvent::Closed(localhost));
+        match next_event(&mut b_events).await {
+            SpeakerEvent::Notification(_, notification) => assert_eq!(notification.cease_subcode(), Some(CeaseSubcode::MaximumNumberOfPrefixesReached)),
+            other => panic!("expected a notification: {:?}", other),
+        }
+        assert_eq!(next_event(&mut b_events).await, SpeakerEvent::Closed(localhost));
+
+        // B keeps trying, but only gets back in after the interval.
+        assert!(matches!(next_event(&mut a_events).await, SpeakerEvent::Established(..)));
+        assert!(sent.elapsed() >= Duration::from_millis(500));
+        assert!(!a_handle.established().is_empty());
+    }
+
+    #[tokio::test]
+    async fn speaker_unknown_neighbor_test() {
+        let a = Speaker::bind(SpeakerConfig {
+            local_as: 65000,
+            bgp_identifier: Ipv4Addr::new(10, 0, 0, 1),
+            hold_time: 90,
+            listen: Some("127.0.0.1:0".parse().unwrap()),
+            connect_retry_time: Duration::from_secs(1),
+            extended_message: false,
+            four_octet_as: false,
+            multiple_labels: vec![],
+            confederation_members: vec![],
+            neighbors: vec![NeighborConfig { address: "192.0.2.1".parse().unwrap(), port: 179, remote_as: 65001, passive: true, prefix_limits: vec![], role: None, strict_role: false }],
+        }).await.unwrap();
+        let address = a.local_addr().unwrap();
+        let (_, _events) = a.start();
+
+        // The connection is accepted and then dropped without an OPEN.
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut buffer = [0u8; 19];
+        let n = tokio::time::timeout(Duration::from_secs(5), tokio::io::AsyncReadExt::read(&mut stream, &mut buffer)).await.unwrap().unwrap();
+        assert_eq!(n, 0);
+    }
+}
@@ -0,0 +1,165 @@
+// Clocks and timers for the session layer.
+//
+// Everything that needs the time asks a Clock for it rather than calling
+// Instant::now() itself. The speaker uses the SystemClock, and tests use
+// a MockClock they can move forward by hand, so timers expiring can be
+// tested without waiting for them.
+//
+// The hold and keepalive timers live in the Session (fsm.rs) since they
+// are restarted by what it sends and receives. ConnectRetry is about
+// making connections, so it lives with the speaker, and this file just
+// works out how long to wait. RFC 4271 section 10 asks for the
+// ConnectRetry time to be jittered, so that speakers which lost their
+// sessions at the same time don't all try again at the same time. We
+// also back off exponentially when connecting keeps failing.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug,Clone,Copy,Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// A clock that only moves when told to. Clones share the same time, so a
+// test can keep one and hand the other to whatever it's testing.
+#[derive(Debug,Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock { now: Arc::new(Mutex::new(Instant::now())) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+// Picks a random point between 75% and 100% of a duration, as RFC 4271
+// suggests. This is a xorshift generator, which is plenty for spreading
+// out timers, and seeding it makes tests repeatable.
+#[derive(Debug,Clone)]
+pub struct Jitter {
+    state: u64,
+}
+
+impl Jitter {
+    pub fn new(seed: u64) -> Jitter {
+        Jitter { state: seed | 1 }
+    }
+
+    // Seeded from the time of day, for when it doesn't need to be
+    // repeatable.
+    pub fn from_time() -> Jitter {
+        Jitter::new(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    pub fn apply(&mut self, duration: Duration) -> Duration {
+        let fraction = 0.75 + 0.25 * (self.next() % 1_000_001) as f64 / 1_000_000.0;
+        duration.mul_f64(fraction)
+    }
+}
+
+// Never wait more than this many times the ConnectRetry time.
+const MAX_CONNECT_RETRY_BACKOFF: u32 = 8;
+
+#[derive(Debug,Clone)]
+pub struct ConnectRetry {
+    time: Duration,
+    failures: u32,
+    jitter: Jitter,
+}
+
+impl ConnectRetry {
+    pub fn new(time: Duration, jitter: Jitter) -> ConnectRetry {
+        ConnectRetry { time, failures: 0, jitter }
+    }
+
+    // How long to wait before the next attempt. Each call without a
+    // reset() in between doubles the wait, up to the maximum.
+    pub fn next_delay(&mut self) -> Duration {
+        let backoff = ::std::cmp::min(1 << ::std::cmp::min(self.failures, 31), MAX_CONNECT_RETRY_BACKOFF);
+        self.failures = self.failures.saturating_add(1);
+        self.jitter.apply(self.time * backoff)
+    }
+
+    // Call when a session is established, so the next failure waits the
+    // ConnectRetry time again.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_test() {
+        let clock = MockClock::new();
+        let other = clock.clone();
+        let start = clock.now();
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(other.now() - start, Duration::from_secs(5));
+        assert_eq!(clock.now(), other.now());
+    }
+
+    #[test]
+    fn jitter_test() {
+        let mut jitter = Jitter::new(1);
+        let delays: Vec<Duration> = (0..100).map(|_| jitter.apply(Duration::from_secs(100))).collect();
+
+        assert!(delays.iter().all(|d| *d >= Duration::from_secs(75) && *d <= Duration::from_secs(100)));
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        // The same seed gives the same delays.
+        let mut jitter = Jitter::new(1);
+        assert_eq!(jitter.apply(Duration::from_secs(100)), delays[0]);
+    }
+
+    #[test]
+    fn connect_retry_test() {
+        let mut retry = ConnectRetry::new(Duration::from_secs(10), Jitter::new(42));
+        let within = |d: Duration, secs: u64| d >= Duration::from_secs(secs) * 3 / 4 && d <= Duration::from_secs(secs);
+
+        assert!(within(retry.next_delay(), 10));
+        assert!(within(retry.next_delay(), 20));
+        assert!(within(retry.next_delay(), 40));
+        assert!(within(retry.next_delay(), 80));
+        assert!(within(retry.next_delay(), 80));
+
+        retry.reset();
+        assert!(within(retry.next_delay(), 10));
+    }
+}
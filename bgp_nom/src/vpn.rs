@@ -0,0 +1,450 @@
+// BGP/MPLS IP VPNs (RFC 4364, and RFC 4659 for IPv6), AFI 1 and 2 with
+// SAFI 128.
+//
+// A VPN route is a prefix made unique by the Route Distinguisher in front
+// of it, so that customers can use the same addresses, and the labels to
+// send the traffic with. Which VRFs the route belongs in is said by Route
+// Target extended communities: each VRF imports the routes carrying any
+// of its import targets, and adds its export targets to its own routes.
+//
+// RDs and RTs are written the same way, as the administrator and the
+// assigned number, e.g. "65000:1", "192.0.2.1:5" or "4200000000:1". Which
+// of the three types is meant follows from the administrator: an address,
+// or an AS number that fits in 16 bits or not.
+//
+// Vrfs holds the routes of each VRF, keyed by RD and prefix as routes
+// from different RDs may be for the same prefix. Run the UPDATEs from the
+// other PEs through update(), and use Vrf::export() to build the UPDATE
+// announcing a VRF's own routes.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+use nom::be_u8;
+
+use super::*;
+use super::mpls::{Label, format_labels, labels};
+
+pub const SAFI_VPN: u8 = 128;
+
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Hash,Clone,Copy)]
+pub enum RouteDistinguisher {
+    As2 { asn: u16, value: u32 },
+    Ipv4 { address: Ipv4Addr, value: u16 },
+    As4 { asn: u32, value: u16 },
+}
+
+// Written the same way as an RD, see above.
+pub type RouteTarget = RouteDistinguisher;
+
+impl RouteDistinguisher {
+    pub fn type_code(&self) -> u8 {
+        match *self {
+            RouteDistinguisher::As2 { .. } => 0,
+            RouteDistinguisher::Ipv4 { .. } => 1,
+            RouteDistinguisher::As4 { .. } => 2,
+        }
+    }
+
+    // The six bytes after the type.
+    pub fn value_bytes(&self) -> [u8; 6] {
+        let mut b = [0u8; 6];
+        match *self {
+            RouteDistinguisher::As2 { asn, value } => {
+                b[..2].copy_from_slice(&asn.to_be_bytes());
+                b[2..].copy_from_slice(&value.to_be_bytes());
+            },
+            RouteDistinguisher::Ipv4 { address, value } => {
+                b[..4].copy_from_slice(&address.octets());
+                b[4..].copy_from_slice(&value.to_be_bytes());
+            },
+            RouteDistinguisher::As4 { asn, value } => {
+                b[..4].copy_from_slice(&asn.to_be_bytes());
+                b[4..].copy_from_slice(&value.to_be_bytes());
+            },
+        }
+        b
+    }
+
+    pub fn from_value_bytes(type_code: u8, b: &[u8]) -> Option<RouteDistinguisher> {
+        let u16_at = |i: usize| u16::from_be_bytes([b[i], b[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+        match type_code {
+            0 => Some(RouteDistinguisher::As2 { asn: u16_at(0), value: u32_at(2) }),
+            1 => Some(RouteDistinguisher::Ipv4 { address: Ipv4Addr::from(u32_at(0)), value: u16_at(4) }),
+            2 => Some(RouteDistinguisher::As4 { asn: u32_at(0), value: u16_at(4) }),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[0, self.type_code()]);
+        out.extend_from_slice(&self.value_bytes());
+    }
+}
+
+impl fmt::Display for RouteDistinguisher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteDistinguisher::As2 { asn, value } => write!(f, "{}:{}", asn, value),
+            RouteDistinguisher::Ipv4 { address, value } => write!(f, "{}:{}", address, value),
+            RouteDistinguisher::As4 { asn, value } => write!(f, "{}:{}", asn, value),
+        }
+    }
+}
+
+impl FromStr for RouteDistinguisher {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<RouteDistinguisher, ()> {
+        let mut parts = s.rsplitn(2, ':');
+        let (value, administrator) = match (parts.next(), parts.next()) {
+            (Some(value), Some(administrator)) => (value, administrator),
+            _ => return Err(()),
+        };
+
+        if let Ok(address) = administrator.parse() {
+            return Ok(RouteDistinguisher::Ipv4 { address, value: value.parse().map_err(|_| ())? });
+        }
+        match administrator.parse::<u32>().map_err(|_| ())? {
+            asn if asn <= 0xffff => Ok(RouteDistinguisher::As2 { asn: asn as u16, value: value.parse().map_err(|_| ())? }),
+            asn => Ok(RouteDistinguisher::As4 { asn, value: value.parse().map_err(|_| ())? }),
+        }
+    }
+}
+
+named!(pub route_distinguisher<&[u8], RouteDistinguisher>,
+    do_parse!(
+        tag!([0u8]) >>
+        type_code: be_u8 >>
+        rd: map_opt!(take!(6), |b: &[u8]| RouteDistinguisher::from_value_bytes(type_code, b)) >>
+        (rd)
+    )
+);
+
+#[derive(Debug,PartialEq,Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VpnPrefix {
+    pub labels: Vec<Label>,
+    pub rd: RouteDistinguisher,
+    pub address: IpAddr,
+    pub length: u8,
+}
+
+impl fmt::Display for VpnPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}/{} label {}", self.rd, self.address, self.length, format_labels(&self.labels))
+    }
+}
+
+// The length covers the labels and the RD as well as the prefix.
+named_args!(pub vpn_prefix(afi: u16, withdrawn: bool) <VpnPrefix>,
+    do_parse!(
+        length: be_u8 >>
+        labels: call!(labels, withdrawn) >>
+        rd: route_distinguisher >>
+        prefix_length: expr_opt!((length as usize).checked_sub(labels.len() * 24 + 64).filter(|l| *l <= max_prefix_length(afi))) >>
+        prefix: take!(prefix_length.div_ceil(8)) >>
+        (VpnPrefix { labels, rd, address: prefix_address(afi, prefix), length: prefix_length as u8 })
+    )
+);
+
+impl VpnPrefix {
+    // The labels, RD and prefix have to fit in a length of 255 bits.
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<(), String> {
+        let length = self.labels.len() * 24 + 64 + self.length as usize;
+        if length > 255 {
+            return Err(format!("VPN prefix {} is too long", self));
+        }
+        out.push(length as u8);
+        for label in &self.labels {
+            label.encode(out);
+        }
+        self.rd.encode(out);
+        out.extend_from_slice(&prefix_bytes(self.address, self.length));
+        Ok(())
+    }
+}
+
+// A VPN route as held in a VRF.
+#[derive(Debug,PartialEq,Clone)]
+pub struct VpnRoute {
+    pub prefix: VpnPrefix,
+    pub next_hop: Option<IpAddr>,
+    pub route_targets: Vec<RouteTarget>,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct Vrf {
+    pub name: String,
+    pub rd: RouteDistinguisher,
+    pub import: Vec<RouteTarget>,
+    pub export: Vec<RouteTarget>,
+    routes: BTreeMap<(RouteDistinguisher, IpAddr, u8), VpnRoute>,
+}
+
+impl Vrf {
+    pub fn new(name: &str, rd: RouteDistinguisher, import: Vec<RouteTarget>, export: Vec<RouteTarget>) -> Vrf {
+        Vrf { name: name.to_string(), rd, import, export, routes: BTreeMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = &VpnRoute> {
+        self.routes.values()
+    }
+
+    // The routes for exactly this prefix, from any RD.
+    pub fn lookup(&self, address: IpAddr, length: u8) -> Vec<&VpnRoute> {
+        self.routes.values().filter(|r| r.prefix.address == address && r.prefix.length == length).collect()
+    }
+
+    fn imports(&self, route_targets: &[RouteTarget]) -> bool {
+        route_targets.iter().any(|rt| self.import.contains(rt))
+    }
+
+    // An UPDATE announcing the prefixes, all of the same address family,
+    // each with its label, with this VRF's RD and export targets.
+    pub fn export(&self, prefixes: &[(IpAddr, u8, Label)], next_hop: IpAddr) -> BgpUpdateMessage {
+        let afi = match prefixes.first().map(|p| p.0) {
+            Some(IpAddr::V6(_)) => AFI_IPV6,
+            _ => AFI_IPV4,
+        };
+        // The next hop has an RD too, always zero.
+        let mut next_hop_bytes = vec![0; 8];
+        match next_hop {
+            IpAddr::V4(address) => next_hop_bytes.extend_from_slice(&address.octets()),
+            IpAddr::V6(address) => next_hop_bytes.extend_from_slice(&address.octets()),
+        }
+        let nlri = prefixes.iter().map(|&(address, length, label)| VpnPrefix { labels: vec![label], rd: self.rd, address, length }).collect();
+
+        let mut path_attributes = vec![
+            BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::Origin(Box::new(OriginAttribute { origin_code: BgpOriginCode::Igp })),
+            },
+            BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: false, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::AsPath(Box::new(AsPathAttribute { as_path: vec![] })),
+            },
+            BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+                attribute: PathAttribute::MpReachNlri(Box::new(MpReachNlriAttribute { afi, safi: SAFI_VPN, next_hop: next_hop_bytes, nlri: MpNlri::Vpn(nlri) })),
+            },
+        ];
+        if !self.export.is_empty() {
+            path_attributes.push(BgpPathAttribute {
+                flags: BgpPathAttributeFlags { optional: true, transitive: true, partial: false, extended_length: false },
+                attribute: PathAttribute::ExtendedCommunities(Box::new(ExtendedCommunitiesAttribute {
+                    communities: self.export.iter().map(|rt| ExtendedCommunity::RouteTarget { target: *rt }).collect(),
+                })),
+            });
+        }
+
+        BgpUpdateMessage { withdrawn_routes: vec![], path_attributes, nlri: vec![] }
+    }
+}
+
+#[derive(Debug,Default)]
+pub struct Vrfs {
+    vrfs: Vec<Vrf>,
+}
+
+impl Vrfs {
+    pub fn new() -> Vrfs {
+        Vrfs::default()
+    }
+
+    pub fn add(&mut self, vrf: Vrf) {
+        self.vrfs.push(vrf);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vrf> {
+        self.vrfs.iter().find(|v| v.name == name)
+    }
+
+    // Import the VPN routes announced in an UPDATE into the VRFs with a
+    // matching import target, and remove the withdrawn ones. A route
+    // announced again without a target a VRF imports is removed from it.
+    pub fn update(&mut self, update: &BgpUpdateMessage) {
+        let route_targets = route_targets(update);
+
+        for attribute in &update.path_attributes {
+            match attribute.attribute {
+                PathAttribute::MpReachNlri(ref mp) if mp.safi == SAFI_VPN => {
+                    if let MpNlri::Vpn(ref prefixes) = mp.nlri {
+                        let next_hop = vpn_next_hop(&mp.next_hop);
+                        for prefix in prefixes {
+                            let key = (prefix.rd, prefix.address, prefix.length);
+                            for vrf in &mut self.vrfs {
+                                match vrf.imports(&route_targets) {
+                                    true => {
+                                        let route = VpnRoute { prefix: prefix.clone(), next_hop, route_targets: route_targets.clone() };
+                                        vrf.routes.insert(key, route);
+                                    },
+                                    false => { vrf.routes.remove(&key); },
+                                }
+                            }
+                        }
+                    }
+                },
+                PathAttribute::MpUnreachNlri(ref mp) if mp.safi == SAFI_VPN => {
+                    if let MpNlri::Vpn(ref prefixes) = mp.withdrawn_routes {
+                        for prefix in prefixes {
+                            for vrf in &mut self.vrfs {
+                                vrf.routes.remove(&(prefix.rd, prefix.address, prefix.length));
+                            }
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+pub fn route_targets(update: &BgpUpdateMessage) -> Vec<RouteTarget> {
+    update.path_attributes.iter().flat_map(|a| match a.attribute {
+        PathAttribute::ExtendedCommunities(ref c) => c.communities.iter().filter_map(|c| match *c {
+            ExtendedCommunity::RouteTarget { target } => Some(target),
+            _ => None,
+        }).collect(),
+        _ => vec![],
+    }).collect()
+}
+
+// The address in a VPN next hop, after its RD.
+fn vpn_next_hop(next_hop: &[u8]) -> Option<IpAddr> {
+    match next_hop.len() {
+        12 => Some(prefix_address(AFI_IPV4, &next_hop[8..])),
+        24 | 48 => Some(prefix_address(AFI_IPV6, &next_hop[8..24])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rd(s: &str) -> RouteDistinguisher {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn route_distinguisher_test() {
+        let cases = [
+            ("65000:1", RouteDistinguisher::As2 { asn: 65000, value: 1 }, [0x00, 0x00, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x01]),
+            ("192.0.2.1:5", RouteDistinguisher::Ipv4 { address: Ipv4Addr::new(192, 0, 2, 1), value: 5 }, [0x00, 0x01, 0xc0, 0x00, 0x02, 0x01, 0x00, 0x05]),
+            ("4200000000:1", RouteDistinguisher::As4 { asn: 4_200_000_000, value: 1 }, [0x00, 0x02, 0xfa, 0x56, 0xea, 0x00, 0x00, 0x01]),
+        ];
+
+        for &(text, rd, bytes) in &cases {
+            assert_eq!(text.parse(), Ok(rd));
+            assert_eq!(rd.to_string(), text);
+            assert_eq!(route_distinguisher(&bytes), Done(&b""[..], rd));
+            let mut encoded = Vec::new();
+            rd.encode(&mut encoded);
+            assert_eq!(encoded, bytes.to_vec());
+        }
+
+        assert_eq!("65000".parse::<RouteDistinguisher>(), Err(()));
+        assert_eq!("4200000000:70000".parse::<RouteDistinguisher>(), Err(()));
+        assert_eq!("192.0.2.1:x".parse::<RouteDistinguisher>(), Err(()));
+        assert!(route_distinguisher(&[0x00, 0x03, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn vpn_prefix_test() {
+        // 10.1.0.0/16 with label 100, 24 + 64 + 16 bits.
+        let input = [104, 0x00, 0x06, 0x41, 0x00, 0x00, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x01, 10, 1];
+        let prefix = VpnPrefix { labels: vec![Label::new(100)], rd: rd("65000:1"), address: "10.1.0.0".parse().unwrap(), length: 16 };
+        assert_eq!(vpn_prefix(&input, AFI_IPV4, false), Done(&b""[..], prefix.clone()));
+        assert_eq!(prefix.to_string(), "65000:1 10.1.0.0/16 label 100");
+        let mut encoded = Vec::new();
+        prefix.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, input.to_vec());
+
+        let labels = vec![Label { value: 16, traffic_class: 0, bottom: false }, Label::new(17)];
+        let prefix = VpnPrefix { labels, rd: rd("192.0.2.1:5"), address: "2001:db8::".parse().unwrap(), length: 32 };
+        let mut encoded = Vec::new();
+        prefix.encode(&mut encoded).unwrap();
+        assert_eq!(encoded.len(), 1 + 6 + 8 + 4);
+        assert_eq!(vpn_prefix(&encoded, AFI_IPV6, false), Done(&b""[..], prefix.clone()));
+
+        // With three labels and the RD there's only room for 119 bits of
+        // prefix.
+        let labels = vec![Label { value: 16, traffic_class: 0, bottom: false }, Label { value: 17, traffic_class: 0, bottom: false }, Label::new(18)];
+        assert!(VpnPrefix { labels: labels.clone(), length: 119, ..prefix.clone() }.encode(&mut Vec::new()).is_ok());
+        assert!(VpnPrefix { labels, length: 120, ..prefix }.encode(&mut Vec::new()).is_err());
+
+        // Too short for the labels and RD, and too long for IPv4.
+        assert!(vpn_prefix(&[80, 0x00, 0x06, 0x41, 0x00, 0x00, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x01], AFI_IPV4, false).is_err());
+        assert!(vpn_prefix(&[121, 0x00, 0x06, 0x41, 0x00, 0x00, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0], AFI_IPV4, false).is_err());
+    }
+
+    fn announce(prefix: &str, length: u8, rd: &str, targets: &[&str]) -> BgpUpdateMessage {
+        let vrf = Vrf::new("pe", rd.parse().unwrap(), vec![], targets.iter().map(|t| t.parse().unwrap()).collect());
+        vrf.export(&[(prefix.parse().unwrap(), length, Label::new(100))], "192.0.2.1".parse().unwrap())
+    }
+
+    fn withdraw(prefix: &str, length: u8, rd: &str) -> BgpUpdateMessage {
+        let prefix = VpnPrefix { labels: vec![Label::withdrawal()], rd: rd.parse().unwrap(), address: prefix.parse().unwrap(), length };
+        BgpUpdateMessage { withdrawn_routes: vec![], nlri: vec![], path_attributes: vec![BgpPathAttribute {
+            flags: BgpPathAttributeFlags { optional: true, transitive: false, partial: false, extended_length: false },
+            attribute: PathAttribute::MpUnreachNlri(Box::new(MpUnreachNlriAttribute { afi: AFI_IPV4, safi: SAFI_VPN, withdrawn_routes: MpNlri::Vpn(vec![prefix]) })),
+        }] }
+    }
+
+    #[test]
+    fn vrf_import_test() {
+        let mut vrfs = Vrfs::new();
+        vrfs.add(Vrf::new("red", rd("65000:1"), vec![rd("65000:1")], vec![rd("65000:1")]));
+        vrfs.add(Vrf::new("blue", rd("65000:2"), vec![rd("65000:2"), rd("65000:100")], vec![rd("65000:2")]));
+
+        vrfs.update(&announce("10.0.0.0", 8, "65000:1", &["65000:1"]));
+        vrfs.update(&announce("10.0.0.0", 8, "65000:2", &["65000:2"]));
+        vrfs.update(&announce("172.16.0.0", 12, "65000:3", &["65000:100", "65000:1"]));
+        vrfs.update(&announce("192.168.0.0", 16, "65000:4", &["65000:999"]));
+
+        let red = vrfs.get("red").unwrap();
+        assert_eq!(red.len(), 2);
+        let route = red.lookup("10.0.0.0".parse().unwrap(), 8)[0];
+        assert_eq!(route.prefix.rd, rd("65000:1"));
+        assert_eq!(route.next_hop, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(route.prefix.labels, vec![Label::new(100)]);
+        let blue = vrfs.get("blue").unwrap();
+        assert_eq!(blue.len(), 2);
+        assert_eq!(blue.lookup("10.0.0.0".parse().unwrap(), 8)[0].prefix.rd, rd("65000:2"));
+        assert_eq!(blue.lookup("172.16.0.0".parse().unwrap(), 12).len(), 1);
+        assert!(vrfs.get("green").is_none());
+
+        // Announced again without the target red imports.
+        vrfs.update(&announce("172.16.0.0", 12, "65000:3", &["65000:100"]));
+        assert_eq!(vrfs.get("red").unwrap().lookup("172.16.0.0".parse().unwrap(), 12).len(), 0);
+        assert_eq!(vrfs.get("blue").unwrap().len(), 2);
+
+        // Withdrawn, only for the RD given.
+        vrfs.update(&withdraw("10.0.0.0", 8, "65000:1"));
+        assert!(vrfs.get("red").unwrap().is_empty());
+        assert_eq!(vrfs.get("blue").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn vrf_export_test() {
+        let update = announce("10.1.0.0", 16, "65000:1", &["65000:1", "192.0.2.1:5"]);
+        assert_eq!(route_targets(&update), vec![rd("65000:1"), rd("192.0.2.1:5")]);
+
+        // Survives the trip over the wire.
+        let message = BgpMessage::Update(Box::new(update));
+        assert_eq!(parse_bgp_message(&message.encode().unwrap()), Done(&b""[..], message));
+    }
+}